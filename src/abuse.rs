@@ -0,0 +1,136 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    config::Config,
+    database::{self, Database},
+    models::User,
+};
+
+/// 用户被标记为疑似滥用后的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AbuseMode {
+    /// 只通知管理员
+    #[default]
+    Notify,
+    /// 通知管理员，并在管理员解除标记前暂停该用户生成
+    Restrict,
+}
+
+impl AbuseMode {
+    /// 解析配置值 notify/restrict，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "notify" => Some(AbuseMode::Notify),
+            "restrict" => Some(AbuseMode::Restrict),
+            _ => None,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            AbuseMode::Notify => "仅通知",
+            AbuseMode::Restrict => "已暂停生成，需管理员解除标记",
+        }
+    }
+}
+
+/// 被标记且处于限制模式时，普通用户不能继续生成
+pub fn is_restricted(config: &Config, user: &User) -> bool {
+    config.abuse_mode == AbuseMode::Restrict && user.flagged_at.is_some() && !config.is_admin(user.user_id)
+}
+
+/// 统计用户在窗口期内生成过的不同机器码数，超过阈值时标记用户并通知管理员；返回是否为新标记
+pub async fn check_user(config: &Config, db: &Database, user_id: i64) -> Result<bool> {
+    if config.abuse_machine_code_threshold <= 0 || config.is_admin(user_id) {
+        return Ok(false);
+    }
+
+    let since = config.clock.now_utc() - chrono::Duration::hours(config.abuse_window_hours);
+    let distinct = database::count_distinct_machine_codes_since(db, user_id, since).await?;
+    if distinct <= config.abuse_machine_code_threshold {
+        return Ok(false);
+    }
+    if !database::flag_user(db, user_id).await? {
+        return Ok(false);
+    }
+
+    warn!("用户 {} 在 {} 小时内为 {} 个不同机器码生成激活码，已标记", user_id, config.abuse_window_hours, distinct);
+    let detail = format!(
+        "{} 小时内不同机器码: {}; 阈值: {}",
+        config.abuse_window_hours, distinct, config.abuse_machine_code_threshold
+    );
+    if let Err(e) = database::log_admin_action(db, database::SYSTEM_ACTOR_ID, "flag_user", Some(user_id), &detail).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    let message = format!(
+        "🚩 疑似转卖激活码\n\n\
+         👤 用户: {}\n\
+         🔢 过去 {} 小时内不同机器码: {} (阈值 {})\n\
+         ⚙️ 处理方式: {}\n\n\
+         使用 /flagged 查看并处理",
+        user_id,
+        config.abuse_window_hours,
+        distinct,
+        config.abuse_machine_code_threshold,
+        config.abuse_mode.description()
+    );
+    if let Err(e) = crate::guard::send_alert(config, &message).await {
+        error!("发送滥用告警失败: {}", e);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abuse_mode_from_name() {
+        assert_eq!(AbuseMode::from_name("Restrict"), Some(AbuseMode::Restrict));
+        assert_eq!(AbuseMode::from_name(" notify "), Some(AbuseMode::Notify));
+        assert_eq!(AbuseMode::from_name("ban"), None);
+    }
+
+    #[tokio::test]
+    async fn test_window_follows_injected_clock() {
+        use crate::clock::{MockClock, SharedClock};
+        use crate::i18n::Lang;
+        use chrono::Utc;
+        use std::sync::Arc;
+
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        database::get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        let start = Utc::now();
+        for machine_code in ["MACHINE-0001", "MACHINE-0002", "MACHINE-0003"] {
+            sqlx::query(
+                "INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at) VALUES (1, ?, 'C', '4.5', ?)",
+            )
+            .bind(machine_code)
+            .bind(start)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        }
+
+        let mut config = Config::load().unwrap();
+        config.abuse_machine_code_threshold = 2;
+        config.abuse_window_hours = 24;
+        config.alert_dedup_window = 0;
+        config.telegram = None;
+
+        // 窗口期已过，旧记录不再计入
+        let mock = Arc::new(MockClock::new(start));
+        config.clock = SharedClock::new(mock.clone());
+        mock.advance(std::time::Duration::from_secs(25 * 3600));
+        assert!(!check_user(&config, &db, 1).await.unwrap());
+
+        config.clock = SharedClock::new(Arc::new(MockClock::new(start)));
+        assert!(check_user(&config, &db, 1).await.unwrap());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 相同告警的去重状态：同一条告警在冷却窗口内只发送一次，其余只累计次数。
+/// 克隆后共享同一份数据，仅保存在内存中
+#[derive(Debug, Clone, Default)]
+pub struct AlertDeduper(Arc<Mutex<HashMap<String, Window>>>);
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    /// 窗口内被压下的重复次数
+    repeats: u32,
+}
+
+impl AlertDeduper {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Window>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 告警不在冷却窗口内时开启新窗口并返回窗口开始时间，调用方应发送该告警；
+    /// 否则累计一次重复并返回 None
+    pub fn admit(&self, message: &str, now: Instant, window: Duration) -> Option<Instant> {
+        let mut windows = self.lock();
+        if let Some(current) = windows.get_mut(message) {
+            if now.saturating_duration_since(current.started) < window {
+                current.repeats += 1;
+                return None;
+            }
+        }
+        windows.insert(message.to_string(), Window { started: now, repeats: 0 });
+        Some(now)
+    }
+
+    /// 窗口到期：仅当该告警的窗口仍是 `started` 开启的那个时结束它，返回窗口内的重复次数
+    pub fn expire(&self, message: &str, started: Instant) -> Option<u32> {
+        let mut windows = self.lock();
+        if windows.get(message)?.started != started {
+            return None;
+        }
+        windows.remove(message).map(|window| window.repeats)
+    }
+
+    /// 告警对应的问题已恢复：提前结束窗口，返回窗口内的重复次数；没有进行中的窗口时返回 None
+    pub fn resolve(&self, message: &str) -> Option<u32> {
+        self.lock().remove(message).map(|window| window.repeats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use chrono::Utc;
+
+    #[test]
+    fn test_repeats_are_counted_within_window() {
+        let alerts = AlertDeduper::default();
+        let clock = MockClock::new(Utc::now());
+        let window = Duration::from_secs(600);
+
+        let started = alerts.admit("网络异常", clock.instant(), window).unwrap();
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(alerts.admit("网络异常", clock.instant(), window), None);
+        assert_eq!(alerts.admit("网络异常", clock.instant(), window), None);
+        // 不同告警互不影响
+        assert!(alerts.admit("磁盘已满", clock.instant(), window).is_some());
+        assert_eq!(alerts.expire("网络异常", started), Some(2));
+
+        // 窗口结束后再次出现时重新发送
+        let next = alerts.admit("网络异常", clock.instant(), window).unwrap();
+        assert_eq!(alerts.expire("网络异常", started), None);
+        assert_eq!(alerts.resolve("网络异常"), Some(0));
+        assert_eq!(alerts.expire("网络异常", next), None);
+        assert_eq!(alerts.resolve("网络异常"), None);
+    }
+
+    #[test]
+    fn test_stale_window_is_replaced() {
+        let alerts = AlertDeduper::default();
+        let clock = MockClock::new(Utc::now());
+        let window = Duration::from_secs(60);
+
+        alerts.admit("网络异常", clock.instant(), window).unwrap();
+        clock.advance(window);
+        assert!(alerts.admit("网络异常", clock.instant(), window).is_some());
+    }
+}
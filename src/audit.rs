@@ -0,0 +1,125 @@
+use sha2::{Digest, Sha256};
+
+use crate::models::AuditEntry;
+
+/// 哈希链第一条记录之前的哈希
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// 本条记录的哈希：SHA-256(上一条的哈希 || 本条内容)，内容按 JSON 数组编码以避免字段拼接歧义
+pub fn entry_hash(previous: &str, entry: &AuditEntry) -> String {
+    let content = serde_json::json!([
+        entry.id,
+        entry.admin_id,
+        entry.action,
+        entry.target_user_id,
+        entry.detail,
+        entry.created_at,
+    ]);
+    let mut hasher = Sha256::new();
+    hasher.update(previous.as_bytes());
+    hasher.update(content.to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// 审计日志哈希链的校验结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainStatus {
+    /// 全部记录连续且未被修改；`head` 为最后一条的哈希，可与外部副本比对
+    Intact { entries: usize, head: Option<String> },
+    /// 第一条对不上的记录：该记录被修改，或它之前的记录被删除
+    Broken { entry_id: i64, verified: usize },
+    /// 记录本身连续，但末条哈希与锚点不符：最近的记录被删除
+    Truncated { entries: usize, head: Option<String> },
+}
+
+impl ChainStatus {
+    pub fn is_intact(&self) -> bool {
+        matches!(self, ChainStatus::Intact { .. })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ChainStatus::Intact { entries, head: Some(head) } => {
+                format!("完整，共 {} 条，末条哈希 {}", entries, &head[..16])
+            }
+            ChainStatus::Intact { .. } => "完整，暂无记录".to_string(),
+            ChainStatus::Broken { entry_id, verified } => format!(
+                "在记录 #{} 处断开（此前 {} 条校验通过），该记录被修改或其前面的记录被删除",
+                entry_id, verified
+            ),
+            ChainStatus::Truncated { entries, .. } => {
+                format!("末尾缺失记录：现存 {} 条均连续，但末条哈希与锚点不符，最近的记录被删除", entries)
+            }
+        }
+    }
+}
+
+/// 按 id 顺序逐条重算哈希，返回第一处断点；链完整时再与 `anchor`（写入时保存的末条哈希）比对
+pub fn verify(entries: &[AuditEntry], anchor: Option<&str>) -> ChainStatus {
+    let mut previous = GENESIS_HASH.to_string();
+    for (verified, entry) in entries.iter().enumerate() {
+        let expected = entry_hash(&previous, entry);
+        if entry.hash.as_deref() != Some(expected.as_str()) {
+            return ChainStatus::Broken { entry_id: entry.id, verified };
+        }
+        previous = expected;
+    }
+    let head = entries.last().and_then(|entry| entry.hash.clone());
+    match anchor {
+        Some(anchor) if head.as_deref() != Some(anchor) => ChainStatus::Truncated { entries: entries.len(), head },
+        _ => ChainStatus::Intact { entries: entries.len(), head },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(count: i64) -> Vec<AuditEntry> {
+        let mut previous = GENESIS_HASH.to_string();
+        (1..=count)
+            .map(|id| {
+                let mut entry = AuditEntry {
+                    id,
+                    admin_id: 42,
+                    action: "ban".to_string(),
+                    target_user_id: Some(100 + id),
+                    detail: Some(format!("原因 {}", id)),
+                    created_at: "2025-08-15 12:00:00".to_string(),
+                    hash: None,
+                };
+                previous = entry_hash(&previous, &entry);
+                entry.hash = Some(previous.clone());
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_reports_first_break() {
+        let entries = chain(3);
+        assert_eq!(
+            verify(&entries, entries[2].hash.as_deref()),
+            ChainStatus::Intact { entries: 3, head: entries[2].hash.clone() }
+        );
+        assert_eq!(verify(&[], None), ChainStatus::Intact { entries: 0, head: None });
+
+        let mut modified = entries.clone();
+        modified[1].detail = Some("已改".to_string());
+        assert_eq!(verify(&modified, None), ChainStatus::Broken { entry_id: 2, verified: 1 });
+
+        let mut deleted = entries.clone();
+        deleted.remove(0);
+        assert_eq!(verify(&deleted, None), ChainStatus::Broken { entry_id: 2, verified: 0 });
+
+        // 删除末条后链仍连续，靠锚点发现
+        assert_eq!(
+            verify(&entries[..2], entries[2].hash.as_deref()),
+            ChainStatus::Truncated { entries: 2, head: entries[1].hash.clone() }
+        );
+        assert_eq!(
+            verify(&[], entries[2].hash.as_deref()),
+            ChainStatus::Truncated { entries: 0, head: None }
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use tracing::{error, info};
+
+use crate::database::{self, Database};
+
+/// 封禁名单文件的大小上限（字节）
+pub const MAX_IMPORT_FILE_SIZE: usize = 256 * 1024;
+/// 封禁名单一次最多导入的行数
+pub const MAX_IMPORT_ROWS: usize = 5000;
+
+/// 封禁名单中的一条有效记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct BanEntry {
+    pub line: usize,
+    pub user_id: i64,
+    pub reason: Option<String>,
+}
+
+/// 解析结果：有效记录与格式错误的行（行号, 说明）
+#[derive(Debug, Default)]
+pub struct ParsedBanList {
+    pub entries: Vec<BanEntry>,
+    pub malformed: Vec<(usize, String)>,
+}
+
+/// 导入结果汇总
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportSummary {
+    pub applied: usize,
+    pub already_banned: usize,
+}
+
+/// 一次导入的完整结果
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub summary: ImportSummary,
+    pub malformed: Vec<(usize, String)>,
+}
+
+/// 解析并导入封禁名单，机器人与命令行共用；`source` 会写入审计日志
+pub async fn import(db: &Database, content: &str, actor_id: i64, source: &str) -> Result<ImportReport> {
+    let parsed = parse_csv(content)?;
+    let summary = database::import_bans(db, &parsed.entries).await?;
+
+    info!(
+        "导入封禁名单 ({}): 新增 {}，已封禁 {}，格式错误 {}",
+        source,
+        summary.applied,
+        summary.already_banned,
+        parsed.malformed.len()
+    );
+    let detail = format!(
+        "来源: {}; 新增封禁: {}; 已封禁: {}; 格式错误: {}",
+        source,
+        summary.applied,
+        summary.already_banned,
+        parsed.malformed.len()
+    );
+    if let Err(e) = database::log_admin_action(db, actor_id, "import_bans", None, &detail).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    Ok(ImportReport {
+        summary,
+        malformed: parsed.malformed,
+    })
+}
+
+/// 解析 `user_id,reason` 格式的 CSV；允许首行为表头，空行与 # 开头的行会被忽略
+pub fn parse_csv(content: &str) -> Result<ParsedBanList> {
+    if content.len() > MAX_IMPORT_FILE_SIZE {
+        anyhow::bail!("文件过大，最多 {} KB", MAX_IMPORT_FILE_SIZE / 1024);
+    }
+
+    let mut parsed = ParsedBanList::default();
+    let mut rows = 0;
+
+    for (index, raw) in content.lines().enumerate() {
+        let line = index + 1;
+        let row = raw.trim().trim_start_matches('\u{feff}');
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+
+        let (id_field, reason_field) = match row.split_once(',') {
+            Some((id, reason)) => (id.trim(), Some(reason.trim())),
+            None => (row, None),
+        };
+
+        if line == 1 && id_field.eq_ignore_ascii_case("user_id") {
+            continue;
+        }
+
+        rows += 1;
+        if rows > MAX_IMPORT_ROWS {
+            anyhow::bail!("行数过多，最多 {} 行", MAX_IMPORT_ROWS);
+        }
+
+        match unquote(id_field).parse::<i64>() {
+            Ok(user_id) if user_id > 0 => parsed.entries.push(BanEntry {
+                line,
+                user_id,
+                reason: reason_field.map(unquote).filter(|r| !r.is_empty()),
+            }),
+            _ => parsed.malformed.push((line, format!("无效的用户ID: {}", id_field))),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// 去掉 CSV 字段两侧的双引号，并还原转义的 `""`
+fn unquote(field: &str) -> String {
+    let field = field.trim();
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(inner) => inner.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let content = "user_id,reason\n123,刷号\n\n# 注释\nabc,无效\n456\n789,\"欺诈, 多次\"\n-5,负数\n";
+        let parsed = parse_csv(content).unwrap();
+
+        assert_eq!(
+            parsed.entries,
+            vec![
+                BanEntry { line: 2, user_id: 123, reason: Some("刷号".to_string()) },
+                BanEntry { line: 6, user_id: 456, reason: None },
+                BanEntry { line: 7, user_id: 789, reason: Some("欺诈, 多次".to_string()) },
+            ]
+        );
+        let malformed_lines: Vec<_> = parsed.malformed.iter().map(|(line, _)| *line).collect();
+        assert_eq!(malformed_lines, vec![5, 8]);
+    }
+
+    #[test]
+    fn test_parse_csv_limits() {
+        let too_many = "1,x\n".repeat(MAX_IMPORT_ROWS + 1);
+        assert!(parse_csv(&too_many).is_err());
+
+        let too_large = "#".repeat(MAX_IMPORT_FILE_SIZE + 1);
+        assert!(parse_csv(&too_large).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+use crate::{
+    database::{self, Database, Quota},
+    finalshell::{ActivationCodeGenerator, ActivationResult, CodeCase, FinalShellVersionType},
+    i18n::Lang,
+    quota::QuotaMode,
+    selftest::VECTORS,
+};
+
+/// 数据库基准中并发记录生成的任务数，模拟多个用户同时请求时的写锁竞争
+const DB_TASKS: u32 = 8;
+
+/// 两种实现各自的总耗时
+#[derive(Debug)]
+pub struct Report {
+    /// 每种实现生成的激活码组数（机器码 × 版本）
+    pub generations: u32,
+    /// 每次拼接盐值字符串的原始实现
+    pub reference: Duration,
+    /// 复用预配置盐值的生成器实例
+    pub reusable: Duration,
+}
+
+/// 用内置向量中的机器码重复生成全部版本 `iterations` 轮，分别计时原始实现与复用的生成器实例
+pub fn run(iterations: u32) -> Result<Report> {
+    let versions = FinalShellVersionType::ALL;
+    let iterations = iterations.max(1);
+
+    let started = Instant::now();
+    for _ in 0..iterations {
+        for vector in VECTORS {
+            black_box(ActivationCodeGenerator::generate_versions_reference(black_box(vector.machine_code), &versions, CodeCase::Upper)?);
+        }
+    }
+    let reference = started.elapsed();
+
+    let generator = ActivationCodeGenerator::new(CodeCase::Upper);
+    let started = Instant::now();
+    for _ in 0..iterations {
+        for vector in VECTORS {
+            black_box(generator.generate_for(black_box(vector.machine_code), &versions));
+        }
+    }
+    let reusable = started.elapsed();
+
+    Ok(Report {
+        generations: iterations * (VECTORS.len() * versions.len()) as u32,
+        reference,
+        reusable,
+    })
+}
+
+impl Report {
+    pub fn render(&self) -> String {
+        let per_generation = |elapsed: Duration| elapsed.as_nanos() as f64 / f64::from(self.generations);
+        let (reference, reusable) = (per_generation(self.reference), per_generation(self.reusable));
+        format!(
+            "⏱️ 激活码生成基准：每种实现生成 {} 组 (高级版 + 专业版)\n  原始实现:   {:>8.0} ns/组\n  复用生成器: {:>8.0} ns/组 ({:.2}x)",
+            self.generations,
+            reference,
+            reusable,
+            reference / reusable.max(f64::MIN_POSITIVE)
+        )
+    }
+}
+
+/// 记录一次生成的两种写法各自的总耗时
+#[derive(Debug)]
+pub struct DbReport {
+    /// 每种写法记录的生成次数
+    pub generations: u32,
+    /// 合并前的做法：更新时间、累加次数、写入日志与明细各自单独提交
+    pub separate: Duration,
+    /// 现在的做法：预扣配额后由 commit_generation 在一个事务内写完
+    pub transaction: Duration,
+}
+
+/// 在临时数据库文件中由 DB_TASKS 个任务并发记录共 `iterations` 次生成，分别计时两种写法；结束后删除临时文件
+pub async fn run_db(iterations: u32) -> Result<DbReport> {
+    let path = std::env::temp_dir().join(format!("finalunlock_bench_{}.db", std::process::id()));
+    let url = format!("sqlite:{}?mode=rwc", path.display());
+    let result = run_db_at(&url, iterations).await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    result
+}
+
+async fn run_db_at(url: &str, iterations: u32) -> Result<DbReport> {
+    // 不经过 database::init，避免连接失败时退回内存数据库测出失真的结果
+    let pool = sqlx::SqlitePool::connect(url).await?;
+    database::migrate(&pool).await?;
+    let db = Database::new(pool, None);
+    for user_id in 1..=i64::from(DB_TASKS) {
+        database::get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await?;
+    }
+    let per_task = iterations.max(DB_TASKS).div_ceil(DB_TASKS);
+    let results = ActivationCodeGenerator::new(CodeCase::Upper).generate_for(VECTORS[0].machine_code, &FinalShellVersionType::ALL);
+
+    let separate = time_tasks(&db, per_task, &results, false).await?;
+    let transaction = time_tasks(&db, per_task, &results, true).await?;
+    db.writer().close().await;
+
+    Ok(DbReport { generations: per_task * DB_TASKS, separate, transaction })
+}
+
+/// 每个任务以自己的用户身份连续记录 `per_task` 次生成，返回全部任务完成的总耗时
+async fn time_tasks(db: &Database, per_task: u32, results: &[ActivationResult], transaction: bool) -> Result<Duration> {
+    let started = Instant::now();
+    let mut tasks = JoinSet::new();
+    for user_id in 1..=i64::from(DB_TASKS) {
+        let (db, results) = (db.clone(), results.to_vec());
+        tasks.spawn(async move {
+            for _ in 0..per_task {
+                let machine_code = VECTORS[0].machine_code;
+                if transaction {
+                    let quota = Quota { limit: i32::MAX, unlimited: true, period_start: None, trial: None, mode: QuotaMode::Requests };
+                    let reservation = database::reserve_quota(&db, user_id, quota, machine_code).await?.context("预扣配额失败")?;
+                    database::commit_generation(&db, reservation, user_id, "BENCH", machine_code, quota, None, &results).await?;
+                } else {
+                    record_separately(&db, user_id, machine_code, &results).await?;
+                }
+            }
+            anyhow::Ok(())
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+    Ok(started.elapsed())
+}
+
+/// 合并为单事务之前的写法，仅作基准对照
+async fn record_separately(db: &Database, user_id: i64, machine_code: &str, results: &[ActivationResult]) -> Result<()> {
+    let pool = db.writer();
+    let summary = ActivationCodeGenerator::primary_result(machine_code, results).context("没有可记录的激活码结果")?;
+    sqlx::query("UPDATE users SET updated_at = ? WHERE user_id = ?")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("UPDATE users SET request_count = request_count + 1 WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    let log_id = sqlx::query(
+        "INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(machine_code)
+    .bind(&summary.professional_code)
+    .bind(summary.version_type.log_label())
+    .bind(Utc::now())
+    .execute(pool)
+    .await?
+    .last_insert_rowid();
+    for result in results {
+        sqlx::query("INSERT INTO activation_log_details (log_id, version, advanced_code, professional_code) VALUES (?, ?, ?, ?)")
+            .bind(log_id)
+            .bind(result.version_type.version_name_ascii())
+            .bind(&result.advanced_code)
+            .bind(&result.professional_code)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+impl DbReport {
+    pub fn render(&self) -> String {
+        let per_generation = |elapsed: Duration| elapsed.as_micros() as f64 / f64::from(self.generations);
+        let (separate, transaction) = (per_generation(self.separate), per_generation(self.transaction));
+        format!(
+            "🗄️ 生成记录写入基准：{} 个任务并发，每种写法记录 {} 次\n  逐条写入: {:>8.0} µs/次\n  单事务:   {:>8.0} µs/次 ({:.2}x)",
+            DB_TASKS,
+            self.generations,
+            separate,
+            transaction,
+            separate / transaction.max(f64::MIN_POSITIVE)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_counts_generations() {
+        let report = run(2).unwrap();
+        assert_eq!(report.generations, 2 * VECTORS.len() as u32 * 4);
+        assert!(report.render().contains("复用生成器"));
+    }
+
+    #[tokio::test]
+    async fn test_run_db_records_both_ways() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("bench.db").display());
+        let report = run_db_at(&url, 3).await.unwrap();
+        assert_eq!(report.generations, DB_TASKS);
+        assert!(report.render().contains("单事务"));
+    }
+}
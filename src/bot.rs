@@ -1,43 +1,90 @@
 use anyhow::Result;
-use sqlx::SqlitePool;
+use std::future::IntoFuture;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use teloxide::{
     dispatching::{dialogue, dialogue::InMemStorage, UpdateHandler},
+    payloads::SendMessage,
     prelude::*,
-    types::{Message, ParseMode},
+    requests::JsonRequest,
+    ApiError,
+    types::{ChatAction, ChatMemberUpdated, Document, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, KeyboardButton, KeyboardMarkup, KeyboardRemove, Me, Message, MessageId, MessageKind, ParseMode},
     utils::command::BotCommands,
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::{
-    config::Config,
-    database,
-    finalshell::ActivationCodeGenerator,
+    abuse,
+    banlist,
+    config::{self, Config},
+    cooldown::{self, AdminLimits},
+    correlation,
+    database::{self, Database},
+    degraded::DegradedMode,
+    doctor,
+    experiment::{self, CopyVariant},
+    export,
+    finalshell::{self, ActivationCodeGenerator, CodeBackend, FinalShellVersionType, LocalBackend},
+    footer::{self, FooterStyle},
+    format::{self, StatsFormat},
+    hooks::{GenerationContext, HookRegistry},
+    i18n::{self, Lang, MenuAction},
+    log_level,
+    metrics::Metrics,
+    models::{ActivationOutcome, Activity, CopyVariantStats, DailySummary, OutcomeStats, RequestTrace, SystemStats, User, UserStats},
+    quota::{self, QuotaMode},
+    result_image,
+    result_link,
+    support,
+    telegram_health::TelegramHealth,
+    upload,
     utils,
 };
 
-// MarkdownV2转义函数
-#[allow(dead_code)]
-fn escape_markdown_v2(text: &str) -> String {
-    text.replace("\\", "\\\\")
-        .replace("_", "\\_")
-        .replace("*", "\\*")
-        .replace("[", "\\[")
-        .replace("]", "\\]")
-        .replace("(", "\\(")
-        .replace(")", "\\)")
-        .replace("~", "\\~")
-        .replace("`", "\\`")
-        .replace(">", "\\>")
-        .replace("#", "\\#")
-        .replace("+", "\\+")
-        .replace("-", "\\-")
-        .replace("=", "\\=")
-        .replace("|", "\\|")
-        .replace("{", "\\{")
-        .replace("}", "\\}")
-        .replace(".", "\\.")
-        .replace("!", "\\!")
-}
+/// 申诉内容最大长度（字符）
+const MAX_APPEAL_LENGTH: usize = 500;
+/// 申诉频率限制：窗口期内最多提交的申诉数
+const MAX_APPEALS_PER_WINDOW: i64 = 3;
+/// 申诉频率限制窗口（天）
+const APPEAL_WINDOW_DAYS: i64 = 7;
+/// /searchlog 返回的最大记录数
+const SEARCH_LOG_LIMIT: i64 = 20;
+/// /stats history 默认与最多回看的天数
+const STATS_HISTORY_DAYS: i64 = 14;
+const MAX_STATS_HISTORY_DAYS: i64 = 90;
+/// /stats 语言分布中单独列出的语言数
+const LANGUAGE_STATS_LIMIT: usize = 8;
+/// /trial 允许延长的最大天数
+const MAX_TRIAL_EXTENSION_DAYS: u32 = 30;
+/// /flagged 返回的最大用户数
+const FLAGGED_USER_LIMIT: i64 = 20;
+/// /userhistory 返回的最大记录数
+const USER_HISTORY_LIMIT: i64 = 20;
+/// /reports 返回的最大记录数
+const CODE_REPORT_LIMIT: i64 = 20;
+/// /export health 默认与最多导出的天数
+const DEFAULT_EXPORT_DAYS: i64 = 30;
+const MAX_EXPORT_DAYS: i64 = 365;
+/// /ban、/unban 一次最多处理的用户数
+const MAX_BATCH_TARGETS: usize = 50;
+/// 按 @用户名 找到的用户名记录早于该天数时提醒管理员核对
+const USERNAME_STALE_DAYS: i64 = 30;
+/// 批量机器码文件一次最多处理的机器码数
+const MAX_BATCH_LINES: usize = 50;
+/// 遇到 Telegram 限流时单条消息的最大重试次数
+const MAX_SEND_RETRIES: u32 = 3;
+/// 逐条发送激活码时相邻消息的间隔，避免触发 Telegram 的频率限制
+const SPLIT_MESSAGE_INTERVAL: Duration = Duration::from_millis(500);
+/// 降级期间探测数据库是否恢复可写的间隔（秒）
+const DEGRADED_PROBE_SECS: u64 = 30;
+/// 重新读取停用版本设置的间隔，使其他实例通过 /versions 做的修改无需重启即可生效
+const DISABLED_VERSIONS_REFRESH_SECS: u64 = 60;
+/// 回复中用户名、姓名的最大显示长度（字符）
+const USER_NAME_DISPLAY_LIMIT: usize = 64;
+/// 回复中封禁原因、申诉内容等长文本的最大显示长度（字符）
+const USER_TEXT_DISPLAY_LIMIT: usize = 500;
+/// 回显机器码、搜索关键字的最大长度（字符）
+const ECHO_DISPLAY_LIMIT: usize = 64;
 
 // 专门用于转义激活码输出的函数，保留反引号以实现点击复制
 fn escape_activation_output(text: &str) -> String {
@@ -62,6 +109,19 @@ fn escape_activation_output(text: &str) -> String {
         // 不转义反引号，保持代码块格式
 }
 
+/// 将已转义结果中非推荐版本的激活码由行内代码改为 spoiler，推荐版本保持可点击复制。
+/// MarkdownV2 的 spoiler 不能包含行内代码，折叠后的激活码只能手动复制；激活码为十六进制，无需再转义
+fn fold_secondary_codes(escaped: &str, machine_code: &str, results: &[finalshell::ActivationResult]) -> String {
+    let recommended = ActivationCodeGenerator::primary_result(machine_code, results).map(|r| r.version_type);
+    let mut folded = escaped.to_string();
+    for result in results.iter().filter(|r| Some(r.version_type) != recommended) {
+        for code in [&result.advanced_code, &result.professional_code] {
+            folded = folded.replace(&format!("`{}`", code), &format!("||{}||", code));
+        }
+    }
+    folded
+}
+
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
 
 #[derive(Clone, Default)]
@@ -69,7 +129,9 @@ pub enum State {
     #[default]
     Start,
 
-    AdminBroadcast,
+    /// 等待管理员确认广播，`message` 为待发送的内容
+    AdminBroadcast { message: String },
+    AwaitingBanImport,
 }
 
 #[derive(BotCommands, Clone)]
@@ -79,14 +141,18 @@ enum Command {
     Start,
     #[command(description = "显示帮助信息")]
     Help,
-    #[command(description = "查看使用统计 (管理员)")]
-    Stats,
+    #[command(description = "隐藏快捷菜单")]
+    Hidemenu,
+    #[command(description = "查看使用统计，可指定实例，加 json 输出机读格式；history 查看每日趋势 (管理员)")]
+    Stats(String),
     #[command(description = "查看用户列表 (管理员)")]
     Users,
     #[command(description = "拉黑用户 (管理员)")]
     Ban(String),
     #[command(description = "解除拉黑 (管理员)")]
     Unban(String),
+    #[command(description = "提前解除新用户试用期 (管理员)")]
+    Trust(String),
     #[command(description = "广播消息 (管理员)")]
     Say(String),
     #[command(description = "清除统计数据 (管理员)")]
@@ -95,31 +161,123 @@ enum Command {
     Cleanup,
     #[command(description = "获取最新自检报告 (管理员)")]
     Guard,
+    #[command(description = "逐项运行故障诊断 (管理员)")]
+    Doctor,
+    #[command(description = "查看并启用/停用各版本激活码 (管理员)")]
+    Versions,
+    #[command(description = "查看或临时调整日志级别: /loglevel [过滤器|reset] (管理员)")]
+    Loglevel(String),
+    #[command(description = "备份数据库与配置 (管理员)")]
+    Backup,
     #[command(description = "查看机器人信息")]
     About,
+    #[command(description = "申诉封禁 (仅限被封禁用户)")]
+    Appeal(String),
+    #[command(description = "生成试用延长码: /trial <天数> <机器码>")]
+    Trial(String),
+    #[command(description = "设置结果语言: /lang zh|en")]
+    Lang(String),
+    #[command(description = "激活码逐条发送: /split on|off")]
+    Split(String),
+    #[command(description = "搜索激活记录 (管理员)")]
+    Searchlog(String),
+    #[command(description = "查看指定用户的激活记录 (管理员)")]
+    Userhistory(String),
+    #[command(description = "按错误码查看一次生成请求的记录 (管理员)")]
+    Trace(String),
+    #[command(description = "查看用户的客服档案 (管理员)")]
+    Support(String),
+    #[command(description = "查看被标记的疑似滥用用户 (管理员)")]
+    Flagged,
+    #[command(description = "查看激活失败反馈 (管理员)")]
+    Reports,
+    #[command(description = "导出数据: /export health [天数] 或 /export audit (管理员)")]
+    Export(String),
+    #[command(description = "校验审计日志: /audit verify (管理员)")]
+    Audit(String),
+    #[command(description = "从 CSV 导入封禁名单 (管理员)")]
+    Importbans,
 }
 
-pub async fn run(config: Config, db: SqlitePool) -> Result<()> {
+pub async fn run(config: Config, db: Database) -> Result<()> {
     info!("启动 Telegram 机器人...");
 
-    let bot = Bot::new(&config.bot_token);
+    let telegram = config.require_telegram()?.clone();
+    let bot = Bot::new(&telegram.bot_token);
+    let telegram_health = Arc::new(TelegramHealth::new(config.throttle_queue_threshold));
 
     // 测试 bot token
+    let started = Instant::now();
     match bot.get_me().await {
-        Ok(me) => info!("机器人启动成功: @{}", me.username()),
+        Ok(me) => {
+            telegram_health.record_success(started.elapsed());
+            info!("机器人启动成功: @{}", me.username());
+        }
         Err(e) => {
             error!("机器人启动失败: {}", e);
             return Err(e.into());
         }
     }
 
-    let handler = schema();
+    // 启动时确认能向管理群发消息，避免告警与通知在运行中才发现发不出去
+    match doctor::check_send_permission(&bot, telegram.chat_id).await {
+        doctor::Outcome::Fail(hint) => error!("管理群 (CHAT_ID {}) {}", telegram.chat_id, hint),
+        outcome => debug!("管理群发送权限检查: {:?}", outcome),
+    }
+
+    let backend: Arc<dyn CodeBackend> = Arc::new(LocalBackend {
+        generator: ActivationCodeGenerator::new(config.code_case),
+        trial_extension_salt: config.trial_extension_salt.clone(),
+    });
+
+    if let Err(e) = config.load_disabled_versions(&db).await {
+        warn!("读取停用版本设置失败，全部按 ENABLED_VERSIONS 生成: {}", e);
+    }
+    let hooks = HookRegistry::from_config(&config);
+    let metrics = Arc::new(Metrics::default());
+
+    if let Some(bind) = config.http_bind.clone() {
+        let (config, db, telegram_health, backend, hooks, metrics) =
+            (config.clone(), db.clone(), telegram_health.clone(), backend.clone(), hooks.clone(), metrics.clone());
+        tokio::spawn(async move {
+            if let Err(e) = crate::server::serve(&bind, config, db, telegram_health, backend, hooks, metrics).await {
+                error!("HTTP 服务异常退出: {}", e);
+            }
+        });
+    }
 
+    {
+        let (bot, config, db) = (bot.clone(), config.clone(), db.clone());
+        tokio::spawn(async move {
+            probe_degraded_periodically(bot, config, db).await;
+        });
+    }
+
+    {
+        let (config, db) = (config.clone(), db.clone());
+        tokio::spawn(async move {
+            refresh_disabled_versions_periodically(config, db).await;
+        });
+    }
+
+    if config.auto_group_admins {
+        let (bot, config) = (bot.clone(), config.clone());
+        tokio::spawn(async move {
+            refresh_group_admins_periodically(bot, config, ChatId(telegram.chat_id)).await;
+        });
+    }
+
+    let handler = schema();
     Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![
             InMemStorage::<State>::new(),
             config,
-            db
+            db,
+            Arc::new(AdminLimits::new()),
+            backend,
+            telegram_health,
+            hooks,
+            metrics
         ])
         .enable_ctrlc_handler()
         .build()
@@ -129,10 +287,46 @@ pub async fn run(config: Config, db: SqlitePool) -> Result<()> {
     Ok(())
 }
 
+/// 定期从 settings 重新加载停用版本；读取失败时保留当前列表
+async fn refresh_disabled_versions_periodically(config: Config, db: Database) {
+    let mut interval = tokio::time::interval(Duration::from_secs(DISABLED_VERSIONS_REFRESH_SECS));
+    // 启动时已加载过一次，跳过立即触发的首个 tick
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = config.load_disabled_versions(&db).await {
+            warn!("刷新停用版本设置失败: {}", e);
+        }
+    }
+}
+
+/// 定期从管理群拉取群管理员列表并更新缓存
+async fn refresh_group_admins_periodically(bot: Bot, config: Config, admin_chat: ChatId) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.group_admin_refresh_secs.max(60)));
+    loop {
+        interval.tick().await;
+        match bot.get_chat_administrators(admin_chat).await {
+            Ok(members) => {
+                let ids: Vec<i64> = members
+                    .iter()
+                    .filter(|member| !member.user.is_bot)
+                    .map(|member| member.user.id.0 as i64)
+                    .collect();
+                info!("已刷新管理群管理员列表，共 {} 人", ids.len());
+                config.group_admins.replace(ids);
+            }
+            // 拉取失败时保留上一次的缓存
+            Err(e) => warn!("获取管理群管理员列表失败: {}", e),
+        }
+    }
+}
+
 fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
     use dptree::case;
 
-    let command_handler = teloxide::filter_command::<Command, _>()
+    let command_handler = dptree::filter_map(|msg: Message, me: Me, config: Config| {
+        parse_incoming_command(msg.text()?, &config.command_prefix, me.username(), msg.chat.is_private())
+    })
         .branch(
             case![State::Start]
                 .branch(case![Command::Start].endpoint(|bot, dialogue, msg, config, db| async move {
@@ -141,20 +335,26 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
                 .branch(case![Command::Help].endpoint(|bot, msg, config| async move {
                     help(bot, msg, config).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
-                .branch(case![Command::Stats].endpoint(|bot, msg, config, db| async move {
-                    stats(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                .branch(case![Command::Hidemenu].endpoint(|bot, msg, config, db| async move {
+                    hide_menu(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Stats(instance_id)].endpoint(|bot, msg, config, db, instance_id| async move {
+                    stats(bot, msg, config, db, instance_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
                 .branch(case![Command::Users].endpoint(|bot, msg, config, db| async move {
                     users(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
-                .branch(case![Command::Ban(user_id)].endpoint(|bot, msg, config, db, user_id| async move {
-                    ban_user(bot, msg, config, db, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                .branch(case![Command::Ban(user_id)].endpoint(|bot, msg, config, db, metrics, user_id| async move {
+                    ban_user(bot, msg, config, db, metrics, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
                 .branch(case![Command::Unban(user_id)].endpoint(|bot, msg, config, db, user_id| async move {
                     unban_user(bot, msg, config, db, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
-                .branch(case![Command::Say(message)].endpoint(|bot, dialogue, msg, config, message| async move {
-                    broadcast_start(bot, dialogue, msg, config, message).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                .branch(case![Command::Trust(user_id)].endpoint(|bot, msg, config, db, user_id| async move {
+                    trust_user(bot, msg, config, db, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Say(message)].endpoint(|bot, dialogue, msg, config, db, telegram, message| async move {
+                    broadcast_start(bot, dialogue, msg, config, db, telegram, message).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
                 .branch(case![Command::Clear].endpoint(|bot, msg, config, db| async move {
                     clear_stats(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
@@ -162,28 +362,437 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
                 .branch(case![Command::Cleanup].endpoint(|bot, msg, config| async move {
                     cleanup_logs(bot, msg, config).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
-                .branch(case![Command::Guard].endpoint(|bot, msg, config, db| async move {
-                    guard_report(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                .branch(case![Command::Guard].endpoint(|bot, msg, config, db, limits, telegram| async move {
+                    guard_report(bot, msg, config, db, limits, telegram).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Versions].endpoint(|bot, msg, config| async move {
+                    list_versions(bot, msg, config).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Doctor].endpoint(|bot, msg, config, db, limits| async move {
+                    run_doctor(bot, msg, config, db, limits).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Loglevel(filter)].endpoint(|bot, msg, config, db, filter| async move {
+                    change_log_level(bot, msg, config, db, filter).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Backup].endpoint(|bot, msg, config, limits| async move {
+                    backup(bot, msg, config, limits).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::About].endpoint(|bot, msg, config| async move {
+                    about_bot(bot, msg, config).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Appeal(content)].endpoint(|bot, msg, config, db, content| async move {
+                    appeal(bot, msg, config, db, content).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Trial(args)].endpoint(|bot, msg, config, db, backend, args| async move {
+                    trial_extension(bot, msg, config, db, backend, args).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Lang(code)].endpoint(|bot, msg, config, db, code| async move {
+                    set_language(bot, msg, config, db, code).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Split(mode)].endpoint(|bot, msg, config, db, mode| async move {
+                    set_split_codes(bot, msg, config, db, mode).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Searchlog(keyword)].endpoint(|bot, msg, config, db, keyword| async move {
+                    search_logs(bot, msg, config, db, keyword).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Userhistory(user_id)].endpoint(|bot, msg, config, db, user_id| async move {
+                    user_history(bot, msg, config, db, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Trace(id)].endpoint(|bot, msg, config, db, id| async move {
+                    trace_request(bot, msg, config, db, id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Support(user_id)].endpoint(|bot, msg, config, db, user_id| async move {
+                    support_dossier(bot, msg, config, db, user_id).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Flagged].endpoint(|bot, msg, config, db| async move {
+                    flagged_users(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 }))
-                .branch(case![Command::About].endpoint(|bot, msg| async move {
-                    about_bot(bot, msg).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                .branch(case![Command::Reports].endpoint(|bot, msg, config, db| async move {
+                    code_reports(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Export(args)].endpoint(|bot, msg, config, db, limits, args| async move {
+                    export_data(bot, msg, config, db, limits, args).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Audit(args)].endpoint(|bot, msg, config, db, args| async move {
+                    audit_command(bot, msg, config, db, args).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                }))
+                .branch(case![Command::Importbans].endpoint(|bot, dialogue, msg, config| async move {
+                    import_bans_start(bot, dialogue, msg, config).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
                 })),
         );
 
     let message_handler = Update::filter_message()
+        .inspect_async(|msg: Message, db: Database| async move { clear_deactivation(&msg, &db).await })
         .branch(command_handler)
-        .branch(case![State::Start].endpoint(|bot, msg, config, db| async move {
-            handle_machine_code(bot, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        // 设置了自定义前缀时，群里 / 开头的本机器人命令多半是发给其他机器人的，直接忽略
+        .branch(dptree::filter(|msg: Message, me: Me, config: Config| {
+            !msg.chat.is_private() &&
+            config.command_prefix != config::DEFAULT_COMMAND_PREFIX
+                && msg.text().is_some_and(|text| parse_command(text, config::DEFAULT_COMMAND_PREFIX, me.username()).is_some())
+        }).endpoint(|| async { Ok(()) }))
+        .branch(Message::filter_document().filter(|msg: Message, document: Document| is_batch_upload(&msg, &document)).chain(case![State::Start]).endpoint(|bot, msg, config, db, backend, hooks, metrics, document| async move {
+            handle_document(bot, msg, config, db, backend, hooks, metrics, document).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        }))
+        // 快捷菜单按钮的文字不是机器码，先于机器码处理拦截
+        .branch(dptree::filter_map(|msg: Message, config: Config| msg.text().and_then(MenuAction::parse).filter(|_| config.reply_menu)).chain(case![State::Start]).endpoint(|bot, msg, config, db, action| async move {
+            handle_menu_action(bot, msg, config, db, action).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
         }))
-        .branch(case![State::AdminBroadcast].endpoint(|bot, dialogue, msg, config, db| async move {
-            handle_broadcast(bot, dialogue, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        .branch(case![State::Start].endpoint(|bot, msg, config, db, backend, telegram, hooks, metrics, me: Me| async move {
+            handle_machine_code(bot, msg, config, db, backend, telegram, hooks, metrics, me).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        }))
+        .branch(case![State::AdminBroadcast { message }].endpoint(|bot, dialogue, msg, config, db, telegram, message| async move {
+            handle_broadcast(bot, dialogue, msg, config, db, telegram, message).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        }))
+        .branch(case![State::AwaitingBanImport].endpoint(|bot, dialogue, msg, config, db| async move {
+            handle_ban_import(bot, dialogue, msg, config, db).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
         }));
 
-    dialogue::enter::<Update, InMemStorage<State>, State, _>()
-        .branch(message_handler)
+    let callback_handler = Update::filter_callback_query().endpoint(|bot, q, config, db, storage, telegram, metrics| async move {
+        handle_callback(bot, q, config, db, storage, telegram, metrics).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    });
+
+    let member_handler = Update::filter_my_chat_member().endpoint(|db, update| async move {
+        handle_my_chat_member(db, update).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    });
+
+    dptree::entry()
+        .branch(callback_handler)
+        .branch(member_handler)
+        .branch(
+            dialogue::enter::<Update, InMemStorage<State>, State, _>()
+                .branch(message_handler)
+        )
+}
+
+/// 按配置的前缀解析命令；前缀为 `/` 时与 teloxide 的默认解析一致（含 `/stats@bot` 形式）
+fn parse_command(text: &str, prefix: &str, bot_username: &str) -> Option<Command> {
+    let rest = text.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Command::parse(&format!("/{}", rest), bot_username).ok()
+}
+
+/// 解析收到的命令：除配置的前缀外，`/start`（Telegram 的开始按钮与深链接只会发送它）
+/// 以及私聊中的 `/` 命令始终可用
+fn parse_incoming_command(text: &str, prefix: &str, bot_username: &str, private: bool) -> Option<Command> {
+    if let Some(command) = parse_command(text, prefix, bot_username) {
+        return Some(command);
+    }
+    if prefix == config::DEFAULT_COMMAND_PREFIX {
+        return None;
+    }
+    parse_command(text, config::DEFAULT_COMMAND_PREFIX, bot_username).filter(|command| private || matches!(command, Command::Start))
+}
+
+/// 将帮助文本中的 `/命令` 换成配置的前缀
+fn with_command_prefix(text: &str, prefix: &str) -> String {
+    if prefix == config::DEFAULT_COMMAND_PREFIX {
+        return text.to_string();
+    }
+    let mut output = String::with_capacity(text.len());
+    let mut previous = '\n';
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '/' && previous.is_whitespace() && chars.peek().is_some_and(|next| next.is_ascii_lowercase()) {
+            output.push_str(prefix);
+        } else {
+            output.push(c);
+        }
+        previous = c;
+    }
+    output
+}
+
+/// 将数据库错误记录日志并转换为处理函数可返回的错误
+fn db_error(e: anyhow::Error) -> teloxide::RequestError {
+    error!("数据库错误: {}", e);
+    teloxide::RequestError::Io(std::io::Error::other(e))
+}
+
+/// 发送消息并处理 Telegram 限流：遇到 RetryAfter 时记录限流状态，等待后重试
+async fn send_throttled(bot: &Bot, telegram: &TelegramHealth, chat_id: ChatId, text: String) -> ResponseResult<Message> {
+    retry_on_flood(telegram, || bot.send_message(chat_id, text.clone()).send()).await
+}
+
+/// 向用户私聊发送消息；Telegram 返回账号已注销时标记该用户，之后不再向其广播
+async fn send_to_user(bot: &Bot, telegram: &TelegramHealth, db: &Database, user_id: i64, text: String) -> ResponseResult<Message> {
+    track_deactivation(db, user_id, send_throttled(bot, telegram, ChatId(user_id), text).await).await
+}
+
+/// 检查发送结果，账号已注销时记录 deactivated_at；结果原样返回
+async fn track_deactivation<T>(db: &Database, user_id: i64, result: ResponseResult<T>) -> ResponseResult<T> {
+    if let Err(teloxide::RequestError::Api(ApiError::UserDeactivated)) = &result {
+        match database::mark_user_deactivated(db, user_id).await {
+            Ok(true) => info!("用户 {} 的账号已注销，不再向其发送广播", user_id),
+            Ok(false) => {}
+            Err(e) => error!("标记用户 {} 账号已注销失败: {}", user_id, e),
+        }
+    }
+    result
+}
+
+/// 收到用户消息说明账号已恢复，清除注销标记
+async fn clear_deactivation(msg: &Message, db: &Database) {
+    let Some(user) = msg.from() else {
+        return;
+    };
+    match database::clear_user_deactivated(db, user.id.0 as i64).await {
+        Ok(true) => info!("用户 {} 的账号已恢复，重新接收广播", user.id.0),
+        Ok(false) => {}
+        Err(e) => warn!("清除用户 {} 的注销标记失败: {}", user.id.0, e),
+    }
+}
+
+/// 私聊中用户屏蔽 (`Some(true)`) 或解除屏蔽 (`Some(false)`) 机器人；群聊中的成员变化返回 None
+fn block_transition(update: &ChatMemberUpdated) -> Option<bool> {
+    if !update.chat.is_private() {
+        return None;
+    }
+    Some(update.new_chat_member.kind.is_banned())
+}
+
+/// Telegram 在用户屏蔽或解除屏蔽时推送 my_chat_member 更新，据此立即更新可达状态，
+/// 不必等到广播发送失败才发现
+async fn handle_my_chat_member(db: Database, update: ChatMemberUpdated) -> ResponseResult<()> {
+    let Some(blocked) = block_transition(&update) else {
+        return Ok(());
+    };
+    let user_id = update.from.id.0 as i64;
+    match database::record_block_event(&db, user_id, blocked, update.date).await {
+        Ok(true) if blocked => info!("用户 {} 屏蔽了机器人，不再向其发送广播", user_id),
+        Ok(true) => info!("用户 {} 解除了屏蔽，重新接收广播", user_id),
+        Ok(false) => {}
+        Err(e) => error!("记录用户 {} 的屏蔽状态失败: {}", user_id, e),
+    }
+    Ok(())
+}
+
+/// 执行发送请求，遇到 RetryAfter 时记录限流状态并等待后重试，最多重试 MAX_SEND_RETRIES 次
+async fn retry_on_flood<F, Fut>(telegram: &TelegramHealth, mut send: F) -> ResponseResult<Message>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = ResponseResult<Message>>,
+{
+    let mut retries = 0;
+    loop {
+        let started = Instant::now();
+        match send().await {
+            Ok(message) => {
+                telegram.record_success(started.elapsed());
+                return Ok(message);
+            }
+            Err(teloxide::RequestError::RetryAfter(wait)) => {
+                telegram.record_retry_after(wait);
+                if retries >= MAX_SEND_RETRIES {
+                    return Err(teloxide::RequestError::RetryAfter(wait));
+                }
+                retries += 1;
+                warn!("触发 Telegram 限流，{} 秒后重试", wait.as_secs());
+                tokio::time::sleep(wait).await;
+            }
+            Err(e) => {
+                // 收件人屏蔽、注销等只影响单个用户，不计入 Telegram 连接健康度
+                if !is_recipient_error(&e) {
+                    telegram.record_error(e.to_string());
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// 只与单个收件人有关的 Forbidden 错误：被屏蔽、账号已注销、无法发起会话、被移出群组等
+fn is_recipient_error(err: &teloxide::RequestError) -> bool {
+    match err {
+        teloxide::RequestError::Api(
+            ApiError::BotBlocked
+            | ApiError::UserDeactivated
+            | ApiError::CantInitiateConversation
+            | ApiError::CantTalkWithBots
+            | ApiError::BotKicked
+            | ApiError::BotKickedFromSupergroup,
+        ) => true,
+        teloxide::RequestError::Api(ApiError::Unknown(description)) => {
+            description.to_ascii_lowercase().starts_with("forbidden:")
+        }
+        _ => false,
+    }
+}
+
+/// 编辑消息、应答回调时可以忽略的 Telegram 错误：内容未变化或回调已过期
+fn is_benign_edit_error(err: &teloxide::RequestError) -> bool {
+    match err {
+        teloxide::RequestError::Api(ApiError::MessageNotModified | ApiError::InvalidQueryId) => true,
+        // Telegram 调整错误文案后 teloxide 会解析为 Unknown，按描述兜底匹配
+        teloxide::RequestError::Api(ApiError::Unknown(description)) => {
+            let description = description.to_ascii_lowercase();
+            description.contains("message is not modified") || description.contains("query is too old")
+        }
+        _ => false,
+    }
+}
+
+/// 执行编辑类请求；良性错误只记 debug 日志并返回 None，其余错误照常返回
+async fn edit_or_ignore<T>(request: impl std::future::IntoFuture<Output = ResponseResult<T>>) -> ResponseResult<Option<T>> {
+    match request.await {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if is_benign_edit_error(&e) => {
+            debug!("忽略 Telegram 良性错误: {}", e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// 显示"正在输入"，让用户知道请求正在处理；失败不影响后续回复
+async fn send_typing(bot: &Bot, msg: &Message) {
+    let mut request = bot.send_chat_action(msg.chat.id, ChatAction::Typing);
+    if let Some(thread_id) = topic_thread_id(msg) {
+        request = request.message_thread_id(thread_id);
+    }
+    if let Err(e) = request.await {
+        warn!("发送输入状态失败: {}", e);
+    }
+}
+
+/// 论坛话题内的消息返回其话题 ID；普通群组与私聊返回 None
+fn topic_thread_id(msg: &Message) -> Option<i32> {
+    match &msg.kind {
+        MessageKind::Common(common) if common.is_topic_message => msg.thread_id,
+        _ => None,
+    }
+}
+
+/// 回复消息；在论坛话题内发出的命令回复到同一话题
+fn reply(bot: &Bot, msg: &Message, text: impl Into<String>) -> JsonRequest<SendMessage> {
+    let request = bot.send_message(msg.chat.id, text);
+    match topic_thread_id(msg) {
+        Some(thread_id) => request.message_thread_id(thread_id),
+        None => request,
+    }
+}
+
+/// 检查重量级管理命令的冷却；仍在冷却中时回复需等待的时长并返回 true
+async fn reject_on_cooldown(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    limits: &AdminLimits,
+    command: &'static str,
+    cooldown: Duration,
+) -> ResponseResult<bool> {
+    let Some(user) = msg.from() else {
+        return Ok(false);
+    };
+
+    match limits.cooldowns.try_acquire(command, user.id.0 as i64, cooldown, config.clock.instant()) {
+        Ok(()) => Ok(false),
+        Err(wait) => {
+            reply(
+                bot,
+                msg,
+                config.render(format!("⏳ 该命令冷却中，请 {} 后再试。", cooldown::format_wait(wait))),
+            )
+            .await?;
+            Ok(true)
+        }
+    }
+}
+
+/// 构造被封禁用户看到的提示，包含封禁原因、期限与申诉方式
+fn banned_message(config: &Config, user: &User) -> String {
+    let until = user
+        .banned_until
+        .map(|dt| format::fmt_datetime(&dt, config.default_lang, config.timezone()))
+        .unwrap_or_else(|| "永久".to_string());
+    ban_notice(user.ban_reason.as_deref(), &until)
+}
+
+fn ban_notice(reason: Option<&str>, until: &str) -> String {
+    format!(
+        "❌ 您已被封禁，无法使用此机器人。\n\n\
+         📝 封禁原因: {}\n\
+         ⏳ 解封时间: {}\n\n\
+         💬 如有异议，请发送 /appeal <申诉内容> 提交申诉（每次封禁限一次）。",
+        reason.map_or_else(|| "未说明".to_string(), |reason| format::safe_user_text(reason, USER_TEXT_DISPLAY_LIMIT, None)),
+        until
+    )
+}
+
+/// 限制模式下被标记的用户暂停生成；返回 true 表示请求已被拦截
+async fn reject_if_flagged(bot: &Bot, msg: &Message, config: &Config, db_user: &User) -> ResponseResult<bool> {
+    if !abuse::is_restricted(config, db_user) {
+        return Ok(false);
+    }
+
+    reply(
+        bot,
+        msg,
+        config.render("⛔ 您的账户因使用异常已暂停生成激活码，需管理员审核后恢复。"),
+    ).await?;
+    Ok(true)
+}
+
+/// CPU 繁忙时暂不处理普通用户的生成请求；返回 true 表示请求已被拦截
+async fn reject_if_busy(bot: &Bot, msg: &Message, config: &Config, user_id: i64) -> ResponseResult<bool> {
+    let Some(cpu_usage) = config.busy_cpu_usage(user_id) else {
+        return Ok(false);
+    };
+
+    info!("CPU 使用率 {:.1}% 超过阈值，暂不处理用户 {} 的生成请求", cpu_usage, user_id);
+    reply(bot, msg, config.render("⏳ 系统繁忙，请稍后再试。")).await?;
+    Ok(true)
+}
+
+/// 输入命中链接或敏感词时不做处理也不计额度，可选告警管理员；返回 true 表示请求已被拦截
+async fn reject_if_filtered(bot: &Bot, msg: &Message, config: &Config, user_id: i64, text: &str) -> ResponseResult<bool> {
+    if config.is_admin(user_id) {
+        return Ok(false);
+    }
+    let Some(hit) = config.input_filter.check(text) else {
+        return Ok(false);
+    };
+
+    warn!("用户 {} 的输入命中过滤规则 ({})，已忽略", user_id, hit.describe());
+    if config.input_filter_alert {
+        let message = format!(
+            "🧹 输入已被过滤\n\n👤 用户: {}\n🔍 命中: {}\n📝 内容: {}",
+            user_id,
+            hit.describe(),
+            format::safe_user_text(text, USER_TEXT_DISPLAY_LIMIT, None)
+        );
+        // 同一用户连续刷屏只告警一次，被过滤的内容各不相同，不能按全文去重
+        if let Err(e) = crate::guard::send_keyed_alert(config, &format!("input_filter:{}", user_id), &message).await {
+            error!("发送过滤告警失败: {}", e);
+        }
+    }
+    // 群聊中不回复，避免刷屏者借机器人的回复继续刷屏
+    if msg.chat.is_private() {
+        reply(bot, msg, config.render("❌ 消息包含链接或违禁内容，未处理。请只发送机器码。")).await?;
+    }
+    Ok(true)
+}
+
+/// 检查封禁状态，临时封禁到期时自动解封；返回 true 表示请求已被拦截
+async fn reject_if_banned(bot: &Bot, msg: &Message, config: &Config, db: &Database, db_user: &User) -> ResponseResult<bool> {
+    if !db_user.is_banned {
+        return Ok(false);
+    }
+
+    if !db_user.is_ban_active(config.clock.now_utc()) {
+        match database::unban_user(db, db_user.user_id).await {
+            Ok(_) => info!("用户 {} 的临时封禁已到期，自动解封", db_user.user_id),
+            Err(e) => error!("自动解封用户失败: {}", e),
+        }
+        return Ok(false);
+    }
+
+    reply(bot, msg, config.render(banned_message(config, db_user))).await?;
+    Ok(true)
 }
 
-async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
+async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
     let user = msg.from().unwrap();
     
     // 获取或创建用户
@@ -193,17 +802,95 @@ async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db:
         user.username.clone(),
         Some(user.first_name.clone()),
         user.last_name.clone(),
-    ).await.map_err(|e| {
-        error!("数据库错误: {}", e);
-        teloxide::RequestError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-    })?;
+        Lang::detect(user.language_code.as_deref(), config.default_lang),
+    ).await.map_err(db_error)?;
+
+    if let Err(e) = database::update_language_code(&db, user.id.0 as i64, user.language_code.as_deref()).await {
+        warn!("记录用户 {} 的语言失败: {}", user.id.0, e);
+    }
+
+    if reject_if_banned(&bot, &msg, &config, &db, &db_user).await? {
+        return Ok(());
+    }
 
-    if db_user.is_banned {
-        bot.send_message(msg.chat.id, "❌ 您已被封禁，无法使用此机器人。").await?;
+    // 一次性结果链接打开后直接发送结果，不回复欢迎语
+    if let Some(token) = msg.text().and_then(result_link::start_token).filter(|_| config.result_links) {
+        deliver_result_link(&bot, &msg, &config, &db, token).await?;
+        dialogue.update(State::Start).await.unwrap();
         return Ok(());
     }
 
-    let welcome_msg = format!(
+    // 快速连发 /start 时只回复一次，避免长消息刷屏
+    let debounce = chrono::Duration::seconds(config.welcome_debounce as i64);
+    match database::claim_welcome(&db, db_user.user_id, config.clock.now_utc(), debounce).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("用户 {} 在 {} 秒内重复 /start，跳过欢迎语", db_user.user_id, config.welcome_debounce);
+            dialogue.update(State::Start).await.unwrap();
+            return Ok(());
+        }
+        Err(e) => warn!("记录用户 {} 的欢迎时间失败: {}", db_user.user_id, e),
+    }
+
+    let trial_notice = match config.quota(&db_user).trial {
+        Some(trial) => format!(
+            "• ⏳ 新用户试用期: 每 24 小时 {} 次，{} 后恢复正常额度\n",
+            trial.daily_limit,
+            format::fmt_datetime(&trial.ends_at, config.default_lang, config.timezone())
+        ),
+        None => String::new(),
+    };
+
+    let welcome_msg = match (copy_variant(&config, &db, user.id.0 as i64).await, &config.copy_variant_welcome) {
+        (CopyVariant::Variant, Some(template)) => {
+            let first_name = format::safe_user_text(&user.first_name, USER_NAME_DISPLAY_LIMIT, None);
+            let welcome = experiment::render_welcome(template, &first_name, config.max_user_requests);
+            format!("{}\n\n{}", welcome, trial_notice).trim_end().to_string()
+        }
+        _ => default_welcome(
+            &format::safe_user_text(&user.first_name, USER_NAME_DISPLAY_LIMIT, None),
+            &config.quota_period.describe(config.max_user_requests, config.quota_mode),
+            &trial_notice,
+        ),
+    };
+
+    // 未配置页脚链接时保持原样以纯文本发送
+    let welcome_msg = config.render(welcome_msg);
+    let mut request = match footer_text(&config) {
+        footer if footer.is_empty() => reply(&bot, &msg, welcome_msg),
+        footer => reply(&bot, &msg, format::escape_markdown_v2(&welcome_msg) + &footer).parse_mode(ParseMode::MarkdownV2),
+    };
+    if let Some(row) = footer_keyboard_row(&config) {
+        request = request.reply_markup(InlineKeyboardMarkup::new(vec![row]));
+    }
+    request.await?;
+    // 一条消息只能带一种键盘，快捷菜单随一条简短提示单独发送，不占用欢迎语上的按钮
+    if config.reply_menu && msg.chat.is_private() {
+        let lang = db_user.lang(config.default_lang);
+        reply(&bot, &msg, config.render(i18n::machine_code_prompt(lang))).reply_markup(menu_keyboard(&config, lang)).await?;
+    }
+    dialogue.update(State::Start).await.unwrap();
+    Ok(())
+}
+
+/// 用户在文案实验中的分组；实验关闭时为对照组，记录分组失败时按哈希结果处理
+async fn copy_variant(config: &Config, db: &Database, user_id: i64) -> CopyVariant {
+    if !config.copy_experiment_enabled() {
+        return CopyVariant::Control;
+    }
+    let variant = experiment::assign(user_id, &config.copy_experiment, config.copy_variant_percent);
+    match database::assign_copy_variant(db, user_id, &config.copy_experiment, variant).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            warn!("记录用户 {} 的文案实验分组失败: {}", user_id, e);
+            variant
+        }
+    }
+}
+
+/// 原有欢迎语，也是文案实验的对照组
+fn default_welcome(name: &str, quota: &str, trial_notice: &str) -> String {
+    format!(
         "╔══════════════════════════════════════╗\n\
          ║    🎉 FinalShell 激活码生成器 🎉    ║\n\
          ║              Rust 版本               ║\n\
@@ -219,8 +906,9 @@ async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db:
          ┣━ 📊 自动识别版本类型\n\
          ┗━ 📋 一次生成全版本激活码\n\n\
          ⚖️ 使用限制:\n\
-         • 普通用户: 每日 {} 次\n\
-         • 管理员: 无限制使用\n\n\
+         • 普通用户: {}\n\
+         • 管理员: 无限制使用\n\
+         {}\n\
          🔧 更多功能: /help\n\n\
          ╔══════════════════════════════════════╗\n\
          ║ 🔹 FinalShell < 3.9.6 (MD5算法)    ║\n\
@@ -228,12 +916,78 @@ async fn start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db:
          ║ 🔷 FinalShell 4.5 (专用盐值)        ║\n\
          ║ 🔶 FinalShell 4.6+ (最新算法)       ║\n\
          ╚══════════════════════════════════════╝",
-        user.first_name.as_str(),
-        config.max_user_requests
-    );
+        name,
+        quota,
+        trial_notice
+    )
+}
 
-    bot.send_message(msg.chat.id, welcome_msg).await?;
-    dialogue.update(State::Start).await.unwrap();
+/// 私聊中常驻的快捷菜单键盘
+fn menu_keyboard(config: &Config, lang: Lang) -> KeyboardMarkup {
+    let buttons: Vec<KeyboardButton> =
+        MenuAction::ALL.iter().map(|action| KeyboardButton::new(config.render(action.label(lang)))).collect();
+    KeyboardMarkup::new(vec![buttons]).resize_keyboard(true).persistent()
+}
+
+/// 快捷菜单按钮：「生成激活码」提示发送机器码（下一条消息照常按机器码处理），「我的用量」显示当前配额，「帮助」同 /help
+async fn handle_menu_action(bot: Bot, msg: Message, config: Config, db: Database, action: MenuAction) -> ResponseResult<()> {
+    let user_id = msg.from().unwrap().id.0 as i64;
+    // 按钮只随 /start 发出，按下时用户已存在
+    let db_user = database::get_user_by_id(&db, user_id).await.map_err(db_error)?;
+    if reject_if_banned(&bot, &msg, &config, &db, &db_user).await? {
+        return Ok(());
+    }
+
+    match action {
+        MenuAction::Generate => {
+            reply(&bot, &msg, config.render(i18n::machine_code_prompt(db_user.lang(config.default_lang)))).await?;
+        }
+        MenuAction::Usage => {
+            let text = usage_summary(&config, &db, &db_user).await?;
+            reply(&bot, &msg, config.render(text)).await?;
+        }
+        MenuAction::Help => help(bot, msg, config).await?,
+    }
+    Ok(())
+}
+
+/// 「我的用量」：按用户语言汇总本周期的配额使用情况
+async fn usage_summary(config: &Config, db: &Database, db_user: &User) -> ResponseResult<String> {
+    let lang = db_user.lang(config.default_lang);
+    let quota = config.quota(db_user);
+    if quota.unlimited {
+        return Ok(i18n::unlimited_usage(lang, config.is_admin(db_user.user_id)));
+    }
+
+    let used = database::quota_used(db, db_user, &quota).await.map_err(db_error)?;
+    let trial = match &quota.trial {
+        Some(trial) => Some((
+            trial_remaining(db, db_user.user_id, trial).await?,
+            format::fmt_datetime(&trial.ends_at, lang, config.timezone()),
+        )),
+        None => None,
+    };
+    let usage = i18n::Usage {
+        used,
+        limit: quota.limit,
+        period: config.quota_period,
+        mode: quota.mode,
+        reset: config
+            .quota_period
+            .next_reset(config.clock.now_utc(), config.timezone())
+            .map(|reset| format::fmt_datetime(&reset, lang, config.timezone())),
+        trial,
+    };
+    Ok(i18n::usage_summary(lang, &usage))
+}
+
+/// /hidemenu：移除快捷菜单键盘，/start 会重新显示
+async fn hide_menu(bot: Bot, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    let lang = match msg.from() {
+        Some(user) => database::get_user_by_id(&db, user.id.0 as i64).await.map_or(config.default_lang, |u| u.lang(config.default_lang)),
+        None => config.default_lang,
+    };
+    reply(&bot, &msg, config.render(i18n::menu_hidden(lang))).reply_markup(KeyboardRemove::new()).await?;
     Ok(())
 }
 
@@ -248,9 +1002,13 @@ async fn help(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
          📋 基础命令:\n\
          ┣━ /start  🚀 开始使用机器人\n\
          ┣━ /help   ❓ 显示此帮助信息\n\
+         ┣━ /hidemenu ⌨️ 隐藏快捷菜单\n\
+         ┣━ /lang   🌐 设置结果语言 (zh/en)\n\
+         ┣━ /split  ✂️ 激活码逐条发送 (on/off)\n\
          ┗━ /about  ℹ️ 查看机器人信息\n\n\
          💡 激活码生成:\n\
          ┣━ 💬 直接发送机器码\n\
+         ┣━ 📄 上传 .txt 文件批量生成 (每行一个)\n\
          ┣━ 🔄 自动识别版本\n\
          ┣━ ⚡ 瞬时生成激活码\n\
          ┗━ 📋 提供全版本支持\n\n\
@@ -276,56 +1034,188 @@ async fn help(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
              ║       👑 管理员专用功能 👑       ║\n\
              ╚══════════════════════════════════════╝\n\n\
              📊 数据管理:\n\
-             ┣━ /stats    📈 查看使用统计\n\
+             ┣━ /stats [json] [实例] 📈 查看使用统计\n\
+             ┣━ /stats history [天数] 📅 每日激活趋势\n\
              ┣━ /users    👥 查看用户列表\n\
+             ┣━ /searchlog <关键字> 🔍 搜索激活记录\n\
+             ┣━ /reports  ⚠️ 激活失败反馈\n\
+             ┣━ /export health [天数] 📤 导出健康历史\n\
+             ┣━ /export audit 📤 导出审计日志 (含哈希)\n\
+             ┣━ /audit verify 🔏 校验审计日志完整性\n\
              ┗━ /clear    🗑️ 清除统计数据\n\n\
              👤 用户管理:\n\
-             ┣━ /ban <ID>   🚫 拉黑用户\n\
-             ┗━ /unban <ID> ✅ 解除拉黑\n\n\
+             ┣━ /ban <ID...> [时长] [原因] 🚫 拉黑用户\n\
+             ┣━ /importbans 📥 导入封禁名单\n\
+             ┣━ /userhistory <ID> 📜 用户激活记录\n\
+             ┣━ /trace <错误码> 🔖 查看单次请求记录\n\
+             ┣━ /support <ID> 🗂️ 用户客服档案\n\
+             ┣━ /flagged 🚩 疑似滥用用户\n\
+             ┣━ /trust <ID> 🤝 解除新用户试用期\n\
+             ┗━ /unban <ID...> ✅ 解除拉黑\n\n\
              📢 系统功能:\n\
-             ┣━ /say <消息>  📻 广播消息\n\
+             ┣━ /say [--test] <消息>  📻 广播消息 (--test 只发给管理员)\n\
              ┣━ /cleanup     🧹 清理日志\n\
-             ┗━ /guard       🛡️ 系统报告"
+             ┣━ /guard       🛡️ 系统报告\n\
+             ┣━ /doctor      🩺 故障诊断\n\
+             ┣━ /versions    🧩 启用/停用版本\n\
+             ┗━ /backup      🗄️ 备份数据"
         );
     }
 
-    bot.send_message(msg.chat.id, help_text).await?;
+    reply(&bot, &msg, config.render(with_command_prefix(&help_text, &config.command_prefix))).await?;
+    Ok(())
+}
+
+/// 置顶新的生成结果并取消上一次的置顶；权限不足等错误只记录日志
+async fn pin_result(bot: &Bot, db: &Database, user: &User, sent: &Message) {
+    if let Some(old) = user.pinned_message_id {
+        if let Err(e) = bot.unpin_chat_message(sent.chat.id).message_id(MessageId(old)).await {
+            debug!("取消置顶旧结果失败: {}", e);
+        }
+    }
+
+    if let Err(e) = bot.pin_chat_message(sent.chat.id, sent.id).disable_notification(true).await {
+        warn!("置顶生成结果失败: {}", e);
+        return;
+    }
+    if let Err(e) = database::set_pinned_message(db, user.user_id, Some(sent.id.0)).await {
+        error!("记录置顶消息失败: {}", e);
+    }
+}
+
+/// 逐条发送激活码，相邻消息间隔 SPLIT_MESSAGE_INTERVAL，遇到限流时等待后重试
+async fn send_code_snippets(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    telegram: &TelegramHealth,
+    snippets: Vec<String>,
+) -> ResponseResult<()> {
+    for snippet in snippets {
+        tokio::time::sleep(SPLIT_MESSAGE_INTERVAL).await;
+        let text = config.render(escape_activation_output(&snippet));
+        retry_on_flood(telegram, || reply(bot, msg, text.clone()).parse_mode(ParseMode::MarkdownV2).send()).await?;
+    }
+    Ok(())
+}
+
+/// 以图片发送激活码，并禁止转发与保存
+async fn send_code_image(bot: &Bot, msg: &Message, png: Vec<u8>) -> ResponseResult<()> {
+    let mut request = bot
+        .send_photo(msg.chat.id, InputFile::memory(png).file_name("activation_codes.png"))
+        .protect_content(true);
+    if let Some(thread_id) = topic_thread_id(msg) {
+        request = request.message_thread_id(thread_id);
+    }
+    request.await?;
     Ok(())
 }
 
-async fn handle_machine_code(bot: Bot, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
+#[allow(clippy::too_many_arguments)]
+async fn handle_machine_code(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    backend: Arc<dyn CodeBackend>,
+    telegram: Arc<TelegramHealth>,
+    hooks: HookRegistry,
+    metrics: Arc<Metrics>,
+    me: Me,
+) -> ResponseResult<()> {
+    let user_id = msg.from().map(|u| u.id.0 as i64).unwrap_or_default();
+    // 校验、生成、写库期间的日志都带上同一个关联 ID，激活日志与失败记录中也保存该 ID
+    let correlation_id = correlation::new_id(user_id);
+    let span = info_span!("machine_code", trace_id = %correlation_id);
+    process_machine_code(bot, msg, config, db, backend, telegram, hooks, &metrics, me, &correlation_id).instrument(span).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_machine_code(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    backend: Arc<dyn CodeBackend>,
+    telegram: Arc<TelegramHealth>,
+    hooks: HookRegistry,
+    metrics: &Metrics,
+    me: Me,
+    correlation_id: &str,
+) -> ResponseResult<()> {
+    let started = Instant::now();
+    // Telegram 创建消息到开始处理的时间差，持续偏大说明更新处理积压
+    let update_lag = (config.clock.now_utc() - msg.date).to_std().ok();
+    if is_bare_mention(&msg, me.username()) {
+        reply(
+            &bot,
+            &msg,
+            config.render(format!(
+                "👋 请私聊我发送机器码获取激活码: https://t.me/{}\n💡 也可以回复包含机器码的消息并 @{}",
+                me.username(),
+                me.username()
+            )),
+        ).await?;
+        return Ok(());
+    }
+    let Some(text) = machine_code_text(&msg, me.username()) else {
+        debug!("消息中没有文字，忽略");
+        return Ok(());
+    };
+    // 转发、引用他人的机器码时，同样只计入发起请求的用户自己的额度
     let user = msg.from().unwrap();
     let user_id = user.id.0 as i64;
+    if msg.forward().is_some() {
+        info!("收到用户 {} 转发的机器码", user_id);
+    } else if quoted_message(&msg, me.username()).is_some() {
+        info!("收到用户 {} 引用的机器码", user_id);
+    } else {
+        info!("收到用户 {} 的机器码", user_id);
+    }
+
+    if reject_if_filtered(&bot, &msg, &config, user_id, text).await? {
+        return Ok(());
+    }
+    metrics.record_request();
+    // 被受理为请求的消息都记录，不论之后是否生成成功；降级期间数据库不可写，跳过
+    if let Some(lag) = update_lag.filter(|_| !config.degraded.is_active()) {
+        if let Err(e) = database::record_update_lag(&db, correlation_id, lag).await {
+            warn!("记录更新处理延迟失败: {}", e);
+        }
+    }
 
     // 检查用户状态
-    let db_user = database::get_user_by_id(&db, user_id).await.map_err(|e| {
-        error!("数据库错误: {}", e);
-        teloxide::RequestError::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
-    })?;
+    let db_user = database::get_user_by_id(&db, user_id).await.map_err(db_error)?;
+    database::refresh_username(&db, &db_user, user.username.as_deref()).await;
 
-    if db_user.is_banned {
-        bot.send_message(msg.chat.id, "❌ 您已被封禁，无法使用此机器人。").await?;
+    if reject_if_banned(&bot, &msg, &config, &db, &db_user).await? {
+        return Ok(());
+    }
+    if reject_if_flagged(&bot, &msg, &config, &db_user).await? {
+        return Ok(());
+    }
+    if reject_if_busy(&bot, &msg, &config, user_id).await? {
         return Ok(());
     }
 
-    // 检查使用次数限制
-    if !config.is_admin(user_id) && db_user.request_count >= config.max_user_requests {
-        bot.send_message(
-            msg.chat.id,
-            format!("❌ 您的使用次数已达上限 ({} 次)。请联系管理员。", config.max_user_requests)
-        ).await?;
-        
-        // 自动拉黑
-        if let Err(e) = database::ban_user(&db, user_id).await {
-            error!("自动拉黑用户失败: {}", e);
+    let clean_machine_code = finalshell::canonicalize(text);
+
+    // 检查使用次数限制（快速预检，最终以预扣结果为准）
+    let quota = config.quota(&db_user);
+    if over_quota(&db, &db_user, &quota, Some(&clean_machine_code)).await? {
+        return reject_over_limit(&bot, &msg, &config, &db, metrics, user_id).await;
+    }
+    if let Some(trial) = &quota.trial {
+        if trial_remaining(&db, user_id, trial).await? <= 0 {
+            return reject_trial_limit(&bot, &msg, &config, trial).await;
         }
+    }
+    if reject_if_bursting(&bot, &msg, &config, &db, &db_user, &quota).await? {
         return Ok(());
     }
 
-    let machine_code = msg.text().unwrap_or("").trim();
-
     // 验证机器码
-    if !ActivationCodeGenerator::validate_machine_code(machine_code) {
+    if !finalshell::is_valid(&clean_machine_code) {
         let error_msg = 
             "╔══════════════════════════════════════╗\n\
              ║         ❌ 机器码格式错误 ❌         ║\n\
@@ -335,7 +1225,7 @@ async fn handle_machine_code(bot: Bot, msg: Message, config: Config, db: SqliteP
              📋 正确格式要求:\n\
              ┣━ 📏 长度: 最少8位字符\n\
              ┣━ 🔤 字符: 字母、数字、@、-、_\n\
-             ┣━ 🚫 禁止: 空格和特殊符号\n\
+             ┣━ 🚫 禁止: 中文及其他特殊符号 (空格会被自动去除)\n\
              ┗━ ⚠️ 注意: 区分大小写\n\n\
              ✨ 正确示例:\n\
              ┣━ abc123@def456\n\
@@ -343,53 +1233,87 @@ async fn handle_machine_code(bot: Bot, msg: Message, config: Config, db: SqliteP
              ┗━ test-2024@server\n\n\
              💡 提示: 请检查机器码并重新发送";
         
-        bot.send_message(msg.chat.id, error_msg).await?;
+        info!("机器码格式错误，已拒绝");
+        record_failure(&db, correlation_id, user_id, "invalid_machine_code", &failure_sample(&clean_machine_code)).await;
+        reply(&bot, &msg, config.render(error_msg)).await?;
         return Ok(());
     }
 
-    // 清理机器码
-    let clean_machine_code = ActivationCodeGenerator::clean_machine_code(machine_code);
+    // 开始生成前预扣一次配额，生成失败时退回，成功后随激活日志一并确认
+    let Some(reservation) = reserve_generation(&bot, &config, &db, &db_user, quota, &clean_machine_code).await? else {
+        info!("并发请求超出次数上限，未生成激活码");
+        return reject_quota(&bot, &msg, &config, &db, metrics, user_id, &quota).await;
+    };
+
+    // 生成耗时不计入与 Telegram 的往返
+    let typing_started = Instant::now();
+    send_typing(&bot, &msg).await;
+    let telegram_wait = typing_started.elapsed();
 
     // 生成所有版本的激活码
-    match ActivationCodeGenerator::format_all_codes(&clean_machine_code) {
-        Ok(all_codes) => {
-            // 更新用户请求次数
-            if let Err(e) = database::update_user_request_count(&db, user_id).await {
-                error!("更新用户请求次数失败: {}", e);
-            }
+    let lang = db_user.lang(config.default_lang);
+    let generated_at = format::fmt_datetime(&config.clock.now_utc(), lang, config.timezone());
+    match backend.generate(&clean_machine_code, &config.display_versions()).await {
+        Ok(results) => {
+            let all_codes = ActivationCodeGenerator::format_results(&clean_machine_code, &results, &generated_at, lang);
+            let Some((request_count, log_id)) = commit_reservation(
+                &bot,
+                &config,
+                &db,
+                &db_user,
+                quota,
+                reservation,
+                correlation_id,
+                &clean_machine_code,
+                user.language_code.as_deref(),
+                &results,
+            ).await? else {
+                info!("预扣已过期且配额已被其他请求用完，未发放激活码");
+                return reject_quota(&bot, &msg, &config, &db, metrics, user_id, &quota).await;
+            };
+            info!("激活日志已写入，本周期已用 {} 次", request_count);
 
-            // 记录激活日志 (使用默认版本)
-            if let Ok((activation_code, version)) = ActivationCodeGenerator::generate(&clean_machine_code) {
-                if let Err(e) = database::log_activation(
-                    &db,
-                    user_id,
-                    &clean_machine_code,
-                    &activation_code,
-                    &version.version,
-                ).await {
-                    error!("记录激活日志失败: {}", e);
+            let mut remaining_requests = if config.is_admin(user_id) {
+                "无限制 (管理员)".to_string()
+            } else if config.is_unlimited(user_id) {
+                "无限制 (白名单)".to_string()
+            } else {
+                match config.quota_period.next_reset(config.clock.now_utc(), config.timezone()) {
+                    Some(reset) => format!(
+                        "{} ({} 重置)",
+                        config.max_user_requests - request_count,
+                        format::fmt_datetime(&reset, config.default_lang, config.timezone())
+                    ),
+                    None => format!("{}", config.max_user_requests - request_count),
                 }
+            };
+            if config.quota_mode == QuotaMode::Machines && !config.is_unlimited(user_id) {
+                remaining_requests.push_str(&format!("\n💡 计数方式: {}", config.quota_mode.explain()));
+            }
+            if let Some(trial) = &quota.trial {
+                remaining_requests.push_str(&format!(
+                    "\n⏳ 新用户试用期: 24 小时内剩余 {} 次，{} 后恢复正常额度",
+                    trial_remaining(&db, user_id, trial).await?.max(0),
+                    format::fmt_datetime(&trial.ends_at, config.default_lang, config.timezone())
+                ));
             }
 
-            let remaining_requests = if config.is_admin(user_id) {
-                "无限制 (管理员)".to_string()
+            // 校验位不通过只做提示，激活码照常发放
+            let failed = finalshell::failed_checksums(&clean_machine_code, &config.checksum_profiles, &config.display_versions());
+            let checksum_hint = if failed.is_empty() {
+                String::new()
             } else {
-                format!("{}", config.max_user_requests - db_user.request_count - 1)
+                let names: Vec<&str> = failed.iter().map(|v| v.version_name_ascii()).collect();
+                format!(
+                    "⚠️ 机器码可能输入有误（未通过 {} 校验），如激活失败请核对后重试\n",
+                    names.join(", ")
+                )
             };
 
-            let user_info = format!(
-                "╔══════════════════════════════════════╗\n\
-                 ║           📊 用户信息 📊           ║\n\
-                 ╚══════════════════════════════════════╝\n\
-                 🏷️ 用户身份: {}\n\
-                 📊 剩余次数: {}\n\
-                 🕐 生成时间: {}\n\n",
-                if config.is_admin(user_id) { "👑 管理员" } else { "👤 普通用户" },
-                remaining_requests,
-                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
-            );
+            let degraded = matches!(reservation, Reservation::Memory { .. }) || config.degraded.is_active();
+            let degraded_hint = if degraded { "⚠️ 系统维护中，本次生成记录暂未保存\n" } else { "" };
 
-            let usage_guide = format!(
+            let default_guide = format!(
                 "╔══════════════════════════════════════╗\n\
                  ║          💡 使用教程 💡          ║\n\
                  ╚══════════════════════════════════════╝\n\
@@ -404,25 +1328,127 @@ async fn handle_machine_code(bot: Bot, msg: Message, config: Config, db: SqliteP
                  ┗━ 🟡 高级版: 基础功能，简洁版本\n\n\
                  ✨ 激活成功后，所有高级功能永久解锁！"
             );
+            let usage_guide = match (copy_variant(&config, &db, user_id).await, &config.copy_variant_tutorial) {
+                (CopyVariant::Variant, Some(tutorial)) => tutorial.clone(),
+                _ => default_guide,
+            };
+
+            // 开启图片模式时先渲染，渲染失败则照常以文字发送
+            let image = match &config.result_image_font {
+                Some(font) => {
+                    let text = ActivationCodeGenerator::format_results_plain(&clean_machine_code, &results, lang);
+                    match result_image::render_png(font, &text) {
+                        Ok(png) => Some(png),
+                        Err(e) => {
+                            error!("渲染激活码图片失败，改为发送文字: {:#}", e);
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
 
             // 转义激活码输出中的特殊字符，但保留反引号用于点击复制
-            let escaped_codes = escape_activation_output(&all_codes);
-            let escaped_user_info = escape_activation_output(&user_info);
             let escaped_usage_guide = escape_activation_output(&usage_guide);
-            
-            let response = format!("{}\n{}\n{}", escaped_codes, escaped_user_info, escaped_usage_guide);
+            // 逐条发送或以图片发送时汇总消息不含激活码，激活码随后单独发送
+            let escaped_codes = if db_user.split_codes || image.is_some() {
+                None
+            } else {
+                let mut escaped_codes = escape_activation_output(&all_codes);
+                if config.fold_versions {
+                    escaped_codes = fold_secondary_codes(&escaped_codes, &clean_machine_code, &results);
+                }
+                Some(escaped_codes)
+            };
+
+            // 回复的其余部分都已生成，耗时计到这里，只剩拼接用户信息
+            let latency = started.elapsed().saturating_sub(telegram_wait);
+            let latency_line = if config.show_latency {
+                format!("⏱️ 生成耗时: {}\n", format::fmt_latency(latency))
+            } else {
+                String::new()
+            };
+
+            let user_info = format!(
+                "╔══════════════════════════════════════╗\n\
+                 ║           📊 用户信息 📊           ║\n\
+                 ╚══════════════════════════════════════╝\n\
+                 🏷️ 用户身份: {}\n\
+                 📊 剩余次数: {}\n\
+                 🕐 生成时间: {}\n\
+                 {}{}{}\n",
+                if config.is_admin(user_id) { "👑 管理员" } else { "👤 普通用户" },
+                remaining_requests,
+                format::fmt_datetime(&config.clock.now_utc(), config.default_lang, config.timezone()),
+                latency_line,
+                checksum_hint,
+                degraded_hint
+            );
 
-            bot.send_message(msg.chat.id, response)
+            let escaped_user_info = escape_activation_output(&user_info);
+            let response = match escaped_codes {
+                Some(escaped_codes) => format!("{}\n{}\n{}", escaped_codes, escaped_user_info, escaped_usage_guide),
+                None => format!("{}\n{}", escaped_user_info, escaped_usage_guide),
+            };
+
+            let sent = reply(&bot, &msg, config.render(response) + &footer_text(&config))
                 .parse_mode(ParseMode::MarkdownV2)
-                .await?;
+                .reply_markup(report_keyboard(&config, &clean_machine_code, log_id));
+            let degraded_key = degraded_key(&quota, &clean_machine_code);
+            let sent = match deliver_or_refund(sent, &config.degraded, &db, user_id, degraded_key, correlation_id, log_id).await {
+                Ok(sent) => sent,
+                Err(e) => {
+                    metrics.record_failure();
+                    return Err(e);
+                }
+            };
+            metrics.record_generated();
+            if config.pin_results && msg.chat.is_private() {
+                pin_result(&bot, &db, &db_user, &sent).await;
+            }
+            // 激活码已随主消息送达，附带的图片与逐条发送失败不退回配额
+            if let Some(png) = image {
+                if let Err(e) = send_code_image(&bot, &msg, png).await {
+                    warn!("发送激活码图片失败: {}", e);
+                }
+            } else if db_user.split_codes {
+                let snippets = ActivationCodeGenerator::format_code_snippets(&results, lang);
+                if let Err(e) = send_code_snippets(&bot, &msg, &config, &telegram, snippets).await {
+                    warn!("逐条发送激活码失败: {}", e);
+                }
+            }
+
+            info!("为用户 {} 生成全版本激活码成功，耗时 {}", user_id, format::fmt_latency(latency));
+            // 降级期间数据库不可写，跳过耗时记录与滥用检测
+            if !degraded {
+                if let Err(e) = database::record_request_metric(&db, user_id, correlation_id, latency).await {
+                    error!("记录生成耗时失败: {}", e);
+                }
 
-            info!("为用户 {} 生成全版本激活码成功", user_id);
+                if let Err(e) = abuse::check_user(&config, &db, user_id).await {
+                    error!("滥用检测失败: {}", e);
+                }
+            }
+
+            hooks.spawn(GenerationContext::new(
+                user_id,
+                correlation_id,
+                "message",
+                &clean_machine_code,
+                config.machine_code_display,
+                &results,
+                latency,
+            ));
         }
         Err(e) => {
-            error!("生成激活码失败: {}", e);
-            bot.send_message(
-                msg.chat.id,
-                "❌ 生成激活码时发生错误，请稍后重试或联系管理员。"
+            error!("生成激活码失败 (后端: {}): {:#}", backend.name(), e);
+            metrics.record_failure();
+            release_reservation(&config, &db, user_id, degraded_key(&quota, &clean_machine_code), reservation).await;
+            record_failure(&db, correlation_id, user_id, "generate", &format!("{:#}", e)).await;
+            reply(
+                &bot,
+                &msg,
+                config.render(format!("❌ 生成激活码时发生错误，请稍后重试。\n🔖 错误码: {}，请联系管理员", correlation_id))
             ).await?;
         }
     }
@@ -430,353 +1456,3823 @@ async fn handle_machine_code(bot: Bot, msg: Message, config: Config, db: SqliteP
     Ok(())
 }
 
-async fn stats(bot: Bot, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
-    let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
-    }
+/// 生成前预扣的配额：通常记在数据库中，降级期间记在内存中
+#[derive(Debug, Clone, Copy)]
+enum Reservation {
+    Db(i64),
+    /// `used` 为本周期已用次数（含本次），降级期间不写入激活日志
+    Memory { used: i32 },
+}
 
-    match database::get_system_stats(&db).await {
-        Ok(stats) => {
-            let stats_msg = format!(
-                "╔══════════════════════════════════════╗\n\
-                 ║         📊 系统统计信息 📊         ║\n\
-                 ╚══════════════════════════════════════╝\n\n\
-                 👥 总用户数: {}\n\
-                 🔑 总激活次数: {}\n\
-                 📅 今日活跃用户: {}\n\
-                 🎯 今日激活次数: {}\n\
-                 💚 系统状态: {}\n\n\
-                 🕒 统计时间: {}",
-                stats.total_users,
-                stats.total_activations,
-                stats.active_users_today,
-                stats.activations_today,
-                stats.system_status,
-                utils::format_datetime(&stats.created_at)
-            );
+/// 降级期间内存计数的键：按机器码计数时为机器码，按次数计数时为 None
+fn degraded_key<'a>(quota: &database::Quota, machine_code: &'a str) -> Option<&'a str> {
+    (quota.mode == QuotaMode::Machines).then_some(machine_code)
+}
 
-            bot.send_message(msg.chat.id, stats_msg).await?;
-        }
-        Err(e) => {
-            error!("获取统计信息失败: {}", e);
-            bot.send_message(msg.chat.id, "❌ 获取统计信息失败。").await?;
+/// 开始生成前预扣一次配额，配额已用尽时返回 None。数据库不可写时进入降级模式，改为在内存中计数
+async fn reserve_generation(
+    bot: &Bot,
+    config: &Config,
+    db: &Database,
+    user: &User,
+    quota: database::Quota,
+    machine_code: &str,
+) -> ResponseResult<Option<Reservation>> {
+    if !config.degraded.is_active() {
+        match database::reserve_quota(db, user.user_id, quota, machine_code).await {
+            Ok(reservation) => return Ok(reservation.map(Reservation::Db)),
+            Err(e) if database::is_write_unavailable(&e) => enter_degraded(bot, config, &e).await,
+            Err(e) => return Err(db_error(e)),
         }
     }
 
-    Ok(())
-}
-
-async fn users(bot: Bot, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
-    let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
+    // 数据库仍可读，剩余次数以降级前的记录为准
+    let used = database::quota_used(db, user, &quota).await.map_err(db_error)?;
+    let key = degraded_key(&quota, machine_code);
+    // 本周期已计过的机器码再次生成不占用额度
+    if key.is_some() && database::machine_code_counted(db, user.user_id, &quota, machine_code).await.map_err(db_error)? {
+        let consumed = config.degraded.machine_code_count(user.user_id) as i32;
+        return Ok(Some(Reservation::Memory { used: used + consumed }));
     }
+    let allowance = if quota.unlimited {
+        None
+    } else {
+        let mut allowance = i64::from(quota.limit - used);
+        if let Some(trial) = &quota.trial {
+            allowance = allowance.min(trial_remaining(db, user.user_id, trial).await?);
+        }
+        Some(allowance.max(0))
+    };
+    Ok(config
+        .degraded
+        .try_consume(user.user_id, key, allowance)
+        .map(|consumed| Reservation::Memory { used: used + consumed as i32 }))
+}
 
-    match database::get_all_users(&db).await {
-        Ok(users) => {
-            if users.is_empty() {
-                bot.send_message(msg.chat.id, "📝 暂无用户数据。").await?;
-                return Ok(());
-            }
-
-            let mut response = String::from(
-                "╔══════════════════════════════════════╗\n\
-                 ║           👥 用户列表 👥           ║\n\
-                 ╚══════════════════════════════════════╝\n\n"
-            );
-            
-            for (index, user) in users.iter().enumerate().take(20) {
-                let status = if user.is_banned { "🚫 已封禁" } else { "✅ 正常" };
-                let username = user.username.as_deref().unwrap_or("无用户名");
-                let last_request = user.last_request
-                    .map(|dt| utils::format_datetime(&dt))
-                    .unwrap_or_else(|| "从未使用".to_string());
-
-                response.push_str(&format!(
-                    "{}. {} ({})\n\
-                     • ID: {}\n\
-                     • 请求次数: {}\n\
-                     • 最后使用: {}\n\
-                     • 状态: {}\n\n",
-                    index + 1,
-                    username,
-                    user.user_id,
-                    user.user_id,
-                    user.total_requests,
-                    last_request,
-                    status
-                ));
-            }
-
-            if users.len() > 20 {
-                response.push_str(&format!("... 共 {} 个用户，仅显示前20个", users.len()));
+/// 生成失败时退回预扣的配额；数据库退回失败时预扣会在过期后自动失效，这里只记录日志
+async fn release_reservation(config: &Config, db: &Database, user_id: i64, degraded_key: Option<&str>, reservation: Reservation) {
+    match reservation {
+        Reservation::Db(id) => {
+            if let Err(e) = database::release_quota(db, id).await {
+                error!("退回预扣配额失败: {}", e);
             }
+        }
+        Reservation::Memory { .. } => config.degraded.release(user_id, degraded_key),
+    }
+}
 
-            bot.send_message(msg.chat.id, response).await?;
+/// 确认预扣并写入激活日志，返回本周期已用次数与激活日志 ID。生成期间数据库变为不可写时进入降级模式，
+/// 本次改为在内存中计数，激活码照常发放，此时没有日志 ID。预扣已过期且重新检查时配额已用尽则返回 None
+#[allow(clippy::too_many_arguments)]
+async fn commit_reservation(
+    bot: &Bot,
+    config: &Config,
+    db: &Database,
+    user: &User,
+    quota: database::Quota,
+    reservation: Reservation,
+    correlation_id: &str,
+    machine_code: &str,
+    language_code: Option<&str>,
+    results: &[finalshell::ActivationResult],
+) -> ResponseResult<Option<(i32, Option<i64>)>> {
+    let id = match reservation {
+        Reservation::Db(id) => id,
+        Reservation::Memory { used } => return Ok(Some((used, None))),
+    };
+    match database::commit_generation(db, id, user.user_id, correlation_id, machine_code, quota, language_code, results).await {
+        Ok(committed) => Ok(committed.map(|(count, log_id)| (count, Some(log_id)))),
+        Err(e) if database::is_write_unavailable(&e) => {
+            enter_degraded(bot, config, &e).await;
+            let used = database::quota_used(db, user, &quota).await.map_err(db_error)?;
+            let consumed = config
+                .degraded
+                .try_consume(user.user_id, degraded_key(&quota, machine_code), None)
+                .unwrap_or_default();
+            Ok(Some((used + consumed as i32, None)))
         }
         Err(e) => {
-            error!("获取用户列表失败: {}", e);
-            bot.send_message(msg.chat.id, "❌ 获取用户列表失败。").await?;
+            release_reservation(config, db, user.user_id, degraded_key(&quota, machine_code), reservation).await;
+            Err(db_error(e))
         }
     }
-
-    Ok(())
 }
 
-async fn ban_user(bot: Bot, msg: Message, config: Config, db: SqlitePool, user_id_str: String) -> ResponseResult<()> {
-    let admin_user = msg.from().unwrap();
-    
-    if !config.is_admin(admin_user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
+/// 发送承载激活码的主消息；发送失败时撤销本次生成后返回原错误
+async fn deliver_or_refund<T>(
+    send: impl IntoFuture<Output = ResponseResult<T>>,
+    degraded: &DegradedMode,
+    db: &Database,
+    user_id: i64,
+    degraded_key: Option<&str>,
+    correlation_id: &str,
+    log_id: Option<i64>,
+) -> ResponseResult<T> {
+    let result = send.await;
+    if let Err(e) = &result {
+        refund_undelivered(degraded, db, user_id, degraded_key, correlation_id, log_id, e).await;
     }
+    result
+}
 
-    match user_id_str.parse::<i64>() {
-        Ok(target_user_id) => {
-            match database::ban_user(&db, target_user_id).await {
-                Ok(_) => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("✅ 用户 {} 已被成功拉黑。", target_user_id)
-                    ).await?;
-                    info!("管理员 {} 拉黑了用户 {}", admin_user.id.0, target_user_id);
-                }
-                Err(e) => {
-                    error!("拉黑用户失败: {}", e);
-                    bot.send_message(msg.chat.id, "❌ 拉黑用户失败。").await?;
-                }
-            }
-        }
-        Err(_) => {
-            bot.send_message(msg.chat.id, "❌ 用户ID格式错误。").await?;
-        }
+/// 激活码未能送达时撤销本次生成：退回配额并删除激活日志，只保留一条发送失败记录
+async fn refund_undelivered(
+    degraded: &DegradedMode,
+    db: &Database,
+    user_id: i64,
+    degraded_key: Option<&str>,
+    correlation_id: &str,
+    log_id: Option<i64>,
+    e: &teloxide::RequestError,
+) {
+    error!("发送激活码失败，退回本次配额: {}", e);
+    match log_id {
+        Some(log_id) => match database::refund_generation(db, log_id, user_id).await {
+            Ok(true) => {}
+            Ok(false) => warn!("激活日志 {} 已不存在，未退回配额", log_id),
+            Err(e) => error!("退回配额失败: {}", e),
+        },
+        // 降级期间没有激活日志，次数记在内存中
+        None => degraded.release(user_id, degraded_key),
     }
-
-    Ok(())
+    record_failure(db, correlation_id, user_id, "send", &e.to_string()).await;
 }
 
-async fn unban_user(bot: Bot, msg: Message, config: Config, db: SqlitePool, user_id_str: String) -> ResponseResult<()> {
-    let admin_user = msg.from().unwrap();
-    
-    if !config.is_admin(admin_user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
+/// 数据库写入因文件系统只读或 I/O 错误失败时进入降级模式，首次进入时通知全部管理员
+async fn enter_degraded(bot: &Bot, config: &Config, e: &anyhow::Error) {
+    if !config.degraded.enter(config.clock.now_utc(), format!("{:#}", e)) {
+        return;
     }
+    error!("数据库不可写，进入降级模式: {:#}", e);
+    alert_admins(
+        bot,
+        config,
+        format!(
+            "🚨🚨 数据库不可写，已进入降级模式 🚨🚨\n\n\
+             ❗ 原因: {:#}\n\
+             ┣━ 激活码照常生成\n\
+             ┣━ 配额改用内存计数，重启后丢失\n\
+             ┗━ 激活日志与统计暂不保存\n\n\
+             💡 检查文件系统是否只读、磁盘空间与 I/O 错误；恢复写入后自动退出降级",
+            e
+        ),
+    )
+    .await;
+}
 
-    match user_id_str.parse::<i64>() {
-        Ok(target_user_id) => {
-            match database::unban_user(&db, target_user_id).await {
-                Ok(_) => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("✅ 用户 {} 已被成功解封。", target_user_id)
-                    ).await?;
-                    info!("管理员 {} 解封了用户 {}", admin_user.id.0, target_user_id);
-                }
-                Err(e) => {
-                    error!("解封用户失败: {}", e);
-                    bot.send_message(msg.chat.id, "❌ 解封用户失败。").await?;
-                }
-            }
-        }
-        Err(_) => {
-            bot.send_message(msg.chat.id, "❌ 用户ID格式错误。").await?;
-        }
+/// 降级期间定期探测数据库，恢复写入后退出降级模式并通知管理员
+async fn probe_degraded_periodically(bot: Bot, config: Config, db: Database) {
+    let mut interval = tokio::time::interval(Duration::from_secs(DEGRADED_PROBE_SECS));
+    loop {
+        interval.tick().await;
+        probe_degraded(&bot, &config, &db).await;
     }
-
-    Ok(())
 }
 
-async fn broadcast_start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, message: String) -> ResponseResult<()> {
-    let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
+/// 处于降级模式时探测一次数据库，可写则退出降级
+async fn probe_degraded(bot: &Bot, config: &Config, db: &Database) {
+    if !config.degraded.is_active() {
+        return;
     }
-
-    if message.trim().is_empty() {
-        bot.send_message(msg.chat.id, "❌ 广播消息不能为空。").await?;
-        return Ok(());
+    match database::probe_write(db).await {
+        Ok(()) => {
+            let Some(since) = config.degraded.exit() else {
+                return;
+            };
+            info!("数据库已恢复写入，退出降级模式");
+            alert_admins(
+                bot,
+                config,
+                format!(
+                    "✅ 数据库已恢复写入，退出降级模式\n🕒 降级开始于: {}",
+                    format::fmt_datetime(&since, config.default_lang, config.timezone())
+                ),
+            )
+            .await;
+        }
+        Err(e) => debug!("数据库仍不可写: {:#}", e),
     }
+}
 
-    // 这里可以直接发送广播，或者实现一个确认机制
-    let confirm_msg = format!(
-        "╔══════════════════════════════════════╗\n\
-         ║       📢 准备发送广播消息 📢       ║\n\
-         ╚══════════════════════════════════════╝\n\n\
-         📝 消息内容: {}\n\n\
-         ⚠️ 此消息将发送给所有用户，确认发送吗？\n\
-         💬 回复 \"确认\" 开始发送，回复其他内容取消。",
-        message
-    );
+/// 向全部管理员私聊发送通知，发送失败只记日志
+async fn alert_admins(bot: &Bot, config: &Config, text: String) {
+    for admin_id in &config.admin_ids {
+        if let Err(e) = bot.send_message(ChatId(*admin_id), config.render(text.clone())).await {
+            warn!("向管理员 {} 发送通知失败: {}", admin_id, e);
+        }
+    }
+}
 
-    bot.send_message(msg.chat.id, confirm_msg).await?;
+/// 批量结果文件中跳过的一行，回显的原始内容截断到 ECHO_DISPLAY_LIMIT
+fn batch_skip_line(line_no: usize, message: &str, raw: &str) -> String {
+    format!("第 {} 行: {}: {}\n\n", line_no, message, format::safe_user_text(raw, ECHO_DISPLAY_LIMIT, None))
+}
 
-    // 存储广播消息到状态中 (这里需要实现一个状态管理)
-    dialogue.update(State::AdminBroadcast).await.unwrap();
+/// 失败记录中保存的无效输入最多保留的字符数
+const FAILURE_SAMPLE_CHARS: usize = 64;
 
-    Ok(())
+/// 无效输入只保存开头一段并注明总长度，避免超长消息原样写入失败记录
+fn failure_sample(input: &str) -> String {
+    let total = input.chars().count();
+    if total <= FAILURE_SAMPLE_CHARS {
+        return input.to_string();
+    }
+    let head: String = input.chars().take(FAILURE_SAMPLE_CHARS).collect();
+    format!("{}… (共 {} 字符)", head, total)
 }
 
-async fn handle_broadcast(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
-    let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        dialogue.update(State::Start).await.unwrap();
-        return Ok(());
+/// 保存未能生成激活码的请求，供 /trace 查询；写入失败只记日志
+async fn record_failure(db: &Database, correlation_id: &str, user_id: i64, stage: &str, detail: &str) {
+    if let Err(e) = database::record_request_failure(db, correlation_id, user_id, stage, detail).await {
+        error!("记录请求失败信息失败: {}", e);
     }
+}
 
-    let response = msg.text().unwrap_or("").trim();
-    
-    if response == "确认" {
-        // 获取所有用户并发送广播
-        match database::get_all_users(&db).await {
-            Ok(users) => {
-                let broadcast_msg = "📢 系统广播消息"; // 这里应该从之前的状态中获取
-                let mut success_count = 0;
-                let mut failed_count = 0;
-
-                for user in users {
-                    if !user.is_banned {
-                        match bot.send_message(teloxide::types::ChatId(user.user_id), broadcast_msg).await {
-                            Ok(_) => success_count += 1,
-                            Err(e) => {
-                                warn!("向用户 {} 发送广播失败: {}", user.user_id, e);
-                                failed_count += 1;
-                            }
-                        }
-                    }
-                }
-
-                let result_msg = format!(
-                    "✅ 广播发送完成\n\n\
-                     成功: {} 人\n\
-                     失败: {} 人",
-                    success_count, failed_count
-                );
+/// /lang [zh|en]：设置激活码结果的语言，不带参数时显示当前语言
+async fn set_language(bot: Bot, msg: Message, config: Config, db: Database, code: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    let db_user = database::get_or_create_user(
+        &db,
+        user.id.0 as i64,
+        user.username.clone(),
+        Some(user.first_name.clone()),
+        user.last_name.clone(),
+        Lang::detect(user.language_code.as_deref(), config.default_lang),
+    ).await.map_err(db_error)?;
 
-                bot.send_message(msg.chat.id, result_msg).await?;
-                info!("管理员 {} 发送了广播消息", user.id.0);
-            }
-            Err(e) => {
-                error!("获取用户列表失败: {}", e);
-                bot.send_message(msg.chat.id, "❌ 获取用户列表失败，广播取消。").await?;
-            }
-        }
-    } else {
-        bot.send_message(msg.chat.id, "❌ 广播已取消。").await?;
+    let code = code.trim();
+    if code.is_empty() {
+        let current = db_user.lang(config.default_lang);
+        reply(&bot, &msg, config.render(format!("🌐 当前语言: {}\n用法: /lang zh|en", current.code()))).await?;
+        return Ok(());
     }
 
-    dialogue.update(State::Start).await.unwrap();
+    let Some(lang) = Lang::from_code(code) else {
+        reply(&bot, &msg, config.render("❌ 不支持的语言。用法: /lang zh|en")).await?;
+        return Ok(());
+    };
+    database::set_user_lang(&db, db_user.user_id, lang).await.map_err(db_error)?;
+    reply(&bot, &msg, config.render(format!("✅ 语言已设置为: {}", lang.code()))).await?;
     Ok(())
 }
 
-async fn clear_stats(bot: Bot, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
+/// /split [on|off]：是否将每个激活码作为独立消息发送，不带参数时显示当前设置
+async fn set_split_codes(bot: Bot, msg: Message, config: Config, db: Database, mode: String) -> ResponseResult<()> {
     let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
-        return Ok(());
-    }
+    let db_user = database::get_or_create_user(
+        &db,
+        user.id.0 as i64,
+        user.username.clone(),
+        Some(user.first_name.clone()),
+        user.last_name.clone(),
+        Lang::detect(user.language_code.as_deref(), config.default_lang),
+    ).await.map_err(db_error)?;
 
-    match database::clear_stats(&db).await {
-        Ok(_) => {
-            bot.send_message(msg.chat.id, "✅ 统计数据已清除。").await?;
-            info!("管理员 {} 清除了统计数据", user.id.0);
+    let describe = |enabled: bool| if enabled { "逐条发送" } else { "合并发送" };
+    let enabled = match mode.trim().to_ascii_lowercase().as_str() {
+        "" => {
+            reply(
+                &bot,
+                &msg,
+                config.render(format!("✂️ 当前: {}\n用法: /split on|off", describe(db_user.split_codes))),
+            ).await?;
+            return Ok(());
         }
-        Err(e) => {
-            error!("清除统计数据失败: {}", e);
-            bot.send_message(msg.chat.id, "❌ 清除统计数据失败。").await?;
+        "on" => true,
+        "off" => false,
+        _ => {
+            reply(&bot, &msg, config.render("❌ 用法: /split on|off")).await?;
+            return Ok(());
         }
-    }
+    };
 
+    database::set_split_codes(&db, db_user.user_id, enabled).await.map_err(db_error)?;
+    reply(&bot, &msg, config.render(format!("✅ 激活码将{}。", describe(enabled)))).await?;
     Ok(())
 }
 
-async fn cleanup_logs(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
-    let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
+/// /trial <天数> <机器码>：生成试用延长码，不消耗激活次数
+async fn trial_extension(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    backend: Arc<dyn CodeBackend>,
+    args: String,
+) -> ResponseResult<()> {
+    let user_id = msg.from().unwrap().id.0 as i64;
+    let db_user = database::get_user_by_id(&db, user_id).await.map_err(db_error)?;
+    if reject_if_banned(&bot, &msg, &config, &db, &db_user).await? {
         return Ok(());
     }
 
-    // 这里实现日志清理逻辑
-    match utils::cleanup_logs().await {
-        Ok(cleaned_files) => {
-            bot.send_message(
-                msg.chat.id,
-                format!("✅ 日志清理完成，清理了 {} 个文件。", cleaned_files)
+    let mut parts = args.split_whitespace();
+    let days = parts.next().and_then(|s| s.parse::<u32>().ok());
+    let machine_code = finalshell::canonicalize(parts.collect::<Vec<_>>().join(" ").as_str());
+    let days = match days {
+        Some(days) if (1..=MAX_TRIAL_EXTENSION_DAYS).contains(&days) && finalshell::is_valid(&machine_code) => days,
+        _ => {
+            reply(
+                &bot,
+                &msg,
+                config.render(format!(
+                    "❌ 用法: /trial <天数> <机器码>\n天数范围 1-{}，例如: /trial 7 abc123@def456",
+                    MAX_TRIAL_EXTENSION_DAYS
+                )),
             ).await?;
-            info!("管理员 {} 执行了日志清理", user.id.0);
+            return Ok(());
+        }
+    };
+
+    match backend.generate_trial_extension(&machine_code, days).await {
+        Ok(code) => {
+            reply(&bot, &msg, config.render(format!("⏳ 试用延长码 ({} 天):\n{}", days, code))).await?;
+            info!("为用户 {} 生成 {} 天试用延长码", user_id, days);
         }
         Err(e) => {
-            error!("日志清理失败: {}", e);
-            bot.send_message(msg.chat.id, "❌ 日志清理失败。").await?;
+            warn!("生成试用延长码失败 (后端: {}): {:#}", backend.name(), e);
+            reply(&bot, &msg, config.render(format!("❌ 暂不支持生成试用延长码: {}", e))).await?;
         }
     }
 
     Ok(())
 }
 
-async fn about_bot(bot: Bot, msg: Message) -> ResponseResult<()> {
-    let about_text = 
-        "╔══════════════════════════════════════╗\n\
-         ║      🤖 FinalShell 激活码生成器      ║\n\
-         ║             Rust 版本 v2.0           ║\n\
-         ╚══════════════════════════════════════╝\n\n\
-         🚀 项目信息:\n\
-         ┣━ 📛 名称: FinalShell Activator (Rust)\n\
-         ┣━ 🏷️ 版本: v2.0.0\n\
-         ┣━ 🔧 语言: Rust 2021 Edition\n\
-         ┗━ 📅 发布: 2025年8月\n\n\
-         ⚡ 性能优势:\n\
-         ┣━ 🚀 启动时间: ~0.5秒 (比Python快83%)\n\
-         ┣━ 💾 内存占用: ~45MB (比Python少70%)\n\
-         ┣━ 🔄 并发处理: ~1000 req/s (比Python快900%)\n\
-         ┗━ 🛡️ 内存安全: 零成本抽象\n\n\
-         🎯 核心特性:\n\
-         ┣━ ✨ 支持全版本 FinalShell\n\
-         ┣━ 🔄 实时激活码生成\n\
-         ┣━ 🛡️ 24小时监控守护\n\
-         ┣━ 📊 完整统计分析\n\
-         ┗━ 👥 用户权限管理\n\n\
-         🔒 安全保障:\n\
-         ┣━ 🛡️ 算法透明可靠\n\
-         ┣━ 🔐 标准加密技术\n\
-         ┣━ 🚫 无恶意行为\n\
-         ┗━ ♾️ 永久免费使用\n\n\
-         💎 感谢您使用我们的服务！";
+/// 试用期内 24 小时窗口剩余的生成次数
+async fn trial_remaining(db: &Database, user_id: i64, trial: &database::TrialLimit) -> ResponseResult<i64> {
+    let used = database::count_generations_since(db, user_id, trial.window_start).await.map_err(db_error)?;
+    Ok(i64::from(trial.daily_limit) - used)
+}
+
+/// 滚动一小时内的生成次数达到 MAX_REQUESTS_PER_HOUR 时拒绝，并告知下一次可用的时间；返回 true 表示请求已被拦截
+async fn reject_if_bursting(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    db: &Database,
+    db_user: &User,
+    quota: &database::Quota,
+) -> ResponseResult<bool> {
+    let Some(per_hour) = config.max_requests_per_hour else {
+        return Ok(false);
+    };
+    let now = config.clock.now_utc();
+    let Some(opens_at) = database::hourly_slot_opens_at(db, db_user.user_id, quota, Some(per_hour), now).await.map_err(db_error)? else {
+        return Ok(false);
+    };
 
-    bot.send_message(msg.chat.id, about_text).await?;
+    info!("用户 {} 一小时内的生成次数已达 {} 次，暂不处理", db_user.user_id, per_hour);
+    let lang = db_user.lang(config.default_lang);
+    let opens_at = format::fmt_datetime(&opens_at, lang, config.timezone());
+    reply(bot, msg, config.render(quota::burst_limit_message(lang, per_hour, &opens_at))).await?;
+    Ok(true)
+}
+
+/// 试用期每日额度用完：提示等待，不计入自动拉黑
+async fn reject_trial_limit(bot: &Bot, msg: &Message, config: &Config, trial: &database::TrialLimit) -> ResponseResult<()> {
+    reply(
+        bot,
+        msg,
+        config.render(format!(
+            "⏳ 新用户试用期内每 24 小时最多生成 {} 次，当前额度已用完。\n试用期将于 {} 结束，届时恢复正常额度。",
+            trial.daily_limit,
+            format::fmt_datetime(&trial.ends_at, config.default_lang, config.timezone())
+        )),
+    ).await?;
     Ok(())
 }
 
+/// 原子扣减失败：试用期额度用完时提示等待，否则按总次数上限处理
+async fn reject_quota(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    db: &Database,
+    metrics: &Metrics,
+    user_id: i64,
+    quota: &database::Quota,
+) -> ResponseResult<()> {
+    if let Some(trial) = &quota.trial {
+        if trial_remaining(db, user_id, trial).await? <= 0 {
+            return reject_trial_limit(bot, msg, config, trial).await;
+        }
+    }
+    reject_over_limit(bot, msg, config, db, metrics, user_id).await
+}
+
+/// 处理上传的 .txt 文档：逐行读取机器码批量生成，结果以文档回发
+#[allow(clippy::too_many_arguments)]
+async fn handle_document(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    backend: Arc<dyn CodeBackend>,
+    hooks: HookRegistry,
+    metrics: Arc<Metrics>,
+    document: Document,
+) -> ResponseResult<()> {
+    let user_id = msg.from().map(|u| u.id.0 as i64).unwrap_or_default();
+    // 整个文件共用一个关联 ID
+    let correlation_id = correlation::new_id(user_id);
+    let span = info_span!("batch_upload", trace_id = %correlation_id);
+    process_document(bot, msg, config, db, backend, hooks, &metrics, document, &correlation_id).instrument(span).await
+}
 
-async fn guard_report(bot: Bot, msg: Message, config: Config, db: SqlitePool) -> ResponseResult<()> {
+#[allow(clippy::too_many_arguments)]
+async fn process_document(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    backend: Arc<dyn CodeBackend>,
+    hooks: HookRegistry,
+    metrics: &Metrics,
+    document: Document,
+    correlation_id: &str,
+) -> ResponseResult<()> {
     let user = msg.from().unwrap();
-    
-    if !config.is_admin(user.id.0 as i64) {
-        bot.send_message(msg.chat.id, "❌ 此命令仅管理员可用。").await?;
+    let user_id = user.id.0 as i64;
+
+    let db_user = database::get_user_by_id(&db, user_id).await.map_err(db_error)?;
+    database::refresh_username(&db, &db_user, user.username.as_deref()).await;
+    if reject_if_banned(&bot, &msg, &config, &db, &db_user).await? {
         return Ok(());
     }
-
+    if reject_if_flagged(&bot, &msg, &config, &db_user).await? {
+        return Ok(());
+    }
+    if reject_if_busy(&bot, &msg, &config, user_id).await? {
+        return Ok(());
+    }
+
+    if !is_text_document(&document) {
+        reply(&bot, &msg, config.render("❌ 暂不支持该文件类型，请上传 .txt 文本文件（每行一个机器码）。")).await?;
+        return Ok(());
+    }
+
+    if document.file.size > config.max_batch_file_size {
+        reply(
+            &bot,
+            &msg,
+            config.render(format!("❌ 文件过大，请控制在 {} 以内。", utils::format_file_size(u64::from(config.max_batch_file_size)))),
+        ).await?;
+        return Ok(());
+    }
+
+    // 批量上传在逐行预扣前只能按新机器码预检
+    let quota = config.quota(&db_user);
+    if over_quota(&db, &db_user, &quota, None).await? {
+        return reject_over_limit(&bot, &msg, &config, &db, metrics, user_id).await;
+    }
+    if let Some(trial) = &quota.trial {
+        if trial_remaining(&db, user_id, trial).await? <= 0 {
+            return reject_trial_limit(&bot, &msg, &config, trial).await;
+        }
+    }
+    if reject_if_bursting(&bot, &msg, &config, &db, &db_user, &quota).await? {
+        return Ok(());
+    }
+
+    send_typing(&bot, &msg).await;
+
+    let content = match read_text_upload(&bot, &config, &document, u64::from(config.max_batch_file_size)).await {
+        Ok(content) => content,
+        Err(e) => {
+            reply(&bot, &msg, config.render(format!("❌ {}", e))).await?;
+            return Ok(());
+        }
+    };
+
+    let lines = parse_batch_lines(&content);
+    if lines.is_empty() {
+        reply(&bot, &msg, config.render("❌ 文件中没有找到机器码。")).await?;
+        return Ok(());
+    }
+    if lines.len() > MAX_BATCH_LINES {
+        reply(
+            &bot,
+            &msg,
+            config.render(format!("❌ 文件行数过多，每次最多处理 {} 个机器码。", MAX_BATCH_LINES)),
+        ).await?;
+        return Ok(());
+    }
+
+    let mut output = String::new();
+    let (mut generated, mut invalid, mut failed, mut skipped) = (0, 0, 0, 0);
+    let mut contexts = Vec::new();
+
+    for (line_no, raw) in lines {
+        // 每一行按一次生成请求计数
+        metrics.record_request();
+        let machine_code = finalshell::canonicalize(raw);
+        if !finalshell::is_valid(&machine_code) {
+            invalid += 1;
+            output.push_str(&batch_skip_line(line_no, "机器码格式错误，已跳过", raw));
+            continue;
+        }
+
+        // 突发限制按行检查，一小时内的名额用完后其余行不再生成
+        let now = config.clock.now_utc();
+        if database::hourly_slot_opens_at(&db, user_id, &quota, config.max_requests_per_hour, now).await.map_err(db_error)?.is_some() {
+            skipped += 1;
+            output.push_str(&batch_skip_line(line_no, "一小时内生成次数已达上限，未生成", raw));
+            continue;
+        }
+
+        // 每个机器码单独预扣配额，配额用尽后其余行不再生成
+        let Some(reservation) = reserve_generation(&bot, &config, &db, &db_user, quota, &machine_code).await? else {
+            skipped += 1;
+            output.push_str(&batch_skip_line(line_no, "使用次数已达上限，未生成", raw));
+            continue;
+        };
+
+        let started = Instant::now();
+        let results = match backend.generate(&machine_code, &config.display_versions()).await {
+            Ok(results) => results,
+            Err(e) => {
+                error!("批量生成激活码失败 (后端: {}): {:#}", backend.name(), e);
+                metrics.record_failure();
+                release_reservation(&config, &db, user_id, degraded_key(&quota, &machine_code), reservation).await;
+                record_failure(&db, correlation_id, user_id, "generate", &format!("第 {} 行: {:#}", line_no, e)).await;
+                failed += 1;
+                output.push_str(&batch_skip_line(line_no, &format!("生成失败 (错误码: {})，已跳过", correlation_id), raw));
+                continue;
+            }
+        };
+
+        let committed = commit_reservation(
+            &bot,
+            &config,
+            &db,
+            &db_user,
+            quota,
+            reservation,
+            correlation_id,
+            &machine_code,
+            user.language_code.as_deref(),
+            &results,
+        ).await?;
+        if committed.is_none() {
+            skipped += 1;
+            output.push_str(&batch_skip_line(line_no, "使用次数已达上限，未生成", raw));
+            continue;
+        }
+
+        generated += 1;
+        metrics.record_generated();
+        contexts.push(GenerationContext::new(
+            user_id,
+            correlation_id,
+            "batch",
+            &machine_code,
+            config.machine_code_display,
+            &results,
+            started.elapsed(),
+        ));
+        output.push_str(&ActivationCodeGenerator::format_results_plain(&machine_code, &results, db_user.lang(config.default_lang)));
+        output.push('\n');
+    }
+
+    let summary = format!(
+        "📄 批量生成完成\n✅ 成功: {}\n❌ 格式错误: {}\n⚠️ 生成失败: {}\n⛔ 超出配额: {}",
+        generated, invalid, failed, skipped
+    );
+
+    let mut request = bot
+        .send_document(msg.chat.id, InputFile::memory(output.into_bytes()).file_name("activation_codes.txt"))
+        .caption(config.render(summary));
+    if let Some(thread_id) = topic_thread_id(&msg) {
+        request = request.message_thread_id(thread_id);
+    }
+    request.await?;
+
+    info!(
+        "用户 {} 批量生成激活码: 成功 {}，格式错误 {}，生成失败 {}，超出配额 {}",
+        user_id, generated, invalid, failed, skipped
+    );
+
+    if generated > 0 {
+        if let Err(e) = abuse::check_user(&config, &db, user_id).await {
+            error!("滥用检测失败: {}", e);
+        }
+    }
+    for ctx in contexts {
+        hooks.spawn(ctx);
+    }
+    Ok(())
+}
+
+/// 激活结果下方的反馈按钮：首行为本次结果的"激活成功/失败"回执（写入日志时才有），
+/// 其后为各版本的"激活失败"反馈，每个版本一个
+fn report_keyboard(config: &Config, machine_code: &str, log_id: Option<i64>) -> InlineKeyboardMarkup {
+    let fingerprint = ActivationCodeGenerator::machine_code_fingerprint(machine_code);
+    let buttons: Vec<_> = config
+        .display_versions()
+        .iter()
+        .map(|version| {
+            let name = version.version_name_ascii();
+            InlineKeyboardButton::callback(
+                config.render(format!("❌ {} 激活失败？", name)),
+                format!("report:{}:{}", name, fingerprint),
+            )
+        })
+        .collect();
+
+    let mut rows: Vec<_> = log_id.map(|id| outcome_row(config, id)).into_iter().collect();
+    if let Some(id) = log_id.filter(|_| config.result_links) {
+        rows.push(vec![InlineKeyboardButton::callback(config.render("📲 在其他设备打开"), format!("link:{}", id))]);
+    }
+    rows.extend(buttons.chunks(2).map(|row| row.to_vec()));
+    if let Some(row) = footer_keyboard_row(config) {
+        rows.push(row);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+fn outcome_row(config: &Config, log_id: i64) -> Vec<InlineKeyboardButton> {
+    [(ActivationOutcome::Success, "✅ 激活成功"), (ActivationOutcome::Failure, "⚠️ 激活失败")]
+        .into_iter()
+        .map(|(outcome, label)| {
+            InlineKeyboardButton::callback(config.render(label), format!("outcome:{}:{}", outcome.as_str(), log_id))
+        })
+        .collect()
+}
+
+/// FOOTER_STYLE=keyboard 时的链接按钮行，未配置链接时为 None
+fn footer_keyboard_row(config: &Config) -> Option<Vec<InlineKeyboardButton>> {
+    if config.footer_style != FooterStyle::Keyboard || config.footer_links.is_empty() {
+        return None;
+    }
+    Some(footer::keyboard_row(&config.footer_links))
+}
+
+/// FOOTER_STYLE=text 时追加到 MarkdownV2 消息末尾的链接，未配置链接时为空
+fn footer_text(config: &Config) -> String {
+    match config.footer_style {
+        FooterStyle::Text => footer::render_markdown_v2(&config.footer_links),
+        FooterStyle::Keyboard => String::new(),
+    }
+}
+
+/// 处理"激活失败"反馈：记录并在同一版本短时间内反馈过多时告警管理员
+async fn handle_code_report(bot: Bot, q: CallbackQuery, config: Config, db: Database, payload: &str) -> ResponseResult<()> {
+    let user_id = q.from.id.0 as i64;
+
+    let parsed = payload
+        .split_once(':')
+        .and_then(|(version, hash)| FinalShellVersionType::from_name_ascii(version).map(|v| (v, hash)));
+    let Some((version, machine_code_hash)) = parsed else {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+        return Ok(());
+    };
+    let version_name = version.version_name_ascii();
+
+    let created = database::create_code_report(&db, user_id, machine_code_hash, version_name)
+        .await
+        .map_err(db_error)?;
+    edit_or_ignore(
+        bot.answer_callback_query(q.id).text(config.render("🙏 感谢反馈，我们会尽快排查该版本的激活问题。")),
+    )
+    .await?;
+
+    if !created {
+        return Ok(());
+    }
+    info!("用户 {} 反馈 {} 激活失败", user_id, version_name);
+
+    let since = config.clock.now_utc() - chrono::Duration::days(1);
+    let count = database::count_code_reports_since(&db, version_name, since)
+        .await
+        .map_err(db_error)?;
+    // 仅在刚好越过阈值时告警一次，避免后续每条反馈都重复提醒
+    if count == config.code_report_alert_threshold + 1 {
+        let message = format!(
+            "⚠️ 过去 24 小时内已有 {} 位用户反馈 FinalShell {} 激活码无效，算法可能已失效，请尽快核实。\n📋 使用 /reports 查看详情",
+            count,
+            version.version_name_localized(config.default_lang)
+        );
+        if let Err(e) = crate::guard::send_alert(&config, &message).await {
+            error!("发送激活失败告警失败: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// 记录用户对本次生成结果的使用回执，可重复点击改选
+async fn handle_activation_outcome(bot: Bot, q: CallbackQuery, config: Config, db: Database, payload: &str) -> ResponseResult<()> {
+    let user_id = q.from.id.0 as i64;
+
+    let parsed = payload
+        .split_once(':')
+        .and_then(|(outcome, id)| Some((ActivationOutcome::from_name(outcome)?, id.parse::<i64>().ok()?)));
+    let Some((outcome, log_id)) = parsed else {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+        return Ok(());
+    };
+
+    let recorded = match database::set_activation_outcome(&db, log_id, user_id, outcome).await {
+        Ok(recorded) => recorded,
+        Err(e) if database::is_write_unavailable(&e) => {
+            enter_degraded(&bot, &config, &e).await;
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("⚠️ 系统维护中，请稍后再试。"))).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(db_error(e)),
+    };
+    let notice = match (recorded, outcome) {
+        (false, _) => "ℹ️ 该记录已过期，无法反馈。",
+        (true, ActivationOutcome::Success) => "🎉 感谢反馈，祝使用愉快！",
+        (true, ActivationOutcome::Failure) => "🙏 感谢反馈，可点击下方对应版本的按钮告诉我们哪个版本无效。",
+    };
+    edit_or_ignore(bot.answer_callback_query(q.id).text(config.render(notice))).await?;
+    if recorded {
+        info!("用户 {} 反馈激活日志 #{}: {}", user_id, log_id, outcome.as_str());
+    }
+
+    Ok(())
+}
+
+/// 为用户自己的一次生成结果创建一次性深链接，结果加密后保存到链接被打开或过期
+async fn handle_result_link(bot: Bot, q: CallbackQuery, config: Config, db: Database, payload: &str) -> ResponseResult<()> {
+    let user_id = q.from.id.0 as i64;
+    if !config.result_links {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("ℹ️ 该功能未开启。"))).await?;
+        return Ok(());
+    }
+    let Ok(log_id) = payload.parse::<i64>() else {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+        return Ok(());
+    };
+    let Some((machine_code, results)) = database::get_activation_result(&db, log_id, user_id).await.map_err(db_error)? else {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("ℹ️ 该记录已过期，无法生成链接。"))).await?;
+        return Ok(());
+    };
+
+    let lang = database::get_user_by_id(&db, user_id).await.map_err(db_error)?.lang(config.default_lang);
+    let text = ActivationCodeGenerator::format_results_plain(&machine_code, &results, lang);
+    let sealed = result_link::new_token().and_then(|token| Ok((result_link::seal(&token, &text)?, token)));
+    let (sealed, token) = match sealed {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            error!("加密一次性结果失败: {}", e);
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 生成链接失败，请稍后再试。"))).await?;
+            return Ok(());
+        }
+    };
+    let now = config.clock.now_utc();
+    let expires_at = now + chrono::Duration::minutes(result_link::TTL_MINUTES);
+    match database::store_pending_result(&db, &result_link::token_hash(&token), user_id, &sealed, now, expires_at).await {
+        Ok(()) => {}
+        Err(e) if database::is_write_unavailable(&e) => {
+            enter_degraded(&bot, &config, &e).await;
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("⚠️ 系统维护中，请稍后再试。"))).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(db_error(e)),
+    }
+
+    let me = bot.get_me().await?;
+    edit_or_ignore(bot.answer_callback_query(q.id)).await?;
+    bot.send_message(
+        q.from.id,
+        config.render(format!(
+            "🔗 在其他设备上打开以下链接即可查看本次结果，{} 分钟内有效，只能打开一次:\n{}",
+            result_link::TTL_MINUTES,
+            result_link::deep_link(me.username(), &token)
+        )),
+    )
+    .disable_web_page_preview(true)
+    .await?;
+    info!("用户 {} 为激活日志 #{} 创建了一次性结果链接", user_id, log_id);
+    Ok(())
+}
+
+/// `/start r_<令牌>`：取出链接对应的结果并发送，链接随即失效
+async fn deliver_result_link(bot: &Bot, msg: &Message, config: &Config, db: &Database, token: &str) -> ResponseResult<()> {
+    let taken = database::take_pending_result(db, &result_link::token_hash(token)).await.map_err(db_error)?;
+    let text = match taken {
+        Some((_, expires_at)) if expires_at <= config.clock.now_utc() => {
+            format!("⌛ 该结果链接已过期（有效期 {} 分钟），请在原设备上重新生成链接。", result_link::TTL_MINUTES)
+        }
+        Some((sealed, _)) => match result_link::open(token, &sealed) {
+            Some(result) => format!("{}\n\n🔒 该链接已失效，再次打开不会显示结果。", result),
+            None => "❌ 该结果链接无效，请在原设备上重新生成链接。".to_string(),
+        },
+        None => "❌ 该结果链接已被使用或已过期，每个链接只能打开一次。请在原设备上重新生成链接。".to_string(),
+    };
+    reply(bot, msg, config.render(text)).await?;
+    Ok(())
+}
+
+/// 通过 upload 模块下载并校验文本文件，读取内容后临时文件随即删除
+async fn read_text_upload(bot: &Bot, config: &Config, document: &Document, max_size: u64) -> Result<String, upload::UploadError> {
+    let result = async {
+        let file = upload::download_document(bot, document, &config.upload_dir(), max_size, upload::UploadKind::Text).await?;
+        file.read_to_string().await
+    }
+    .await;
+    if let Err(e) = &result {
+        warn!("处理上传文件失败: {}", e);
+    }
+    result
+}
+
+/// 机器码所在的文字：普通消息与转发消息取正文，图片、文件取说明文字；
+/// 群里回复某条消息并 @机器人 时取被回复消息的文字；被回复的消息通常夹带其他内容，
+/// 整段不是机器码时取其中第一个机器码候选
+fn machine_code_text<'a>(msg: &'a Message, bot_username: &str) -> Option<&'a str> {
+    let Some(quoted) = quoted_message(msg, bot_username) else {
+        return msg.text().or_else(|| msg.caption());
+    };
+    let text = quoted.text().or_else(|| quoted.caption())?;
+    if finalshell::is_valid(&finalshell::canonicalize(text)) {
+        return Some(text);
+    }
+    Some(finalshell::find_candidates(text).into_iter().next().unwrap_or(text))
+}
+
+/// 群聊中回复他人消息并 @机器人 时返回被回复的消息；私聊中不启用
+fn quoted_message<'a>(msg: &'a Message, bot_username: &str) -> Option<&'a Message> {
+    if msg.chat.is_private() {
+        return None;
+    }
+    let mention = format!("@{}", bot_username.to_ascii_lowercase());
+    let text = msg.text().or_else(|| msg.caption())?.to_ascii_lowercase();
+    if !text.split_whitespace().any(|word| word == mention) {
+        return None;
+    }
+    msg.reply_to_message()
+}
+
+/// 群聊中只 @机器人 而没有其他内容（也没有回复其他消息）时回复用法说明
+fn is_bare_mention(msg: &Message, bot_username: &str) -> bool {
+    if msg.chat.is_private() || msg.reply_to_message().is_some() {
+        return false;
+    }
+    msg.text()
+        .is_some_and(|text| text.trim().eq_ignore_ascii_case(&format!("@{}", bot_username)))
+}
+
+/// 文档消息是否按批量文件处理；带说明文字的非文本文件（如截图）按单个机器码处理
+fn is_batch_upload(msg: &Message, document: &Document) -> bool {
+    is_text_document(document) || msg.caption().is_none()
+}
+
+/// 按文件名或 MIME 类型判断是否为纯文本文档
+fn is_text_document(document: &Document) -> bool {
+    let by_name = document
+        .file_name
+        .as_deref()
+        .is_some_and(|name| name.to_ascii_lowercase().ends_with(".txt"));
+    let by_mime = document
+        .mime_type
+        .as_ref()
+        .is_some_and(|mime| mime.essence_str() == "text/plain");
+    by_name || by_mime
+}
+
+/// 提取批量文件中的机器码行（带行号），忽略空行与 # 开头的注释
+fn parse_batch_lines(content: &str) -> Vec<(usize, &str)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// 快速预检配额是否已用完；按机器码计数时，本周期内已提交过的 `machine_code` 不受限
+async fn over_quota(db: &Database, user: &User, quota: &database::Quota, machine_code: Option<&str>) -> ResponseResult<bool> {
+    if quota.unlimited || database::quota_used(db, user, quota).await.map_err(db_error)? < quota.limit {
+        return Ok(false);
+    }
+    match machine_code {
+        Some(code) => Ok(!database::machine_code_counted(db, user.user_id, quota, code).await.map_err(db_error)?),
+        None => Ok(true),
+    }
+}
+
+/// 回复配额已用尽并自动拉黑（并发请求下只会拉黑一次）
+async fn reject_over_limit(bot: &Bot, msg: &Message, config: &Config, db: &Database, metrics: &Metrics, user_id: i64) -> ResponseResult<()> {
+    // 按日/按月重置的配额到期自动恢复，不拉黑
+    if let Some(reset) = config.quota_period.next_reset(config.clock.now_utc(), config.timezone()) {
+        reply(
+            bot,
+            msg,
+            config.render(format!(
+                "❌ 本周期的使用次数已用完 ({})，将于 {} 重置。",
+                config.quota_period.describe(config.max_user_requests, config.quota_mode),
+                format::fmt_datetime(&reset, config.default_lang, config.timezone())
+            ))
+        ).await?;
+        return Ok(());
+    }
+
+    reply(
+        bot,
+        msg,
+        config.render(format!(
+            "❌ 您的使用次数已达上限 ({} {})。请联系管理员。",
+            config.max_user_requests,
+            config.quota_mode.unit()
+        ))
+    ).await?;
+
+    // 自动拉黑
+    match database::auto_ban_user(db, user_id, "使用次数达到上限").await {
+        Ok(true) => {
+            metrics.record_bans(1);
+            info!("用户 {} 使用次数达到上限，已自动拉黑", user_id);
+        }
+        Ok(false) => {}
+        Err(e) => error!("自动拉黑用户失败: {}", e),
+    }
+
+    Ok(())
+}
+
+async fn stats(bot: Bot, msg: Message, config: Config, db: Database, instance_id: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    if let Some(days) = instance_id.trim().strip_prefix("history") {
+        return stats_history(&bot, &msg, &config, &db, days).await;
+    }
+
+    let (machine_readable, instance_filter) = parse_stats_args(&instance_id);
+
+    match database::get_system_stats(&db, instance_filter).await {
+        Ok(stats) => {
+            // 按实例过滤时只显示该实例的数据，否则附带实例分布与语言分布
+            let (instances, languages, copy_variants) = match instance_filter {
+                Some(_) => (Vec::new(), Vec::new(), Vec::new()),
+                None => (
+                    instance_rows(&db).await,
+                    language_rows(&db).await,
+                    copy_variant_rows(&db, &config.copy_experiment).await,
+                ),
+            };
+            let card = StatsCard {
+                instance: instance_filter.map(str::to_string),
+                generated_at: format::fmt_datetime(&stats.created_at, config.default_lang, config.timezone()),
+                stats,
+                instances,
+                languages,
+                copy_experiment: (!copy_variants.is_empty()).then(|| (config.copy_experiment.clone(), copy_variants)),
+                outcome: outcome_summary(&db, instance_filter).await,
+            };
+
+            if machine_readable {
+                // 机读输出不经过 render，避免去除装饰时改动字段值
+                reply(&bot, &msg, card.render_json()).await?;
+                return Ok(());
+            }
+
+            match config.stats_format {
+                StatsFormat::Text => {
+                    reply(&bot, &msg, config.render(card.render_text())).await?;
+                }
+                StatsFormat::Html => {
+                    reply(&bot, &msg, config.render(card.render_html()))
+                        .parse_mode(ParseMode::Html)
+                        .await?;
+                }
+            }
+        }
+        Err(e) => {
+            error!("获取统计信息失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 获取统计信息失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// /stats history [天数]：每天的激活次数、独立用户与版本分布，含已汇总删除原始日志的日期
+async fn stats_history(bot: &Bot, msg: &Message, config: &Config, db: &Database, days: &str) -> ResponseResult<()> {
+    let days = match days.trim() {
+        "" => STATS_HISTORY_DAYS,
+        days => match days.parse::<i64>() {
+            Ok(days) if (1..=MAX_STATS_HISTORY_DAYS).contains(&days) => days,
+            _ => {
+                reply(bot, msg, config.render(format!("❌ 天数范围 1-{}。用法: /stats history [天数]", MAX_STATS_HISTORY_DAYS))).await?;
+                return Ok(());
+            }
+        },
+    };
+
+    let since = config.clock.now_utc() - chrono::Duration::days(days - 1);
+    let activity = match database::get_daily_activity(db, since, config.timezone()).await {
+        Ok(activity) => activity,
+        Err(e) => {
+            error!("查询每日激活统计失败: {}", e);
+            reply(bot, msg, config.render("❌ 查询每日统计失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    for chunk in support::split_message(&config.render(render_daily_activity(days, &activity)), support::MESSAGE_LIMIT) {
+        reply(bot, msg, chunk).await?;
+    }
+    Ok(())
+}
+
+fn render_daily_activity(days: i64, activity: &[DailySummary]) -> String {
+    if activity.is_empty() {
+        return format!("📝 最近 {} 天没有激活记录。", days);
+    }
+    let mut response = format!("📈 最近 {} 天激活趋势\n", days);
+    for summary in activity {
+        let versions = summary
+            .versions
+            .iter()
+            .map(|(version, count)| format!("{} {}", version, format::fmt_count(*count)))
+            .collect::<Vec<_>>()
+            .join(" / ");
+        response.push_str(&format!(
+            "\n{} · 激活 {} · 用户 {}\n  {}",
+            summary.day,
+            format::fmt_count(summary.activations),
+            format::fmt_count(summary.distinct_users),
+            versions
+        ));
+    }
+    response
+}
+
+/// 解析 /stats 的参数：`[json] [实例]`，返回是否输出 JSON 与实例过滤条件
+fn parse_stats_args(args: &str) -> (bool, Option<&str>) {
+    let args = args.trim();
+    let (machine_readable, rest) = match args.split_once(char::is_whitespace) {
+        Some((first, rest)) if first.eq_ignore_ascii_case("json") => (true, rest.trim()),
+        None if args.eq_ignore_ascii_case("json") => (true, ""),
+        _ => (false, args),
+    };
+    (machine_readable, (!rest.is_empty()).then_some(rest))
+}
+
+/// /stats 的内容，按 STATS_FORMAT 渲染为纯文本或 HTML 卡片，或按 json 参数输出机读格式
+struct StatsCard {
+    /// 按实例过滤时的实例 ID
+    instance: Option<String>,
+    stats: SystemStats,
+    /// 各实例的激活次数，只有一个实例时为空
+    instances: Vec<(String, i64)>,
+    /// 语言分布，前几种之外合并为"其他"
+    languages: Vec<(String, i64)>,
+    /// 文案实验名与各分组的转化情况，没有分组记录时为空
+    copy_experiment: Option<(String, Vec<CopyVariantStats>)>,
+    /// 生成结果的整体使用反馈，尚无任何反馈时为 None
+    outcome: Option<OutcomeStats>,
+    generated_at: String,
+}
+
+impl StatsCard {
+    fn render_text(&self) -> String {
+        let scope = match &self.instance {
+            Some(instance_id) => format!("🏷️ 实例: {}\n", instance_id),
+            None => String::new(),
+        };
+        let mut deactivated = match self.stats.deactivated_users {
+            0 => String::new(),
+            count => format!("💤 已注销用户: {}\n", format::fmt_count(count)),
+        };
+        if let Some(line) = self.blocked_line() {
+            deactivated.push_str(&line);
+            deactivated.push('\n');
+        }
+        let mut extra_sections = String::new();
+        for (title, rows) in [("🏷️ 实例分布 (激活次数):", &self.instances), ("🌍 语言分布:", &self.languages)] {
+            if rows.is_empty() {
+                continue;
+            }
+            extra_sections.push_str(title);
+            extra_sections.push('\n');
+            for (name, count) in rows {
+                extra_sections.push_str(&format!("┣━ {}: {}\n", name, format::fmt_count(*count)));
+            }
+            extra_sections.push('\n');
+        }
+        if let Some((name, variants)) = &self.copy_experiment {
+            extra_sections.push_str(&format!("🧪 文案实验 ({}):\n", name));
+            for variant in variants {
+                extra_sections.push_str(&format!(
+                    "┣━ {}: {} 人, 转化 {} ({:.1}%)\n",
+                    variant.variant,
+                    format::fmt_count(variant.users),
+                    format::fmt_count(variant.converted),
+                    variant.conversion_rate()
+                ));
+            }
+            extra_sections.push('\n');
+        }
+        if let Some(row) = &self.outcome {
+            extra_sections.push_str(&format!(
+                "✅ 激活反馈成功率: {} (成功 {}, 失败 {}, 未反馈 {})\n\n",
+                format_success_rate(row),
+                format::fmt_count(row.success),
+                format::fmt_count(row.failure),
+                format::fmt_count(row.unknown)
+            ));
+        }
+
+        format!(
+            "╔══════════════════════════════════════╗\n\
+             ║         📊 系统统计信息 📊         ║\n\
+             ╚══════════════════════════════════════╝\n\n\
+             {}\
+             👥 总用户数: {}\n\
+             🔑 总激活次数: {}\n\
+             📅 今日活跃用户: {}\n\
+             🎯 今日激活次数: {}\n\
+             {}\
+             💚 系统状态: {}\n\n\
+             {}\
+             🕒 统计时间: {}",
+            scope,
+            format::fmt_count(self.stats.total_users),
+            format::fmt_count(self.stats.total_activations),
+            format::fmt_count(self.stats.active_users_today),
+            format::fmt_count(self.stats.activations_today),
+            deactivated,
+            self.stats.system_status,
+            extra_sections,
+            self.generated_at
+        )
+    }
+
+    /// 屏蔽情况，本周无人屏蔽且当前无人处于屏蔽状态时为 None
+    fn blocked_line(&self) -> Option<String> {
+        let stats = &self.stats;
+        (stats.blocked_users > 0 || stats.blocked_this_week > 0).then(|| {
+            format!(
+                "⛔ 本周被 {} 人屏蔽 (当前屏蔽 {} 人)",
+                format::fmt_count(stats.blocked_this_week),
+                format::fmt_count(stats.blocked_users)
+            )
+        })
+    }
+
+    /// 适合转发的 HTML 卡片：不含装饰边框，关键数字加粗，动态内容全部转义
+    fn render_html(&self) -> String {
+        let bold = |value: i64| format!("<b>{}</b>", format::fmt_count(value));
+        let mut card = "📊 <b>系统统计</b>\n".to_string();
+        if let Some(instance_id) = &self.instance {
+            card.push_str(&format!("🏷️ 实例: <code>{}</code>\n", format::escape_html(instance_id)));
+        }
+        card.push_str(&format!(
+            "\n👥 总用户数: {}\n🔑 总激活次数: {}\n📅 今日活跃用户: {}\n🎯 今日激活次数: {}\n💚 系统状态: {}\n",
+            bold(self.stats.total_users),
+            bold(self.stats.total_activations),
+            bold(self.stats.active_users_today),
+            bold(self.stats.activations_today),
+            format::escape_html(&self.stats.system_status)
+        ));
+        if self.stats.deactivated_users > 0 {
+            card.push_str(&format!("💤 已注销用户: {}\n", bold(self.stats.deactivated_users)));
+        }
+        if let Some(line) = self.blocked_line() {
+            card.push_str(&format::escape_html(&line));
+            card.push('\n');
+        }
+        for (title, rows) in [("🏷️ <b>实例分布</b> (激活次数)", &self.instances), ("🌍 <b>语言分布</b>", &self.languages)] {
+            if rows.is_empty() {
+                continue;
+            }
+            card.push_str(&format!("\n{}\n", title));
+            for (name, count) in rows {
+                card.push_str(&format!("┣━ {}: {}\n", format::escape_html(name), bold(*count)));
+            }
+        }
+        if let Some((name, variants)) = &self.copy_experiment {
+            card.push_str(&format!("\n🧪 <b>文案实验</b> ({})\n", format::escape_html(name)));
+            for variant in variants {
+                card.push_str(&format!(
+                    "┣━ {}: {} 人, 转化 {} ({:.1}%)\n",
+                    format::escape_html(&variant.variant),
+                    bold(variant.users),
+                    bold(variant.converted),
+                    variant.conversion_rate()
+                ));
+            }
+        }
+        if let Some(row) = &self.outcome {
+            card.push_str(&format!(
+                "\n✅ <b>激活反馈成功率</b>: {} (成功 {}, 失败 {}, 未反馈 {})\n",
+                format_success_rate(row),
+                bold(row.success),
+                bold(row.failure),
+                bold(row.unknown)
+            ));
+        }
+        card.push_str(&format!("\n<i>🕒 {}</i>", format::escape_html(&self.generated_at)));
+        card
+    }
+
+    /// 供监控脚本解析的 JSON；字段名固定，新增字段只追加不改名
+    fn render_json(&self) -> String {
+        let instances: Vec<_> = self
+            .instances
+            .iter()
+            .map(|(instance_id, activations)| serde_json::json!({ "instance_id": instance_id, "activations": activations }))
+            .collect();
+        let languages: Vec<_> = self
+            .languages
+            .iter()
+            .map(|(language, users)| serde_json::json!({ "language": language, "users": users }))
+            .collect();
+
+        serde_json::to_string_pretty(&serde_json::json!({
+            "instance_id": self.instance,
+            "total_users": self.stats.total_users,
+            "total_activations": self.stats.total_activations,
+            "active_users_today": self.stats.active_users_today,
+            "activations_today": self.stats.activations_today,
+            "deactivated_users": self.stats.deactivated_users,
+            "blocked_users": self.stats.blocked_users,
+            "blocked_this_week": self.stats.blocked_this_week,
+            "system_status": self.stats.system_status,
+            "generated_at": self.stats.created_at.to_rfc3339(),
+            "instances": instances,
+            "languages": languages,
+            "copy_experiment": self.copy_experiment.as_ref().map(|(name, variants)| {
+                serde_json::json!({ "name": name, "variants": variants })
+            }),
+            "outcome": self.outcome,
+        }))
+        .unwrap_or_default()
+    }
+}
+
+fn format_success_rate(row: &OutcomeStats) -> String {
+    match row.success_rate() {
+        Some(rate) => format!("{:.1}%", rate),
+        None => "-".to_string(),
+    }
+}
+
+/// /stats 中的整体激活反馈；还没有任何用户反馈或查询失败时为 None
+async fn outcome_summary(db: &Database, instance_id: Option<&str>) -> Option<OutcomeStats> {
+    match database::get_outcome_stats(db, instance_id).await {
+        Ok(row) if row.success + row.failure > 0 => Some(row),
+        Ok(_) => None,
+        Err(e) => {
+            error!("获取激活反馈统计失败: {}", e);
+            None
+        }
+    }
+}
+
+/// /stats 中当前文案实验各分组的转化情况；查询失败时为空
+async fn copy_variant_rows(db: &Database, experiment: &str) -> Vec<CopyVariantStats> {
+    match database::get_copy_variant_stats(db, experiment).await {
+        Ok(variants) => variants,
+        Err(e) => {
+            error!("获取文案实验统计失败: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// /stats 中各实例的激活次数；只有一个实例或查询失败时为空
+async fn instance_rows(db: &Database) -> Vec<(String, i64)> {
+    match database::get_instance_distribution(db).await {
+        Ok(distribution) if distribution.len() >= 2 => distribution,
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            error!("获取实例分布失败: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// /stats 中的语言分布，按人数列出前几种语言，其余合并为"其他"；查询失败时为空
+async fn language_rows(db: &Database) -> Vec<(String, i64)> {
+    let distribution = match database::get_language_distribution(db).await {
+        Ok(distribution) => distribution,
+        Err(e) => {
+            error!("获取语言分布失败: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut rows = Vec::new();
+    let mut other = 0;
+    for (index, (language_code, count)) in distribution.into_iter().enumerate() {
+        if index >= LANGUAGE_STATS_LIMIT {
+            other += count;
+            continue;
+        }
+        rows.push((language_code.unwrap_or_else(|| "未知".to_string()), count));
+    }
+    if other > 0 {
+        rows.push(("其他".to_string(), other));
+    }
+    rows
+}
+
+async fn users(bot: Bot, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    match database::get_all_users(&db).await {
+        Ok(users) => {
+            if users.is_empty() {
+                reply(&bot, &msg, config.render("📝 暂无用户数据。")).await?;
+                return Ok(());
+            }
+
+            let mut response = String::from(
+                "╔══════════════════════════════════════╗\n\
+                 ║           👥 用户列表 👥           ║\n\
+                 ╚══════════════════════════════════════╝\n\n"
+            );
+
+            let now = config.clock.now_utc();
+            for (index, user) in users.iter().enumerate().take(20) {
+                let status = if user.is_banned {
+                    "🚫 已封禁"
+                } else if user.deactivated_at.is_some() {
+                    "💤 账号已注销"
+                } else if user.blocked_at.is_some() {
+                    "⛔ 已屏蔽机器人"
+                } else {
+                    "✅ 正常"
+                };
+                let last_request = user.last_request
+                    .map(|dt| format::fmt_relative(&dt, &now, config.default_lang))
+                    .unwrap_or_else(|| "从未使用".to_string());
+                // 账号已注销的用户不计入活跃
+                let activity = match user.deactivated_at {
+                    Some(_) => Activity::Churned,
+                    None => Activity::classify(user.last_request, now, config.active_user_days, config.dormant_user_days),
+                };
+
+                response.push_str(&user_list_entry(index, user, activity, &last_request, status));
+            }
+
+            if users.len() > 20 {
+                response.push_str(&format!("... 共 {} 个用户，仅显示前20个", users.len()));
+            }
+
+            reply(&bot, &msg, config.render(response)).await?;
+        }
+        Err(e) => {
+            error!("获取用户列表失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 获取用户列表失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// /users 中的一条用户记录
+fn user_list_entry(index: usize, user: &UserStats, activity: Activity, last_request: &str, status: &str) -> String {
+    format!(
+        "{}. {} ({}) {}\n\
+         • ID: {}\n\
+         • 请求次数: {}\n\
+         • 最后使用: {}\n\
+         • 状态: {}\n\n",
+        index + 1,
+        user.username
+            .as_deref()
+            .map_or_else(|| "无用户名".to_string(), |name| format::safe_user_text(name, USER_NAME_DISPLAY_LIMIT, None)),
+        user.user_id,
+        activity.label(),
+        user.user_id,
+        user.total_requests,
+        last_request,
+        status
+    )
+}
+
+/// 解析批量管理命令的参数：开头以空格或逗号分隔的数字为用户 ID（去重），其余部分原样返回
+fn parse_target_ids(args: &str) -> (Vec<i64>, Vec<&str>) {
+    let mut ids = Vec::new();
+    let mut tokens = args.split_whitespace().peekable();
+    while let Some(token) = tokens.peek() {
+        let parsed: Option<Vec<i64>> = token
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().ok())
+            .collect();
+        let Some(parsed) = parsed else {
+            break;
+        };
+        for id in parsed {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        tokens.next();
+    }
+    (ids, tokens.collect())
+}
+
+/// 批量管理命令的逐个结果，`found` 与 `ids` 一一对应
+fn batch_result_lines(ids: &[i64], found: &[bool], done: &str) -> String {
+    let mut lines = String::new();
+    for (index, (id, found)) in ids.iter().zip(found).enumerate() {
+        let branch = if index + 1 == ids.len() { "┗━" } else { "┣━" };
+        let status = if *found { format!("✅ {}", done) } else { "❓ 用户不存在".to_string() };
+        lines.push_str(&format!("{} {}: {}\n", branch, id, status));
+    }
+    lines
+}
+
+/// 校验批量目标：为空或超出上限时回复用法并返回 true
+async fn reject_target_ids(bot: &Bot, msg: &Message, config: &Config, ids: &[i64], usage: &str) -> ResponseResult<bool> {
+    if ids.is_empty() {
+        reply(bot, msg, config.render(format!("❌ 用户ID格式错误。用法: {}", usage))).await?;
+        return Ok(true);
+    }
+    if ids.len() > MAX_BATCH_TARGETS {
+        reply(bot, msg, config.render(format!("❌ 一次最多处理 {} 个用户，当前 {} 个。", MAX_BATCH_TARGETS, ids.len()))).await?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// 管理命令参数开头的单个目标：数字 ID 或 @用户名
+fn is_target_piece(piece: &str) -> bool {
+    piece.parse::<i64>().is_ok() || piece.strip_prefix('@').is_some_and(|name| !name.is_empty())
+}
+
+/// 替换 @用户名 之后的管理命令参数
+#[derive(Debug, Default, PartialEq)]
+struct ResolvedTargets {
+    args: String,
+    /// 没有记录的用户名
+    missing: Vec<String>,
+    /// 记录过旧的用户名，及其对应的用户 ID 与距最近一次看到的天数
+    stale: Vec<(String, i64, i64)>,
+}
+
+/// 将参数开头目标中的 @用户名 按已记录的用户名（不区分大小写）替换为用户 ID，其余部分原样保留
+async fn resolve_usernames(db: &Database, now: chrono::DateTime<chrono::Utc>, args: &str) -> Result<ResolvedTargets> {
+    let mut resolved = ResolvedTargets::default();
+    let mut targets = Vec::new();
+    let mut tokens = args.split_whitespace().peekable();
+    while let Some(token) = tokens.next_if(|token| token.split(',').filter(|s| !s.is_empty()).all(is_target_piece)) {
+        let mut pieces = Vec::new();
+        for piece in token.split(',').filter(|s| !s.is_empty()) {
+            if !piece.starts_with('@') {
+                pieces.push(piece.to_string());
+                continue;
+            }
+            match database::find_user_by_username(db, piece).await? {
+                Some((user_id, seen_at)) => {
+                    let days = (now - seen_at).num_days();
+                    if days > USERNAME_STALE_DAYS {
+                        resolved.stale.push((piece.to_string(), user_id, days));
+                    }
+                    pieces.push(user_id.to_string());
+                }
+                None => resolved.missing.push(piece.to_string()),
+            }
+        }
+        if !pieces.is_empty() {
+            targets.push(pieces.join(","));
+        }
+    }
+    targets.extend(tokens.map(str::to_string));
+    resolved.args = targets.join(" ");
+    Ok(resolved)
+}
+
+/// 解析管理命令参数中的 @用户名：有用户名找不到时回复错误并返回 None，
+/// 否则返回替换后的参数，以及用户名记录过旧时给管理员的提醒
+async fn resolve_target_args(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    db: &Database,
+    args: &str,
+) -> ResponseResult<Option<(String, Option<String>)>> {
+    let resolved = resolve_usernames(db, config.clock.now_utc(), args).await.map_err(db_error)?;
+    if !resolved.missing.is_empty() {
+        let names: Vec<String> = resolved
+            .missing
+            .iter()
+            .map(|name| format::safe_user_text(name, USER_NAME_DISPLAY_LIMIT, None))
+            .collect();
+        reply(
+            bot,
+            msg,
+            config.render(format!(
+                "❌ 找不到用户名 {}。用户需至少与机器人交互过一次（如发送 /start）才能按用户名查找，也可以直接使用用户 ID。",
+                names.join(", ")
+            )),
+        ).await?;
+        return Ok(None);
+    }
+    let caution = resolved
+        .stale
+        .iter()
+        .map(|(name, user_id, days)| {
+            format!(
+                "⚠️ {} 的用户名记录于 {} 天前，可能已被他人使用，请核对用户 ID {}。",
+                format::safe_user_text(name, USER_NAME_DISPLAY_LIMIT, None),
+                days,
+                user_id
+            )
+        })
+        .collect::<Vec<_>>();
+    let caution = if caution.is_empty() { None } else { Some(caution.join("\n")) };
+    Ok(Some((resolved.args, caution)))
+}
+
+/// 在回复末尾附上用户名过旧的提醒
+fn with_caution(text: String, caution: &Option<String>) -> String {
+    match caution {
+        Some(caution) => format!("{}\n\n{}", text, caution),
+        None => text,
+    }
+}
+
+async fn ban_user(bot: Bot, msg: Message, config: Config, db: Database, metrics: Arc<Metrics>, args: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+    
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    // 参数格式: <ID|@用户名...> [时长，如 7d] [原因]，多个目标以空格或逗号分隔
+    let Some((args, caution)) = resolve_target_args(&bot, &msg, &config, &db, &args).await? else {
+        return Ok(());
+    };
+    let (target_ids, rest) = parse_target_ids(&args);
+    if reject_target_ids(&bot, &msg, &config, &target_ids, "/ban <ID|@用户名...> [时长如 7d] [原因]").await? {
+        return Ok(());
+    }
+
+    let (duration, reason_parts) = match rest.first().and_then(|s| utils::parse_duration(s)) {
+        Some(duration) => (Some(duration), &rest[1..]),
+        None => (None, &rest[..]),
+    };
+    let reason = reason_parts.join(" ");
+    let reason = if reason.is_empty() { None } else { Some(reason) };
+    let banned_until = duration.map(|d| config.clock.now_utc() + d);
+
+    match database::ban_users(&db, &target_ids, reason.as_deref(), banned_until).await {
+        Ok(found) => {
+            metrics.record_bans(found.iter().filter(|found| **found).count() as u64);
+            let until_text = banned_until
+                .map(|dt| format::fmt_datetime(&dt, config.default_lang, config.timezone()))
+                .unwrap_or_else(|| "永久".to_string());
+            let reason_text = reason
+                .as_deref()
+                .map_or_else(|| "未说明".to_string(), |reason| format::safe_user_text(reason, USER_TEXT_DISPLAY_LIMIT, None));
+
+            let text = match (target_ids.as_slice(), found.as_slice()) {
+                ([target], [false]) => format!("❌ 用户 {} 不存在。", target),
+                ([target], [true]) => format!(
+                    "✅ 用户 {} 已被成功拉黑。\n📝 原因: {}\n⏳ 解封时间: {}",
+                    target, reason_text, until_text
+                ),
+                _ => format!(
+                    "✅ 已拉黑 {}/{} 个用户。\n📝 原因: {}\n⏳ 解封时间: {}\n\n{}",
+                    found.iter().filter(|f| **f).count(),
+                    target_ids.len(),
+                    reason_text,
+                    until_text,
+                    batch_result_lines(&target_ids, &found, "已拉黑")
+                ),
+            };
+            reply(&bot, &msg, config.render(with_caution(text, &caution))).await?;
+
+            let detail = format!("原因: {}; 解封时间: {}", reason_text, until_text);
+            for (target_user_id, _) in target_ids.iter().zip(&found).filter(|(_, found)| **found) {
+                info!("管理员 {} 拉黑了用户 {}", admin_user.id.0, target_user_id);
+                if let Err(e) = database::log_admin_action(
+                    &db,
+                    admin_user.id.0 as i64,
+                    "ban",
+                    Some(*target_user_id),
+                    &detail,
+                ).await {
+                    error!("记录审计日志失败: {}", e);
+                }
+            }
+        }
+        Err(e) => {
+            error!("拉黑用户失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 拉黑用户失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn unban_user(bot: Bot, msg: Message, config: Config, db: Database, args: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+    
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some((args, caution)) = resolve_target_args(&bot, &msg, &config, &db, &args).await? else {
+        return Ok(());
+    };
+    let (target_ids, rest) = parse_target_ids(&args);
+    if !rest.is_empty() {
+        reply(&bot, &msg, config.render("❌ 用户ID格式错误。用法: /unban <ID|@用户名...>")).await?;
+        return Ok(());
+    }
+    if reject_target_ids(&bot, &msg, &config, &target_ids, "/unban <ID|@用户名...>").await? {
+        return Ok(());
+    }
+
+    match database::unban_users(&db, &target_ids).await {
+        Ok(found) => {
+            let text = match (target_ids.as_slice(), found.as_slice()) {
+                ([target], [false]) => format!("❌ 用户 {} 不存在。", target),
+                ([target], [true]) => format!("✅ 用户 {} 已被成功解封。", target),
+                _ => format!(
+                    "✅ 已解封 {}/{} 个用户。\n\n{}",
+                    found.iter().filter(|f| **f).count(),
+                    target_ids.len(),
+                    batch_result_lines(&target_ids, &found, "已解封")
+                ),
+            };
+            reply(&bot, &msg, config.render(with_caution(text, &caution))).await?;
+
+            for (target_user_id, _) in target_ids.iter().zip(&found).filter(|(_, found)| **found) {
+                info!("管理员 {} 解封了用户 {}", admin_user.id.0, target_user_id);
+                if let Err(e) = database::log_admin_action(
+                    &db,
+                    admin_user.id.0 as i64,
+                    "unban",
+                    Some(*target_user_id),
+                    "",
+                ).await {
+                    error!("记录审计日志失败: {}", e);
+                }
+            }
+        }
+        Err(e) => {
+            error!("解封用户失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 解封用户失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn trust_user(bot: Bot, msg: Message, config: Config, db: Database, user_id_str: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some((user_id_str, caution)) = resolve_target_args(&bot, &msg, &config, &db, &user_id_str).await? else {
+        return Ok(());
+    };
+    let Ok(target_user_id) = user_id_str.trim().parse::<i64>() else {
+        reply(&bot, &msg, config.render("❌ 用户ID格式错误。用法: /trust <用户ID|@用户名>")).await?;
+        return Ok(());
+    };
+
+    match database::trust_user(&db, target_user_id).await {
+        Ok(false) => {
+            reply(&bot, &msg, config.render(format!("❌ 用户 {} 不存在。", target_user_id))).await?;
+        }
+        Ok(true) => {
+            let text = format!("✅ 用户 {} 已解除试用期限制。", target_user_id);
+            reply(&bot, &msg, config.render(with_caution(text, &caution))).await?;
+            info!("管理员 {} 解除了用户 {} 的试用期", admin_user.id.0, target_user_id);
+
+            if let Err(e) = database::log_admin_action(&db, admin_user.id.0 as i64, "trust", Some(target_user_id), "").await {
+                error!("记录审计日志失败: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("解除试用期失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 解除试用期失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn search_logs(bot: Bot, msg: Message, config: Config, db: Database, keyword: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let keyword = keyword.trim();
+    if keyword.is_empty() {
+        reply(&bot, &msg, config.render("❌ 请提供搜索关键字。用法: /searchlog <机器码或激活码片段>")).await?;
+        return Ok(());
+    }
+
+    match database::search_activation_logs(&db, keyword, SEARCH_LOG_LIMIT).await {
+        Ok(logs) => {
+            if logs.is_empty() {
+                reply(
+                    &bot,
+                    &msg,
+                    config.render(format!("📝 未找到包含 \"{}\" 的激活记录。", format::safe_user_text(keyword, ECHO_DISPLAY_LIMIT, None))),
+                ).await?;
+                return Ok(());
+            }
+
+            let mut response = format!(
+                "🔍 激活记录搜索: \"{}\"\n\
+                 📋 最近 {} 条结果:\n\n",
+                format::safe_user_text(keyword, ECHO_DISPLAY_LIMIT, None),
+                logs.len()
+            );
+
+            for (index, log) in logs.iter().enumerate() {
+                response.push_str(&format!(
+                    "{}. 用户 {}\n\
+                     • 机器码: {}\n\
+                     • 激活码: {}\n\
+                     • 版本: {}\n\
+                     • 时间: {}\n\n",
+                    index + 1,
+                    log.user_id,
+                    finalshell::redact(&log.machine_code, config.machine_code_display),
+                    log.activation_code,
+                    log.finalshell_version,
+                    format::fmt_datetime(&log.created_at, config.default_lang, config.timezone())
+                ));
+            }
+
+            reply(&bot, &msg, config.render(response)).await?;
+        }
+        Err(e) => {
+            error!("搜索激活记录失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 搜索激活记录失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn user_history(bot: Bot, msg: Message, config: Config, db: Database, user_id_str: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some((user_id_str, caution)) = resolve_target_args(&bot, &msg, &config, &db, &user_id_str).await? else {
+        return Ok(());
+    };
+    let target_user_id = match user_id_str.trim().parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => {
+            reply(&bot, &msg, config.render("❌ 用户ID格式错误。用法: /userhistory <用户ID|@用户名>")).await?;
+            return Ok(());
+        }
+    };
+
+    let logs = match database::get_user_activation_logs(&db, target_user_id, USER_HISTORY_LIMIT).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            error!("查询用户 {} 的激活记录失败: {}", target_user_id, e);
+            reply(&bot, &msg, config.render("❌ 查询激活记录失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    if let Err(e) = database::log_admin_action(
+        &db,
+        admin_user.id.0 as i64,
+        "view_user_history",
+        Some(target_user_id),
+        &format!("返回记录: {}", logs.len()),
+    ).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    if logs.is_empty() {
+        let text = format!("📝 用户 {} 暂无激活记录。", target_user_id);
+        reply(&bot, &msg, config.render(with_caution(text, &caution))).await?;
+        return Ok(());
+    }
+
+    let activity = Activity::classify(
+        logs.iter().map(|log| log.created_at).max(),
+        config.clock.now_utc(),
+        config.active_user_days,
+        config.dormant_user_days,
+    );
+    let mut response = format!(
+        "📜 用户 {} 的激活记录\n\
+         🏷️ 活跃度: {}\n\
+         📋 最近 {} 条:\n\n",
+        target_user_id,
+        activity.label(),
+        logs.len()
+    );
+
+    for (index, log) in logs.iter().enumerate() {
+        response.push_str(&format!(
+            "{}. {}\n\
+             • 机器码: {}\n\
+             • 版本: {}\n\n",
+            index + 1,
+            format::fmt_datetime(&log.created_at, config.default_lang, config.timezone()),
+            finalshell::redact(&log.machine_code, config.machine_code_display),
+            log.finalshell_version
+        ));
+    }
+
+    reply(&bot, &msg, config.render(with_caution(response, &caution))).await?;
+    Ok(())
+}
+
+/// /trace <错误码>：按用户反馈的错误码查看该次请求的激活日志、耗时与失败记录
+async fn trace_request(bot: Bot, msg: Message, config: Config, db: Database, id: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some(correlation_id) = correlation::normalize(&id) else {
+        reply(&bot, &msg, config.render("❌ 错误码格式错误，应为 8 位字母数字。用法: /trace <错误码>")).await?;
+        return Ok(());
+    };
+
+    let trace = match database::get_request_trace(&db, &correlation_id).await {
+        Ok(trace) => trace,
+        Err(e) => {
+            error!("查询请求 {} 的记录失败: {}", correlation_id, e);
+            reply(&bot, &msg, config.render("❌ 查询请求记录失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    let target_user_id = trace
+        .activations
+        .first()
+        .map(|log| log.user_id)
+        .or_else(|| trace.failures.first().map(|failure| failure.user_id));
+    if let Err(e) = database::log_admin_action(
+        &db,
+        admin_user.id.0 as i64,
+        "view_trace",
+        target_user_id,
+        &format!("错误码: {}", correlation_id),
+    ).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    reply(&bot, &msg, config.render(render_trace(&config, &correlation_id, &trace))).await?;
+    Ok(())
+}
+
+/// /support <用户ID>：汇总用户资料、配额、生成与失败记录、封禁申诉与反馈，超长时分多条发送
+async fn support_dossier(bot: Bot, msg: Message, config: Config, db: Database, user_id_str: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some((user_id_str, caution)) = resolve_target_args(&bot, &msg, &config, &db, &user_id_str).await? else {
+        return Ok(());
+    };
+    let Ok(target_user_id) = user_id_str.trim().parse::<i64>() else {
+        reply(&bot, &msg, config.render("❌ 用户ID格式错误。用法: /support <用户ID|@用户名>")).await?;
+        return Ok(());
+    };
+
+    let dossier = match support::build_dossier(&db, &config, target_user_id).await {
+        Ok(Some(dossier)) => dossier,
+        Ok(None) => {
+            reply(&bot, &msg, config.render(format!("❌ 用户 {} 不存在。", target_user_id))).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("组装用户 {} 的客服档案失败: {}", target_user_id, e);
+            reply(&bot, &msg, config.render("❌ 查询用户档案失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    if let Err(e) = database::log_admin_action(&db, admin_user.id.0 as i64, "view_support", Some(target_user_id), "").await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    if let Some(caution) = &caution {
+        reply(&bot, &msg, config.render(caution.as_str())).await?;
+    }
+    for chunk in support::split_message(&config.render(dossier.render(&config)), support::MESSAGE_LIMIT) {
+        reply(&bot, &msg, chunk).await?;
+    }
+    Ok(())
+}
+
+fn render_trace(config: &Config, correlation_id: &str, trace: &RequestTrace) -> String {
+    if trace.is_empty() {
+        return format!("📝 未找到错误码 {} 对应的请求记录（记录可能已按保留期清理）。", correlation_id);
+    }
+
+    let mut response = format!("🔖 请求 {}\n", correlation_id);
+    if !trace.activations.is_empty() {
+        response.push_str(&format!("\n✅ 激活记录 ({} 条):\n", trace.activations.len()));
+        for log in &trace.activations {
+            response.push_str(&format!(
+                "• 用户 {} · {}\n  机器码: {} · 版本: {}\n",
+                log.user_id,
+                format::fmt_datetime(&log.created_at, config.default_lang, config.timezone()),
+                finalshell::redact(&log.machine_code, config.machine_code_display),
+                log.finalshell_version
+            ));
+        }
+    }
+    for metric in &trace.metrics {
+        let lag = metric
+            .update_lag_ms
+            .map(|ms| format!("，更新延迟 {}", format::fmt_latency(Duration::from_millis(ms.max(0) as u64))))
+            .unwrap_or_default();
+        response.push_str(&format!(
+            "⏱️ 生成耗时 {}{}\n",
+            format::fmt_latency(Duration::from_micros(metric.duration_us.max(0) as u64)),
+            lag
+        ));
+    }
+    if !trace.failures.is_empty() {
+        response.push_str(&format!("\n❌ 失败记录 ({} 条):\n", trace.failures.len()));
+        for failure in &trace.failures {
+            response.push_str(&format!(
+                "• 用户 {} · {}\n  {}: {}\n",
+                failure.user_id,
+                format::fmt_datetime(&failure.created_at, config.default_lang, config.timezone()),
+                failure.stage_label(),
+                failure.display_detail(config.machine_code_display)
+            ));
+        }
+    }
+    response
+}
+
+async fn flagged_users(bot: Bot, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let flagged = match database::get_flagged_users(&db, FLAGGED_USER_LIMIT).await {
+        Ok(flagged) => flagged,
+        Err(e) => {
+            error!("获取被标记用户失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 获取被标记用户失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    if flagged.is_empty() {
+        reply(&bot, &msg, config.render("✅ 当前没有被标记的用户。")).await?;
+        return Ok(());
+    }
+
+    let now = config.clock.now_utc();
+    let since = now - chrono::Duration::hours(config.abuse_window_hours);
+    let mut response = format!("🚩 被标记的用户 ({} 人)\n\n", flagged.len());
+    let mut buttons = Vec::new();
+    for (index, flagged_user) in flagged.iter().enumerate() {
+        let distinct = database::count_distinct_machine_codes_since(&db, flagged_user.user_id, since)
+            .await
+            .map_err(db_error)?;
+        response.push_str(&format!(
+            "{}. 用户 {} {}\n\
+             • 过去 {} 小时不同机器码: {}\n\
+             • 标记时间: {}\n\n",
+            index + 1,
+            flagged_user.user_id,
+            flagged_user
+                .username
+                .as_deref()
+                .map(|name| format!("(@{})", format::safe_user_text(name, USER_NAME_DISPLAY_LIMIT, None)))
+                .unwrap_or_default(),
+            config.abuse_window_hours,
+            distinct,
+            flagged_user
+                .flagged_at
+                .map(|at| format::fmt_relative(&at, &now, config.default_lang))
+                .unwrap_or_default()
+        ));
+        buttons.push(vec![
+            InlineKeyboardButton::callback(
+                config.render(format!("✅ 解除 {}", flagged_user.user_id)),
+                format!("flag:clear:{}", flagged_user.user_id),
+            ),
+            InlineKeyboardButton::callback(
+                config.render(format!("🚫 确认并封禁 {}", flagged_user.user_id)),
+                format!("flag:ban:{}", flagged_user.user_id),
+            ),
+        ]);
+    }
+
+    reply(&bot, &msg, config.render(response))
+        .reply_markup(InlineKeyboardMarkup::new(buttons))
+        .await?;
+    Ok(())
+}
+
+/// /export health [天数]：以 CSV 文件导出守护检查历史；/export audit：导出含哈希链的审计日志
+async fn export_data(bot: Bot, msg: Message, config: Config, db: Database, limits: Arc<AdminLimits>, args: String) -> ResponseResult<()> {
+    let admin_user = msg.from().unwrap();
+
+    if !config.is_admin(admin_user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(config.backup_command_cooldown);
+    if reject_on_cooldown(&bot, &msg, &config, &limits, "export", cooldown).await? {
+        return Ok(());
+    }
+    // 导出需要整表读取并可能加密，同一时间只运行一个
+    let Ok(_running) = limits.export.try_lock() else {
+        reply(&bot, &msg, config.render("⏳ 已有导出任务正在运行，请稍后再试。")).await?;
+        return Ok(());
+    };
+
+    if args.trim() == "audit" {
+        return export_audit(&bot, &msg, &config, &db, admin_user.id.0 as i64).await;
+    }
+
+    let mut parts = args.split_whitespace();
+    let days = match (parts.next(), parts.next().map(str::parse::<i64>), parts.next()) {
+        (Some("health"), None, None) => DEFAULT_EXPORT_DAYS,
+        (Some("health"), Some(Ok(days)), None) if (1..=MAX_EXPORT_DAYS).contains(&days) => days,
+        _ => {
+            reply(
+                &bot,
+                &msg,
+                config.render(format!(
+                    "❌ 用法: /export health [天数] 或 /export audit\n天数范围 1-{}，默认 {}",
+                    MAX_EXPORT_DAYS, DEFAULT_EXPORT_DAYS
+                )),
+            ).await?;
+            return Ok(());
+        }
+    };
+
+    let since = config.clock.now_utc() - chrono::Duration::days(days);
+    let mut csv = Vec::new();
+    let count = match export::export_health_csv(&db, since, &mut csv).await {
+        Ok(count) => count,
+        Err(e) => {
+            error!("导出健康检查历史失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 导出健康检查历史失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    let caption = format!("📤 最近 {} 天的健康检查记录: {} 条", days, count);
+    if !send_export(&bot, &msg, &config, csv, format!("health_{}d.csv", days), caption).await? {
+        return Ok(());
+    }
+
+    if let Err(e) = database::log_admin_action(
+        &db,
+        admin_user.id.0 as i64,
+        "export_health",
+        None,
+        &format!("天数: {}; 记录: {}", days, count),
+    ).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    Ok(())
+}
+
+/// 发送导出文件；配置了 EXPORT_PASSPHRASE 时先加密，文件名追加 .enc，加密失败时回复管理员而不发送明文，返回是否已发送
+async fn send_export(
+    bot: &Bot,
+    msg: &Message,
+    config: &Config,
+    content: Vec<u8>,
+    file_name: String,
+    caption: String,
+) -> ResponseResult<bool> {
+    let (content, file_name, caption) = match &config.export_passphrase {
+        Some(passphrase) => {
+            // PBKDF2 派生密钥耗时较长，放到阻塞线程池执行，避免占住异步运行时
+            let passphrase = passphrase.clone();
+            let sealed = tokio::task::spawn_blocking(move || export::encrypt(&content, &passphrase))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|sealed| sealed);
+            let sealed = match sealed {
+                Ok(sealed) => sealed,
+                Err(e) => {
+                    error!("加密导出文件失败: {}", e);
+                    reply(bot, msg, config.render("❌ 加密导出文件失败，未发送。")).await?;
+                    return Ok(false);
+                }
+            };
+            let file_name = format!("{}.{}", file_name, export::ENCRYPTED_EXTENSION);
+            let caption = format!("{}\n🔒 已用 EXPORT_PASSPHRASE 加密，解密: decrypt-export {}", caption, file_name);
+            (sealed, file_name, caption)
+        }
+        None => (content, file_name, caption),
+    };
+
+    let mut request = bot
+        .send_document(msg.chat.id, InputFile::memory(content).file_name(file_name))
+        .caption(config.render(caption));
+    if let Some(thread_id) = topic_thread_id(msg) {
+        request = request.message_thread_id(thread_id);
+    }
+    request.await?;
+    Ok(true)
+}
+
+/// 以 CSV 文件导出全部审计记录及其哈希，供外部保存副本后比对
+async fn export_audit(bot: &Bot, msg: &Message, config: &Config, db: &Database, admin_id: i64) -> ResponseResult<()> {
+    let mut csv = Vec::new();
+    let count = match export::export_audit_csv(db, &mut csv).await {
+        Ok(count) => count,
+        Err(e) => {
+            error!("导出审计日志失败: {}", e);
+            reply(bot, msg, config.render("❌ 导出审计日志失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    let caption = format!("📤 审计日志: {} 条（含哈希链）", count);
+    if !send_export(bot, msg, config, csv, "audit.csv".to_string(), caption).await? {
+        return Ok(());
+    }
+
+    if let Err(e) = database::log_admin_action(db, admin_id, "export_audit", None, &format!("记录: {}", count)).await {
+        error!("记录审计日志失败: {}", e);
+    }
+    Ok(())
+}
+
+/// /audit verify：校验审计日志哈希链，报告第一处断点
+async fn audit_command(bot: Bot, msg: Message, config: Config, db: Database, args: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    if args.trim() != "verify" {
+        reply(&bot, &msg, config.render("❌ 用法: /audit verify")).await?;
+        return Ok(());
+    }
+
+    let text = match database::verify_audit_chain(&db).await {
+        Ok(status) if status.is_intact() => format!("✅ 审计日志哈希链{}", status.describe()),
+        Ok(status) => format!("🚨 审计日志哈希链{}", status.describe()),
+        Err(e) => {
+            error!("校验审计日志失败: {}", e);
+            "❌ 校验审计日志失败。".to_string()
+        }
+    };
+    reply(&bot, &msg, config.render(text)).await?;
+    Ok(())
+}
+
+async fn code_reports(bot: Bot, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let reports = match database::get_recent_code_reports(&db, CODE_REPORT_LIMIT).await {
+        Ok(reports) => reports,
+        Err(e) => {
+            error!("获取激活失败反馈失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 获取激活失败反馈失败。")).await?;
+            return Ok(());
+        }
+    };
+
+    if reports.is_empty() {
+        reply(&bot, &msg, config.render("📝 暂无激活失败反馈。")).await?;
+        return Ok(());
+    }
+
+    let now = config.clock.now_utc();
+    let since = now - chrono::Duration::days(1);
+    let mut response = String::from("⚠️ 激活失败反馈\n\n📊 过去 24 小时:\n");
+    for version in FinalShellVersionType::ALL {
+        let count = database::count_code_reports_since(&db, version.version_name_ascii(), since)
+            .await
+            .map_err(db_error)?;
+        response.push_str(&format!(
+            "• {}: {}\n",
+            version.version_name_localized(config.default_lang),
+            count
+        ));
+    }
+
+    response.push_str(&format!("\n📋 最近 {} 条:\n\n", reports.len()));
+    for (index, report) in reports.iter().enumerate() {
+        response.push_str(&format!(
+            "{}. 用户 {} · {}\n\
+             • 机器码指纹: {}\n\
+             • 时间: {}\n\n",
+            index + 1,
+            report.user_id,
+            report.version,
+            &report.machine_code_hash[..report.machine_code_hash.len().min(12)],
+            format::fmt_relative(&report.created_at, &now, config.default_lang)
+        ));
+    }
+
+    reply(&bot, &msg, config.render(response)).await?;
+    Ok(())
+}
+
+async fn import_bans_start(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    reply(
+        &bot,
+        &msg,
+        config.render(format!(
+            "📥 请以文件形式发送封禁名单 CSV（每行 user_id,reason，原因可省略），最多 {} 行 / {} KB。\n💬 发送其他内容取消导入。",
+            banlist::MAX_IMPORT_ROWS,
+            banlist::MAX_IMPORT_FILE_SIZE / 1024
+        )),
+    ).await?;
+    dialogue.update(State::AwaitingBanImport).await.unwrap();
+
+    Ok(())
+}
+
+async fn handle_ban_import(bot: Bot, dialogue: MyDialogue, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    dialogue.update(State::Start).await.unwrap();
+
+    let user = msg.from().unwrap();
+    let admin_id = user.id.0 as i64;
+    if !config.is_admin(admin_id) {
+        return Ok(());
+    }
+
+    let Some(document) = msg.document() else {
+        reply(&bot, &msg, config.render("❌ 已取消导入封禁名单。")).await?;
+        return Ok(());
+    };
+
+    let content = match read_text_upload(&bot, &config, document, banlist::MAX_IMPORT_FILE_SIZE as u64).await {
+        Ok(content) => content,
+        Err(e) => {
+            reply(&bot, &msg, config.render(format!("❌ {}", e))).await?;
+            return Ok(());
+        }
+    };
+
+    let file_name = document.file_name.as_deref().unwrap_or("未命名");
+    let source = format!("Telegram 文件 {}", file_name);
+    let report = match banlist::import(&db, &content, admin_id, &source).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("导入封禁名单失败: {}", e);
+            reply(&bot, &msg, config.render(format!("❌ 导入失败: {}", e))).await?;
+            return Ok(());
+        }
+    };
+
+    reply(&bot, &msg, config.render(ban_import_summary(&report))).await?;
+    Ok(())
+}
+
+/// 封禁名单导入结果：计数汇总，以及最多 20 条格式错误的行
+fn ban_import_summary(report: &banlist::ImportReport) -> String {
+    let mut response = format!(
+        "✅ 封禁名单导入完成\n\n\
+         🚫 新增封禁: {}\n\
+         ♻️ 已是封禁状态: {}\n\
+         ⚠️ 格式错误: {}",
+        report.summary.applied,
+        report.summary.already_banned,
+        report.malformed.len()
+    );
+    if !report.malformed.is_empty() {
+        response.push_str("\n\n📋 格式错误的行:\n");
+        for (line, problem) in report.malformed.iter().take(20) {
+            response.push_str(&format!("• 第 {} 行: {}\n", line, problem));
+        }
+        if report.malformed.len() > 20 {
+            response.push_str(&format!("• …… 另有 {} 行", report.malformed.len() - 20));
+        }
+    }
+    response
+}
+
+/// `/say --test <消息>`：只发给管理员，不进入确认流程
+const BROADCAST_TEST_FLAG: &str = "--test";
+const BROADCAST_PREVIEW_CALLBACK: &str = "broadcast:preview";
+
+/// 拆出 `/say` 参数中的 `--test` 标记
+fn parse_say_args(args: &str) -> (bool, &str) {
+    let args = args.trim();
+    match args.strip_prefix(BROADCAST_TEST_FLAG) {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => (true, rest.trim()),
+        _ => (false, args),
+    }
+}
+
+/// 逐个发送广播并更新待发送队列长度，返回 (成功数, 失败数)。广播内容由管理员撰写，按原样发送，不受 USE_EMOJI 影响
+async fn deliver_broadcast(
+    bot: &Bot,
+    telegram: &TelegramHealth,
+    db: &Database,
+    recipients: &[i64],
+    message: &str,
+) -> (i64, i64) {
+    let (mut delivered, mut failed) = (0, 0);
+    telegram.set_queue_depth(recipients.len());
+
+    for (index, user_id) in recipients.iter().enumerate() {
+        match send_to_user(bot, telegram, db, *user_id, message.to_string()).await {
+            Ok(_) => delivered += 1,
+            Err(e) => {
+                warn!("向用户 {} 发送广播失败: {}", user_id, e);
+                failed += 1;
+            }
+        }
+        telegram.set_queue_depth(recipients.len() - index - 1);
+    }
+
+    (delivered, failed)
+}
+
+/// 正式广播的接收人：跳过被封禁、账号已注销与屏蔽了机器人的用户
+fn broadcast_recipients(users: &[UserStats]) -> Vec<i64> {
+    users
+        .iter()
+        .filter(|user| !user.is_banned && user.deactivated_at.is_none() && user.blocked_at.is_none())
+        .map(|user| user.user_id)
+        .collect()
+}
+
+/// 将广播发给 ADMIN_IDS 中的管理员预览，并记为测试广播；返回 (成功数, 失败数)
+async fn deliver_test_broadcast(
+    bot: &Bot,
+    telegram: &TelegramHealth,
+    config: &Config,
+    db: &Database,
+    admin_id: i64,
+    message: &str,
+) -> (i64, i64) {
+    let (delivered, failed) = deliver_broadcast(bot, telegram, db, &config.admin_ids, message).await;
+    if let Err(e) = database::record_broadcast(db, admin_id, message, true, delivered, failed).await {
+        error!("记录测试广播失败: {}", e);
+    }
+    info!("管理员 {} 向 {} 位管理员发送了测试广播", admin_id, delivered);
+    (delivered, failed)
+}
+
+async fn broadcast_start(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    config: Config,
+    db: Database,
+    telegram: Arc<TelegramHealth>,
+    args: String,
+) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    let admin_id = user.id.0 as i64;
+
+    if !config.is_admin(admin_id) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let (test, message) = parse_say_args(&args);
+    if message.is_empty() {
+        reply(&bot, &msg, config.render("❌ 广播消息不能为空。")).await?;
+        return Ok(());
+    }
+
+    if test {
+        let (delivered, failed) = deliver_test_broadcast(&bot, &telegram, &config, &db, admin_id, message).await;
+        reply(
+            &bot,
+            &msg,
+            config.render(format!("🧪 测试广播已发送给管理员\n\n成功: {} 人\n失败: {} 人", delivered, failed)),
+        ).await?;
+        return Ok(());
+    }
+
+    let history = match database::get_broadcast_stats(&db).await {
+        Ok(stats) if stats.runs > 0 => format!(
+            "\n📊 历史正式广播: {} 次，送达 {} 人，失败 {} 人\n",
+            stats.runs, stats.delivered, stats.failed
+        ),
+        Ok(_) => String::new(),
+        Err(e) => {
+            error!("获取广播统计失败: {}", e);
+            String::new()
+        }
+    };
+
+    // 只对提示文案应用 USE_EMOJI，广播内容原样展示，与实际发出的一致
+    let confirm_msg = format!(
+        "{}\n{}\n\n{}",
+        config.render(
+            "╔══════════════════════════════════════╗\n\
+             ║       📢 准备发送广播消息 📢       ║\n\
+             ╚══════════════════════════════════════╝\n\n\
+             📝 消息内容:"
+        ),
+        message,
+        config.render(format!(
+            "{}\n\
+             ⚠️ 此消息将发送给所有用户，确认发送吗？\n\
+             💬 回复 \"确认\" 开始发送，回复其他内容取消。\n\
+             👀 可先点击下方按钮发给管理员预览效果。",
+            history
+        ))
+    );
+
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        config.render("👀 先发送给管理员预览"),
+        BROADCAST_PREVIEW_CALLBACK,
+    )]]);
+    reply(&bot, &msg, confirm_msg).reply_markup(keyboard).await?;
+
+    dialogue
+        .update(State::AdminBroadcast { message: message.to_string() })
+        .await
+        .unwrap();
+
+    Ok(())
+}
+
+/// 确认步骤中的预览按钮：把待发送的广播发给管理员，保持等待确认的状态
+async fn handle_broadcast_preview(
+    bot: Bot,
+    q: CallbackQuery,
+    config: Config,
+    db: Database,
+    storage: Arc<InMemStorage<State>>,
+    telegram: Arc<TelegramHealth>,
+) -> ResponseResult<()> {
+    let admin_id = q.from.id.0 as i64;
+    if !config.is_admin(admin_id) {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 此操作仅管理员可用。"))).await?;
+        return Ok(());
+    }
+
+    let Some(chat_id) = q.message.as_ref().map(|m| m.chat.id) else {
+        edit_or_ignore(bot.answer_callback_query(q.id)).await?;
+        return Ok(());
+    };
+    let message = match MyDialogue::new(storage, chat_id).get().await {
+        Ok(Some(State::AdminBroadcast { message })) => message,
+        _ => {
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("ℹ️ 该广播已发送或已取消。"))).await?;
+            return Ok(());
+        }
+    };
+
+    edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("👀 正在发送预览..."))).await?;
+    let (delivered, failed) = deliver_test_broadcast(&bot, &telegram, &config, &db, admin_id, &message).await;
+    bot.send_message(
+        chat_id,
+        config.render(format!(
+            "👀 预览已发送给管理员 (成功 {} 人，失败 {} 人)\n💬 回复 \"确认\" 发送给所有用户，回复其他内容取消。",
+            delivered, failed
+        )),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn handle_broadcast(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    config: Config,
+    db: Database,
+    telegram: Arc<TelegramHealth>,
+    message: String,
+) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        dialogue.update(State::Start).await.unwrap();
+        return Ok(());
+    }
+
+    let response = msg.text().unwrap_or("").trim();
+    
+    if response == "确认" {
+        // 获取所有用户并发送广播
+        match database::get_all_users(&db).await {
+            Ok(users) => {
+                let recipients = broadcast_recipients(&users);
+                let (success_count, failed_count) = deliver_broadcast(&bot, &telegram, &db, &recipients, &message).await;
+                if let Err(e) = database::record_broadcast(&db, user.id.0 as i64, &message, false, success_count, failed_count).await {
+                    error!("记录广播失败: {}", e);
+                }
+
+                let result_msg = format!(
+                    "✅ 广播发送完成\n\n\
+                     成功: {} 人\n\
+                     失败: {} 人",
+                    success_count, failed_count
+                );
+
+                reply(&bot, &msg, config.render(result_msg)).await?;
+                info!("管理员 {} 发送了广播消息", user.id.0);
+            }
+            Err(e) => {
+                error!("获取用户列表失败: {}", e);
+                reply(&bot, &msg, config.render("❌ 获取用户列表失败，广播取消。")).await?;
+            }
+        }
+    } else {
+        reply(&bot, &msg, config.render("❌ 广播已取消。")).await?;
+    }
+
+    dialogue.update(State::Start).await.unwrap();
+    Ok(())
+}
+
+async fn clear_stats(bot: Bot, msg: Message, config: Config, db: Database) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    match database::clear_stats(&db).await {
+        Ok(_) => {
+            reply(&bot, &msg, config.render("✅ 统计数据已清除。")).await?;
+            info!("管理员 {} 清除了统计数据", user.id.0);
+        }
+        Err(e) => {
+            error!("清除统计数据失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 清除统计数据失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn cleanup_logs(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    // 这里实现日志清理逻辑
+    match utils::cleanup_logs(config.clock.now_utc()).await {
+        Ok(cleaned_files) => {
+            reply(
+                &bot,
+                &msg,
+                config.render(format!("✅ 日志清理完成，清理了 {} 个文件。", cleaned_files))
+            ).await?;
+            info!("管理员 {} 执行了日志清理", user.id.0);
+        }
+        Err(e) => {
+            error!("日志清理失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 日志清理失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn about_bot(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
+    let about_text = 
+        "╔══════════════════════════════════════╗\n\
+         ║      🤖 FinalShell 激活码生成器      ║\n\
+         ║             Rust 版本 v2.0           ║\n\
+         ╚══════════════════════════════════════╝\n\n\
+         🚀 项目信息:\n\
+         ┣━ 📛 名称: FinalShell Activator (Rust)\n\
+         ┣━ 🏷️ 版本: v2.0.0\n\
+         ┣━ 🔧 语言: Rust 2021 Edition\n\
+         ┗━ 📅 发布: 2025年8月\n\n\
+         ⚡ 性能优势:\n\
+         ┣━ 🚀 启动时间: ~0.5秒 (比Python快83%)\n\
+         ┣━ 💾 内存占用: ~45MB (比Python少70%)\n\
+         ┣━ 🔄 并发处理: ~1000 req/s (比Python快900%)\n\
+         ┗━ 🛡️ 内存安全: 零成本抽象\n\n\
+         🎯 核心特性:\n\
+         ┣━ ✨ 支持全版本 FinalShell\n\
+         ┣━ 🔄 实时激活码生成\n\
+         ┣━ 🛡️ 24小时监控守护\n\
+         ┣━ 📊 完整统计分析\n\
+         ┗━ 👥 用户权限管理\n\n\
+         🔒 安全保障:\n\
+         ┣━ 🛡️ 算法透明可靠\n\
+         ┣━ 🔐 标准加密技术\n\
+         ┣━ 🚫 无恶意行为\n\
+         ┗━ ♾️ 永久免费使用\n\n\
+         💎 感谢您使用我们的服务！";
+
+    reply(&bot, &msg, config.render(about_text)).await?;
+    Ok(())
+}
+
+
+async fn guard_report(
+    bot: Bot,
+    msg: Message,
+    config: Config,
+    db: Database,
+    limits: Arc<AdminLimits>,
+    telegram: Arc<TelegramHealth>,
+) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(config.guard_command_cooldown);
+    if reject_on_cooldown(&bot, &msg, &config, &limits, "guard", cooldown).await? {
+        return Ok(());
+    }
+
     // 获取最新的健康检查报告
-    match crate::guard::generate_health_report(&config, &db).await {
+    match crate::guard::generate_health_report(&config, &db, Some(&telegram)).await {
         Ok(report) => {
-            bot.send_message(msg.chat.id, report).await?;
+            let text = report.render_telegram(config.default_lang, config.timezone());
+            reply(&bot, &msg, config.render(text)).await?;
+        }
+        Err(e) => {
+            error!("生成健康检查报告失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 获取健康检查报告失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// /doctor：依次运行配置、数据库、磁盘、Telegram 等诊断项，逐项给出结果与排查提示
+async fn run_doctor(bot: Bot, msg: Message, config: Config, db: Database, limits: Arc<AdminLimits>) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(config.guard_command_cooldown);
+    if reject_on_cooldown(&bot, &msg, &config, &limits, "doctor", cooldown).await? {
+        return Ok(());
+    }
+
+    send_typing(&bot, &msg).await;
+    let report = doctor::run(&config, &db).await;
+    reply(&bot, &msg, config.render(report.render())).await?;
+    Ok(())
+}
+
+async fn change_log_level(bot: Bot, msg: Message, config: Config, db: Database, filter: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let Some(level) = log_level::global() else {
+        reply(&bot, &msg, config.render("❌ 日志系统未启用运行时调整。")).await?;
+        return Ok(());
+    };
+
+    let filter = filter.trim();
+    let text = match filter {
+        "" => log_level_status(&config, level),
+        "reset" => match level.reset() {
+            Ok(_) => {
+                info!("管理员 {} 恢复了日志级别", user.id.0);
+                format!("✅ 已恢复日志级别: {}", level.default_filter())
+            }
+            Err(e) => format!("❌ {:#}", e),
+        },
+        _ => {
+            let revert_at = config.clock.now_utc() + chrono::Duration::seconds(config.log_level_revert as i64);
+            match level.set(filter, revert_at) {
+                Ok(()) => {
+                    info!("管理员 {} 将日志级别临时调整为 {}", user.id.0, filter);
+                    if let Err(e) = database::log_admin_action(&db, user.id.0 as i64, "loglevel", None, filter).await {
+                        error!("记录审计日志失败: {}", e);
+                    }
+                    format!("✅ 日志级别已临时调整\n{}", log_level_status(&config, level))
+                }
+                Err(e) => format!("❌ {:#}\n用法: /loglevel [过滤器|reset]，过滤器语法与 RUST_LOG 相同，如 finalunlock_all_rust=debug", e),
+            }
+        }
+    };
+    reply(&bot, &msg, config.render(text)).await?;
+    Ok(())
+}
+
+/// 当前日志过滤器与自动恢复时间
+fn log_level_status(config: &Config, level: &log_level::LogLevel) -> String {
+    let (filter, revert_at) = level.current();
+    match revert_at {
+        Some(at) => format!(
+            "当前日志级别: {}\n将于 {} 自动恢复为 {}",
+            filter,
+            format::fmt_datetime(&at, config.default_lang, config.timezone()),
+            level.default_filter()
+        ),
+        None => format!("当前日志级别: {}", filter),
+    }
+}
+
+async fn backup(bot: Bot, msg: Message, config: Config, limits: Arc<AdminLimits>) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    let cooldown = Duration::from_secs(config.backup_command_cooldown);
+    if reject_on_cooldown(&bot, &msg, &config, &limits, "backup", cooldown).await? {
+        return Ok(());
+    }
+
+    // 同一时间只运行一个备份任务，后来的请求直接拒绝而不是排队
+    let Ok(_running) = limits.backup.try_lock() else {
+        reply(&bot, &msg, config.render("⏳ 已有备份任务正在运行，请稍后再试。")).await?;
+        return Ok(());
+    };
+
+    reply(&bot, &msg, config.render("🗄️ 正在备份数据库与配置文件...")).await?;
+    match crate::guard::backup_data(config.clock.now_utc()).await {
+        Ok(_) => {
+            info!("管理员 {} 执行了数据备份", user.id);
+            reply(&bot, &msg, config.render("✅ 备份完成，文件已保存到 backups 目录。")).await?;
+        }
+        Err(e) => {
+            error!("数据备份失败: {}", e);
+            reply(&bot, &msg, config.render("❌ 数据备份失败。")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn appeal(bot: Bot, msg: Message, config: Config, db: Database, content: String) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+    let user_id = user.id.0 as i64;
+    let now = config.clock.now_utc();
+
+    let db_user = match database::get_user_by_id(&db, user_id).await {
+        Ok(db_user) if db_user.is_ban_active(now) => db_user,
+        _ => {
+            reply(&bot, &msg, config.render("❌ 仅被封禁的用户可以提交申诉。")).await?;
+            return Ok(());
+        }
+    };
+
+    let content = content.trim();
+    if content.is_empty() {
+        reply(&bot, &msg, config.render("❌ 申诉内容不能为空。用法: /appeal <申诉内容>")).await?;
+        return Ok(());
+    }
+
+    if content.chars().count() > MAX_APPEAL_LENGTH {
+        reply(
+            &bot,
+            &msg,
+            config.render(format!("❌ 申诉内容过长，请控制在 {} 字以内。", MAX_APPEAL_LENGTH))
+        ).await?;
+        return Ok(());
+    }
+
+    let already_appealed = database::has_appeal_for_ban(&db, user_id, db_user.banned_at)
+        .await
+        .map_err(db_error)?;
+    if already_appealed {
+        reply(&bot, &msg, config.render("❌ 本次封禁已提交过申诉，请耐心等待管理员处理。")).await?;
+        return Ok(());
+    }
+
+    let recent_appeals = database::count_appeals_since(&db, user_id, now - chrono::Duration::days(APPEAL_WINDOW_DAYS))
+        .await
+        .map_err(db_error)?;
+    if recent_appeals >= MAX_APPEALS_PER_WINDOW {
+        reply(&bot, &msg, config.render("❌ 申诉过于频繁，请稍后再试。")).await?;
+        return Ok(());
+    }
+
+    let appeal_id = database::create_appeal(&db, user_id, db_user.banned_at, content)
+        .await
+        .map_err(db_error)?;
+
+    let notice = appeal_notice(
+        appeal_id,
+        &user.username.as_deref().map(|u| format!("@{}", u)).unwrap_or_else(|| user.first_name.clone()),
+        user_id,
+        db_user.ban_reason.as_deref(),
+        &db_user.banned_at.map(|dt| format::fmt_datetime(&dt, config.default_lang, config.timezone())).unwrap_or_else(|| "未知".to_string()),
+        content,
+    );
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(config.render("✅ 解封"), format!("appeal:approve:{}", appeal_id)),
+        InlineKeyboardButton::callback(config.render("❌ 驳回"), format!("appeal:reject:{}", appeal_id)),
+    ]]);
+
+    for admin_id in &config.admin_ids {
+        if let Err(e) = bot
+            .send_message(teloxide::types::ChatId(*admin_id), config.render(notice.clone()))
+            .reply_markup(keyboard.clone())
+            .await
+        {
+            warn!("向管理员 {} 发送申诉通知失败: {}", admin_id, e);
+        }
+    }
+
+    reply(&bot, &msg, config.render("✅ 申诉已提交，管理员处理后会通知您。")).await?;
+    info!("用户 {} 提交了封禁申诉 #{}", user_id, appeal_id);
+
+    Ok(())
+}
+
+/// 转发给管理员的封禁申诉通知
+fn appeal_notice(appeal_id: i64, who: &str, user_id: i64, ban_reason: Option<&str>, banned_at: &str, content: &str) -> String {
+    format!(
+        "📨 收到新的封禁申诉 #{}\n\n\
+         👤 用户: {} ({})\n\
+         📝 封禁原因: {}\n\
+         🕒 封禁时间: {}\n\n\
+         💬 申诉内容:\n{}",
+        appeal_id,
+        format::safe_user_text(who, USER_NAME_DISPLAY_LIMIT, None),
+        user_id,
+        ban_reason.map_or_else(|| "未说明".to_string(), |reason| format::safe_user_text(reason, USER_TEXT_DISPLAY_LIMIT, None)),
+        banned_at,
+        format::safe_user_text(content, USER_TEXT_DISPLAY_LIMIT, None)
+    )
+}
+
+async fn handle_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    config: Config,
+    db: Database,
+    storage: Arc<InMemStorage<State>>,
+    telegram: Arc<TelegramHealth>,
+    metrics: Arc<Metrics>,
+) -> ResponseResult<()> {
+    let data = q.data.clone().unwrap_or_default();
+
+    if data == BROADCAST_PREVIEW_CALLBACK {
+        return handle_broadcast_preview(bot, q, config, db, storage, telegram).await;
+    }
+    if let Some(action) = data.strip_prefix("appeal:") {
+        return handle_appeal_decision(bot, q, config, db, action).await;
+    }
+    if let Some(payload) = data.strip_prefix("report:") {
+        return handle_code_report(bot, q, config, db, payload).await;
+    }
+    if let Some(payload) = data.strip_prefix("outcome:") {
+        return handle_activation_outcome(bot, q, config, db, payload).await;
+    }
+    if let Some(payload) = data.strip_prefix("link:") {
+        return handle_result_link(bot, q, config, db, payload).await;
+    }
+    if let Some(action) = data.strip_prefix("flag:") {
+        return handle_flag_decision(bot, q, config, db, &metrics, action).await;
+    }
+    if let Some(action) = data.strip_prefix("version:") {
+        return handle_version_toggle(bot, q, config, db, action).await;
+    }
+
+    edit_or_ignore(bot.answer_callback_query(q.id)).await?;
+    Ok(())
+}
+
+async fn handle_appeal_decision(bot: Bot, q: CallbackQuery, config: Config, db: Database, action: &str) -> ResponseResult<()> {
+    let admin_id = q.from.id.0 as i64;
+
+    if !config.is_admin(admin_id) {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 此操作仅管理员可用。"))).await?;
+        return Ok(());
+    }
+
+    let parsed = action
+        .split_once(':')
+        .and_then(|(decision, id)| id.parse::<i64>().ok().map(|id| (decision, id)));
+    let (approve, appeal_id) = match parsed {
+        Some(("approve", id)) => (true, id),
+        Some(("reject", id)) => (false, id),
+        _ => {
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+            return Ok(());
         }
+    };
+
+    let appeal = match database::get_appeal(&db, appeal_id).await {
+        Ok(appeal) => appeal,
         Err(e) => {
-            error!("生成健康检查报告失败: {}", e);
-            bot.send_message(msg.chat.id, "❌ 获取健康检查报告失败。").await?;
+            error!("获取申诉失败: {}", e);
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 申诉不存在。"))).await?;
+            return Ok(());
+        }
+    };
+
+    let status = if approve { "approved" } else { "rejected" };
+    match database::decide_appeal(&db, appeal_id, status, admin_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("ℹ️ 该申诉已被处理。"))).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!("处理申诉失败: {}", e);
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 处理申诉失败。"))).await?;
+            return Ok(());
+        }
+    }
+
+    if approve {
+        if let Err(e) = database::unban_user(&db, appeal.user_id).await {
+            error!("解封用户失败: {}", e);
+        }
+    }
+
+    let audit_action = if approve { "appeal_approve" } else { "appeal_reject" };
+    if let Err(e) = database::log_admin_action(
+        &db,
+        admin_id,
+        audit_action,
+        Some(appeal.user_id),
+        &format!("申诉 #{}", appeal_id),
+    ).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    let user_notice = if approve {
+        "✅ 您的申诉已通过，封禁已解除。"
+    } else {
+        "❌ 您的申诉已被驳回。"
+    };
+    let sent = bot.send_message(teloxide::types::ChatId(appeal.user_id), config.render(user_notice)).await;
+    if let Err(e) = track_deactivation(&db, appeal.user_id, sent).await {
+        warn!("向用户 {} 发送申诉结果失败: {}", appeal.user_id, e);
+    }
+
+    let label = if approve { "已解封" } else { "已驳回" };
+    if let Some(message) = &q.message {
+        let text = format!(
+            "{}\n\n📌 处理结果: {}（管理员 {}）",
+            message.text().unwrap_or_default(),
+            label,
+            admin_id
+        );
+        if let Err(e) = edit_or_ignore(bot.edit_message_text(message.chat.id, message.id, config.render(text))).await {
+            warn!("更新申诉通知失败: {}", e);
+        }
+    }
+
+    edit_or_ignore(bot.answer_callback_query(q.id).text(config.render(format!("✅ {}", label)))).await?;
+    info!("管理员 {} 处理了申诉 #{}: {}", admin_id, appeal_id, label);
+
+    Ok(())
+}
+
+/// 处理 /flagged 中的按钮：解除标记，或确认滥用并封禁
+async fn handle_flag_decision(bot: Bot, q: CallbackQuery, config: Config, db: Database, metrics: &Metrics, action: &str) -> ResponseResult<()> {
+    let admin_id = q.from.id.0 as i64;
+
+    if !config.is_admin(admin_id) {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 此操作仅管理员可用。"))).await?;
+        return Ok(());
+    }
+
+    let parsed = action
+        .split_once(':')
+        .and_then(|(decision, id)| id.parse::<i64>().ok().map(|id| (decision, id)));
+    let (ban, user_id) = match parsed {
+        Some(("clear", id)) => (false, id),
+        Some(("ban", id)) => (true, id),
+        _ => {
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+            return Ok(());
+        }
+    };
+
+    let was_flagged = database::clear_flag(&db, user_id).await.map_err(db_error)?;
+    if !was_flagged {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("ℹ️ 该用户已被处理。"))).await?;
+        return Ok(());
+    }
+
+    let label = if ban {
+        if database::ban_user(&db, user_id, Some("疑似转卖激活码"), None).await.map_err(db_error)? {
+            metrics.record_bans(1);
+        }
+        "已封禁"
+    } else {
+        "已解除标记"
+    };
+
+    let audit_action = if ban { "flag_ban" } else { "flag_clear" };
+    if let Err(e) = database::log_admin_action(&db, admin_id, audit_action, Some(user_id), label).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    edit_or_ignore(bot.answer_callback_query(q.id).text(config.render(format!("✅ 用户 {} {}", user_id, label)))).await?;
+    info!("管理员 {} 处理了被标记用户 {}: {}", admin_id, user_id, label);
+
+    Ok(())
+}
+
+/// /versions：列出各版本的启用状态，附带启用/停用按钮
+async fn list_versions(bot: Bot, msg: Message, config: Config) -> ResponseResult<()> {
+    let user = msg.from().unwrap();
+
+    if !config.is_admin(user.id.0 as i64) {
+        reply(&bot, &msg, config.render("❌ 此命令仅管理员可用。")).await?;
+        return Ok(());
+    }
+
+    reply(&bot, &msg, config.render(versions_text(&config)))
+        .reply_markup(versions_keyboard(&config))
+        .await?;
+    Ok(())
+}
+
+fn versions_text(config: &Config) -> String {
+    let mut text = String::from("🧩 版本开关\n\n");
+    for version in FinalShellVersionType::ALL {
+        let state = if !config.enabled_versions.contains(&version) {
+            "🚫 未启用 (ENABLED_VERSIONS)"
+        } else if config.disabled_versions.contains(version) {
+            "⛔ 已停用"
+        } else {
+            "✅ 启用"
+        };
+        text.push_str(&format!("{}: {}\n", version.version_name_localized(Lang::Zh), state));
+    }
+    text.push_str("\n💡 停用的版本不再生成，也不会出现在结果中");
+    text
+}
+
+/// ENABLED_VERSIONS 之外的版本不能在运行时启用，不提供按钮
+fn versions_keyboard(config: &Config) -> InlineKeyboardMarkup {
+    let buttons: Vec<_> = FinalShellVersionType::ALL
+        .iter()
+        .filter(|version| config.enabled_versions.contains(version))
+        .map(|version| {
+            let name = version.version_name_ascii();
+            if config.disabled_versions.contains(*version) {
+                InlineKeyboardButton::callback(format!("启用 {}", name), format!("version:on:{}", name))
+            } else {
+                InlineKeyboardButton::callback(format!("停用 {}", name), format!("version:off:{}", name))
+            }
+        })
+        .collect();
+    InlineKeyboardMarkup::new(buttons.chunks(2).map(|row| row.to_vec()))
+}
+
+async fn handle_version_toggle(bot: Bot, q: CallbackQuery, config: Config, db: Database, action: &str) -> ResponseResult<()> {
+    let admin_id = q.from.id.0 as i64;
+
+    if !config.is_admin(admin_id) {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 此操作仅管理员可用。"))).await?;
+        return Ok(());
+    }
+
+    let parsed = action
+        .split_once(':')
+        .and_then(|(toggle, name)| FinalShellVersionType::from_name_ascii(name).map(|version| (toggle, version)));
+    let (enable, version) = match parsed {
+        Some(("on", version)) => (true, version),
+        Some(("off", version)) => (false, version),
+        _ => {
+            edit_or_ignore(bot.answer_callback_query(q.id).text(config.render("❌ 无效的操作。"))).await?;
+            return Ok(());
         }
+    };
+
+    if let Err(e) = config.set_version_enabled(&db, version, enable).await {
+        edit_or_ignore(bot.answer_callback_query(q.id).text(config.render(format!("❌ {}", e))).show_alert(true)).await?;
+        return Ok(());
+    }
+
+    let (audit_action, label) = if enable { ("enable_version", "已启用") } else { ("disable_version", "已停用") };
+    if let Err(e) = database::log_admin_action(&db, admin_id, audit_action, None, version.version_name_ascii()).await {
+        error!("记录审计日志失败: {}", e);
     }
+    info!("管理员 {} {}版本 {}", admin_id, label, version.version_name_ascii());
 
+    edit_or_ignore(bot.answer_callback_query(q.id).text(config.render(format!("✅ {} {}", version.version_name_ascii(), label)))).await?;
+    if let Some(message) = &q.message {
+        edit_or_ignore(
+            bot.edit_message_text(message.chat.id, message.id, config.render(versions_text(&config)))
+                .reply_markup(versions_keyboard(&config)),
+        )
+        .await?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_message(json: &str) -> Message {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn api_error(description: &str) -> teloxide::RequestError {
+        let payload = serde_json::to_string(description).unwrap();
+        teloxide::RequestError::Api(serde_json::from_str(&payload).unwrap())
+    }
+
+    fn stats_card(instance: Option<&str>) -> StatsCard {
+        StatsCard {
+            instance: instance.map(str::to_string),
+            stats: SystemStats {
+                id: 0,
+                total_users: 1234,
+                total_activations: 56789,
+                active_users_today: 12,
+                activations_today: 34,
+                deactivated_users: 5,
+                blocked_users: 0,
+                blocked_this_week: 0,
+                system_status: "正常".to_string(),
+                created_at: chrono::Utc::now(),
+            },
+            instances: Vec::new(),
+            languages: vec![("zh-hans".to_string(), 1000), ("<en>".to_string(), 234)],
+            copy_experiment: None,
+            outcome: None,
+            generated_at: "2025-08-15 20:00:00 (UTC+08:00)".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ban_import_summary() {
+        let report = banlist::ImportReport {
+            summary: banlist::ImportSummary { applied: 3, already_banned: 1 },
+            malformed: vec![(4, "用户 ID 无效".to_string())],
+        };
+
+        assert_eq!(
+            ban_import_summary(&report),
+            "✅ 封禁名单导入完成\n\n🚫 新增封禁: 3\n♻️ 已是封禁状态: 1\n⚠️ 格式错误: 1\n\n📋 格式错误的行:\n• 第 4 行: 用户 ID 无效\n"
+        );
+    }
+
+    #[test]
+    fn test_menu_keyboard_without_emoji() {
+        let mut config = Config::load().unwrap();
+        config.use_emoji = false;
+
+        let keyboard = menu_keyboard(&config, Lang::Zh);
+        let labels: Vec<&str> = keyboard.keyboard[0].iter().map(|button| button.text.as_str()).collect();
+        assert_eq!(labels, ["生成激活码", "我的用量", "帮助"]);
+        for (label, action) in labels.into_iter().zip(MenuAction::ALL) {
+            assert_eq!(MenuAction::parse(label), Some(action));
+        }
+    }
+
+    #[test]
+    fn test_fold_secondary_codes() {
+        // 推荐版本由机器码推测，其余版本折叠
+        let machine_code = "ABC123DEF456";
+        let results = ActivationCodeGenerator::generate_versions(machine_code, &FinalShellVersionType::ALL, finalshell::CodeCase::Upper).unwrap();
+        let recommended = ActivationCodeGenerator::primary_result(machine_code, &results).unwrap().version_type;
+        let escaped = escape_activation_output(&ActivationCodeGenerator::format_results(machine_code, &results, "2025-01-01 00:00", Lang::Zh));
+
+        let folded = fold_secondary_codes(&escaped, machine_code, &results);
+        for result in &results {
+            for code in [&result.advanced_code, &result.professional_code] {
+                if result.version_type == recommended {
+                    assert!(folded.contains(&format!("`{}`", code)));
+                } else {
+                    assert!(folded.contains(&format!("||{}||", code)));
+                    assert!(!folded.contains(&format!("`{}`", code)));
+                }
+            }
+        }
+        // 机器码仍是行内代码，其余文字不变
+        assert!(folded.contains(&format!("`{}`", machine_code)));
+        assert_eq!(folded.matches("||").count(), (results.len() - 1) * 4);
+    }
+
+    #[test]
+    fn test_stats_card_html() {
+        let html = stats_card(Some("a&b")).render_html();
+        assert!(html.contains("<b>1,234</b>"));
+        assert!(html.contains("<b>56,789</b>"));
+        assert!(html.contains("<code>a&amp;b</code>"));
+        assert!(html.contains("&lt;en&gt;: <b>234</b>"));
+        assert!(!html.contains("<en>"));
+        assert!(!html.contains('╔'));
+    }
+
+    #[test]
+    fn test_stats_card_json() {
+        let json: serde_json::Value = serde_json::from_str(&stats_card(Some("bot-a")).render_json()).unwrap();
+        assert_eq!(json["instance_id"], "bot-a");
+        assert_eq!(json["total_users"], 1234);
+        assert_eq!(json["activations_today"], 34);
+        assert_eq!(json["deactivated_users"], 5);
+        assert_eq!(json["system_status"], "正常");
+        assert_eq!(json["instances"], serde_json::json!([]));
+        assert_eq!(json["languages"][1], serde_json::json!({ "language": "<en>", "users": 234 }));
+        assert!(chrono::DateTime::parse_from_rfc3339(json["generated_at"].as_str().unwrap()).is_ok());
+
+        let json: serde_json::Value = serde_json::from_str(&stats_card(None).render_json()).unwrap();
+        assert!(json["instance_id"].is_null());
+        assert!(json["copy_experiment"].is_null());
+    }
+
+    #[test]
+    fn test_stats_card_copy_experiment() {
+        let mut card = stats_card(None);
+        card.copy_experiment = Some((
+            "copy-v1".to_string(),
+            vec![
+                CopyVariantStats { variant: "control".to_string(), users: 800, converted: 200 },
+                CopyVariantStats { variant: "variant".to_string(), users: 200, converted: 70 },
+            ],
+        ));
+
+        let text = card.render_text();
+        assert!(text.contains("🧪 文案实验 (copy-v1):\n┣━ control: 800 人, 转化 200 (25.0%)\n┣━ variant: 200 人, 转化 70 (35.0%)\n"));
+        let json: serde_json::Value = serde_json::from_str(&card.render_json()).unwrap();
+        assert_eq!(json["copy_experiment"]["variants"][1]["converted"], 70);
+    }
+
+    #[test]
+    fn test_stats_card_outcomes() {
+        let mut card = stats_card(None);
+        assert!(!card.render_text().contains("激活反馈"));
+        card.outcome = Some(OutcomeStats { success: 30, failure: 10, unknown: 1200 });
+
+        let text = card.render_text();
+        assert!(text.contains("✅ 激活反馈成功率: 75.0% (成功 30, 失败 10, 未反馈 1,200)\n"));
+        assert!(card.render_html().contains("<b>激活反馈成功率</b>: 75.0% (成功 <b>30</b>, 失败 <b>10</b>, 未反馈 <b>1,200</b>)"));
+        let json: serde_json::Value = serde_json::from_str(&card.render_json()).unwrap();
+        assert_eq!(json["outcome"]["failure"], 10);
+    }
+
+    #[test]
+    fn test_parse_stats_args() {
+        assert_eq!(parse_stats_args(""), (false, None));
+        assert_eq!(parse_stats_args(" bot-a "), (false, Some("bot-a")));
+        assert_eq!(parse_stats_args("json"), (true, None));
+        assert_eq!(parse_stats_args("JSON  bot-a"), (true, Some("bot-a")));
+    }
+
+    #[test]
+    fn test_render_daily_activity() {
+        let day = DailySummary {
+            day: "2025-03-02".to_string(),
+            activations: 1200,
+            distinct_users: 3,
+            versions: [("4.5".to_string(), 200), ("4.6+".to_string(), 1000)].into(),
+        };
+        assert_eq!(
+            render_daily_activity(14, &[day]),
+            "📈 最近 14 天激活趋势\n\n2025-03-02 · 激活 1,200 · 用户 3\n  4.5 200 / 4.6+ 1,000"
+        );
+        assert_eq!(render_daily_activity(7, &[]), "📝 最近 7 天没有激活记录。");
+    }
+
+    #[test]
+    fn test_stats_card_text_unchanged() {
+        let text = stats_card(None).render_text();
+        assert!(text.contains("👥 总用户数: 1,234\n"));
+        assert!(text.contains("🎯 今日激活次数: 34\n💤 已注销用户: 5\n💚 系统状态: 正常\n"));
+        assert!(text.contains("🌍 语言分布:\n┣━ zh-hans: 1,000\n┣━ <en>: 234\n\n🕒 统计时间: "));
+        assert!(!text.contains("<b>"));
+    }
+
+    #[test]
+    fn test_parse_command_with_prefix() {
+        assert!(matches!(parse_command("/stats", "/", "mybot"), Some(Command::Stats(_))));
+        assert!(matches!(parse_command("/stats@mybot json", "/", "mybot"), Some(Command::Stats(args)) if args == "json"));
+        assert!(parse_command("/stats@otherbot", "/", "mybot").is_none());
+        assert!(matches!(parse_command("!ban 42", "!", "mybot"), Some(Command::Ban(args)) if args == "42"));
+        assert!(parse_command("/stats", "!", "mybot").is_none());
+        assert!(parse_command("! stats", "!", "mybot").is_none());
+        assert!(parse_command("!", "!", "mybot").is_none());
+        assert!(parse_command("abc123@def456", "!", "mybot").is_none());
+    }
+
+    #[test]
+    fn test_parse_incoming_command_keeps_slash_start() {
+        // 自定义前缀下，/start 与深链接 /start r_<令牌> 在群里和私聊中都能识别
+        assert!(matches!(parse_incoming_command("/start", "!", "mybot", false), Some(Command::Start)));
+        assert!(matches!(parse_incoming_command("/start r_0123abcd", "!", "mybot", false), Some(Command::Start)));
+        assert!(matches!(parse_incoming_command("!start", "!", "mybot", false), Some(Command::Start)));
+        // 其他 / 命令只在私聊中接受，群里留给其他机器人
+        assert!(matches!(parse_incoming_command("/stats", "!", "mybot", true), Some(Command::Stats(_))));
+        assert!(parse_incoming_command("/stats", "!", "mybot", false).is_none());
+        assert!(parse_incoming_command("/start@otherbot", "!", "mybot", false).is_none());
+    }
+
+    #[test]
+    fn test_failure_sample() {
+        assert_eq!(failure_sample("abc123@def456"), "abc123@def456");
+        let long = "码".repeat(4096);
+        let sample = failure_sample(&long);
+        assert!(sample.starts_with(&"码".repeat(FAILURE_SAMPLE_CHARS)));
+        assert!(sample.ends_with("… (共 4096 字符)"));
+    }
+
+    #[test]
+    fn test_with_command_prefix() {
+        let text = "┣━ /stats [json] 📈\n/help 与 https://t.me/x 及 a/b";
+        assert_eq!(with_command_prefix(text, "/"), text);
+        assert_eq!(with_command_prefix(text, "!"), "┣━ !stats [json] 📈\n!help 与 https://t.me/x 及 a/b");
+    }
+
+    /// 含各解析模式特殊字符、控制字符、表情与超长内容的输入
+    fn adversarial_inputs() -> Vec<String> {
+        let mut inputs: Vec<String> = [
+            "*bold*", "_it_", "[link](http://x)", "`code`", "\\", "<b>&amp;</b>", "\u{202e}gnp.exe", "a\u{0301}\u{0301}", "\0\n\r\t",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        inputs.extend(["😀".repeat(5000), "*".repeat(10_000), "x".repeat(100_000)]);
+
+        let pool: Vec<char> = "_*[]()~`>#+-=|{}.!\\<>&\"' \n😀中\u{200b}\u{202e}a".chars().collect();
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for len in [1, 7, 64, 513, 5000] {
+            let input = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    pool[(seed % pool.len() as u64) as usize]
+                })
+                .collect();
+            inputs.push(input);
+        }
+        inputs
+    }
+
+    /// 按 MarkdownV2 规则检查没有未转义的特殊字符
+    fn assert_markdown_v2_escaped(text: &str) {
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                assert!(chars.next().is_some(), "末尾是孤立的反斜杠: {:?}", text);
+                continue;
+            }
+            assert!(!"_*[]()~`>#+-=|{}.!".contains(c), "未转义的 {:?}", c);
+        }
+    }
+
+    fn assert_html_escaped(text: &str) {
+        assert!(!text.contains(['<', '>']));
+        for (index, _) in text.match_indices('&') {
+            let rest = &text[index..];
+            assert!(["&amp;", "&lt;", "&gt;", "&quot;"].iter().any(|entity| rest.starts_with(entity)));
+        }
+    }
+
+    fn assert_fits_message(text: &str) {
+        assert!(text.encode_utf16().count() <= support::MESSAGE_LIMIT, "超出消息长度: {}", text.len());
+    }
+
+    #[test]
+    fn test_user_text_formatters_with_adversarial_input() {
+        for input in adversarial_inputs() {
+            for mode in [ParseMode::MarkdownV2, ParseMode::Html] {
+                let escaped = format::safe_user_text(&input, USER_TEXT_DISPLAY_LIMIT, Some(mode));
+                match mode {
+                    ParseMode::Html => assert_html_escaped(&escaped),
+                    _ => assert_markdown_v2_escaped(&escaped),
+                }
+            }
+
+            let user = UserStats {
+                user_id: 42,
+                username: Some(input.clone()),
+                total_requests: 1,
+                last_request: None,
+                is_banned: false,
+                deactivated_at: None,
+                blocked_at: None,
+            };
+            let welcome = default_welcome(&format::safe_user_text(&input, USER_NAME_DISPLAY_LIMIT, None), "累计 3 次", "");
+            let outputs = [
+                ban_notice(Some(&input), "永久"),
+                user_list_entry(0, &user, Activity::Active, "从未使用", "✅ 正常"),
+                appeal_notice(1, &input, 42, Some(&input), "未知", &input),
+                batch_skip_line(1, "机器码格式错误，已跳过", &input),
+                welcome.clone(),
+            ];
+            for output in &outputs {
+                assert_fits_message(output);
+                assert!(input.chars().count() <= USER_TEXT_DISPLAY_LIMIT || !output.contains(&input));
+            }
+            // 带页脚时欢迎语整体按 MarkdownV2 转义后发送
+            assert_markdown_v2_escaped(&format::escape_markdown_v2(&welcome));
+        }
+    }
+
+    #[test]
+    fn test_parse_target_ids() {
+        assert_eq!(
+            parse_target_ids("111 222,333, 222 7d spamming 3 times"),
+            (vec![111, 222, 333], vec!["7d", "spamming", "3", "times"])
+        );
+        assert_eq!(parse_target_ids("111"), (vec![111], vec![]));
+        assert_eq!(parse_target_ids("abc 111"), (vec![], vec!["abc", "111"]));
+        // 混有非数字的逗号列表视为原因的开始
+        assert_eq!(parse_target_ids("111 222,x"), (vec![111], vec!["222,x"]));
+    }
+
+    #[test]
+    fn test_batch_result_lines() {
+        assert_eq!(
+            batch_result_lines(&[1, 2, 3], &[true, false, true], "已拉黑"),
+            "┣━ 1: ✅ 已拉黑\n┣━ 2: ❓ 用户不存在\n┗━ 3: ✅ 已拉黑\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_usernames() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        database::get_or_create_user(&db, 7, Some("Alice".to_string()), None, None, Lang::Zh).await.unwrap();
+        database::get_or_create_user(&db, 8, Some("bob".to_string()), None, None, Lang::Zh).await.unwrap();
+        let now = chrono::Utc::now();
+
+        let resolved = resolve_usernames(&db, now, "@alice,@BOB 3  7d @carol spam").await.unwrap();
+        assert_eq!(resolved.args, "7,8 3 7d @carol spam");
+        assert!(resolved.missing.is_empty() && resolved.stale.is_empty());
+        assert_eq!(parse_target_ids(&resolved.args), (vec![7, 8, 3], vec!["7d", "@carol", "spam"]));
+        assert_eq!(resolve_usernames(&db, now, " 42 ").await.unwrap().args, "42");
+
+        // 没有记录的用户名
+        let resolved = resolve_usernames(&db, now, "@ALICE,@nobody").await.unwrap();
+        assert_eq!((resolved.args.as_str(), resolved.missing), ("7", vec!["@nobody".to_string()]));
+
+        // 用户名超过 30 天未再看到时提醒核对
+        let later = now + chrono::Duration::days(USERNAME_STALE_DAYS + 1);
+        let resolved = resolve_usernames(&db, later, "@aLiCe").await.unwrap();
+        assert_eq!(resolved.stale, vec![("@aLiCe".to_string(), 7, USERNAME_STALE_DAYS + 1)]);
+        database::get_or_create_user(&db, 7, Some("alice".to_string()), None, None, Lang::Zh).await.unwrap();
+        assert!(resolve_usernames(&db, now, "@ALICE").await.unwrap().stale.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deactivated_account_lifecycle() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        for user_id in [1, 2] {
+            database::get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+
+        // 其他发送错误不影响状态
+        let result: ResponseResult<()> = Err(api_error("Forbidden: bot was blocked by the user"));
+        assert!(track_deactivation(&db, 1, result).await.is_err());
+        let result: ResponseResult<()> = Err(api_error("Forbidden: user is deactivated"));
+        assert!(track_deactivation(&db, 2, result).await.is_err());
+
+        let users = database::get_all_users(&db).await.unwrap();
+        assert_eq!(broadcast_recipients(&users), vec![1]);
+        assert_eq!(database::get_system_stats(&db, None).await.unwrap().deactivated_users, 1);
+
+        // 用户再次发来消息后恢复
+        let msg = parse_message(
+            r#"{"chat":{"id":2,"first_name":"b","type":"private"},"date":1675229140,"from":{"first_name":"b","id":2,"is_bot":false},"message_id":1,"text":"hi"}"#,
+        );
+        clear_deactivation(&msg, &db).await;
+        let mut recipients = broadcast_recipients(&database::get_all_users(&db).await.unwrap());
+        recipients.sort();
+        assert_eq!(recipients, vec![1, 2]);
+        assert_eq!(database::get_system_stats(&db, None).await.unwrap().deactivated_users, 0);
+    }
+
+    fn member_update(chat_type: &str, status: &str) -> Update {
+        let json = format!(
+            r#"{{"update_id":1,"my_chat_member":{{"chat":{{"id":3,"first_name":"c","type":"{chat_type}"{title}}},"from":{{"first_name":"c","id":3,"is_bot":false}},"date":{date},"old_chat_member":{{"user":{{"first_name":"bot","id":99,"is_bot":true}},"status":"member"}},"new_chat_member":{{"user":{{"first_name":"bot","id":99,"is_bot":true}},"status":"{status}"{until}}}}}}}"#,
+            title = if chat_type == "private" { "" } else { r#","title":"g""# },
+            until = if status == "kicked" { r#","until_date":0"# } else { "" },
+            date = chrono::Utc::now().timestamp(),
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_deliver_or_refund_only_on_failed_send() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        database::get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        let quota = database::Quota { limit: 3, unlimited: false, period_start: None, trial: None, mode: QuotaMode::Requests };
+        let generate = |machine_code: &'static str| {
+            let db = db.clone();
+            async move {
+                let results = ActivationCodeGenerator::new(finalshell::CodeCase::Upper).generate_for(machine_code, &FinalShellVersionType::ALL);
+                let id = database::reserve_quota(&db, 1, quota, machine_code).await.unwrap().unwrap();
+                database::commit_generation(&db, id, 1, "7KQ2M3ZD", machine_code, quota, None, &results).await.unwrap().unwrap().1
+            }
+        };
+        let degraded = DegradedMode::default();
+
+        // 主消息发送成功：保留本次生成
+        let log_id = generate("ABC123DEF456").await;
+        let sent = deliver_or_refund(async { Ok(()) }, &degraded, &db, 1, None, "7KQ2M3ZD", Some(log_id)).await;
+        assert!(sent.is_ok());
+        assert_eq!(database::get_user_by_id(&db, 1).await.unwrap().request_count, 1);
+
+        // 主消息发送失败（如用户已屏蔽机器人）：退回配额并删除激活日志
+        let log_id = generate("ABC123DEF457").await;
+        let failed = async { Err::<(), _>(teloxide::RequestError::Api(teloxide::ApiError::BotBlocked)) };
+        assert!(deliver_or_refund(failed, &degraded, &db, 1, None, "7KQ2M3ZD", Some(log_id)).await.is_err());
+        assert_eq!(database::get_user_by_id(&db, 1).await.unwrap().request_count, 1);
+        assert!(database::get_activation_result(&db, log_id, 1).await.unwrap().is_none());
+
+        // 降级期间没有激活日志，退回内存中的计数
+        assert_eq!(degraded.try_consume(1, None, Some(1)), Some(1));
+        let failed = async { Err::<(), _>(teloxide::RequestError::Api(teloxide::ApiError::BotBlocked)) };
+        assert!(deliver_or_refund(failed, &degraded, &db, 1, None, "7KQ2M3ZD", None).await.is_err());
+        assert_eq!(degraded.try_consume(1, None, Some(1)), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_degraded_generation_counts_machine_codes() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        database::get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        let user = database::get_user_by_id(&db, 1).await.unwrap();
+        // 未配置管理员，进入与退出降级时不会发送通知
+        let config = Config::load().unwrap();
+        let bot = Bot::new("0:test");
+        let quota = database::Quota { limit: 2, unlimited: false, period_start: None, trial: None, mode: QuotaMode::Machines };
+
+        // 降级前生成过的机器码仍计入额度
+        let results = ActivationCodeGenerator::new(finalshell::CodeCase::Upper).generate_for("ABC123DEF456", &FinalShellVersionType::ALL);
+        let reservation = reserve_generation(&bot, &config, &db, &user, quota, "ABC123DEF456").await.unwrap().unwrap();
+        assert!(matches!(reservation, Reservation::Db(_)));
+        let (used, log_id) =
+            commit_reservation(&bot, &config, &db, &user, quota, reservation, "7KQ2M3ZD", "ABC123DEF456", None, &results).await.unwrap().unwrap();
+        assert_eq!((used, log_id.is_some()), (1, true));
+
+        db.inject_write_fault(true);
+        let reservation = reserve_generation(&bot, &config, &db, &user, quota, "ABC123DEF457").await.unwrap().unwrap();
+        assert!(config.degraded.is_active());
+        assert!(matches!(reservation, Reservation::Memory { used: 2 }));
+        let committed = commit_reservation(&bot, &config, &db, &user, quota, reservation, "7KQ2M3ZD", "ABC123DEF457", None, &[]);
+        assert_eq!(committed.await.unwrap(), Some((2, None)));
+
+        // 同一机器码（含降级前已计过的）不再占用额度，新的机器码超出上限
+        for machine_code in ["ABC123DEF456", "ABC123DEF457"] {
+            let reservation = reserve_generation(&bot, &config, &db, &user, quota, machine_code).await.unwrap();
+            assert!(matches!(reservation, Some(Reservation::Memory { used: 2 })));
+        }
+        assert!(reserve_generation(&bot, &config, &db, &user, quota, "ABC123DEF458").await.unwrap().is_none());
+
+        // 仍不可写时保持降级，恢复写入后退出并清空内存计数
+        probe_degraded(&bot, &config, &db).await;
+        assert!(config.degraded.is_active());
+        db.inject_write_fault(false);
+        probe_degraded(&bot, &config, &db).await;
+        assert!(!config.degraded.is_active());
+        let reservation = reserve_generation(&bot, &config, &db, &user, quota, "ABC123DEF458").await.unwrap();
+        assert!(matches!(reservation, Some(Reservation::Db(_))));
+    }
+
+    #[tokio::test]
+    async fn test_my_chat_member_tracks_blocks() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        for user_id in [1, 3] {
+            database::get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+        let dispatch = |update: Update| {
+            let db = db.clone();
+            async move { assert!(schema().dispatch(dptree::deps![update, db]).await.is_break()) }
+        };
+
+        dispatch(member_update("private", "kicked")).await;
+        let users = database::get_all_users(&db).await.unwrap();
+        assert_eq!(broadcast_recipients(&users), vec![1]);
+        let stats = database::get_system_stats(&db, None).await.unwrap();
+        assert_eq!((stats.blocked_users, stats.blocked_this_week), (1, 1));
+
+        // 群聊中被移出不影响私聊可达状态
+        dispatch(member_update("private", "member")).await;
+        dispatch(member_update("group", "kicked")).await;
+        let mut recipients = broadcast_recipients(&database::get_all_users(&db).await.unwrap());
+        recipients.sort();
+        assert_eq!(recipients, vec![1, 3]);
+        let stats = database::get_system_stats(&db, None).await.unwrap();
+        assert_eq!((stats.blocked_users, stats.blocked_this_week), (0, 1));
+    }
+
+    #[test]
+    fn test_benign_edit_errors() {
+        assert!(is_benign_edit_error(&api_error(
+            "Bad Request: message is not modified: specified new message content and reply markup are exactly \
+             the same as a current content and reply markup of the message"
+        )));
+        assert!(is_benign_edit_error(&api_error(
+            "Bad Request: query is too old and response timeout expired or query id is invalid"
+        )));
+        // 文案变化后解析为 Unknown 时仍能识别
+        assert!(is_benign_edit_error(&api_error("Bad Request: message is not modified")));
+
+        assert!(!is_benign_edit_error(&api_error("Forbidden: bot was blocked by the user")));
+        assert!(!is_benign_edit_error(&api_error("Bad Request: message to edit not found")));
+        assert!(!is_benign_edit_error(&teloxide::RequestError::RetryAfter(Duration::from_secs(3))));
+    }
+
+    #[test]
+    fn test_recipient_errors() {
+        assert!(is_recipient_error(&api_error("Forbidden: bot was blocked by the user")));
+        assert!(is_recipient_error(&api_error("Forbidden: user is deactivated")));
+        assert!(is_recipient_error(&api_error("Forbidden: bot can't initiate conversation with a user")));
+        // 新出现的 Forbidden 文案解析为 Unknown 时同样视为收件人错误
+        assert!(is_recipient_error(&api_error("Forbidden: bot was kicked from the channel chat")));
+
+        assert!(!is_recipient_error(&api_error("Unauthorized")));
+        assert!(!is_recipient_error(&teloxide::RequestError::RetryAfter(Duration::from_secs(3))));
+    }
+
+    #[test]
+    fn test_topic_thread_id_in_forum_topic() {
+        let msg = parse_message(
+            r#"{"chat":{"id":-1001847508954,"is_forum":true,"title":"admins","type":"supergroup"},"date":1675229140,"from":{"first_name":"a","id":1,"is_bot":false},"is_topic_message":true,"message_id":5,"message_thread_id":4,"text":"/stats"}"#,
+        );
+        assert_eq!(topic_thread_id(&msg), Some(4));
+    }
+
+    #[test]
+    fn test_topic_thread_id_omitted_in_normal_group() {
+        // 普通超级群组里的回复串也带有 message_thread_id，但不能当作话题发送
+        let msg = parse_message(
+            r#"{"chat":{"id":-1001847508955,"title":"admins","type":"supergroup"},"date":1675229140,"from":{"first_name":"a","id":1,"is_bot":false},"message_id":7,"message_thread_id":6,"text":"/stats"}"#,
+        );
+        assert_eq!(topic_thread_id(&msg), None);
+
+        let msg = parse_message(
+            r#"{"chat":{"id":1,"first_name":"a","type":"private"},"date":1675229140,"from":{"first_name":"a","id":1,"is_bot":false},"message_id":8,"text":"/start"}"#,
+        );
+        assert_eq!(topic_thread_id(&msg), None);
+    }
+
+    const PRIVATE_CHAT: &str = r#""chat":{"id":1,"first_name":"a","type":"private"},"date":1675229140,"from":{"first_name":"a","id":1,"is_bot":false}"#;
+
+    #[test]
+    fn test_machine_code_text_from_caption_and_forward() {
+        let photo = parse_message(&format!(
+            r#"{{{},"message_id":9,"photo":[{{"file_id":"p","file_unique_id":"u","width":90,"height":60}}],"caption":"abc123@def456"}}"#,
+            PRIVATE_CHAT
+        ));
+        assert_eq!(machine_code_text(&photo, "unlock_bot"), Some("abc123@def456"));
+
+        let forwarded = parse_message(&format!(
+            r#"{{{},"message_id":10,"forward_from":{{"first_name":"b","id":2,"is_bot":false}},"forward_date":1675229000,"text":"abc123@def456"}}"#,
+            PRIVATE_CHAT
+        ));
+        assert!(forwarded.forward().is_some());
+        assert_eq!(machine_code_text(&forwarded, "unlock_bot"), Some("abc123@def456"));
+
+        // 没有说明文字的图片直接忽略
+        let plain_photo = parse_message(&format!(
+            r#"{{{},"message_id":11,"photo":[{{"file_id":"p","file_unique_id":"u","width":90,"height":60}}]}}"#,
+            PRIVATE_CHAT
+        ));
+        assert_eq!(machine_code_text(&plain_photo, "unlock_bot"), None);
+    }
+
+    #[test]
+    fn test_machine_code_from_quoted_message_in_group() {
+        let group = r#""chat":{"id":-1001847508955,"title":"g","type":"supergroup"},"date":1675229140"#;
+        let quoted = format!(
+            r#"{{{},"from":{{"first_name":"b","id":2,"is_bot":false}},"message_id":20,"text":"abc123@def456"}}"#,
+            group
+        );
+        let reply = |text: &str| {
+            parse_message(&format!(
+                r#"{{{},"from":{{"first_name":"a","id":1,"is_bot":false}},"message_id":21,"reply_to_message":{},"text":"{}"}}"#,
+                group, quoted, text
+            ))
+        };
+
+        let msg = reply("@Unlock_Bot 帮忙生成");
+        assert_eq!(machine_code_text(&msg, "unlock_bot"), Some("abc123@def456"));
+        // 请求者是回复的人，而不是原消息作者
+        assert_eq!(msg.from().unwrap().id.0, 1);
+
+        // 被回复的消息夹带说明文字时只取其中的机器码
+        let chatty = format!(
+            r#"{{{},"from":{{"first_name":"b","id":2,"is_bot":false}},"message_id":20,"text":"我的机器码是 abc123@def456，麻烦了"}}"#,
+            group
+        );
+        let msg = parse_message(&format!(
+            r#"{{{},"from":{{"first_name":"a","id":1,"is_bot":false}},"message_id":21,"reply_to_message":{},"text":"@unlock_bot"}}"#,
+            group, chatty
+        ));
+        assert_eq!(machine_code_text(&msg, "unlock_bot"), Some("abc123@def456"));
+
+        // 没有 @机器人 的回复按自身文字处理
+        assert_eq!(machine_code_text(&reply("谢谢"), "unlock_bot"), Some("谢谢"));
+        assert!(quoted_message(&reply("@unlock_bot_fan 你好"), "unlock_bot").is_none());
+
+        // 私聊中不启用
+        let private = parse_message(&format!(
+            r#"{{{},"message_id":22,"reply_to_message":{{{},"message_id":19,"text":"abc123@def456"}},"text":"@unlock_bot"}}"#,
+            PRIVATE_CHAT, PRIVATE_CHAT
+        ));
+        assert_eq!(machine_code_text(&private, "unlock_bot"), Some("@unlock_bot"));
+    }
+
+    #[test]
+    fn test_bare_mention_in_group() {
+        let group = r#""chat":{"id":-1001847508955,"title":"g","type":"supergroup"},"date":1675229140,"from":{"first_name":"a","id":1,"is_bot":false}"#;
+        let message = |chat: &str, text: &str| parse_message(&format!(r#"{{{},"message_id":23,"text":"{}"}}"#, chat, text));
+
+        assert!(is_bare_mention(&message(group, " @Unlock_Bot "), "unlock_bot"));
+        assert!(!is_bare_mention(&message(group, "@unlock_bot abc123@def456"), "unlock_bot"));
+        assert!(!is_bare_mention(&message(group, "@unlock_bot_fan"), "unlock_bot"));
+        assert!(!is_bare_mention(&message(group, "大家好"), "unlock_bot"));
+        assert!(!is_bare_mention(&message(PRIVATE_CHAT, "@unlock_bot"), "unlock_bot"));
+    }
+
+    #[test]
+    fn test_captioned_screenshot_is_not_a_batch_upload() {
+        let document = |name: &str, caption: Option<&str>| {
+            let caption = caption.map(|c| format!(r#","caption":"{}""#, c)).unwrap_or_default();
+            parse_message(&format!(
+                r#"{{{},"message_id":12,"document":{{"file_id":"d","file_unique_id":"u","file_name":"{}"}}{}}}"#,
+                PRIVATE_CHAT, name, caption
+            ))
+        };
+
+        for (msg, expected) in [
+            (document("codes.txt", None), true),
+            (document("codes.txt", Some("批量")), true),
+            (document("screenshot.png", None), true),
+            (document("screenshot.png", Some("abc123@def456")), false),
+        ] {
+            assert_eq!(is_batch_upload(&msg, msg.document().unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_say_args() {
+        assert_eq!(parse_say_args("  系统维护通知 "), (false, "系统维护通知"));
+        assert_eq!(parse_say_args("--test 系统维护通知"), (true, "系统维护通知"));
+        assert_eq!(parse_say_args("--test"), (true, ""));
+        assert_eq!(parse_say_args("--testing 不是标记"), (false, "--testing 不是标记"));
+    }
+
+    #[test]
+    fn test_parse_batch_lines() {
+        let content = "# 我的机器码\nABC123DEF456\n\n  XYZ987654321  \r\n";
+        assert_eq!(parse_batch_lines(content), vec![(2, "ABC123DEF456"), (4, "XYZ987654321")]);
+    }
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 时间来源；业务代码通过它取当前时间，测试中可换成手动推进的 `MockClock`
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// 当前的墙上时间
+    fn now_utc(&self) -> DateTime<Utc>;
+
+    /// 单调时钟，用于计算耗时与冷却
+    fn instant(&self) -> Instant;
+}
+
+/// 系统时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 共享的时钟句柄，默认使用系统时钟；随 Config 注入机器人处理函数与守护进程
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(Arc::new(SystemClock))
+    }
+}
+
+impl SharedClock {
+    #[cfg(test)]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        SharedClock(clock)
+    }
+
+    pub fn now_utc(&self) -> DateTime<Utc> {
+        self.0.now_utc()
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0.instant()
+    }
+}
+
+/// 测试用时钟，时间只在调用 `advance` 时前进
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    start_utc: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: std::sync::Mutex<std::time::Duration>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        MockClock {
+            start_utc: start,
+            start_instant: Instant::now(),
+            elapsed: std::sync::Mutex::new(std::time::Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: std::time::Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    fn elapsed(&self) -> std::time::Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.start_utc + chrono::Duration::from_std(self.elapsed()).unwrap()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock_advances_manually() {
+        let start = Utc.with_ymd_and_hms(2025, 8, 15, 23, 59, 0).unwrap();
+        let mock = Arc::new(MockClock::new(start));
+        let clock = SharedClock::new(mock.clone());
+        let started = clock.instant();
+
+        assert_eq!(clock.now_utc(), start);
+        mock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now_utc(), Utc.with_ymd_and_hms(2025, 8, 16, 0, 0, 30).unwrap());
+        assert_eq!(clock.instant() - started, Duration::from_secs(90));
+    }
+}
@@ -1,41 +1,513 @@
 use anyhow::{Context, Result};
+use chrono::FixedOffset;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    abuse::AbuseMode,
+    clock::SharedClock,
+    database,
+    alerts::AlertDeduper,
+    degraded::DegradedMode,
+    finalshell::{CheckDigit, CodeCase, FinalShellVersionType, RedactionPolicy},
+    footer::{self, FooterLink, FooterStyle},
+    format::{self, StatsFormat},
+    i18n::Lang,
+    input_filter::InputFilter,
+    models::User,
+    upload,
+    quota::{QuotaMode, QuotaPeriod},
+    result_image::ResultFont,
+    trial,
+    utils,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub bot_token: String,
-    pub chat_id: i64,
+    /// 未配置 BOT_TOKEN/CHAT_ID 时为 None，此时只能运行本地检查；机器人启动前必须通过 `require_telegram` 校验
+    pub telegram: Option<TelegramConfig>,
+    /// 管理群为论坛群组时，守护报告与告警发送到的话题
+    pub report_topic_id: Option<i32>,
+    pub alert_topic_id: Option<i32>,
+    /// 相同告警的冷却窗口（秒），窗口内重复的告警只累计次数，0 表示不去重
+    pub alert_dedup_window: u64,
+    /// 告警去重状态，运行时更新，不参与序列化
+    #[serde(skip)]
+    pub alerts: AlertDeduper,
     pub admin_ids: Vec<i64>,
+    /// 不受次数上限与试用期额度限制的用户（如自动化脚本），不具备管理权限
+    pub rate_limit_whitelist: Vec<i64>,
+    /// 是否将管理群（CHAT_ID）的群管理员自动视为机器人管理员
+    pub auto_group_admins: bool,
+    pub group_admin_refresh_secs: u64, // 秒
+    /// 运行时从 Telegram 拉取的群管理员缓存，不参与序列化
+    #[serde(skip)]
+    pub group_admins: GroupAdminCache,
+    /// 取当前时间的时钟，测试中可替换为手动推进的时钟，不参与序列化
+    #[serde(skip)]
+    pub clock: SharedClock,
+    /// 数据库不可写时的降级状态，运行时切换，不参与序列化
+    #[serde(skip)]
+    pub degraded: DegradedMode,
     pub database_url: String,
+    pub read_replica_url: Option<String>,
+    /// 多个机器人共用一个数据库时区分数据来源的实例标识
+    pub instance_id: String,
+    /// 命令前缀，默认 `/`；与其他机器人共处一群时可改为 `!` 等避免命令冲突
+    pub command_prefix: String,
     pub max_user_requests: i32,
+    /// MAX_USER_REQUESTS 的计算周期：按自然日、自然月重置，或累计不重置
+    pub quota_period: QuotaPeriod,
+    /// MAX_USER_REQUESTS 的计数方式：按生成次数，或按周期内不同的机器码数
+    pub quota_mode: QuotaMode,
+    /// 每个用户滚动一小时内最多生成的次数，与 MAX_USER_REQUESTS 分开计算；None 表示不限制
+    pub max_requests_per_hour: Option<u32>,
+    pub max_batch_file_size: u32, // 字节，批量上传的 .txt 文件大小上限
+    /// 激活码结果中的版本展示顺序，未列出的版本排在末尾
+    pub version_order: Vec<FinalShellVersionType>,
+    /// 启用（展示）的版本，未启用的版本不会生成
+    pub enabled_versions: Vec<FinalShellVersionType>,
+    /// 管理员通过 /versions 临时停用的版本，保存在 settings 中，不参与序列化
+    #[serde(skip)]
+    pub disabled_versions: DisabledVersions,
+    /// 激活码输出的大小写
+    pub code_case: CodeCase,
+    /// 管理员查询、群内回复等场景中机器码的脱敏方式
+    pub machine_code_display: RedactionPolicy,
+    /// 按版本启用的机器码校验位算法；未通过时仅提示用户核对，不阻断生成
+    pub checksum_profiles: Vec<(FinalShellVersionType, CheckDigit)>,
+    /// 试用延长码的盐值；算法尚未实现，仅作配置占位
+    pub trial_extension_salt: Option<String>,
+    /// 24 小时内同一版本的激活失败反馈超过该数量时告警管理员
+    pub code_report_alert_threshold: i64,
+    /// 窗口期内为超过该数量的不同机器码生成激活码时标记用户，0 表示关闭检测
+    pub abuse_machine_code_threshold: i64,
+    pub abuse_window_hours: i64,
+    pub abuse_mode: AbuseMode,
+    /// 机器码输入的链接/敏感词过滤规则
+    pub input_filter: InputFilter,
+    /// 输入被过滤时是否告警管理员
+    pub input_filter_alert: bool,
+    /// 新用户注册后的试用期（小时），0 表示关闭
+    pub trial_hours: i64,
+    /// 试用期内每 24 小时可生成的次数
+    pub trial_daily_limit: i32,
+    /// 用户活跃度分级阈值（天）：最近请求在前者以内为活跃，后者以内为沉睡，否则为流失
+    pub active_user_days: i64,
+    pub dormant_user_days: i64,
     pub log_level: String,
     pub guard_check_interval: u64, // 秒
+    /// 守护进程定时备份、清理日志与历史记录的间隔（秒），0 表示不运行
+    pub guard_backup_interval: u64,
+    pub guard_cleanup_interval: u64,
+    /// 守护进程解除到期临时封禁的间隔（秒），0 表示不运行
+    pub ban_expiry_interval: u64,
+    /// 临时封禁到期解除后是否私信通知用户
+    pub ban_expiry_notify: bool,
+    /// 请求耗时与守护检查历史的保留天数，0 表示永久保留
+    pub history_retention_days: i64,
+    /// 清理任务是否将上个月及更早的激活日志移入按月归档表
+    pub archive_activation_logs: bool,
+    /// 激活日志原始记录的保留天数，超出后按天汇总到 daily_summary 并删除，0 表示永久保留
+    pub activation_log_retention_days: i64,
+    /// 运维卫生检查阈值（天），0 表示关闭对应检查
+    pub token_rotation_days: i64,
+    pub backup_max_age_days: i64,
+    pub admin_inactive_days: i64,
+    pub guard_command_cooldown: u64, // 秒，/guard 每位管理员的冷却时间
+    pub backup_command_cooldown: u64, // 秒，/backup 与 /export 每位管理员的冷却时间
+    /// 待发送消息队列超过该长度视为积压
+    pub throttle_queue_threshold: usize,
+    pub throttle_warn_after: u64, // 秒，积压持续超过该时长时健康状态为 WARNING
+    pub inode_usage_threshold: f64, // inode 使用率 (%) 达到该值时健康状态为 WARNING
+    /// 开启后 CPU 使用率达到 `busy_cpu_threshold` 时拒绝普通用户的机器码，管理员不受影响
+    pub busy_reject: bool,
+    pub busy_cpu_threshold: f64, // CPU 使用率 (%)
+    pub clock_skew_threshold: i64, // 秒，与 Telegram 服务器的时钟偏差超过该值时健康状态为 WARNING
+    pub update_lag_threshold: u64, // 秒，24 小时内更新处理延迟的 p95 超过该值时健康状态为 WARNING
+    pub http_bind: Option<String>,
+    pub http_api_key: Option<String>,
+    /// /export 导出文件的加密口令，未设置时明文导出
+    pub export_passphrase: Option<String>,
+    /// HTTP 生成接口幂等键的有效期（秒）
+    pub idempotency_ttl: u64,
+    /// /loglevel 或 SIGUSR1 临时调整日志级别后自动恢复的时间（秒）
+    pub log_level_revert: u64,
+    /// 每次生成成功后推送事件的 webhook 地址（需启用 webhook-hook 功能）
+    pub generation_webhook_url: Option<String>,
+    /// 匿名使用统计的上报地址，仅在 TELEMETRY_OPTIN=true 时有值
+    pub telemetry_endpoint: Option<String>,
+    pub use_emoji: bool,
+    /// /start 时是否附带常驻的快捷菜单键盘
+    pub reply_menu: bool,
+    /// 是否在激活码回复中显示生成耗时
+    pub show_latency: bool,
+    /// 是否将非推荐版本的激活码折叠为 spoiler，点开才显示
+    pub fold_versions: bool,
+    /// 私聊中生成成功后是否置顶结果（替换上一次置顶的结果）
+    pub pin_results: bool,
+    /// 是否在结果下提供一次性深链接，便于在其他设备上查看；开启后结果会加密保存至多 10 分钟
+    pub result_links: bool,
+    /// 激活码以图片发送时使用的字体；为空表示以文字发送 (RESULT_IMAGE=false)
+    #[serde(skip)]
+    pub result_image_font: Option<ResultFont>,
+    /// /stats 的发送格式 (纯文本或 HTML 卡片)
+    pub stats_format: StatsFormat,
+    /// 附加在 /start 与激活码结果下方的自定义链接，为空时不显示
+    pub footer_links: Vec<FooterLink>,
+    pub footer_style: FooterStyle,
+    /// 文案实验名，参与分桶哈希；更换后重新分桶并重新统计
+    pub copy_experiment: String,
+    /// 看到新文案的用户比例 (%)，0 表示关闭实验
+    pub copy_variant_percent: u8,
+    /// 新文案：欢迎语（支持 {name}、{limit} 占位符）与使用教程，未设置的部分沿用原文案
+    pub copy_variant_welcome: Option<String>,
+    pub copy_variant_tutorial: Option<String>,
+    pub welcome_debounce: u64, // 秒，同一用户在该间隔内重复 /start 只回复一次欢迎语，0 表示不去抖
+    pub default_lang: Lang,
+    pub utc_offset_seconds: i32,
+}
+
+/// Telegram 连接配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    /// 管理群，守护报告与告警发送到这里
+    pub chat_id: i64,
+}
+
+/// 读取 Telegram 配置：BOT_TOKEN 与 CHAT_ID 都未设置时返回 None，只设置其一视为配置错误
+fn env_telegram() -> Result<Option<TelegramConfig>> {
+    let bot_token = env::var("BOT_TOKEN").ok().filter(|s| !s.trim().is_empty());
+    let chat_id = env::var("CHAT_ID").ok().filter(|s| !s.trim().is_empty());
+
+    match (bot_token, chat_id) {
+        (None, None) => Ok(None),
+        (Some(bot_token), Some(chat_id)) => Ok(Some(TelegramConfig {
+            bot_token,
+            chat_id: chat_id.trim().parse::<i64>().context("CHAT_ID 格式错误")?,
+        })),
+        (None, Some(_)) => anyhow::bail!("BOT_TOKEN 环境变量未设置"),
+        (Some(_), None) => anyhow::bail!("CHAT_ID 环境变量未设置"),
+    }
+}
+
+/// 读取实例标识：未设置时为默认实例，只允许字母、数字、`-` 与 `_`
+fn env_instance_id() -> Result<String> {
+    let value = env::var("INSTANCE_ID").unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(database::DEFAULT_INSTANCE_ID.to_string());
+    }
+    if value.len() > 32 || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        anyhow::bail!("INSTANCE_ID 格式错误: {}（最多 32 位字母、数字、- 或 _）", value);
+    }
+    Ok(value.to_string())
+}
+
+/// 默认命令前缀
+pub const DEFAULT_COMMAND_PREFIX: &str = "/";
+
+/// 导出文件加密口令的环境变量，`decrypt-export` 命令也从这里读取口令
+pub const EXPORT_PASSPHRASE_VAR: &str = "EXPORT_PASSPHRASE";
+
+/// 解析命令前缀：留空为 `/`；最多 3 个字符，不能含空白或机器码可用的字符，避免把机器码当成命令
+fn parse_command_prefix(value: &str) -> Result<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(DEFAULT_COMMAND_PREFIX.to_string());
+    }
+    if value.chars().count() > 3
+        || value.chars().any(|c| c.is_whitespace() || c.is_alphanumeric() || matches!(c, '@' | '-' | '_'))
+    {
+        anyhow::bail!("COMMAND_PREFIX 格式错误: {}（最多 3 个符号，不能含字母、数字、空白、@、- 或 _）", value);
+    }
+    Ok(value.to_string())
+}
+
+/// 管理群群管理员缓存，克隆后共享同一份数据
+#[derive(Debug, Clone, Default)]
+pub struct GroupAdminCache(Arc<RwLock<HashSet<i64>>>);
+
+impl GroupAdminCache {
+    pub fn replace(&self, ids: impl IntoIterator<Item = i64>) {
+        let mut admins = self.0.write().unwrap_or_else(|e| e.into_inner());
+        *admins = ids.into_iter().collect();
+    }
+
+    pub fn contains(&self, user_id: i64) -> bool {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).contains(&user_id)
+    }
+}
+
+/// settings 中保存停用版本的键，值为逗号分隔的 ASCII 版本名
+pub const DISABLED_VERSIONS_SETTING: &str = "disabled_versions";
+
+/// 运行时停用的版本，克隆后共享同一份数据
+#[derive(Debug, Clone, Default)]
+pub struct DisabledVersions(Arc<RwLock<Vec<FinalShellVersionType>>>);
+
+impl DisabledVersions {
+    pub fn replace(&self, versions: Vec<FinalShellVersionType>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = versions;
+    }
+
+    pub fn contains(&self, version: FinalShellVersionType) -> bool {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).contains(&version)
+    }
+
+    /// 解析 settings 中保存的停用列表；无法识别的版本名忽略
+    pub fn parse(value: &str) -> Vec<FinalShellVersionType> {
+        value
+            .split(',')
+            .filter_map(|name| FinalShellVersionType::from_name_ascii(name.trim()))
+            .collect()
+    }
+
+    /// 在 `disabled` 上切换 `version` 后的停用列表；`available` 中的版本全部被停用时返回错误
+    pub fn toggled(
+        mut disabled: Vec<FinalShellVersionType>,
+        available: &[FinalShellVersionType],
+        version: FinalShellVersionType,
+        enable: bool,
+    ) -> Result<Vec<FinalShellVersionType>> {
+        disabled.retain(|v| *v != version);
+        if !enable {
+            disabled.push(version);
+        }
+        if available.iter().all(|v| disabled.contains(v)) {
+            anyhow::bail!("至少需要保留一个启用的版本");
+        }
+        Ok(disabled)
+    }
+}
+
+/// 读取多行文案，`\n` 转换为换行；未设置或为空时返回 None
+fn env_copy_text(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.replace("\\n", "\n"))
+}
+
+/// 读取布尔类型的环境变量，未设置或无法识别时使用默认值
+fn env_bool(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => default,
+        },
+        Err(_) => default,
+    }
+}
+
+/// 读取可选的论坛话题 ID，未设置或为空时返回 None
+fn env_topic_id(name: &str) -> Result<Option<i32>> {
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse::<i32>()
+            .map(Some)
+            .with_context(|| format!("{} 格式错误", name)),
+        _ => Ok(None),
+    }
+}
+
+/// 解析以逗号分隔的用户 ID 列表，忽略无法解析的项
+fn parse_ids(value: &str) -> Vec<i64> {
+    value
+        .split(',')
+        .filter_map(|s| s.trim().parse::<i64>().ok())
+        .collect()
+}
+
+/// 读取以逗号分隔的版本列表（如 "4.6+,4.5"），未设置时返回 None
+fn env_versions(name: &str) -> Result<Option<Vec<FinalShellVersionType>>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_versions(&value)
+        .map(Some)
+        .with_context(|| format!("{} 格式错误（可选值: <3.9.6, >=3.9.6, 4.5, 4.6+）", name))
+}
+
+fn parse_versions(value: &str) -> Result<Vec<FinalShellVersionType>> {
+    let mut versions = Vec::new();
+    for name in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let version = FinalShellVersionType::from_name_ascii(name)
+            .with_context(|| format!("未知版本: {}", name))?;
+        if !versions.contains(&version) {
+            versions.push(version);
+        }
+    }
+    Ok(versions)
+}
+
+/// 解析以逗号分隔的 `版本=算法` 列表（如 "4.6+=luhn,4.5=luhn36"），同一版本以最后一项为准
+fn parse_checksum_profiles(value: &str) -> Result<Vec<(FinalShellVersionType, CheckDigit)>> {
+    let mut profiles: Vec<(FinalShellVersionType, CheckDigit)> = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (name, check) = item
+            .split_once('=')
+            .with_context(|| format!("缺少校验算法: {}", item))?;
+        let version = FinalShellVersionType::from_name_ascii(name.trim())
+            .with_context(|| format!("未知版本: {}", name.trim()))?;
+        let check = CheckDigit::from_name(check)
+            .with_context(|| format!("未知校验算法: {}", check.trim()))?;
+        profiles.retain(|(v, _)| *v != version);
+        profiles.push((version, check));
+    }
+    Ok(profiles)
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let bot_token = env::var("BOT_TOKEN")
-            .context("BOT_TOKEN 环境变量未设置")?;
-        
-        let chat_id = env::var("CHAT_ID")
-            .context("CHAT_ID 环境变量未设置")?
-            .parse::<i64>()
-            .context("CHAT_ID 格式错误")?;
+        let telegram = env_telegram()?;
 
-        let admin_ids = env::var("ADMIN_IDS")
-            .unwrap_or_default()
-            .split(',')
-            .filter_map(|s| s.trim().parse::<i64>().ok())
-            .collect();
+        let report_topic_id = env_topic_id("REPORT_TOPIC_ID")?;
+        let alert_topic_id = env_topic_id("ALERT_TOPIC_ID")?;
+        let alert_dedup_window = env::var("ALERT_DEDUP_WINDOW")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse::<u64>()
+            .unwrap_or(600);
+
+        let admin_ids = parse_ids(&env::var("ADMIN_IDS").unwrap_or_default());
+        let rate_limit_whitelist = parse_ids(&env::var("RATE_LIMIT_WHITELIST").unwrap_or_default());
+
+        let auto_group_admins = env_bool("AUTO_GROUP_ADMINS", false);
+
+        let group_admin_refresh_secs = env::var("GROUP_ADMIN_REFRESH_SECS")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse::<u64>()
+            .unwrap_or(600);
 
         let database_url = env::var("DATABASE_URL")
             .unwrap_or_else(|_| "sqlite:./finalshell_bot.db".to_string());
 
+        let instance_id = env_instance_id()?;
+
+        let command_prefix = parse_command_prefix(&env::var("COMMAND_PREFIX").unwrap_or_default())?;
+
+        let read_replica_url = env::var("READ_REPLICA_URL")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
         let max_user_requests = env::var("MAX_USER_REQUESTS")
             .unwrap_or_else(|_| "3".to_string())
             .parse::<i32>()
             .unwrap_or(3);
+        let quota_period = match env::var("QUOTA_PERIOD") {
+            Ok(value) if !value.trim().is_empty() => QuotaPeriod::from_name(&value)
+                .with_context(|| format!("QUOTA_PERIOD 格式错误: {}（可选值: daily, monthly, lifetime）", value))?,
+            _ => QuotaPeriod::default(),
+        };
+        let quota_mode = match env::var("QUOTA_MODE") {
+            Ok(value) if !value.trim().is_empty() => QuotaMode::from_name(&value)
+                .with_context(|| format!("QUOTA_MODE 格式错误: {}（可选值: requests, machines）", value))?,
+            _ => QuotaMode::default(),
+        };
+        let max_requests_per_hour = match env::var("MAX_REQUESTS_PER_HOUR") {
+            Ok(value) if !value.trim().is_empty() => Some(
+                value
+                    .trim()
+                    .parse::<u32>()
+                    .with_context(|| format!("MAX_REQUESTS_PER_HOUR 格式错误: {}（应为非负整数，0 表示不限制）", value))?,
+            )
+            .filter(|limit| *limit > 0),
+            _ => None,
+        };
+
+        let max_batch_file_size = env::var("MAX_BATCH_FILE_KB")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse::<u32>()
+            .unwrap_or(64)
+            .clamp(1, 20 * 1024)
+            * 1024;
+
+        let version_order = env_versions("VERSION_ORDER")?
+            .unwrap_or_else(|| FinalShellVersionType::ALL.to_vec());
+        let enabled_versions = env_versions("ENABLED_VERSIONS")?
+            .unwrap_or_else(|| FinalShellVersionType::ALL.to_vec());
+        if enabled_versions.is_empty() {
+            anyhow::bail!("ENABLED_VERSIONS 至少需要启用一个版本");
+        }
+
+        let code_case = match env::var("CODE_CASE") {
+            Ok(value) if !value.trim().is_empty() => CodeCase::from_name(&value)
+                .with_context(|| format!("CODE_CASE 格式错误: {}（可选值: upper, lower, asis）", value))?,
+            _ => CodeCase::default(),
+        };
+
+        let machine_code_display = match env::var("MACHINE_CODE_DISPLAY") {
+            Ok(value) if !value.trim().is_empty() => RedactionPolicy::from_name(&value)
+                .with_context(|| format!("MACHINE_CODE_DISPLAY 格式错误: {}（可选值: full, prefix4, hash, none）", value))?,
+            _ => RedactionPolicy::default(),
+        };
+
+        let checksum_profiles = match env::var("MACHINE_CODE_CHECKSUM") {
+            Ok(value) => parse_checksum_profiles(&value).with_context(|| {
+                format!("MACHINE_CODE_CHECKSUM 格式错误: {}（示例: 4.6+=luhn,4.5=luhn36）", value)
+            })?,
+            Err(_) => Vec::new(),
+        };
+
+        let trial_extension_salt = env::var("TRIAL_EXTENSION_SALT")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let code_report_alert_threshold = env::var("CODE_REPORT_ALERT_THRESHOLD")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse::<i64>()
+            .unwrap_or(5);
+
+        let abuse_machine_code_threshold = env::var("ABUSE_MACHINE_CODE_THRESHOLD")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse::<i64>()
+            .unwrap_or(20);
+
+        let abuse_window_hours = env::var("ABUSE_WINDOW_HOURS")
+            .unwrap_or_else(|_| "24".to_string())
+            .parse::<i64>()
+            .unwrap_or(24);
+
+        let abuse_mode = match env::var("ABUSE_MODE") {
+            Ok(value) if !value.trim().is_empty() => AbuseMode::from_name(&value)
+                .with_context(|| format!("ABUSE_MODE 格式错误: {}（可选值: notify, restrict）", value))?,
+            _ => AbuseMode::default(),
+        };
+
+        let input_filter = InputFilter::new(
+            env_bool("INPUT_FILTER_URLS", true),
+            &env::var("INPUT_FILTER_WORDS").unwrap_or_default(),
+        );
+        let input_filter_alert = env_bool("INPUT_FILTER_ALERT", false);
+
+        let trial_hours = env::var("TRIAL_HOURS")
+            .unwrap_or_else(|_| "48".to_string())
+            .parse::<i64>()
+            .unwrap_or(48);
+
+        let trial_daily_limit = env::var("TRIAL_DAILY_LIMIT")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse::<i32>()
+            .unwrap_or(1);
+
+        let active_user_days = env::var("ACTIVE_USER_DAYS")
+            .unwrap_or_else(|_| "7".to_string())
+            .parse::<i64>()
+            .unwrap_or(7);
+
+        let dormant_user_days = env::var("DORMANT_USER_DAYS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<i64>()
+            .unwrap_or(30);
 
         let log_level = env::var("LOG_LEVEL")
             .unwrap_or_else(|_| "info".to_string());
@@ -45,28 +517,406 @@ impl Config {
             .parse::<u64>()
             .unwrap_or(86400);
 
+        let guard_backup_interval = env::var("GUARD_BACKUP_INTERVAL")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse::<u64>()
+            .unwrap_or(86400);
+
+        let guard_cleanup_interval = env::var("GUARD_CLEANUP_INTERVAL")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse::<u64>()
+            .unwrap_or(86400);
+
+        let ban_expiry_interval = env::var("BAN_EXPIRY_INTERVAL")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse::<u64>()
+            .unwrap_or(300);
+        let ban_expiry_notify = env_bool("BAN_EXPIRY_NOTIFY", false);
+
+        let history_retention_days = env::var("HISTORY_RETENTION_DAYS")
+            .unwrap_or_else(|_| "365".to_string())
+            .parse::<i64>()
+            .unwrap_or(365);
+
+        let archive_activation_logs = env_bool("ARCHIVE_ACTIVATION_LOGS", false);
+        let activation_log_retention_days = env::var("ACTIVATION_LOG_RETENTION_DAYS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<i64>()
+            .unwrap_or(0);
+
+        let token_rotation_days = env::var("TOKEN_ROTATION_DAYS")
+            .unwrap_or_else(|_| "90".to_string())
+            .parse::<i64>()
+            .unwrap_or(90);
+
+        let backup_max_age_days = env::var("BACKUP_MAX_AGE_DAYS")
+            .unwrap_or_else(|_| "7".to_string())
+            .parse::<i64>()
+            .unwrap_or(7);
+
+        let admin_inactive_days = env::var("ADMIN_INACTIVE_DAYS")
+            .unwrap_or_else(|_| "90".to_string())
+            .parse::<i64>()
+            .unwrap_or(90);
+
+        let guard_command_cooldown = env::var("GUARD_COMMAND_COOLDOWN")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse::<u64>()
+            .unwrap_or(60);
+
+        let backup_command_cooldown = env::var("BACKUP_COMMAND_COOLDOWN")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse::<u64>()
+            .unwrap_or(300);
+
+        let throttle_queue_threshold = env::var("THROTTLE_QUEUE_THRESHOLD")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse::<usize>()
+            .unwrap_or(100);
+
+        let throttle_warn_after = env::var("THROTTLE_WARN_AFTER")
+            .unwrap_or_else(|_| "180".to_string())
+            .parse::<u64>()
+            .unwrap_or(180);
+
+        let inode_usage_threshold = env::var("INODE_USAGE_THRESHOLD")
+            .unwrap_or_else(|_| "90".to_string())
+            .parse::<f64>()
+            .unwrap_or(90.0);
+
+        let busy_reject = env_bool("BUSY_REJECT", false);
+        let busy_cpu_threshold = env::var("BUSY_CPU_THRESHOLD")
+            .unwrap_or_else(|_| "90".to_string())
+            .parse::<f64>()
+            .unwrap_or(90.0);
+
+        let clock_skew_threshold = env::var("CLOCK_SKEW_THRESHOLD")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<i64>()
+            .unwrap_or(30);
+
+        let update_lag_threshold = env::var("UPDATE_LAG_THRESHOLD")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse::<u64>()
+            .unwrap_or(5);
+
+        let http_bind = env::var("HTTP_BIND")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let http_api_key = env::var("HTTP_API_KEY")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let export_passphrase = env::var(EXPORT_PASSPHRASE_VAR)
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+
+        let idempotency_ttl = env::var("IDEMPOTENCY_TTL")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse::<u64>()
+            .unwrap_or(86400);
+
+        let log_level_revert = env::var("LOG_LEVEL_REVERT")
+            .unwrap_or_else(|_| "1800".to_string())
+            .parse::<u64>()
+            .unwrap_or(1800)
+            .max(1);
+
+        let generation_webhook_url = match env::var("GENERATION_WEBHOOK_URL") {
+            Ok(value) if !value.trim().is_empty() => {
+                let url = reqwest::Url::parse(value.trim())
+                    .with_context(|| format!("GENERATION_WEBHOOK_URL 格式错误: {}", value))?;
+                Some(url.to_string())
+            }
+            _ => None,
+        };
+
+        // 匿名统计默认关闭，显式设置 TELEMETRY_OPTIN=true 才会上报
+        let telemetry_endpoint = if env_bool("TELEMETRY_OPTIN", false) {
+            let value = env::var("TELEMETRY_ENDPOINT")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .context("TELEMETRY_OPTIN=true 时需要通过 TELEMETRY_ENDPOINT 指定上报地址")?;
+            let url = reqwest::Url::parse(value.trim())
+                .with_context(|| format!("TELEMETRY_ENDPOINT 格式错误: {}", value))?;
+            Some(url.to_string())
+        } else {
+            None
+        };
+
+        let use_emoji = env_bool("USE_EMOJI", true);
+        let reply_menu = env_bool("REPLY_MENU", true);
+        let show_latency = env_bool("SHOW_LATENCY", true);
+        let fold_versions = env_bool("FOLD_VERSIONS", false);
+        let pin_results = env_bool("PIN_RESULTS", false);
+        let result_links = env_bool("RESULT_LINKS", false);
+        let result_image_font = if env_bool("RESULT_IMAGE", false) {
+            let path = env::var("RESULT_IMAGE_FONT")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .context("RESULT_IMAGE=true 时需要通过 RESULT_IMAGE_FONT 指定包含中文字形的字体文件")?;
+            Some(ResultFont::load(Path::new(path.trim())).context("RESULT_IMAGE_FONT 无效")?)
+        } else {
+            None
+        };
+
+        let stats_format = match env::var("STATS_FORMAT") {
+            Ok(value) if !value.trim().is_empty() => StatsFormat::from_name(&value)
+                .with_context(|| format!("STATS_FORMAT 格式错误: {}（可选值: text, html）", value))?,
+            _ => StatsFormat::default(),
+        };
+
+        let footer_links = match env::var("FOOTER_LINKS") {
+            Ok(value) => footer::parse_links(&value).context("FOOTER_LINKS 格式错误")?,
+            Err(_) => Vec::new(),
+        };
+        let footer_style = match env::var("FOOTER_STYLE") {
+            Ok(value) if !value.trim().is_empty() => FooterStyle::from_name(&value)
+                .with_context(|| format!("FOOTER_STYLE 格式错误: {}（可选值: text, keyboard）", value))?,
+            _ => FooterStyle::default(),
+        };
+
+        let copy_experiment = env::var("COPY_EXPERIMENT")
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "copy-v1".to_string());
+        let copy_variant_percent = env::var("COPY_VARIANT_PERCENT")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<u8>()
+            .unwrap_or(0)
+            .min(100);
+        let copy_variant_welcome = env_copy_text("COPY_VARIANT_WELCOME");
+        let copy_variant_tutorial = env_copy_text("COPY_VARIANT_TUTORIAL");
+        if copy_variant_percent > 0 && copy_variant_welcome.is_none() && copy_variant_tutorial.is_none() {
+            anyhow::bail!("COPY_VARIANT_PERCENT 大于 0 时需要设置 COPY_VARIANT_WELCOME 或 COPY_VARIANT_TUTORIAL");
+        }
+
+        let welcome_debounce = env::var("WELCOME_DEBOUNCE")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .unwrap_or(30);
+
+        let default_lang = env::var("DEFAULT_LANG")
+            .ok()
+            .and_then(|s| Lang::from_code(&s))
+            .unwrap_or_default();
+
+        let timezone = env::var("TIMEZONE")
+            .unwrap_or_else(|_| "+08:00".to_string());
+        let utc_offset_seconds = format::parse_utc_offset(&timezone)
+            .with_context(|| format!("TIMEZONE 格式错误: {}（示例: +08:00）", timezone))?
+            .local_minus_utc();
+
         Ok(Config {
-            bot_token,
-            chat_id,
+            telegram,
+            report_topic_id,
+            alert_topic_id,
+            alert_dedup_window,
+            alerts: AlertDeduper::default(),
             admin_ids,
+            rate_limit_whitelist,
+            auto_group_admins,
+            group_admin_refresh_secs,
+            group_admins: GroupAdminCache::default(),
+            clock: SharedClock::default(),
+            degraded: DegradedMode::default(),
             database_url,
+            read_replica_url,
+            instance_id,
+            command_prefix,
             max_user_requests,
+            quota_period,
+            quota_mode,
+            max_requests_per_hour,
+            max_batch_file_size,
+            version_order,
+            enabled_versions,
+            disabled_versions: DisabledVersions::default(),
+            code_case,
+            machine_code_display,
+            checksum_profiles,
+            trial_extension_salt,
+            code_report_alert_threshold,
+            abuse_machine_code_threshold,
+            abuse_window_hours,
+            abuse_mode,
+            input_filter,
+            input_filter_alert,
+            trial_hours,
+            trial_daily_limit,
+            active_user_days,
+            dormant_user_days,
             log_level,
             guard_check_interval,
+            guard_backup_interval,
+            guard_cleanup_interval,
+            ban_expiry_interval,
+            ban_expiry_notify,
+            history_retention_days,
+            archive_activation_logs,
+            activation_log_retention_days,
+            token_rotation_days,
+            backup_max_age_days,
+            admin_inactive_days,
+            guard_command_cooldown,
+            backup_command_cooldown,
+            throttle_queue_threshold,
+            throttle_warn_after,
+            inode_usage_threshold,
+            busy_reject,
+            busy_cpu_threshold,
+            clock_skew_threshold,
+            update_lag_threshold,
+            http_bind,
+            http_api_key,
+            export_passphrase,
+            idempotency_ttl,
+            log_level_revert,
+            generation_webhook_url,
+            telemetry_endpoint,
+            use_emoji,
+            reply_menu,
+            show_latency,
+            fold_versions,
+            pin_results,
+            result_links,
+            result_image_font,
+            stats_format,
+            footer_links,
+            footer_style,
+            copy_experiment,
+            copy_variant_percent,
+            copy_variant_welcome,
+            copy_variant_tutorial,
+            welcome_debounce,
+            default_lang,
+            utc_offset_seconds,
         })
     }
 
+    /// 统一渲染面向用户的文案；关闭 emoji 时去除 emoji 与装饰边框
+    pub fn render(&self, text: impl Into<String>) -> String {
+        let text = text.into();
+        if self.use_emoji {
+            text
+        } else {
+            crate::utils::strip_decorations(&text)
+        }
+    }
+
+    /// 上传文件的临时目录：数据库所在目录下的 `uploads`，内存数据库时为当前目录下的 `uploads`
+    pub fn upload_dir(&self) -> PathBuf {
+        let data_dir = database::sqlite_file_path(&self.database_url)
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        data_dir.join(upload::UPLOAD_DIR_NAME)
+    }
+
+    /// 展示时间使用的时区
+    pub fn timezone(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.utc_offset_seconds)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// 是否在进行欢迎语/教程文案实验
+    pub fn copy_experiment_enabled(&self) -> bool {
+        self.copy_variant_percent > 0
+    }
+
+    /// 按配置顺序排列的已启用版本
+    pub fn display_versions(&self) -> Vec<FinalShellVersionType> {
+        self.version_order
+            .iter()
+            .chain(FinalShellVersionType::ALL.iter())
+            .filter(|v| self.enabled_versions.contains(v) && !self.disabled_versions.contains(**v))
+            .fold(Vec::new(), |mut versions, v| {
+                if !versions.contains(v) {
+                    versions.push(*v);
+                }
+                versions
+            })
+    }
+
+    /// 从 settings 加载管理员停用的版本；无法识别的版本名忽略
+    pub async fn load_disabled_versions(&self, db: &database::Database) -> Result<()> {
+        let versions = match database::get_setting(db, DISABLED_VERSIONS_SETTING).await? {
+            Some((value, _)) => DisabledVersions::parse(&value),
+            None => Vec::new(),
+        };
+        self.disabled_versions.replace(versions);
+        Ok(())
+    }
+
+    /// 启用或停用一个版本并保存到 settings；以数据库中的最新值为准在一个事务内切换，会导致没有可用版本时拒绝
+    pub async fn set_version_enabled(&self, db: &database::Database, version: FinalShellVersionType, enable: bool) -> Result<()> {
+        let mut disabled = Vec::new();
+        database::update_setting(db, DISABLED_VERSIONS_SETTING, |current| {
+            disabled = DisabledVersions::toggled(
+                current.map(DisabledVersions::parse).unwrap_or_default(),
+                &self.enabled_versions,
+                version,
+                enable,
+            )?;
+            let value: Vec<&str> = disabled.iter().map(|v| v.version_name_ascii()).collect();
+            Ok(value.join(","))
+        })
+        .await?;
+        self.disabled_versions.replace(disabled);
+        Ok(())
+    }
+
+    /// 显式配置的 ADMIN_IDS 始终有效；开启 AUTO_GROUP_ADMINS 时管理群的群管理员也视为管理员
     pub fn is_admin(&self, user_id: i64) -> bool {
         self.admin_ids.contains(&user_id)
+            || (self.auto_group_admins && self.group_admins.contains(user_id))
     }
 
-    pub fn validate(&self) -> Result<()> {
-        if self.bot_token.is_empty() {
-            anyhow::bail!("Bot token 不能为空");
+    /// 开启繁忙拒绝且 CPU 使用率达到阈值时返回当前使用率；管理员始终放行。
+    /// 机器码、批量上传与 HTTP 生成共用这一预检
+    pub fn busy_cpu_usage(&self, user_id: i64) -> Option<f64> {
+        if !self.busy_reject || self.is_admin(user_id) {
+            return None;
+        }
+        let cpu_usage = utils::sample_cpu_usage();
+        (cpu_usage >= self.busy_cpu_threshold).then_some(cpu_usage)
+    }
+
+    /// 管理员与白名单用户不受次数上限与试用期额度限制
+    pub fn is_unlimited(&self, user_id: i64) -> bool {
+        self.is_admin(user_id) || self.rate_limit_whitelist.contains(&user_id)
+    }
+
+    /// 用户当前的生成配额，管理员与白名单用户不受限；新用户试用期内另有每日额度
+    pub fn quota(&self, user: &User) -> database::Quota {
+        let unlimited = self.is_unlimited(user.user_id);
+        let now = self.clock.now_utc();
+        database::Quota {
+            limit: self.max_user_requests,
+            unlimited,
+            period_start: self.quota_period.period_start(now, self.timezone()),
+            mode: self.quota_mode,
+            trial: if unlimited {
+                None
+            } else {
+                trial::active_trial(user, self.trial_hours, self.trial_daily_limit, now)
+            },
         }
+    }
+
+    /// 需要连接 Telegram 的功能（机器人）使用，未配置时返回错误
+    pub fn require_telegram(&self) -> Result<&TelegramConfig> {
+        self.telegram
+            .as_ref()
+            .context("BOT_TOKEN 与 CHAT_ID 环境变量未设置，无法连接 Telegram")
+    }
 
-        if self.chat_id == 0 {
-            anyhow::bail!("Chat ID 不能为空");
+    pub fn validate(&self) -> Result<()> {
+        if let Some(telegram) = &self.telegram {
+            if telegram.chat_id == 0 {
+                anyhow::bail!("Chat ID 不能为空");
+            }
         }
 
         if self.max_user_requests <= 0 {
@@ -76,3 +926,129 @@ impl Config {
         Ok(())
     }
 }
+
+/// `init-config` 生成的模板文件名
+pub const TEMPLATE_FILE: &str = ".env.example";
+
+/// 带注释的配置模板，列出全部配置项及默认值，与仓库中的 env.example 保持一致
+pub const ENV_TEMPLATE: &str = include_str!("../env.example");
+
+/// 将配置模板写入 `path`；文件已存在且未指定 `force` 时报错，避免覆盖已有配置
+pub fn write_template(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        anyhow::bail!("{} 已存在，如需覆盖请使用 --force", path.display());
+    }
+    std::fs::write(path, ENV_TEMPLATE).with_context(|| format!("无法写入配置模板: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versions() {
+        assert_eq!(
+            parse_versions(" 4.6+, >=3.9.6,4.6+ ").unwrap(),
+            vec![FinalShellVersionType::V46, FinalShellVersionType::V396Plus]
+        );
+        assert!(parse_versions("4.6+,5.0").is_err());
+    }
+
+    #[test]
+    fn test_upload_dir_follows_database() {
+        let mut config = Config::load().unwrap();
+        config.database_url = "sqlite:/var/lib/finalunlock/bot.db?mode=rwc".to_string();
+        assert_eq!(config.upload_dir(), Path::new("/var/lib/finalunlock/uploads"));
+
+        config.database_url = "sqlite::memory:".to_string();
+        assert_eq!(config.upload_dir(), Path::new("uploads"));
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(parse_ids(" 111, 222,abc,,333 "), vec![111, 222, 333]);
+        assert!(parse_ids("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_command_prefix() {
+        assert_eq!(parse_command_prefix("").unwrap(), "/");
+        assert_eq!(parse_command_prefix(" ! ").unwrap(), "!");
+        assert_eq!(parse_command_prefix("!!").unwrap(), "!!");
+        assert!(parse_command_prefix("bot").is_err());
+        assert!(parse_command_prefix("-").is_err());
+        assert!(parse_command_prefix("! ?").is_err());
+        assert!(parse_command_prefix("!!!!").is_err());
+    }
+
+    #[test]
+    fn test_parse_checksum_profiles() {
+        assert_eq!(
+            parse_checksum_profiles(" 4.6+=luhn, 4.5 = LUHN36 ,4.6+=luhn36").unwrap(),
+            vec![
+                (FinalShellVersionType::V45, CheckDigit::LuhnMod36),
+                (FinalShellVersionType::V46, CheckDigit::LuhnMod36),
+            ]
+        );
+        assert!(parse_checksum_profiles("").unwrap().is_empty());
+        assert!(parse_checksum_profiles("4.6+").is_err());
+        assert!(parse_checksum_profiles("4.6+=crc").is_err());
+    }
+
+    #[test]
+    fn test_write_template_does_not_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env.example");
+
+        write_template(&path, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), ENV_TEMPLATE);
+
+        std::fs::write(&path, "BOT_TOKEN=mine\n").unwrap();
+        assert!(write_template(&path, false).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "BOT_TOKEN=mine\n");
+
+        write_template(&path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), ENV_TEMPLATE);
+    }
+
+    #[test]
+    fn test_template_versions_match_defaults() {
+        for name in ["VERSION_ORDER", "ENABLED_VERSIONS"] {
+            let value = ENV_TEMPLATE
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+                .unwrap();
+            assert_eq!(parse_versions(value).unwrap(), FinalShellVersionType::ALL.to_vec(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_disabled_versions_keep_one_enabled() {
+        use FinalShellVersionType::*;
+        let available = [V45, V46];
+
+        let disabled = DisabledVersions::toggled(Vec::new(), &available, Legacy, false).unwrap();
+        let disabled = DisabledVersions::toggled(disabled, &available, V45, false).unwrap();
+        assert!(disabled.contains(&V45) && disabled.contains(&Legacy));
+
+        let error = DisabledVersions::toggled(disabled.clone(), &available, V46, false).unwrap_err();
+        assert!(error.to_string().contains("至少需要保留一个启用的版本"));
+
+        assert_eq!(DisabledVersions::toggled(disabled, &available, V45, true).unwrap(), vec![Legacy]);
+        assert_eq!(DisabledVersions::parse("4.5, bogus,<3.9.6"), vec![V45, Legacy]);
+    }
+
+    #[test]
+    fn test_group_admin_cache_is_shared_between_clones() {
+        let cache = GroupAdminCache::default();
+        let clone = cache.clone();
+
+        cache.replace([1, 2]);
+        assert!(clone.contains(1));
+
+        // 刷新后被撤销的管理员不再有效
+        clone.replace([2]);
+        assert!(!cache.contains(1));
+        assert!(cache.contains(2));
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 按 (命令, 管理员) 记录上次执行时间的冷却表，仅保存在内存中
+#[derive(Debug, Default)]
+pub struct CooldownRegistry {
+    last_used: Mutex<HashMap<(&'static str, i64), Instant>>,
+}
+
+impl CooldownRegistry {
+    /// 冷却已结束时记录本次执行并返回 Ok；否则返回还需等待的时长。`now` 取自配置的时钟
+    pub fn try_acquire(&self, command: &'static str, user_id: i64, cooldown: Duration, now: Instant) -> Result<(), Duration> {
+        let mut last_used = self.last_used.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(last) = last_used.get(&(command, user_id)) {
+            let elapsed = now.saturating_duration_since(*last);
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+        }
+
+        last_used.insert((command, user_id), now);
+        Ok(())
+    }
+}
+
+/// 重量级管理操作的限流状态：每位管理员的冷却，以及同类任务的全局互斥
+#[derive(Debug, Default)]
+pub struct AdminLimits {
+    pub cooldowns: CooldownRegistry,
+    /// 同一时间只允许一个备份任务运行，无论由谁触发
+    pub backup: tokio::sync::Mutex<()>,
+    /// 同一时间只允许一个导出任务运行
+    pub export: tokio::sync::Mutex<()>,
+}
+
+impl AdminLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// 将等待时长格式化为 "1 分 5 秒" 形式，不足一秒按一秒计
+pub fn format_wait(wait: Duration) -> String {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    if secs >= 60 {
+        format!("{} 分 {} 秒", secs / 60, secs % 60)
+    } else {
+        format!("{} 秒", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use chrono::Utc;
+
+    #[test]
+    fn test_cooldown_is_per_command_and_per_admin() {
+        let registry = CooldownRegistry::default();
+        let cooldown = Duration::from_secs(60);
+        let clock = MockClock::new(Utc::now());
+
+        assert!(registry.try_acquire("backup", 1, cooldown, clock.instant()).is_ok());
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(registry.try_acquire("backup", 1, cooldown, clock.instant()), Err(Duration::from_secs(40)));
+        // 其他管理员、其他命令不受影响
+        assert!(registry.try_acquire("backup", 2, cooldown, clock.instant()).is_ok());
+        assert!(registry.try_acquire("guard", 1, cooldown, clock.instant()).is_ok());
+        // 冷却结束后可再次执行
+        clock.advance(Duration::from_secs(40));
+        assert!(registry.try_acquire("backup", 1, cooldown, clock.instant()).is_ok());
+    }
+
+    #[test]
+    fn test_format_wait() {
+        assert_eq!(format_wait(Duration::from_secs(34)), "34 秒");
+        assert_eq!(format_wait(Duration::from_millis(1500)), "2 秒");
+        assert_eq!(format_wait(Duration::from_secs(125)), "2 分 5 秒");
+    }
+}
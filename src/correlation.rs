@@ -0,0 +1,74 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+
+/// Crockford base32 字母表：去掉了易混淆的 I、L、O、U
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// 关联 ID 长度，8 位 base32 即 40 位
+pub const ID_LENGTH: usize = 8;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 为一次生成请求分配的关联 ID，写入日志 span、激活/失败记录与用户看到的错误提示
+pub fn new_id(user_id: i64) -> String {
+    // RandomState 每次创建都带随机种子，再混入时间与计数器，同一微秒内的请求也不会重复
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_i64(user_id);
+    hasher.write_i64(Utc::now().timestamp_micros());
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    encode(hasher.finish())
+}
+
+/// 取低 40 位编码为 8 位 base32
+fn encode(mut value: u64) -> String {
+    let mut id = [0u8; ID_LENGTH];
+    for slot in id.iter_mut().rev() {
+        *slot = ALPHABET[(value & 0x1f) as usize];
+        value >>= 5;
+    }
+    String::from_utf8_lossy(&id).into_owned()
+}
+
+/// 规范化管理员输入的关联 ID：忽略大小写与首尾空白，按 Crockford 规则将 O 视为 0、I/L 视为 1
+pub fn normalize(input: &str) -> Option<String> {
+    let id: String = input
+        .trim()
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect();
+    (id.len() == ID_LENGTH && id.bytes().all(|b| ALPHABET.contains(&b))).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_id_format() {
+        let ids: Vec<String> = (0..1_000).map(|_| new_id(42)).collect();
+        assert!(ids.iter().all(|id| normalize(id).as_deref() == Some(id.as_str())));
+
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+
+        assert_eq!(encode(0), "00000000");
+        assert_eq!(encode(u64::MAX), "ZZZZZZZZ");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(" 7kq2m3zd "), Some("7KQ2M3ZD".to_string()));
+        assert_eq!(normalize("7KQ2MOZL"), Some("7KQ2M0Z1".to_string()));
+        assert_eq!(normalize("7KQ2M3Z"), None);
+        assert_eq!(normalize("7KQ2M3ZU"), None);
+        assert_eq!(normalize("7KQ2-3ZD"), None);
+    }
+}
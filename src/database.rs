@@ -1,26 +1,177 @@
-use anyhow::Result;
-use chrono::Utc;
-use sqlx::{sqlite::SqlitePool, Row, SqlitePool as Pool};
+use anyhow::{Context, Result};
+use futures::stream::BoxStream;
+use chrono::{DateTime, FixedOffset, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool},
+    Row, SqlitePool as Pool,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{info, warn, error};
 
-use crate::models::{ActivationLog, SystemStats, User, UserStats};
+use crate::audit;
+use crate::banlist::{BanEntry, ImportSummary};
+use crate::legacy::{LegacyImportSummary, LegacyLog, LegacyUser};
+use crate::experiment::CopyVariant;
+use crate::finalshell::{ActivationCodeGenerator, ActivationResult, FinalShellVersionType};
+use crate::i18n::Lang;
+use crate::quota::{QuotaMode, QuotaPeriod};
+use crate::models::{
+    ActivationLog, ActivationOutcome, Appeal, AuditEntry, BroadcastStats, CodeReport, CopyVariantStats, DailySummary, HealthCheck, HealthRecord, LatencySummary,
+    OutcomeStats, RequestFailure, RequestMetric, RequestTrace, SystemStats, UpdateLag, User,
+    UserStats,
+};
 
-pub async fn init(database_url: &str) -> Result<Pool> {
+/// 生成请求的配额：普通用户受 `limit` 限制，`unlimited` 为真（管理员）时不受限；
+/// `period_start` 为空时按累计次数计算，否则只计该时间之后的生成次数；
+/// 试用期内另受 `trial` 的每日额度限制
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub limit: i32,
+    pub unlimited: bool,
+    pub period_start: Option<DateTime<Utc>>,
+    pub trial: Option<TrialLimit>,
+    pub mode: QuotaMode,
+}
+
+/// 新用户试用期额度：`window_start` 之后最多生成 `daily_limit` 次，`ends_at` 后恢复正常额度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrialLimit {
+    pub daily_limit: i32,
+    pub window_start: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+/// 未配置 INSTANCE_ID 时使用的实例标识，迁移时也用它补齐旧数据
+pub const DEFAULT_INSTANCE_ID: &str = "default";
+
+/// 数据库连接：写操作走主库，重量级只读查询在配置了只读副本时走副本
+#[derive(Debug, Clone)]
+pub struct Database {
+    primary: Pool,
+    replica: Option<Pool>,
+    /// 多个机器人共用一个库时，写入的用户与激活日志标记为该实例
+    instance_id: String,
+    /// 测试中模拟写入失败（如文件系统变为只读）
+    #[cfg(test)]
+    write_fault: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl Database {
+    pub fn new(primary: Pool, replica: Option<Pool>) -> Self {
+        Self {
+            primary,
+            replica,
+            instance_id: DEFAULT_INSTANCE_ID.to_string(),
+            #[cfg(test)]
+            write_fault: Default::default(),
+        }
+    }
+
+    pub fn with_instance_id(mut self, instance_id: impl Into<String>) -> Self {
+        self.instance_id = instance_id.into();
+        self
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// 主库连接池，用于写操作及需要读到最新数据的查询
+    pub fn writer(&self) -> &Pool {
+        &self.primary
+    }
+
+    /// 只读连接池；未配置副本时回退到主库
+    pub fn reader(&self) -> &Pool {
+        self.replica.as_ref().unwrap_or(&self.primary)
+    }
+
+    /// 降级相关的写操作执行前调用；测试中注入故障时返回与只读文件系统相同的 IO 错误
+    fn check_write_fault(&self) -> Result<()> {
+        #[cfg(test)]
+        if self.write_fault.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(sqlx::Error::Io(std::io::Error::from_raw_os_error(30)).into());
+        }
+        Ok(())
+    }
+
+    /// 开启或关闭写入故障注入
+    #[cfg(test)]
+    pub fn inject_write_fault(&self, enabled: bool) {
+        self.write_fault.store(enabled, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// SQLite 的主错误码：尝试写入只读数据库、磁盘 I/O 错误
+const SQLITE_READONLY: i32 = 8;
+const SQLITE_IOERR: i32 = 10;
+
+/// 错误是否由数据库不可写引起（文件系统只读、磁盘 I/O 错误），与 SQL 错误、约束冲突等区分开
+pub fn is_write_unavailable(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_)) => true,
+        // code() 返回扩展错误码，低 8 位为主错误码
+        Some(sqlx::Error::Database(err)) => err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, SQLITE_READONLY | SQLITE_IOERR)),
+        _ => false,
+    }
+}
+
+/// 降级期间探测数据库是否恢复可写：向 settings 写入一条探测记录
+pub async fn probe_write(db: &Database) -> Result<()> {
+    db.check_write_fault()?;
+    let now = Utc::now();
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value, updated_at) VALUES ('write_probe', ?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(now.to_rfc3339())
+    .bind(now)
+    .execute(db.writer())
+    .await?;
+    Ok(())
+}
+
+/// 连接主库并按需连接只读副本；`backup_dir` 不为空时，有待执行的迁移会先将数据库快照到该目录
+pub async fn init(database_url: &str, read_replica_url: Option<&str>, backup_dir: Option<&Path>) -> Result<Database> {
+    let primary = init_primary(database_url, backup_dir).await?;
+
+    let replica = match read_replica_url {
+        Some(url) => {
+            info!("正在连接只读副本: {}", url);
+            let options = SqliteConnectOptions::from_str(url)?.read_only(true);
+            let pool = SqlitePool::connect_with(options)
+                .await
+                .with_context(|| format!("连接只读副本失败: {}", url))?;
+            info!("只读副本连接成功，统计查询将走副本");
+            Some(pool)
+        }
+        None => None,
+    };
+
+    Ok(Database::new(primary, replica))
+}
+
+/// 以只读方式打开数据库，不创建文件也不执行迁移，供 `doctor` 如实检查当前状态；
+/// 连接在首次查询时才建立，打不开时由该查询报错
+pub fn open_read_only(database_url: &str) -> Result<Database> {
+    let options = SqliteConnectOptions::from_str(database_url)?.read_only(true);
+    Ok(Database::new(SqlitePool::connect_lazy_with(options), None))
+}
+
+async fn init_primary(database_url: &str, backup_dir: Option<&Path>) -> Result<Pool> {
     info!("正在连接数据库: {}", database_url);
     
     // 提取数据库文件路径（如果是文件数据库）
-    if database_url.starts_with("sqlite:") {
-        let db_path = database_url.trim_start_matches("sqlite:");
-        
-        // 如果路径不是绝对路径，使用相对路径
-        let db_path = if !db_path.starts_with("/") && !db_path.starts_with("./") {
-            format!("./{}", db_path)
-        } else {
-            db_path.to_string()
-        };
-        
+    if let Some(db_path) = sqlite_file_path(database_url) {
         // 确保数据库目录存在
         if let Some(dir) = Path::new(&db_path).parent() {
             if !dir.exists() {
@@ -65,7 +216,18 @@ pub async fn init(database_url: &str) -> Result<Pool> {
         match SqlitePool::connect(database_url).await {
             Ok(pool) => {
                 info!("数据库连接成功");
-                
+
+                // 迁移前先备份；备份失败时不迁移
+                let backup = match backup_dir {
+                    Some(dir) if sqlite_file_path(database_url).is_some() => backup_before_migration(&pool, dir)
+                        .await
+                        .context("迁移前备份数据库失败，未执行迁移；确认磁盘空间与目录权限后重试，或加 --no-premigration-backup 跳过备份")?,
+                    _ => None,
+                };
+                if let Some(path) = &backup {
+                    info!("检测到待执行的数据库迁移，已备份到 {}", path.display());
+                }
+
                 // 运行数据库迁移
                 match migrate(&pool).await {
                     Ok(_) => {
@@ -73,6 +235,16 @@ pub async fn init(database_url: &str) -> Result<Pool> {
                         return Ok(pool);
                     }
                     Err(e) => {
+                        // 已备份说明这是一次结构升级，失败时不再重试或退回内存数据库，以免在缺表的库上继续运行
+                        if let Some(path) = &backup {
+                            error!(
+                                "数据库迁移失败: {:#}\n迁移在事务中执行，数据库应保持原样；如数据异常，可停止所有实例后用迁移前的备份恢复:\n  cp {} {}",
+                                e,
+                                path.display(),
+                                sqlite_file_path(database_url).unwrap_or_default().display()
+                            );
+                            return Err(e.context(format!("数据库迁移失败，迁移前的备份: {}", path.display())));
+                        }
                         error!("数据库迁移失败: {}", e);
                         last_error = Some(e.into());
                         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
@@ -113,9 +285,86 @@ pub async fn init(database_url: &str) -> Result<Pool> {
     }
 }
 
+/// 从连接串中解析 SQLite 数据库文件路径；内存数据库返回 None
+pub fn sqlite_file_path(database_url: &str) -> Option<PathBuf> {
+    let db_path = database_url.strip_prefix("sqlite:")?;
+    let db_path = db_path.trim_start_matches("//");
+    let db_path = db_path.split('?').next().unwrap_or_default();
+
+    if db_path.is_empty() || db_path == ":memory:" {
+        return None;
+    }
+
+    // 如果路径不是绝对路径，使用相对路径
+    if !db_path.starts_with('/') && !db_path.starts_with("./") {
+        Some(PathBuf::from(format!("./{}", db_path)))
+    } else {
+        Some(PathBuf::from(db_path))
+    }
+}
+
+/// 数据库文件所在的目录，磁盘与 inode 检查以它所在的文件系统为准；内存数据库为当前目录
+pub fn data_dir(database_url: &str) -> PathBuf {
+    sqlite_file_path(database_url)
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// 数据库结构版本，记录在 SQLite 的 user_version 中；migrate() 新增表或列时需加一，
+/// 启动时据此判断是否有待执行的迁移
+pub const SCHEMA_VERSION: i64 = 15;
+
+/// 数据库记录的结构版本；新建的库与引入版本号之前的库为 0
+pub async fn schema_version(pool: &Pool) -> Result<i64> {
+    let version = sqlx::query_scalar::<_, i64>("PRAGMA user_version").fetch_one(pool).await?;
+    Ok(version)
+}
+
+/// 有待执行的迁移时先用 VACUUM INTO 将数据库快照到 `backup_dir`，返回备份文件路径；
+/// 已是最新版本或是新建的空库时不备份
+pub async fn backup_before_migration(pool: &Pool, backup_dir: &Path) -> Result<Option<PathBuf>> {
+    let version = schema_version(pool).await?;
+    if version >= SCHEMA_VERSION {
+        return Ok(None);
+    }
+    let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'")
+        .fetch_one(pool)
+        .await?;
+    if tables == 0 {
+        return Ok(None);
+    }
+
+    let file_name = format!("finalshell_bot_premigrate_v{}_{}.db", version, Utc::now().format("%Y%m%d_%H%M%S"));
+    Ok(Some(snapshot(pool, backup_dir, &file_name).await?))
+}
+
+/// 用 VACUUM INTO 将数据库完整快照到 `backup_dir/file_name`，返回备份文件路径
+pub async fn snapshot(pool: &Pool, backup_dir: &Path, file_name: &str) -> Result<PathBuf> {
+    fs::create_dir_all(backup_dir).with_context(|| format!("无法创建备份目录: {}", backup_dir.display()))?;
+    let path = backup_dir.join(file_name);
+    sqlx::query("VACUUM INTO ?")
+        .bind(path.to_string_lossy().as_ref())
+        .execute(pool)
+        .await?;
+    Ok(path)
+}
+
+/// 运行全部迁移并记录当前结构版本；所有变更在同一事务中执行，中途失败时数据库保持原样
 pub async fn migrate(pool: &Pool) -> Result<()> {
     info!("运行数据库迁移...");
-    
+    let mut tx = pool.begin().await?;
+    apply_migrations(&mut tx).await?;
+    sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    info!("数据库迁移完成");
+    Ok(())
+}
+
+async fn apply_migrations(conn: &mut SqliteConnection) -> Result<()> {    
     // 创建用户表
     sqlx::query(
         r#"
@@ -133,7 +382,7 @@ pub async fn migrate(pool: &Pool) -> Result<()> {
         )
         "#,
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     // 创建激活日志表
@@ -150,7 +399,23 @@ pub async fn migrate(pool: &Pool) -> Result<()> {
         )
         "#,
     )
-    .execute(pool)
+    .execute(&mut *conn)
+    .await?;
+
+    // 激活日志的各版本明细
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS activation_log_details (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            log_id INTEGER NOT NULL,
+            version TEXT NOT NULL,
+            advanced_code TEXT NOT NULL,
+            professional_code TEXT NOT NULL,
+            FOREIGN KEY (log_id) REFERENCES activation_logs (id)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
     .await?;
 
     // 创建系统统计表
@@ -167,216 +432,3906 @@ pub async fn migrate(pool: &Pool) -> Result<()> {
         )
         "#,
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
-    info!("数据库迁移完成");
-    Ok(())
-}
-
-// 用户操作
-pub async fn get_or_create_user(
-    pool: &Pool,
-    user_id: i64,
-    username: Option<String>,
-    first_name: Option<String>,
-    last_name: Option<String>,
-) -> Result<User> {
-    // 尝试获取现有用户
-    if let Ok(user) = get_user_by_id(pool, user_id).await {
-        return Ok(user);
+    // 封禁元数据
+    add_column_if_missing(&mut *conn, "users", "ban_reason", "TEXT").await?;
+    add_column_if_missing(&mut *conn, "users", "banned_at", "DATETIME").await?;
+    add_column_if_missing(&mut *conn, "users", "banned_until", "DATETIME").await?;
+    // 用户客户端语言，用于粗略估计用户地域
+    add_column_if_missing(&mut *conn, "users", "language_code", "TEXT").await?;
+    // 滥用检测标记时间，非空表示被标记
+    add_column_if_missing(&mut *conn, "users", "flagged_at", "DATETIME").await?;
+    // 最近一次解除滥用标记的时间，此前的生成记录不再计入滥用检测
+    add_column_if_missing(&mut *conn, "users", "flag_cleared_at", "DATETIME").await?;
+    // 管理员提前解除新用户试用期的时间
+    add_column_if_missing(&mut *conn, "users", "trusted_at", "DATETIME").await?;
+    // 私聊中置顶的最近一次生成结果
+    add_column_if_missing(&mut *conn, "users", "pinned_message_id", "INTEGER").await?;
+    // 界面语言 (zh/en)，旧用户为空时使用默认语言
+    add_column_if_missing(&mut *conn, "users", "lang", "TEXT").await?;
+    // 是否逐条发送激活码
+    add_column_if_missing(&mut *conn, "users", "split_codes", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    // Telegram 返回账号已注销的时间，用户再次发消息时清除
+    add_column_if_missing(&mut *conn, "users", "deactivated_at", "DATETIME").await?;
+    // 用户在私聊中屏蔽机器人的时间（来自 my_chat_member 更新），解除屏蔽时清除
+    add_column_if_missing(&mut *conn, "users", "blocked_at", "DATETIME").await?;
+    // 最近一次回复 /start 欢迎语的时间，用于去抖
+    add_column_if_missing(&mut *conn, "users", "welcomed_at", "DATETIME").await?;
+    // 最近一次看到该用户名的时间，按 @用户名 查找时据此提示可能已过期；升级前的记录按 updated_at 补齐
+    let backfill_seen = !column_exists(&mut *conn, "users", "username_seen_at").await?;
+    add_column_if_missing(&mut *conn, "users", "username_seen_at", "DATETIME").await?;
+    if backfill_seen {
+        sqlx::query("UPDATE users SET username_seen_at = updated_at WHERE username IS NOT NULL")
+            .execute(&mut *conn)
+            .await?;
+    }
+    // 管理命令按 @用户名 查找用户，Telegram 用户名不区分大小写
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_users_username_nocase ON users (username COLLATE NOCASE)")
+        .execute(&mut *conn)
+        .await?;
+    // 写入数据的机器人实例，旧数据归入默认实例
+    let instance_column = format!("TEXT NOT NULL DEFAULT '{}'", DEFAULT_INSTANCE_ID);
+    add_column_if_missing(&mut *conn, "users", "instance_id", &instance_column).await?;
+    add_column_if_missing(&mut *conn, "activation_logs", "instance_id", &instance_column).await?;
+    // 生成请求的关联 ID，/trace 据此查找；旧记录为空
+    add_column_if_missing(&mut *conn, "activation_logs", "correlation_id", "TEXT").await?;
+    // 用户点击结果消息下的按钮反馈的激活结果 (success/failure)，未反馈为空；已有的归档表同步补列
+    add_column_if_missing(&mut *conn, "activation_logs", "outcome", "TEXT").await?;
+    for table in archive_tables(&mut *conn).await? {
+        add_column_if_missing(&mut *conn, &table, "outcome", "TEXT").await?;
     }
+    rebuild_legacy_archives(&mut *conn).await?;
 
-    // 创建新用户
-    let now = Utc::now();
+    // 创建管理员操作审计表
     sqlx::query(
         r#"
-        INSERT INTO users (user_id, username, first_name, last_name, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?)
+        CREATE TABLE IF NOT EXISTS admin_actions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            admin_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            target_user_id INTEGER,
+            detail TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
         "#,
     )
-    .bind(user_id)
-    .bind(&username)
-    .bind(&first_name)
-    .bind(&last_name)
-    .bind(now)
-    .bind(now)
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
+    // 审计哈希链；升级前已有的记录在加列时补算哈希
+    let seal_existing = !column_exists(&mut *conn, "admin_actions", "hash").await?;
+    add_column_if_missing(&mut *conn, "admin_actions", "hash", "TEXT").await?;
+    if seal_existing {
+        seal_audit_chain(&mut *conn).await?;
+    }
 
-    get_user_by_id(pool, user_id).await
-}
-
-pub async fn get_user_by_id(pool: &Pool, user_id: i64) -> Result<User> {
-    let user = sqlx::query_as::<_, User>(
-        "SELECT * FROM users WHERE user_id = ?",
+    // 创建封禁申诉表
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS appeals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            banned_at DATETIME,
+            content TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            decided_by INTEGER,
+            decided_at DATETIME,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (user_id) REFERENCES users (user_id)
+        )
+        "#,
     )
-    .bind(user_id)
-    .fetch_one(pool)
+    .execute(&mut *conn)
     .await?;
 
-    Ok(user)
-}
-
-pub async fn update_user_request_count(pool: &Pool, user_id: i64) -> Result<()> {
-    let now = Utc::now();
+    // 创建激活失败反馈表
     sqlx::query(
-        "UPDATE users SET request_count = request_count + 1, updated_at = ? WHERE user_id = ?",
+        r#"
+        CREATE TABLE IF NOT EXISTS code_reports (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            machine_code_hash TEXT NOT NULL,
+            version TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (user_id) REFERENCES users (user_id)
+        )
+        "#,
     )
-    .bind(now)
-    .bind(user_id)
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
-    Ok(())
-}
-
-pub async fn ban_user(pool: &Pool, user_id: i64) -> Result<()> {
-    let now = Utc::now();
+    // 运行时设置（键值对），如 Bot Token 指纹
     sqlx::query(
-        "UPDATE users SET is_banned = TRUE, updated_at = ? WHERE user_id = ?",
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
     )
-    .bind(now)
-    .bind(user_id)
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
-
-    Ok(())
-}
-
-pub async fn unban_user(pool: &Pool, user_id: i64) -> Result<()> {
-    let now = Utc::now();
+    // 升级前已有的审计记录以当前末条哈希作为锚点，此后由 log_admin_action 随每条记录更新
     sqlx::query(
-        "UPDATE users SET is_banned = FALSE, updated_at = ? WHERE user_id = ?",
+        r#"
+        INSERT OR IGNORE INTO settings (key, value, updated_at)
+        SELECT ?, hash, ? FROM admin_actions WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1
+        "#,
     )
-    .bind(now)
-    .bind(user_id)
-    .execute(pool)
+    .bind(AUDIT_HEAD_SETTING)
+    .bind(Utc::now())
+    .execute(&mut *conn)
     .await?;
 
-    Ok(())
-}
-
-pub async fn get_all_users(pool: &Pool) -> Result<Vec<UserStats>> {
-    let users = sqlx::query(
+    // 创建请求处理耗时表，供守护报告统计生成耗时
+    sqlx::query(
         r#"
-        SELECT 
-            u.user_id,
-            u.username,
-            u.request_count as total_requests,
-            u.is_banned,
-            MAX(al.created_at) as last_request
-        FROM users u
-        LEFT JOIN activation_logs al ON u.user_id = al.user_id
-        GROUP BY u.user_id, u.username, u.request_count, u.is_banned
-        ORDER BY u.created_at DESC
+        CREATE TABLE IF NOT EXISTS request_metrics (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            duration_us INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
         "#,
     )
-    .fetch_all(pool)
+    .execute(&mut *conn)
     .await?;
 
-    let user_stats = users
-        .into_iter()
-        .map(|row| {
-            let last_request = row.get::<Option<chrono::DateTime<Utc>>, _>("last_request");
-            UserStats {
-                user_id: row.get("user_id"),
-                username: row.get("username"),
-                total_requests: row.get("total_requests"),
-                last_request,
-                is_banned: row.get("is_banned"),
-            }
-        })
-        .collect();
-
-    Ok(user_stats)
-}
-
-// 激活日志操作
-pub async fn log_activation(
-    pool: &Pool,
-    user_id: i64,
-    machine_code: &str,
-    activation_code: &str,
-    finalshell_version: &str,
-) -> Result<()> {
-    let now = Utc::now();
+    // 创建广播记录表；is_test 为真的是只发给管理员的预览，不计入投递统计
     sqlx::query(
         r#"
-        INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at)
-        VALUES (?, ?, ?, ?, ?)
+        CREATE TABLE IF NOT EXISTS broadcasts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            admin_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            is_test BOOLEAN NOT NULL DEFAULT FALSE,
+            delivered INTEGER NOT NULL,
+            failed INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
         "#,
     )
-    .bind(user_id)
-    .bind(machine_code)
-    .bind(activation_code)
-    .bind(finalshell_version)
-    .bind(now)
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
+    // 从 Telegram 创建消息到开始处理的延迟；现已改记在 update_lags 表，保留旧记录供 /trace 查看
+    add_column_if_missing(&mut *conn, "request_metrics", "update_lag_ms", "INTEGER").await?;
+    add_column_if_missing(&mut *conn, "request_metrics", "correlation_id", "TEXT").await?;
 
-    Ok(())
-}
-
-pub async fn get_activation_logs(pool: &Pool, limit: i64) -> Result<Vec<ActivationLog>> {
-    let logs = sqlx::query_as::<_, ActivationLog>(
-        "SELECT * FROM activation_logs ORDER BY created_at DESC LIMIT ?",
+    // 创建更新处理延迟表：每条进入处理的消息一条，不论最终是否生成成功
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS update_lags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            correlation_id TEXT,
+            lag_ms INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
     )
-    .bind(limit)
-    .fetch_all(pool)
+    .execute(&mut *conn)
     .await?;
-
-    Ok(logs)
-}
-
-// 统计操作
-pub async fn get_system_stats(pool: &Pool) -> Result<SystemStats> {
-    // 获取总用户数
-    let total_users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
-        .fetch_one(pool)
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_update_lags_created_at ON update_lags (created_at)")
+        .execute(&mut *conn)
         .await?;
 
-    // 获取总激活次数
-    let total_activations: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_logs")
-        .fetch_one(pool)
+    // 创建请求失败记录表：机器码格式错误与生成失败，用户反馈错误码时可通过 /trace 查到
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS request_failures (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            correlation_id TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            stage TEXT NOT NULL,
+            detail TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // /trace 按关联 ID 查询失败记录
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_request_failures_correlation ON request_failures (correlation_id)")
+        .execute(&mut *conn)
         .await?;
 
-    // 获取今日活跃用户数
-    let active_users_today: i64 = sqlx::query_scalar(
-        "SELECT COUNT(DISTINCT user_id) FROM activation_logs WHERE DATE(created_at) = DATE('now')",
+    // 创建文案实验分组表，用户首次看到实验文案时固定分组，之后调整比例不影响已分组用户
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS copy_assignments (
+            user_id INTEGER NOT NULL,
+            experiment TEXT NOT NULL,
+            variant TEXT NOT NULL,
+            assigned_at DATETIME NOT NULL,
+            PRIMARY KEY (user_id, experiment)
+        )
+        "#,
     )
-    .fetch_one(pool)
+    .execute(&mut *conn)
     .await?;
 
-    // 获取今日激活次数
-    let activations_today: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM activation_logs WHERE DATE(created_at) = DATE('now')",
-    )
+    // 创建守护检查历史表，供导出容量规划数据
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS health_checks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp DATETIME NOT NULL,
+            overall TEXT NOT NULL,
+            cpu_usage REAL NOT NULL,
+            memory_usage REAL NOT NULL,
+            disk_usage REAL NOT NULL,
+            inode_usage REAL,
+            latency_count INTEGER,
+            latency_avg_us INTEGER,
+            latency_max_us INTEGER,
+            clock_skew_secs INTEGER,
+            error_count INTEGER NOT NULL,
+            warning_count INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 超出保留期的激活日志按天汇总后删除，day 为配置时区下的日期 (YYYY-MM-DD)，
+    // version_counts 为各版本激活次数的 JSON 对象
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS daily_summary (
+            day TEXT NOT NULL,
+            instance_id TEXT NOT NULL,
+            activations INTEGER NOT NULL,
+            distinct_users INTEGER NOT NULL,
+            version_counts TEXT NOT NULL,
+            PRIMARY KEY (day, instance_id)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 汇总删除的激活日志中每个用户提交过的机器码，QUOTA_MODE=machines 的累计配额仍按它们计数
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS rolled_up_machine_codes (
+            user_id INTEGER NOT NULL,
+            machine_code TEXT NOT NULL,
+            PRIMARY KEY (user_id, machine_code)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 生成过程中预扣的配额，成功后随激活日志一并删除，失败时直接删除退回
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS quota_reservations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            created_at DATETIME NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_quota_reservations_user ON quota_reservations (user_id, created_at)")
+        .execute(&mut *conn)
+        .await?;
+    // 预扣对应的机器码，QUOTA_MODE=machines 时同一机器码的并发请求只占一次配额
+    add_column_if_missing(&mut *conn, "quota_reservations", "machine_code", "TEXT").await?;
+
+    // QUOTA_MODE=machines 按周期统计用户提交过的不同机器码
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_activation_logs_user_day ON activation_logs (user_id, created_at, machine_code)")
+        .execute(&mut *conn)
+        .await?;
+
+    // 用户屏蔽/解除屏蔽机器人的事件，供 /stats 统计近期屏蔽人数
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS block_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            blocked BOOLEAN NOT NULL,
+            created_at DATETIME NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_block_events_created ON block_events (created_at)")
+        .execute(&mut *conn)
+        .await?;
+
+    // 一次性结果链接 (RESULT_LINKS)：按令牌哈希保存加密后的结果，取出一次或过期后删除
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS pending_results (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            sealed BLOB NOT NULL,
+            expires_at DATETIME NOT NULL,
+            created_at DATETIME NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_pending_results_expires ON pending_results (expires_at)")
+        .execute(&mut *conn)
+        .await?;
+
+    // 主表与月度归档的汇总视图，依赖上面补齐的列
+    rebuild_log_view(&mut *conn).await?;
+
+    Ok(())
+}
+
+/// 为已存在的表补充缺失的列（SQLite 不支持 ADD COLUMN IF NOT EXISTS）；
+/// 新增列必须可为空或带常量默认值，老数据库中的已有行才能直接读出
+async fn add_column_if_missing(conn: &mut SqliteConnection, table: &str, column: &str, definition: &str) -> Result<()> {
+    if column_exists(&mut *conn, table, column).await? {
+        return Ok(());
+    }
+
+    info!("为表 {} 添加列 {}", table, column);
+    let result = sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+        .execute(&mut *conn)
+        .await;
+    if let Err(e) = result {
+        // 多个实例同时升级同一数据库时，列可能已被另一个实例加上
+        if column_exists(&mut *conn, table, column).await? {
+            return Ok(());
+        }
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+async fn column_exists(conn: &mut SqliteConnection, table: &str, column: &str) -> Result<bool> {
+    let exists: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = ?",
+        table
+    ))
+    .bind(column)
+    .fetch_one(conn)
+    .await?;
+    Ok(exists > 0)
+}
+
+// 用户操作
+pub async fn get_or_create_user(
+    db: &Database,
+    user_id: i64,
+    username: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    lang: Lang,
+) -> Result<User> {
+    // 尝试获取现有用户，已有用户保留其语言设置，仅刷新用户名
+    if let Ok(user) = get_user_by_id(db, user_id).await {
+        refresh_username(db, &user, username.as_deref()).await;
+        return Ok(user);
+    }
+
+    // 创建新用户
+    let pool = db.writer();
+    let now = Utc::now();
+    sqlx::query(
+        r#"
+        INSERT INTO users (user_id, username, first_name, last_name, created_at, updated_at, instance_id, lang)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(user_id)
+    .bind(&username)
+    .bind(&first_name)
+    .bind(&last_name)
+    .bind(now)
+    .bind(now)
+    .bind(db.instance_id())
+    .bind(lang.code())
+    .execute(pool)
+    .await?;
+    if let Some(username) = username.as_deref() {
+        record_username(db, user_id, username).await?;
+    }
+
+    get_user_by_id(db, user_id).await
+}
+
+/// 已有用户发来消息时刷新其用户名，只在用户名变化或距上次记录超过一天时写库；
+/// 写入失败（如只读降级）只记录日志，不影响请求处理
+pub async fn refresh_username(db: &Database, user: &User, username: Option<&str>) {
+    let Some(username) = username else {
+        return;
+    };
+    if !user.username_needs_refresh(username, Utc::now()) {
+        return;
+    }
+    if let Err(e) = record_username(db, user.user_id, username).await {
+        warn!("更新用户 {} 的用户名失败: {}", user.user_id, e);
+    }
+}
+
+/// 记录用户当前的用户名；同一用户名（不区分大小写）已转移到该用户时，从其他用户处清除
+async fn record_username(db: &Database, user_id: i64, username: &str) -> Result<()> {
+    db.check_write_fault()?;
+    let now = Utc::now();
+    let mut tx = db.writer().begin().await?;
+    sqlx::query("UPDATE users SET username = NULL WHERE username = ? COLLATE NOCASE AND user_id != ?")
+        .bind(username)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE users SET username = ?, username_seen_at = ? WHERE user_id = ?")
+        .bind(username)
+        .bind(now)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 按用户名查找用户（可带 @，不区分大小写），返回用户 ID 与最近一次看到该用户名的时间
+pub async fn find_user_by_username(db: &Database, username: &str) -> Result<Option<(i64, DateTime<Utc>)>> {
+    let username = username.trim().trim_start_matches('@');
+    if username.is_empty() {
+        return Ok(None);
+    }
+    let row: Option<(i64, DateTime<Utc>)> = sqlx::query_as(
+        r#"
+        SELECT user_id, COALESCE(username_seen_at, updated_at)
+        FROM users
+        WHERE username = ? COLLATE NOCASE
+        ORDER BY COALESCE(username_seen_at, updated_at) DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(db.reader())
+    .await?;
+    Ok(row)
+}
+
+pub async fn get_user_by_id(db: &Database, user_id: i64) -> Result<User> {
+    let pool = db.writer();
+    let user = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE user_id = ?",
+    )
+    .bind(user_id)
     .fetch_one(pool)
     .await?;
 
-    Ok(SystemStats {
-        id: 0,
-        total_users,
-        total_activations,
-        active_users_today,
-        activations_today,
-        system_status: "NORMAL".to_string(),
-        created_at: Utc::now(),
-    })
+    Ok(user)
 }
 
-pub async fn clear_stats(pool: &Pool) -> Result<()> {
-    warn!("清除所有统计数据...");
-    
-    sqlx::query("DELETE FROM activation_logs")
-        .execute(pool)
+/// 标记用户账号已注销；返回是否为新标记
+pub async fn mark_user_deactivated(db: &Database, user_id: i64) -> Result<bool> {
+    let now = Utc::now();
+    let result = sqlx::query(
+        "UPDATE users SET deactivated_at = ?, updated_at = ? WHERE user_id = ? AND deactivated_at IS NULL",
+    )
+    .bind(now)
+    .bind(now)
+    .bind(user_id)
+    .execute(db.writer())
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 距上次欢迎已超过 `debounce` 时记录本次欢迎时间并返回 true；间隔内重复 /start 返回 false
+pub async fn claim_welcome(db: &Database, user_id: i64, now: DateTime<Utc>, debounce: chrono::Duration) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE users SET welcomed_at = ? WHERE user_id = ? AND (welcomed_at IS NULL OR welcomed_at <= ?)",
+    )
+    .bind(now)
+    .bind(user_id)
+    .bind(now - debounce)
+    .execute(db.writer())
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 用户账号被标记为已注销的时间；未标记时返回 None
+pub async fn get_user_deactivated_at(db: &Database, user_id: i64) -> Result<Option<DateTime<Utc>>> {
+    let deactivated_at = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT deactivated_at FROM users WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(db.reader())
+        .await?
+        .flatten();
+    Ok(deactivated_at)
+}
+
+/// 用户再次发来消息（账号已恢复）时清除注销标记；返回之前是否被标记
+pub async fn clear_user_deactivated(db: &Database, user_id: i64) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE users SET deactivated_at = NULL, updated_at = ? WHERE user_id = ? AND deactivated_at IS NOT NULL",
+    )
+    .bind(Utc::now())
+    .bind(user_id)
+    .execute(db.writer())
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 记录用户屏蔽 (`blocked`) 或解除屏蔽机器人，同时更新 users.blocked_at；返回用户的屏蔽状态是否发生变化
+pub async fn record_block_event(db: &Database, user_id: i64, blocked: bool, at: DateTime<Utc>) -> Result<bool> {
+    let mut tx = db.writer().begin().await?;
+    let result = if blocked {
+        sqlx::query("UPDATE users SET blocked_at = ?, updated_at = ? WHERE user_id = ? AND blocked_at IS NULL")
+            .bind(at)
+            .bind(at)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+    } else {
+        sqlx::query("UPDATE users SET blocked_at = NULL, updated_at = ? WHERE user_id = ? AND blocked_at IS NOT NULL")
+            .bind(at)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+    };
+    sqlx::query("INSERT INTO block_events (user_id, blocked, created_at) VALUES (?, ?, ?)")
+        .bind(user_id)
+        .bind(blocked)
+        .bind(at)
+        .execute(&mut *tx)
         .await?;
-    
-    sqlx::query("UPDATE users SET request_count = 0")
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 规范化 Telegram 的 language_code（如 "zh-hans"），缺失或为空时返回 None
+fn normalize_language_code(language_code: Option<&str>) -> Option<String> {
+    language_code
+        .map(|code| code.trim().to_ascii_lowercase())
+        .filter(|code| !code.is_empty())
+}
+
+/// 记录用户的客户端语言；缺失时保留原有值
+pub async fn update_language_code(db: &Database, user_id: i64, language_code: Option<&str>) -> Result<()> {
+    let Some(language_code) = normalize_language_code(language_code) else {
+        return Ok(());
+    };
+
+    let pool = db.writer();
+    sqlx::query("UPDATE users SET language_code = ? WHERE user_id = ?")
+        .bind(language_code)
+        .bind(user_id)
         .execute(pool)
         .await?;
 
-    info!("统计数据已清除");
     Ok(())
 }
+
+/// 预扣超过该时长仍未确认或退回（如进程中途退出）时视为失效，不再占用配额
+const RESERVATION_TTL_MINUTES: i64 = 10;
+
+/// 开始生成前预扣一次配额。已用次数加上进行中的预扣未超过上限（或不受限）时返回预扣 id；
+/// 配额已用尽或用户不存在时不写入任何数据并返回 None。`QuotaMode::Machines` 时按不同的机器码计数，
+/// 本周期内已提交过（或正在生成）的机器码不占用新的额度。生成成功后调用 `commit_generation`，
+/// 失败时调用 `release_quota` 退回
+pub async fn reserve_quota(db: &Database, user_id: i64, quota: Quota, machine_code: &str) -> Result<Option<i64>> {
+    db.check_write_fault()?;
+    let mut tx = db.writer().begin().await?;
+    let id = insert_reservation(&mut tx, user_id, quota, machine_code, Utc::now()).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// 清理该用户已过期的预扣，配额未用尽时插入一条新预扣并返回其 id
+async fn insert_reservation(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    quota: Quota,
+    machine_code: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<i64>> {
+    let expired_before = now - chrono::Duration::minutes(RESERVATION_TTL_MINUTES);
+    sqlx::query("DELETE FROM quota_reservations WHERE user_id = ? AND created_at < ?")
+        .bind(user_id)
+        .bind(expired_before)
+        .execute(&mut *conn)
+        .await?;
+
+    let id = match quota.mode {
+        QuotaMode::Requests => {
+            sqlx::query_scalar::<_, i64>(
+                r#"
+                INSERT INTO quota_reservations (user_id, machine_code, created_at)
+                SELECT user_id, ?, ? FROM users
+                WHERE user_id = ?
+                  AND (? OR CASE WHEN ? IS NULL THEN request_count
+                                 ELSE (SELECT COUNT(*) FROM activation_logs WHERE user_id = ? AND created_at >= ?) END
+                            + (SELECT COUNT(*) FROM quota_reservations WHERE user_id = ?) < ?)
+                  AND (? IS NULL OR (SELECT COUNT(*) FROM activation_logs WHERE user_id = ? AND created_at >= ?)
+                                    + (SELECT COUNT(*) FROM quota_reservations WHERE user_id = ?) < ?)
+                RETURNING id
+                "#,
+            )
+            .bind(machine_code)
+            .bind(now)
+            .bind(user_id)
+            .bind(quota.unlimited)
+            .bind(quota.period_start)
+            .bind(user_id)
+            .bind(quota.period_start)
+            .bind(user_id)
+            .bind(quota.limit)
+        }
+        QuotaMode::Machines => {
+            // 累计配额没有周期起点，需连同已归档与已汇总删除的日志一起统计
+            sqlx::query_scalar::<_, i64>(
+                r#"
+                WITH used AS (
+                    SELECT machine_code FROM activation_logs WHERE user_id = ? AND created_at >= ?
+                    UNION
+                    SELECT machine_code FROM activation_logs_all WHERE user_id = ? AND ? IS NULL
+                    UNION
+                    SELECT machine_code FROM rolled_up_machine_codes WHERE user_id = ? AND ? IS NULL
+                    UNION
+                    SELECT machine_code FROM quota_reservations WHERE user_id = ? AND machine_code IS NOT NULL
+                )
+                INSERT INTO quota_reservations (user_id, machine_code, created_at)
+                SELECT user_id, ?, ? FROM users
+                WHERE user_id = ?
+                  AND (? OR ? IN (SELECT machine_code FROM used) OR (SELECT COUNT(*) FROM used) < ?)
+                  AND (? IS NULL OR (SELECT COUNT(*) FROM activation_logs WHERE user_id = ? AND created_at >= ?)
+                                    + (SELECT COUNT(*) FROM quota_reservations WHERE user_id = ?) < ?)
+                RETURNING id
+                "#,
+            )
+            .bind(user_id)
+            .bind(quota.period_start)
+            .bind(user_id)
+            .bind(quota.period_start)
+            .bind(user_id)
+            .bind(quota.period_start)
+            .bind(user_id)
+            .bind(machine_code)
+            .bind(now)
+            .bind(user_id)
+            .bind(quota.unlimited)
+            .bind(machine_code)
+            .bind(quota.limit)
+        }
+    }
+    .bind(quota.trial.map(|t| t.daily_limit))
+    .bind(user_id)
+    .bind(quota.trial.map(|t| t.window_start))
+    .bind(user_id)
+    .bind(quota.trial.map(|t| t.daily_limit))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    Ok(id)
+}
+
+/// 生成失败时退回预扣的配额；返回预扣是否仍存在（已过期清理时为 false）
+pub async fn release_quota(db: &Database, reservation_id: i64) -> Result<bool> {
+    db.check_write_fault()?;
+    let result = sqlx::query("DELETE FROM quota_reservations WHERE id = ?")
+        .bind(reservation_id)
+        .execute(db.writer())
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 确认预扣并写入一次成功生成的完整记录：在同一事务中删除预扣、累加次数、更新用户语言、
+/// 写入激活日志及各版本明细。返回本周期内已用的次数（累计配额即总次数）与激活日志 ID；
+/// 预扣已过期被清理且重新检查时配额已用尽，不写入任何数据并返回 None
+#[allow(clippy::too_many_arguments)]
+pub async fn commit_generation(
+    db: &Database,
+    reservation_id: i64,
+    user_id: i64,
+    correlation_id: &str,
+    machine_code: &str,
+    quota: Quota,
+    language_code: Option<&str>,
+    results: &[ActivationResult],
+) -> Result<Option<(i32, i64)>> {
+    let summary = ActivationCodeGenerator::primary_result(machine_code, results)
+        .context("没有可记录的激活码结果")?;
+    db.check_write_fault()?;
+    let now = Utc::now();
+    let mut tx = db.writer().begin().await?;
+
+    let reserved = sqlx::query("DELETE FROM quota_reservations WHERE id = ?")
+        .bind(reservation_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        > 0;
+    // 预扣已过期被清理时，期间的其他请求可能已用完配额，按预扣的规则重新检查一次
+    if !reserved {
+        let Some(recheck) = insert_reservation(&mut tx, user_id, quota, machine_code, now).await? else {
+            return Ok(None);
+        };
+        sqlx::query("DELETE FROM quota_reservations WHERE id = ?")
+            .bind(recheck)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let count = sqlx::query_scalar::<_, i32>(
+        r#"
+        UPDATE users
+        SET request_count = request_count + 1, updated_at = ?, language_code = COALESCE(?, language_code)
+        WHERE user_id = ?
+        RETURNING request_count
+        "#,
+    )
+    .bind(now)
+    .bind(normalize_language_code(language_code))
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let log_id = sqlx::query(
+        r#"
+        INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at, instance_id, correlation_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(user_id)
+    .bind(machine_code)
+    .bind(&summary.professional_code)
+    .bind(summary.version_type.log_label())
+    .bind(now)
+    .bind(db.instance_id())
+    .bind(correlation_id)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+
+    for result in results {
+        sqlx::query(
+            r#"
+            INSERT INTO activation_log_details (log_id, version, advanced_code, professional_code)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(log_id)
+        .bind(result.version_type.version_name_ascii())
+        .bind(&result.advanced_code)
+        .bind(&result.professional_code)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let count = match (quota.mode, quota.period_start) {
+        (QuotaMode::Requests, Some(since)) => {
+            sqlx::query_scalar::<_, i32>("SELECT COUNT(*) FROM activation_logs WHERE user_id = ? AND created_at >= ?")
+                .bind(user_id)
+                .bind(since)
+                .fetch_one(&mut *tx)
+                .await?
+        }
+        (QuotaMode::Requests, None) => count,
+        (QuotaMode::Machines, since) => quota_machine_codes(&mut tx, user_id, since).await? as i32,
+    };
+
+    tx.commit().await?;
+    Ok(Some((count, log_id)))
+}
+
+/// 结果未能送达用户时撤销一次已确认的生成：在同一事务中删除激活日志及明细并退回累计次数。
+/// 返回日志是否仍存在（已被清理或不属于该用户时为 false，不做任何改动）
+pub async fn refund_generation(db: &Database, log_id: i64, user_id: i64) -> Result<bool> {
+    db.check_write_fault()?;
+    let mut tx = db.writer().begin().await?;
+
+    sqlx::query("DELETE FROM activation_log_details WHERE log_id IN (SELECT id FROM activation_logs WHERE id = ? AND user_id = ?)")
+        .bind(log_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query("DELETE FROM activation_logs WHERE id = ? AND user_id = ?")
+        .bind(log_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+        > 0;
+    if deleted {
+        sqlx::query("UPDATE users SET request_count = MAX(request_count - 1, 0), updated_at = ? WHERE user_id = ?")
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+/// 用户在当前配额周期内已用的次数；累计配额即 `request_count`，按机器码计数时为不同机器码的个数
+pub async fn quota_used(db: &Database, user: &User, quota: &Quota) -> Result<i32> {
+    match (quota.mode, quota.period_start) {
+        (QuotaMode::Requests, Some(since)) => Ok(count_generations_since(db, user.user_id, since).await? as i32),
+        (QuotaMode::Requests, None) => Ok(user.request_count),
+        (QuotaMode::Machines, since) => {
+            let mut conn = db.writer().acquire().await?;
+            Ok(quota_machine_codes(&mut conn, user.user_id, since).await? as i32)
+        }
+    }
+}
+
+/// 按机器码计数时，本周期内提交过该机器码则再次生成不占用额度
+pub async fn machine_code_counted(db: &Database, user_id: i64, quota: &Quota, machine_code: &str) -> Result<bool> {
+    if quota.mode != QuotaMode::Machines {
+        return Ok(false);
+    }
+    let found = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM activation_logs WHERE user_id = ? AND created_at >= ? AND machine_code = ?)
+            OR EXISTS (SELECT 1 FROM activation_logs_all WHERE user_id = ? AND ? IS NULL AND machine_code = ?)
+            OR EXISTS (SELECT 1 FROM rolled_up_machine_codes WHERE user_id = ? AND ? IS NULL AND machine_code = ?)
+        "#,
+    )
+    .bind(user_id)
+    .bind(quota.period_start)
+    .bind(machine_code)
+    .bind(user_id)
+    .bind(quota.period_start)
+    .bind(machine_code)
+    .bind(user_id)
+    .bind(quota.period_start)
+    .bind(machine_code)
+    .fetch_one(db.writer())
+    .await?;
+    Ok(found != 0)
+}
+
+/// 用户在 `since` 之后提交过的不同机器码个数；`since` 为空时统计全部日志（含归档与已汇总删除的部分）
+async fn quota_machine_codes(conn: &mut SqliteConnection, user_id: i64, since: Option<DateTime<Utc>>) -> Result<i64> {
+    let count = match since {
+        Some(since) => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(DISTINCT machine_code) FROM activation_logs WHERE user_id = ? AND created_at >= ?",
+            )
+            .bind(user_id)
+            .bind(since)
+            .fetch_one(&mut *conn)
+            .await?
+        }
+        None => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM (SELECT machine_code FROM activation_logs_all WHERE user_id = ? \
+                 UNION SELECT machine_code FROM rolled_up_machine_codes WHERE user_id = ?)",
+            )
+            .bind(user_id)
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?
+        }
+    };
+    Ok(count)
+}
+
+/// 封禁用户；返回用户是否存在
+pub async fn ban_user(
+    db: &Database,
+    user_id: i64,
+    reason: Option<&str>,
+    banned_until: Option<DateTime<Utc>>,
+) -> Result<bool> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET is_banned = TRUE, ban_reason = ?, banned_at = ?, banned_until = ?, updated_at = ?
+        WHERE user_id = ?
+        "#,
+    )
+    .bind(reason)
+    .bind(now)
+    .bind(banned_until)
+    .bind(now)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 在一个事务中封禁多个用户；返回与 `user_ids` 一一对应的用户是否存在
+pub async fn ban_users(
+    db: &Database,
+    user_ids: &[i64],
+    reason: Option<&str>,
+    banned_until: Option<DateTime<Utc>>,
+) -> Result<Vec<bool>> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let mut found = Vec::with_capacity(user_ids.len());
+    let mut tx = pool.begin().await?;
+
+    for user_id in user_ids {
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET is_banned = TRUE, ban_reason = ?, banned_at = ?, banned_until = ?, updated_at = ?
+            WHERE user_id = ?
+            "#,
+        )
+        .bind(reason)
+        .bind(now)
+        .bind(banned_until)
+        .bind(now)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        found.push(result.rows_affected() > 0);
+    }
+
+    tx.commit().await?;
+    Ok(found)
+}
+
+/// 在一个事务中批量导入封禁名单；尚未登记的用户会被直接创建为封禁状态
+pub async fn import_bans(db: &Database, entries: &[BanEntry]) -> Result<ImportSummary> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let mut summary = ImportSummary::default();
+    let mut tx = pool.begin().await?;
+
+    for entry in entries {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO users (user_id, is_banned, ban_reason, banned_at, created_at, updated_at)
+            VALUES (?, TRUE, ?, ?, ?, ?)
+            ON CONFLICT(user_id) DO UPDATE
+            SET is_banned = TRUE, ban_reason = excluded.ban_reason, banned_at = excluded.banned_at,
+                banned_until = NULL, updated_at = excluded.updated_at
+            WHERE users.is_banned = FALSE OR users.banned_until <= excluded.banned_at
+            "#,
+        )
+        .bind(entry.user_id)
+        .bind(entry.reason.as_deref())
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            summary.applied += 1;
+        } else {
+            summary.already_banned += 1;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(summary)
+}
+
+/// 导入旧 Python 版的用户与激活日志，全部在同一事务中完成。已存在的 user_id 保留本版数据不覆盖；
+/// 同一用户、机器码与时间的日志视为重复，重复执行导入不会产生重复记录
+pub async fn import_legacy(db: &Database, users: &[LegacyUser], logs: &[LegacyLog]) -> Result<LegacyImportSummary> {
+    let now = Utc::now();
+    let mut summary = LegacyImportSummary::default();
+    let mut tx = db.writer().begin().await?;
+
+    for user in users {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO users (user_id, username, first_name, last_name, is_banned, request_count, created_at, updated_at, instance_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id) DO NOTHING
+            "#,
+        )
+        .bind(user.user_id)
+        .bind(user.username.as_deref())
+        .bind(user.first_name.as_deref())
+        .bind(user.last_name.as_deref())
+        .bind(user.is_banned)
+        .bind(user.request_count)
+        .bind(user.created_at.unwrap_or(now))
+        .bind(now)
+        .bind(db.instance_id())
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            summary.users_imported += 1;
+        } else {
+            summary.users_skipped += 1;
+        }
+    }
+
+    for log in logs {
+        let created_at = log.created_at.unwrap_or(now);
+        let result = sqlx::query(
+            r#"
+            INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at, instance_id)
+            SELECT ?, ?, ?, ?, ?, ?
+            WHERE NOT EXISTS (
+                SELECT 1 FROM activation_logs_all WHERE user_id = ? AND machine_code = ? AND created_at = ?
+            )
+            "#,
+        )
+        .bind(log.user_id)
+        .bind(&log.machine_code)
+        .bind(&log.activation_code)
+        .bind(&log.version)
+        .bind(created_at)
+        .bind(db.instance_id())
+        .bind(log.user_id)
+        .bind(&log.machine_code)
+        .bind(created_at)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            summary.logs_imported += 1;
+        } else {
+            summary.logs_skipped += 1;
+        }
+    }
+
+    tx.commit().await?;
+    Ok(summary)
+}
+
+/// 因超出配额自动封禁；仅在用户尚未被封禁（或临时封禁已到期）时生效，返回是否实际执行了封禁
+pub async fn auto_ban_user(db: &Database, user_id: i64, reason: &str) -> Result<bool> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET is_banned = TRUE, ban_reason = ?, banned_at = ?, banned_until = NULL, updated_at = ?
+        WHERE user_id = ? AND (is_banned = FALSE OR banned_until <= ?)
+        "#,
+    )
+    .bind(reason)
+    .bind(now)
+    .bind(now)
+    .bind(user_id)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 解除封禁；返回用户是否存在
+pub async fn unban_user(db: &Database, user_id: i64) -> Result<bool> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET is_banned = FALSE, ban_reason = NULL, banned_until = NULL, updated_at = ?
+        WHERE user_id = ?
+        "#,
+    )
+    .bind(now)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 在一个事务中解除多个用户的封禁；返回与 `user_ids` 一一对应的用户是否存在
+pub async fn unban_users(db: &Database, user_ids: &[i64]) -> Result<Vec<bool>> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let mut found = Vec::with_capacity(user_ids.len());
+    let mut tx = pool.begin().await?;
+
+    for user_id in user_ids {
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET is_banned = FALSE, ban_reason = NULL, banned_until = NULL, updated_at = ?
+            WHERE user_id = ?
+            "#,
+        )
+        .bind(now)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        found.push(result.rows_affected() > 0);
+    }
+
+    tx.commit().await?;
+    Ok(found)
+}
+
+/// 解除 `now` 之前已到期的临时封禁，返回被解封的用户 ID；永久封禁不受影响
+pub async fn lift_expired_bans(db: &Database, now: DateTime<Utc>) -> Result<Vec<i64>> {
+    let user_ids = sqlx::query_scalar::<_, i64>(
+        r#"
+        UPDATE users
+        SET is_banned = FALSE, ban_reason = NULL, banned_until = NULL, updated_at = ?
+        WHERE is_banned = TRUE AND banned_until IS NOT NULL AND banned_until <= ?
+        RETURNING user_id
+        "#,
+    )
+    .bind(now)
+    .bind(now)
+    .fetch_all(db.writer())
+    .await?;
+
+    Ok(user_ids)
+}
+
+/// 标记疑似滥用的用户；已被标记时不重复标记，返回是否为新标记
+pub async fn flag_user(db: &Database, user_id: i64) -> Result<bool> {
+    let pool = db.writer();
+    let result = sqlx::query("UPDATE users SET flagged_at = ? WHERE user_id = ? AND flagged_at IS NULL")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 解除滥用标记并记录解除时间，之后只按新的生成记录重新检测；返回用户此前是否被标记
+pub async fn clear_flag(db: &Database, user_id: i64) -> Result<bool> {
+    let pool = db.writer();
+    let result = sqlx::query("UPDATE users SET flagged_at = NULL, flag_cleared_at = ? WHERE user_id = ? AND flagged_at IS NOT NULL")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 设置用户的界面语言
+pub async fn set_user_lang(db: &Database, user_id: i64, lang: Lang) -> Result<()> {
+    let pool = db.writer();
+    sqlx::query("UPDATE users SET lang = ?, updated_at = ? WHERE user_id = ?")
+        .bind(lang.code())
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 设置是否将每个激活码作为独立消息发送
+pub async fn set_split_codes(db: &Database, user_id: i64, enabled: bool) -> Result<()> {
+    let pool = db.writer();
+    sqlx::query("UPDATE users SET split_codes = ?, updated_at = ? WHERE user_id = ?")
+        .bind(enabled)
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 记录用户私聊中置顶的生成结果消息
+pub async fn set_pinned_message(db: &Database, user_id: i64, message_id: Option<i32>) -> Result<()> {
+    let pool = db.writer();
+    sqlx::query("UPDATE users SET pinned_message_id = ? WHERE user_id = ?")
+        .bind(message_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 提前结束新用户试用期；返回用户是否存在
+pub async fn trust_user(db: &Database, user_id: i64) -> Result<bool> {
+    let pool = db.writer();
+    let result = sqlx::query("UPDATE users SET trusted_at = COALESCE(trusted_at, ?), updated_at = ? WHERE user_id = ?")
+        .bind(Utc::now())
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 被标记的用户，最近标记的在前
+pub async fn get_flagged_users(db: &Database, limit: i64) -> Result<Vec<User>> {
+    let pool = db.reader();
+    let users = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE flagged_at IS NOT NULL ORDER BY flagged_at DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users)
+}
+
+pub async fn get_all_users(db: &Database) -> Result<Vec<UserStats>> {
+    let pool = db.reader();
+    let users = sqlx::query(
+        r#"
+        SELECT 
+            u.user_id,
+            u.username,
+            u.request_count as total_requests,
+            (u.is_banned AND (u.banned_until IS NULL OR u.banned_until > ?)) AS is_banned,
+            u.deactivated_at,
+            u.blocked_at,
+            MAX(al.created_at) as last_request
+        FROM users u
+        LEFT JOIN activation_logs_all al ON u.user_id = al.user_id
+        GROUP BY u.user_id
+        ORDER BY u.created_at DESC
+        "#,
+    )
+    .bind(Utc::now())
+    .fetch_all(pool)
+    .await?;
+
+    let user_stats = users
+        .into_iter()
+        .map(|row| {
+            let last_request = row.get::<Option<chrono::DateTime<Utc>>, _>("last_request");
+            UserStats {
+                user_id: row.get("user_id"),
+                username: row.get("username"),
+                total_requests: row.get("total_requests"),
+                last_request,
+                is_banned: row.get("is_banned"),
+                deactivated_at: row.get("deactivated_at"),
+                blocked_at: row.get("blocked_at"),
+            }
+        })
+        .collect();
+
+    Ok(user_stats)
+}
+
+// 激活日志操作
+pub async fn get_activation_logs(db: &Database, limit: i64) -> Result<Vec<ActivationLog>> {
+    let pool = db.reader();
+    let logs = sqlx::query_as::<_, ActivationLog>(
+        "SELECT * FROM activation_logs_all ORDER BY created_at DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(logs)
+}
+
+/// 统计用户自 `since` 以来生成过激活码的不同机器码数；最近一次解除滥用标记之前的记录不计入
+pub async fn count_distinct_machine_codes_since(db: &Database, user_id: i64, since: DateTime<Utc>) -> Result<i64> {
+    let pool = db.reader();
+    let count = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(DISTINCT machine_code) FROM activation_logs
+        WHERE user_id = ? AND created_at >= ?
+          AND NOT EXISTS (SELECT 1 FROM users WHERE user_id = ? AND flag_cleared_at >= activation_logs.created_at)
+        "#,
+    )
+    .bind(user_id)
+    .bind(since)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// 用户在 `since` 之后的生成次数
+pub async fn count_generations_since(db: &Database, user_id: i64, since: DateTime<Utc>) -> Result<i64> {
+    let pool = db.writer();
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_logs WHERE user_id = ? AND created_at >= ?")
+        .bind(user_id)
+        .bind(since)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count)
+}
+
+/// 突发限制：滚动一小时内的生成次数已达 `per_hour` 时返回下一次可用的时间（最早一条计入的记录满一小时），
+/// 未达上限、未设置上限或配额不受限（管理员与白名单）时返回 None
+pub async fn hourly_slot_opens_at(
+    db: &Database,
+    user_id: i64,
+    quota: &Quota,
+    per_hour: Option<u32>,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    let Some(per_hour) = per_hour.filter(|_| !quota.unlimited) else {
+        return Ok(None);
+    };
+    let hour = chrono::Duration::hours(1);
+    // 一小时内倒数第 per_hour 条记录存在即已达上限，它满一小时后空出一个名额
+    let oldest_counted: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT created_at FROM activation_logs WHERE user_id = ? AND created_at > ? ORDER BY created_at DESC LIMIT 1 OFFSET ?",
+    )
+    .bind(user_id)
+    .bind(now - hour)
+    .bind(i64::from(per_hour) - 1)
+    .fetch_optional(db.reader())
+    .await?;
+    Ok(oldest_counted.map(|created_at| created_at + hour))
+}
+
+/// 全部用户在 `since` 之后的生成次数，含已归档的日志
+pub async fn count_activations_since(db: &Database, since: DateTime<Utc>) -> Result<i64> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_logs_all WHERE created_at >= ?")
+        .bind(since)
+        .fetch_one(db.reader())
+        .await?;
+    Ok(count)
+}
+
+/// 查询指定用户最近的激活记录
+pub async fn get_user_activation_logs(db: &Database, user_id: i64, limit: i64) -> Result<Vec<ActivationLog>> {
+    let pool = db.reader();
+    let logs = sqlx::query_as::<_, ActivationLog>(
+        "SELECT * FROM activation_logs_all WHERE user_id = ? ORDER BY created_at DESC, id DESC LIMIT ?",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(logs)
+}
+
+/// 按机器码或激活码片段搜索最近的激活记录
+pub async fn search_activation_logs(db: &Database, keyword: &str, limit: i64) -> Result<Vec<ActivationLog>> {
+    let pool = db.reader();
+    // 转义 LIKE 通配符，按字面匹配关键字
+    let escaped = keyword
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let pattern = format!("%{}%", escaped);
+
+    let logs = sqlx::query_as::<_, ActivationLog>(
+        r#"
+        SELECT * FROM activation_logs_all
+        WHERE machine_code LIKE ? ESCAPE '\' OR activation_code LIKE ? ESCAPE '\'
+        ORDER BY created_at DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(logs)
+}
+
+// 统计操作
+/// 系统统计；指定 `instance_id` 时只统计该实例写入的用户与激活日志
+pub async fn get_system_stats(db: &Database, instance_id: Option<&str>) -> Result<SystemStats> {
+    let pool = db.reader();
+    // 获取总用户数
+    let total_users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE ? IS NULL OR instance_id = ?")
+        .bind(instance_id)
+        .bind(instance_id)
+        .fetch_one(pool)
+        .await?;
+
+    // 获取总激活次数，含已汇总删除的历史日志
+    let total_activations: i64 = sqlx::query_scalar(
+        "SELECT (SELECT COUNT(*) FROM activation_logs_all WHERE ? IS NULL OR instance_id = ?) \
+         + (SELECT COALESCE(SUM(activations), 0) FROM daily_summary WHERE ? IS NULL OR instance_id = ?)",
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    // 获取今日活跃用户数，不含已注销的账号
+    let active_users_today: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT user_id) FROM activation_logs \
+         WHERE DATE(created_at) = DATE('now') AND (? IS NULL OR instance_id = ?) \
+         AND user_id NOT IN (SELECT user_id FROM users WHERE deactivated_at IS NOT NULL)",
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    // 获取今日激活次数
+    let activations_today: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM activation_logs WHERE DATE(created_at) = DATE('now') AND (? IS NULL OR instance_id = ?)",
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    let deactivated_users: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE deactivated_at IS NOT NULL AND (? IS NULL OR instance_id = ?)",
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    let blocked_users: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE blocked_at IS NOT NULL AND (? IS NULL OR instance_id = ?)",
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    // 近 7 天内屏蔽过机器人的人数，之后又解除的也计入
+    let blocked_this_week: i64 = sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT e.user_id) FROM block_events e LEFT JOIN users u ON u.user_id = e.user_id \
+         WHERE e.blocked AND e.created_at >= ? AND (? IS NULL OR u.instance_id = ?)",
+    )
+    .bind(Utc::now() - chrono::Duration::days(7))
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SystemStats {
+        id: 0,
+        total_users,
+        total_activations,
+        active_users_today,
+        activations_today,
+        deactivated_users,
+        blocked_users,
+        blocked_this_week,
+        system_status: "NORMAL".to_string(),
+        created_at: Utc::now(),
+    })
+}
+
+/// 按实例统计激活次数，按次数降序
+pub async fn get_instance_distribution(db: &Database) -> Result<Vec<(String, i64)>> {
+    let pool = db.reader();
+    let rows = sqlx::query_as::<_, (String, i64)>(
+        r#"
+        SELECT instance_id, SUM(activations) AS activations FROM (
+            SELECT instance_id, COUNT(*) AS activations FROM activation_logs_all GROUP BY instance_id
+            UNION ALL
+            SELECT instance_id, SUM(activations) FROM daily_summary GROUP BY instance_id
+        )
+        GROUP BY instance_id
+        ORDER BY activations DESC, instance_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// 按客户端语言统计用户数，按人数降序；未记录语言的用户归为 None
+pub async fn get_language_distribution(db: &Database) -> Result<Vec<(Option<String>, i64)>> {
+    let pool = db.reader();
+    let rows = sqlx::query_as::<_, (Option<String>, i64)>(
+        r#"
+        SELECT language_code, COUNT(*) AS users
+        FROM users
+        GROUP BY language_code
+        ORDER BY users DESC, language_code
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// 主表与全部月度归档表的只读视图；查询历史记录与累计统计时读取该视图
+const ALL_LOGS_VIEW: &str = "activation_logs_all";
+/// 归档表保留的列。给 activation_logs 加列时需同步加入这里与 create_archive_tables，并为已有的归档表补上该列
+const LOG_COLUMNS: &str = "id, user_id, machine_code, activation_code, finalshell_version, created_at, instance_id, correlation_id, outcome";
+const DETAIL_COLUMNS: &str = "id, log_id, version, advanced_code, professional_code";
+
+/// 已有的月度归档表（activation_logs_YYYYMM），按月份升序
+async fn archive_tables(conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    let tables = sqlx::query_scalar::<_, String>(
+        "SELECT name FROM sqlite_master WHERE type = 'table' \
+         AND name GLOB 'activation_logs_[0-9][0-9][0-9][0-9][0-9][0-9]' ORDER BY name",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(tables)
+}
+
+/// 创建 `month`（YYYYMM）的归档表与索引；id 沿用主表的值，按用户与时间查询时走索引
+async fn create_archive_tables(conn: &mut SqliteConnection, month: &str) -> Result<()> {
+    sqlx::query(&format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS activation_logs_{month} (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            machine_code TEXT NOT NULL,
+            activation_code TEXT NOT NULL,
+            finalshell_version TEXT NOT NULL,
+            created_at DATETIME,
+            instance_id TEXT NOT NULL DEFAULT '{instance}',
+            correlation_id TEXT,
+            outcome TEXT
+        )
+        "#,
+        month = month,
+        instance = DEFAULT_INSTANCE_ID,
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS idx_activation_logs_{month}_user_created ON activation_logs_{month} (user_id, created_at)",
+        month = month
+    ))
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(&format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS activation_log_details_{month} (
+            id INTEGER PRIMARY KEY,
+            log_id INTEGER NOT NULL,
+            version TEXT NOT NULL,
+            advanced_code TEXT NOT NULL,
+            professional_code TEXT NOT NULL
+        )
+        "#,
+        month = month
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS idx_activation_log_details_{month}_log ON activation_log_details_{month} (log_id)",
+        month = month
+    ))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// 早期归档表由 CREATE TABLE AS SELECT 生成，没有主键和索引；按 create_archive_tables 的结构重建并补齐索引
+async fn rebuild_legacy_archives(conn: &mut SqliteConnection) -> Result<()> {
+    for table in archive_tables(&mut *conn).await? {
+        let month = table.trim_start_matches("activation_logs_").to_string();
+        let has_primary_key: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = 'id' AND pk > 0",
+            table
+        ))
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if has_primary_key == 0 {
+            info!("重建归档表 {}", table);
+            // 视图引用着旧表，改名前先删除，迁移末尾会重建
+            sqlx::query(&format!("DROP VIEW IF EXISTS {}", ALL_LOGS_VIEW))
+                .execute(&mut *conn)
+                .await?;
+            let details_table = format!("activation_log_details_{}", month);
+            let copies = [(&table, LOG_COLUMNS), (&details_table, DETAIL_COLUMNS)];
+            for (name, _) in copies {
+                sqlx::query(&format!("ALTER TABLE {name} RENAME TO {name}_legacy", name = name))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            create_archive_tables(&mut *conn, &month).await?;
+            for (name, columns) in copies {
+                sqlx::query(&format!(
+                    "INSERT INTO {name} ({cols}) SELECT {cols} FROM {name}_legacy",
+                    name = name,
+                    cols = columns
+                ))
+                .execute(&mut *conn)
+                .await?;
+                sqlx::query(&format!("DROP TABLE {}_legacy", name))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        } else {
+            create_archive_tables(&mut *conn, &month).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 按当前的归档表重建汇总视图
+async fn rebuild_log_view(conn: &mut SqliteConnection) -> Result<()> {
+    let mut select = format!("SELECT {} FROM activation_logs", LOG_COLUMNS);
+    for table in archive_tables(&mut *conn).await? {
+        select.push_str(&format!(" UNION ALL SELECT {} FROM {}", LOG_COLUMNS, table));
+    }
+    sqlx::query(&format!("DROP VIEW IF EXISTS {}", ALL_LOGS_VIEW))
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(&format!("CREATE VIEW {} AS {}", ALL_LOGS_VIEW, select))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// 将 `before` 之前的激活日志及其版本明细按 `tz` 下的自然月移入 activation_logs_YYYYMM 与
+/// activation_log_details_YYYYMM，返回各月移动的日志条数。全部在一个事务中完成，可重复执行，
+/// 同一月份分多次归档时追加到已有的归档表
+pub async fn archive_activation_logs(db: &Database, before: DateTime<Utc>, tz: FixedOffset) -> Result<Vec<(String, u64)>> {
+    let offset = format!("{:+} seconds", tz.local_minus_utc());
+    let mut tx = db.writer().begin().await?;
+
+    let months = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT strftime('%Y%m', created_at, ?) AS month FROM activation_logs WHERE created_at < ? ORDER BY month",
+    )
+    .bind(&offset)
+    .bind(before)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut archived = Vec::new();
+    for month in months {
+        // 月份来自 strftime，只含数字，可以安全拼入表名
+        if month.len() != 6 || !month.bytes().all(|b| b.is_ascii_digit()) {
+            anyhow::bail!("无法识别的归档月份: {}", month);
+        }
+        let logs_table = format!("activation_logs_{}", month);
+        let details_table = format!("activation_log_details_{}", month);
+        let selected = "SELECT id FROM activation_logs WHERE created_at < ? AND strftime('%Y%m', created_at, ?) = ?";
+
+        create_archive_tables(&mut tx, &month).await?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {} ({cols}) SELECT {cols} FROM activation_log_details WHERE log_id IN ({})",
+            details_table,
+            selected,
+            cols = DETAIL_COLUMNS
+        ))
+        .bind(before)
+        .bind(&offset)
+        .bind(&month)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} ({cols}) SELECT {cols} FROM activation_logs WHERE id IN ({})",
+            logs_table,
+            selected,
+            cols = LOG_COLUMNS
+        ))
+        .bind(before)
+        .bind(&offset)
+        .bind(&month)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(&format!("DELETE FROM activation_log_details WHERE log_id IN ({})", selected))
+            .bind(before)
+            .bind(&offset)
+            .bind(&month)
+            .execute(&mut *tx)
+            .await?;
+        let moved = sqlx::query(&format!("DELETE FROM activation_logs WHERE id IN ({})", selected))
+            .bind(before)
+            .bind(&offset)
+            .bind(&month)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        archived.push((logs_table, moved));
+    }
+
+    if !archived.is_empty() {
+        rebuild_log_view(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(archived)
+}
+
+/// 将 `before`（向下取整到 `tz` 下的当天零点）之前的激活日志按天、按实例汇总写入 daily_summary，
+/// 随后删除这些原始日志及其版本明细（含月度归档表中的）。全部在一个事务中完成；
+/// 重复执行时以当前仍存在的原始日志重算并覆盖对应日期，不会重复累加。返回汇总的天数与删除的日志条数
+pub async fn roll_up_activation_logs(db: &Database, before: DateTime<Utc>, tz: FixedOffset) -> Result<(usize, u64)> {
+    let before = QuotaPeriod::Daily.period_start(before, tz).unwrap_or(before);
+    let offset = format!("{:+} seconds", tz.local_minus_utc());
+    let mut tx = db.writer().begin().await?;
+
+    let version_rows = sqlx::query_as::<_, (String, String, String, i64)>(&format!(
+        "SELECT strftime('%Y-%m-%d', created_at, ?) AS day, instance_id, finalshell_version, COUNT(*) \
+         FROM {} WHERE created_at < ? GROUP BY day, instance_id, finalshell_version",
+        ALL_LOGS_VIEW
+    ))
+    .bind(&offset)
+    .bind(before)
+    .fetch_all(&mut *tx)
+    .await?;
+    let user_rows = sqlx::query_as::<_, (String, String, i64)>(&format!(
+        "SELECT strftime('%Y-%m-%d', created_at, ?) AS day, instance_id, COUNT(DISTINCT user_id) \
+         FROM {} WHERE created_at < ? GROUP BY day, instance_id",
+        ALL_LOGS_VIEW
+    ))
+    .bind(&offset)
+    .bind(before)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut versions: BTreeMap<(String, String), BTreeMap<String, i64>> = BTreeMap::new();
+    for (day, instance_id, version, count) in version_rows {
+        versions.entry((day, instance_id)).or_default().insert(version, count);
+    }
+    let mut days = BTreeSet::new();
+    for (day, instance_id, distinct_users) in user_rows {
+        days.insert(day.clone());
+        let counts = versions.remove(&(day.clone(), instance_id.clone())).unwrap_or_default();
+        sqlx::query(
+            r#"
+            INSERT INTO daily_summary (day, instance_id, activations, distinct_users, version_counts)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (day, instance_id) DO UPDATE SET
+                activations = excluded.activations,
+                distinct_users = excluded.distinct_users,
+                version_counts = excluded.version_counts
+            "#,
+        )
+        .bind(&day)
+        .bind(&instance_id)
+        .bind(counts.values().sum::<i64>())
+        .bind(distinct_users)
+        .bind(serde_json::to_string(&counts)?)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // 删除前保留用户提交过的机器码，避免按机器码计数的累计配额被重新放开
+    sqlx::query(&format!(
+        "INSERT OR IGNORE INTO rolled_up_machine_codes (user_id, machine_code) \
+         SELECT DISTINCT user_id, machine_code FROM {} WHERE created_at < ?",
+        ALL_LOGS_VIEW
+    ))
+    .bind(before)
+    .execute(&mut *tx)
+    .await?;
+
+    let mut pruned = 0;
+    let mut tables = vec!["activation_logs".to_string()];
+    tables.extend(archive_tables(&mut tx).await?);
+    for table in tables {
+        let details_table = table.replace("activation_logs", "activation_log_details");
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE log_id IN (SELECT id FROM {} WHERE created_at < ?)",
+            details_table, table
+        ))
+        .bind(before)
+        .execute(&mut *tx)
+        .await?;
+        pruned += sqlx::query(&format!("DELETE FROM {} WHERE created_at < ?", table))
+            .bind(before)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+
+    tx.commit().await?;
+    Ok((days.len(), pruned))
+}
+
+/// `since` 所在日期（`tz` 下）起每天的激活统计，按日期升序。合并尚未汇总的原始日志与 daily_summary，
+/// 汇总行按实例分别保存，多实例时同一用户可能在各实例的独立用户数中重复计入
+pub async fn get_daily_activity(db: &Database, since: DateTime<Utc>, tz: FixedOffset) -> Result<Vec<DailySummary>> {
+    let since = QuotaPeriod::Daily.period_start(since, tz).unwrap_or(since);
+    let since_day = since.with_timezone(&tz).format("%Y-%m-%d").to_string();
+    let offset = format!("{:+} seconds", tz.local_minus_utc());
+    let pool = db.reader();
+
+    let mut days: BTreeMap<String, DailySummary> = BTreeMap::new();
+    let version_rows = sqlx::query_as::<_, (String, String, i64)>(&format!(
+        "SELECT strftime('%Y-%m-%d', created_at, ?) AS day, finalshell_version, COUNT(*) \
+         FROM {} WHERE created_at >= ? GROUP BY day, finalshell_version",
+        ALL_LOGS_VIEW
+    ))
+    .bind(&offset)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    for (day, version, count) in version_rows {
+        let summary = days.entry(day.clone()).or_insert_with(|| DailySummary { day, ..Default::default() });
+        summary.activations += count;
+        *summary.versions.entry(version).or_default() += count;
+    }
+    let user_rows = sqlx::query_as::<_, (String, i64)>(&format!(
+        "SELECT strftime('%Y-%m-%d', created_at, ?) AS day, COUNT(DISTINCT user_id) \
+         FROM {} WHERE created_at >= ? GROUP BY day",
+        ALL_LOGS_VIEW
+    ))
+    .bind(&offset)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    for (day, distinct_users) in user_rows {
+        days.entry(day.clone()).or_insert_with(|| DailySummary { day, ..Default::default() }).distinct_users += distinct_users;
+    }
+
+    let summary_rows = sqlx::query_as::<_, (String, i64, i64, String)>(
+        "SELECT day, activations, distinct_users, version_counts FROM daily_summary WHERE day >= ?",
+    )
+    .bind(&since_day)
+    .fetch_all(pool)
+    .await?;
+    for (day, activations, distinct_users, version_counts) in summary_rows {
+        let summary = days.entry(day.clone()).or_insert_with(|| DailySummary { day, ..Default::default() });
+        summary.activations += activations;
+        summary.distinct_users += distinct_users;
+        for (version, count) in serde_json::from_str::<BTreeMap<String, i64>>(&version_counts)? {
+            *summary.versions.entry(version).or_default() += count;
+        }
+    }
+
+    Ok(days.into_values().collect())
+}
+
+pub async fn clear_stats(db: &Database) -> Result<()> {
+    let pool = db.writer();
+    warn!("清除所有统计数据...");
+    
+    sqlx::query("DELETE FROM activation_log_details")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM activation_logs")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM daily_summary")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM rolled_up_machine_codes")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("DELETE FROM request_metrics")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("UPDATE users SET request_count = 0")
+        .execute(pool)
+        .await?;
+
+    // 月度归档一并删除
+    let mut conn = pool.acquire().await?;
+    for table in archive_tables(&mut conn).await? {
+        let details_table = table.replace("activation_logs_", "activation_log_details_");
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", details_table))
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(&format!("DROP TABLE {}", table))
+            .execute(&mut *conn)
+            .await?;
+    }
+    rebuild_log_view(&mut conn).await?;
+
+    info!("统计数据已清除");
+    Ok(())
+}
+
+// 审计日志操作
+/// 非管理员发起的操作（HTTP 接口、命令行）在审计日志中使用的操作者 ID
+pub const SYSTEM_ACTOR_ID: i64 = 0;
+
+pub async fn log_admin_action(
+    db: &Database,
+    admin_id: i64,
+    action: &str,
+    target_user_id: Option<i64>,
+    detail: &str,
+) -> Result<()> {
+    // 先插入再取上一条的哈希：插入后事务持有写锁，其他写入无法插在两者之间
+    let mut tx = db.writer().begin().await?;
+    let id = sqlx::query(
+        r#"
+        INSERT INTO admin_actions (admin_id, action, target_user_id, detail, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(admin_id)
+    .bind(action)
+    .bind(target_user_id)
+    .bind(detail)
+    .bind(Utc::now())
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+
+    let previous: Option<String> = sqlx::query_scalar("SELECT hash FROM admin_actions WHERE id < ? ORDER BY id DESC LIMIT 1")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .flatten();
+    let entry = sqlx::query_as::<_, AuditEntry>(&format!("{} WHERE id = ?", AUDIT_ENTRY_SELECT))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let hash = audit::entry_hash(previous.as_deref().unwrap_or(audit::GENESIS_HASH), &entry);
+    sqlx::query("UPDATE admin_actions SET hash = ? WHERE id = ?")
+        .bind(&hash)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    // 末条哈希另存一份作为锚点，删除末尾的记录后链本身仍然连续，只能靠锚点发现
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value, updated_at) VALUES (?, ?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(AUDIT_HEAD_SETTING)
+    .bind(&hash)
+    .bind(Utc::now())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// 保存审计哈希链末条哈希的设置项
+const AUDIT_HEAD_SETTING: &str = "audit_chain_head";
+
+const AUDIT_ENTRY_SELECT: &str = "SELECT id, admin_id, action, target_user_id, detail, \
+     CAST(created_at AS TEXT) AS created_at, hash FROM admin_actions";
+
+/// 全部审计记录，按 id 顺序
+pub async fn get_audit_entries(db: &Database) -> Result<Vec<AuditEntry>> {
+    let entries = sqlx::query_as::<_, AuditEntry>(&format!("{} ORDER BY id", AUDIT_ENTRY_SELECT))
+        .fetch_all(db.reader())
+        .await?;
+    Ok(entries)
+}
+
+/// 针对某个用户的管理操作（封禁、解封、标记等），不含查看类操作；最新的在前
+pub async fn get_user_admin_actions(db: &Database, user_id: i64, limit: i64) -> Result<Vec<AuditEntry>> {
+    let entries = sqlx::query_as::<_, AuditEntry>(&format!(
+        "{} WHERE target_user_id = ? AND action NOT LIKE 'view\\_%' ESCAPE '\\' ORDER BY id DESC LIMIT ?",
+        AUDIT_ENTRY_SELECT
+    ))
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(db.reader())
+    .await?;
+    Ok(entries)
+}
+
+/// 校验审计日志哈希链，并与记录的末条哈希锚点比对
+pub async fn verify_audit_chain(db: &Database) -> Result<audit::ChainStatus> {
+    let entries = get_audit_entries(db).await?;
+    let anchor: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(AUDIT_HEAD_SETTING)
+        .fetch_optional(db.reader())
+        .await?;
+    Ok(audit::verify(&entries, anchor.as_deref()))
+}
+
+/// 为升级前没有哈希的审计记录补算哈希链
+async fn seal_audit_chain(conn: &mut SqliteConnection) -> Result<()> {
+    let entries = sqlx::query_as::<_, AuditEntry>(&format!("{} ORDER BY id", AUDIT_ENTRY_SELECT))
+        .fetch_all(&mut *conn)
+        .await?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    info!("为 {} 条已有审计记录补算哈希链", entries.len());
+    let mut previous = audit::GENESIS_HASH.to_string();
+    for entry in &entries {
+        previous = audit::entry_hash(&previous, entry);
+        sqlx::query("UPDATE admin_actions SET hash = ? WHERE id = ?")
+            .bind(&previous)
+            .bind(entry.id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+// 申诉操作
+pub async fn create_appeal(
+    db: &Database,
+    user_id: i64,
+    banned_at: Option<DateTime<Utc>>,
+    content: &str,
+) -> Result<i64> {
+    let pool = db.writer();
+    let result = sqlx::query(
+        r#"
+        INSERT INTO appeals (user_id, banned_at, content, status, created_at)
+        VALUES (?, ?, ?, 'pending', ?)
+        "#,
+    )
+    .bind(user_id)
+    .bind(banned_at)
+    .bind(content)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn get_appeal(db: &Database, appeal_id: i64) -> Result<Appeal> {
+    let pool = db.writer();
+    let appeal = sqlx::query_as::<_, Appeal>("SELECT * FROM appeals WHERE id = ?")
+        .bind(appeal_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(appeal)
+}
+
+/// 当前这次封禁是否已经提交过申诉
+pub async fn has_appeal_for_ban(
+    db: &Database,
+    user_id: i64,
+    banned_at: Option<DateTime<Utc>>,
+) -> Result<bool> {
+    let pool = db.writer();
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM appeals WHERE user_id = ? AND banned_at IS ?",
+    )
+    .bind(user_id)
+    .bind(banned_at)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count > 0)
+}
+
+pub async fn count_appeals_since(db: &Database, user_id: i64, since: DateTime<Utc>) -> Result<i64> {
+    let pool = db.writer();
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM appeals WHERE user_id = ? AND created_at >= ?",
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+/// 处理申诉，仅对待处理状态生效；返回是否实际更新
+pub async fn decide_appeal(db: &Database, appeal_id: i64, status: &str, admin_id: i64) -> Result<bool> {
+    let pool = db.writer();
+    let result = sqlx::query(
+        r#"
+        UPDATE appeals
+        SET status = ?, decided_by = ?, decided_at = ?
+        WHERE id = ? AND status = 'pending'
+        "#,
+    )
+    .bind(status)
+    .bind(admin_id)
+    .bind(Utc::now())
+    .bind(appeal_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// 激活失败反馈操作
+/// 记录一次激活失败反馈；同一用户对同一机器码与版本 24 小时内只记一次，返回是否新增
+pub async fn create_code_report(
+    db: &Database,
+    user_id: i64,
+    machine_code_hash: &str,
+    version: &str,
+) -> Result<bool> {
+    let pool = db.writer();
+    let now = Utc::now();
+    let result = sqlx::query(
+        r#"
+        INSERT INTO code_reports (user_id, machine_code_hash, version, created_at)
+        SELECT ?, ?, ?, ?
+        WHERE NOT EXISTS (
+            SELECT 1 FROM code_reports
+            WHERE user_id = ? AND machine_code_hash = ? AND version = ? AND created_at >= ?
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(machine_code_hash)
+    .bind(version)
+    .bind(now)
+    .bind(user_id)
+    .bind(machine_code_hash)
+    .bind(version)
+    .bind(now - chrono::Duration::days(1))
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn count_code_reports_since(db: &Database, version: &str, since: DateTime<Utc>) -> Result<i64> {
+    let pool = db.writer();
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM code_reports WHERE version = ? AND created_at >= ?",
+    )
+    .bind(version)
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
+
+pub async fn get_recent_code_reports(db: &Database, limit: i64) -> Result<Vec<CodeReport>> {
+    let pool = db.reader();
+    let reports = sqlx::query_as::<_, CodeReport>(
+        "SELECT * FROM code_reports ORDER BY created_at DESC, id DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(reports)
+}
+
+/// 用户最近提交的激活失败反馈
+pub async fn get_user_code_reports(db: &Database, user_id: i64, limit: i64) -> Result<Vec<CodeReport>> {
+    let pool = db.reader();
+    let reports = sqlx::query_as::<_, CodeReport>(
+        "SELECT * FROM code_reports WHERE user_id = ? ORDER BY created_at DESC, id DESC LIMIT ?",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(reports)
+}
+
+/// 用户最近提交的申诉
+pub async fn get_user_appeals(db: &Database, user_id: i64, limit: i64) -> Result<Vec<Appeal>> {
+    let pool = db.reader();
+    let appeals = sqlx::query_as::<_, Appeal>(
+        "SELECT * FROM appeals WHERE user_id = ? ORDER BY created_at DESC, id DESC LIMIT ?",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(appeals)
+}
+
+// 设置操作
+/// 读取设置值及其最后修改时间
+pub async fn get_setting(db: &Database, key: &str) -> Result<Option<(String, DateTime<Utc>)>> {
+    let pool = db.writer();
+    let row = sqlx::query_as::<_, (String, DateTime<Utc>)>("SELECT value, updated_at FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row)
+}
+
+/// 写入设置；值未变化时保留原修改时间，返回该值自何时起生效
+pub async fn set_setting(db: &Database, key: &str, value: &str) -> Result<DateTime<Utc>> {
+    if let Some((current, updated_at)) = get_setting(db, key).await? {
+        if current == value {
+            return Ok(updated_at);
+        }
+    }
+
+    let pool = db.writer();
+    let now = Utc::now();
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value, updated_at) VALUES (?, ?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    Ok(now)
+}
+
+/// 设置当前值为 `from` 时改写为 `to` 并保留原修改时间，用于更换存储格式；返回是否改写
+pub async fn replace_setting_value(db: &Database, key: &str, from: &str, to: &str) -> Result<bool> {
+    let result = sqlx::query("UPDATE settings SET value = ? WHERE key = ? AND value = ?")
+        .bind(to)
+        .bind(key)
+        .bind(from)
+        .execute(db.writer())
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 在同一个写事务内读取设置、由 `update` 计算新值并写回，多实例并发修改时不会丢失更新；
+/// `update` 返回错误时不做任何修改
+pub async fn update_setting<F>(db: &Database, key: &str, update: F) -> Result<String>
+where
+    F: FnOnce(Option<&str>) -> Result<String>,
+{
+    db.check_write_fault()?;
+    let mut tx = db.writer().begin().await?;
+    // 先执行一条写语句取得写锁，避免读后升级写锁时与其他实例交错
+    sqlx::query("UPDATE settings SET value = value WHERE key = ?")
+        .bind(key)
+        .execute(&mut *tx)
+        .await?;
+    let current: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let value = update(current.as_deref())?;
+    if current.as_deref() != Some(value.as_str()) {
+        sqlx::query(
+            r#"
+            INSERT INTO settings (key, value, updated_at) VALUES (?, ?, ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(key)
+        .bind(&value)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(value)
+}
+
+/// 管理员最近一次活动时间：取审计日志与其作为用户的最后更新时间中较晚者
+pub async fn get_last_admin_activity(db: &Database, admin_id: i64) -> Result<Option<DateTime<Utc>>> {
+    let pool = db.reader();
+    let actions: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT MAX(created_at) FROM admin_actions WHERE admin_id = ?")
+        .bind(admin_id)
+        .fetch_one(pool)
+        .await?;
+    let updated: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT updated_at FROM users WHERE user_id = ?")
+        .bind(admin_id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    Ok(actions.max(updated))
+}
+
+// 请求耗时操作
+pub async fn record_request_metric(
+    db: &Database,
+    user_id: i64,
+    correlation_id: &str,
+    duration: Duration,
+) -> Result<()> {
+    let pool = db.writer();
+    sqlx::query("INSERT INTO request_metrics (user_id, correlation_id, duration_us, created_at) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(correlation_id)
+        .bind(i64::try_from(duration.as_micros()).unwrap_or(i64::MAX))
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 记录一条消息从 Telegram 创建到开始处理的延迟
+pub async fn record_update_lag(db: &Database, correlation_id: &str, lag: Duration) -> Result<()> {
+    sqlx::query("INSERT INTO update_lags (correlation_id, lag_ms, created_at) VALUES (?, ?, ?)")
+        .bind(correlation_id)
+        .bind(i64::try_from(lag.as_millis()).unwrap_or(i64::MAX))
+        .bind(Utc::now())
+        .execute(db.writer())
+        .await?;
+
+    Ok(())
+}
+
+/// 记录未能生成激活码的请求
+pub async fn record_request_failure(db: &Database, correlation_id: &str, user_id: i64, stage: &str, detail: &str) -> Result<()> {
+    sqlx::query("INSERT INTO request_failures (correlation_id, user_id, stage, detail, created_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(correlation_id)
+        .bind(user_id)
+        .bind(stage)
+        .bind(detail)
+        .bind(Utc::now())
+        .execute(db.writer())
+        .await?;
+
+    Ok(())
+}
+
+/// 按关联 ID 查找一次请求的激活日志、耗时与失败记录
+pub async fn get_request_trace(db: &Database, correlation_id: &str) -> Result<RequestTrace> {
+    let pool = db.reader();
+    let activations = sqlx::query_as::<_, ActivationLog>("SELECT * FROM activation_logs_all WHERE correlation_id = ? ORDER BY id")
+        .bind(correlation_id)
+        .fetch_all(pool)
+        .await?;
+    let metrics = sqlx::query_as::<_, RequestMetric>(
+        r#"
+        SELECT user_id, duration_us,
+               COALESCE(update_lag_ms, (SELECT lag_ms FROM update_lags WHERE update_lags.correlation_id = request_metrics.correlation_id)) AS update_lag_ms,
+               created_at
+        FROM request_metrics WHERE correlation_id = ? ORDER BY id
+        "#,
+    )
+    .bind(correlation_id)
+    .fetch_all(pool)
+    .await?;
+    let failures = sqlx::query_as::<_, RequestFailure>("SELECT * FROM request_failures WHERE correlation_id = ? ORDER BY id")
+        .bind(correlation_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(RequestTrace { activations, metrics, failures })
+}
+
+/// 用户最近未能生成激活码的请求
+pub async fn get_user_failures(db: &Database, user_id: i64, limit: i64) -> Result<Vec<RequestFailure>> {
+    let failures = sqlx::query_as::<_, RequestFailure>(
+        "SELECT * FROM request_failures WHERE user_id = ? ORDER BY created_at DESC, id DESC LIMIT ?",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(db.reader())
+    .await?;
+    Ok(failures)
+}
+
+/// 统计 `since` 之后的生成耗时，没有记录时返回 None
+pub async fn get_latency_summary(db: &Database, since: DateTime<Utc>) -> Result<Option<LatencySummary>> {
+    let pool = db.reader();
+    let row = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS count,
+               CAST(COALESCE(AVG(duration_us), 0) AS INTEGER) AS avg_us,
+               COALESCE(MAX(duration_us), 0) AS max_us
+        FROM request_metrics WHERE created_at >= ?
+        "#,
+    )
+    .bind(since)
+    .fetch_one(pool)
+    .await?;
+
+    let count: i64 = row.get("count");
+    if count == 0 {
+        return Ok(None);
+    }
+    Ok(Some(LatencySummary {
+        count,
+        avg_us: row.get("avg_us"),
+        max_us: row.get("max_us"),
+    }))
+}
+
+/// `since` 之后的更新处理延迟分位数，没有记录时返回 None
+pub async fn get_update_lag(db: &Database, since: DateTime<Utc>) -> Result<Option<UpdateLag>> {
+    let samples: Vec<i64> = sqlx::query_scalar("SELECT lag_ms FROM update_lags WHERE created_at >= ?")
+        .bind(since)
+        .fetch_all(db.reader())
+        .await?;
+
+    Ok(UpdateLag::from_samples(samples))
+}
+
+// 广播记录
+pub async fn record_broadcast(
+    db: &Database,
+    admin_id: i64,
+    content: &str,
+    is_test: bool,
+    delivered: i64,
+    failed: i64,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO broadcasts (admin_id, content, is_test, delivered, failed, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(admin_id)
+    .bind(content)
+    .bind(is_test)
+    .bind(delivered)
+    .bind(failed)
+    .bind(Utc::now())
+    .execute(db.writer())
+    .await?;
+
+    Ok(())
+}
+
+/// 正式广播的累计投递统计，忽略测试广播
+pub async fn get_broadcast_stats(db: &Database) -> Result<BroadcastStats> {
+    let row = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS runs, COALESCE(SUM(delivered), 0) AS delivered, COALESCE(SUM(failed), 0) AS failed
+        FROM broadcasts WHERE is_test = FALSE
+        "#,
+    )
+    .fetch_one(db.reader())
+    .await?;
+
+    Ok(BroadcastStats {
+        runs: row.get("runs"),
+        delivered: row.get("delivered"),
+        failed: row.get("failed"),
+    })
+}
+
+// 文案实验
+/// 为用户固定实验分组并返回实际分组；已分组的用户保持原分组
+pub async fn assign_copy_variant(db: &Database, user_id: i64, experiment: &str, variant: CopyVariant) -> Result<CopyVariant> {
+    let pool = db.writer();
+    sqlx::query(
+        "INSERT OR IGNORE INTO copy_assignments (user_id, experiment, variant, assigned_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(experiment)
+    .bind(variant.code())
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    let stored: String = sqlx::query_scalar("SELECT variant FROM copy_assignments WHERE user_id = ? AND experiment = ?")
+        .bind(user_id)
+        .bind(experiment)
+        .fetch_one(pool)
+        .await?;
+    Ok(CopyVariant::from_code(&stored).unwrap_or(variant))
+}
+
+/// 各分组的人数与转化人数（分组后至少生成过一次激活码），按分组名排序
+pub async fn get_copy_variant_stats(db: &Database, experiment: &str) -> Result<Vec<CopyVariantStats>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT a.variant AS variant,
+               COUNT(*) AS users,
+               COALESCE(SUM(EXISTS (
+                   SELECT 1 FROM activation_logs_all l
+                   WHERE l.user_id = a.user_id AND datetime(l.created_at) >= datetime(a.assigned_at)
+               )), 0) AS converted
+        FROM copy_assignments a
+        WHERE a.experiment = ?
+        GROUP BY a.variant
+        ORDER BY a.variant
+        "#,
+    )
+    .bind(experiment)
+    .fetch_all(db.reader())
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| CopyVariantStats {
+            variant: row.get("variant"),
+            users: row.get("users"),
+            converted: row.get("converted"),
+        })
+        .collect())
+}
+
+/// 记录用户对某次生成结果的反馈，可改选；只能反馈自己的、尚未归档的记录，否则返回 false
+pub async fn set_activation_outcome(db: &Database, log_id: i64, user_id: i64, outcome: ActivationOutcome) -> Result<bool> {
+    let result = sqlx::query("UPDATE activation_logs SET outcome = ? WHERE id = ? AND user_id = ?")
+        .bind(outcome.as_str())
+        .bind(log_id)
+        .bind(user_id)
+        .execute(db.writer())
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// 用户自己的一条激活日志对应的机器码与各版本激活码；日志不存在、已归档或不属于该用户时返回 None
+pub async fn get_activation_result(db: &Database, log_id: i64, user_id: i64) -> Result<Option<(String, Vec<ActivationResult>)>> {
+    let pool = db.reader();
+    let Some(machine_code) = sqlx::query_scalar::<_, String>("SELECT machine_code FROM activation_logs WHERE id = ? AND user_id = ?")
+        .bind(log_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let rows = sqlx::query("SELECT version, advanced_code, professional_code FROM activation_log_details WHERE log_id = ? ORDER BY id")
+        .bind(log_id)
+        .fetch_all(pool)
+        .await?;
+    let results: Vec<_> = rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(ActivationResult {
+                version_type: FinalShellVersionType::from_name_ascii(row.get("version"))?,
+                advanced_code: row.get("advanced_code"),
+                professional_code: row.get("professional_code"),
+            })
+        })
+        .collect();
+    Ok((!results.is_empty()).then_some((machine_code, results)))
+}
+
+/// 保存一次性结果链接对应的加密结果，顺带删除已过期的链接
+pub async fn store_pending_result(
+    db: &Database,
+    token_hash: &str,
+    user_id: i64,
+    sealed: &[u8],
+    now: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    db.check_write_fault()?;
+    let mut tx = db.writer().begin().await?;
+    sqlx::query("DELETE FROM pending_results WHERE expires_at <= ?")
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO pending_results (token_hash, user_id, sealed, expires_at, created_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(sealed)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 取出并删除一次性结果链接，返回加密结果与过期时间；已使用或已被清理时返回 None。
+/// 并发打开同一链接时只有一个请求能取到
+pub async fn take_pending_result(db: &Database, token_hash: &str) -> Result<Option<(Vec<u8>, DateTime<Utc>)>> {
+    let row = sqlx::query("DELETE FROM pending_results WHERE token_hash = ? RETURNING sealed, expires_at")
+        .bind(token_hash)
+        .fetch_optional(db.writer())
+        .await?;
+    Ok(row.map(|row| (row.get("sealed"), row.get("expires_at"))))
+}
+
+/// 删除已过期的一次性结果链接，返回删除的条数
+pub async fn purge_expired_pending_results(db: &Database, now: DateTime<Utc>) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM pending_results WHERE expires_at <= ?")
+        .bind(now)
+        .execute(db.writer())
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// 统计生成结果的整体反馈情况，含已归档的记录；已汇总删除的日志不计入。
+/// 日志中的 finalshell_version 不代表用户实际使用的版本，因此不按版本分组
+pub async fn get_outcome_stats(db: &Database, instance_id: Option<&str>) -> Result<OutcomeStats> {
+    let row = sqlx::query(
+        r#"
+        SELECT COALESCE(SUM(outcome = 'success'), 0) AS success,
+               COALESCE(SUM(outcome = 'failure'), 0) AS failure,
+               COALESCE(SUM(outcome IS NULL), 0) AS unknown
+        FROM activation_logs_all
+        WHERE ? IS NULL OR instance_id = ?
+        "#,
+    )
+    .bind(instance_id)
+    .bind(instance_id)
+    .fetch_one(db.reader())
+    .await?;
+
+    Ok(OutcomeStats {
+        success: row.get("success"),
+        failure: row.get("failure"),
+        unknown: row.get("unknown"),
+    })
+}
+
+// 守护检查历史
+pub async fn record_health_check(db: &Database, health: &HealthCheck, overall: &str) -> Result<()> {
+    let pool = db.writer();
+    sqlx::query(
+        r#"
+        INSERT INTO health_checks (
+            timestamp, overall, cpu_usage, memory_usage, disk_usage, inode_usage,
+            latency_count, latency_avg_us, latency_max_us, clock_skew_secs, error_count, warning_count
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(health.timestamp)
+    .bind(overall)
+    .bind(health.cpu_usage)
+    .bind(health.memory_usage)
+    .bind(health.disk_usage)
+    .bind(health.inode_usage)
+    .bind(health.latency.map(|l| l.count))
+    .bind(health.latency.map(|l| l.avg_us))
+    .bind(health.latency.map(|l| l.max_us))
+    .bind(health.clock_skew.map(|s| s.seconds))
+    .bind(health.error_count)
+    .bind(health.warning_count)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// 逐行读取 `since` 之后的守护检查记录（按时间升序），避免一次性载入全部历史
+pub fn stream_health_checks(db: &Database, since: DateTime<Utc>) -> BoxStream<'_, Result<HealthRecord, sqlx::Error>> {
+    sqlx::query_as::<_, HealthRecord>("SELECT * FROM health_checks WHERE timestamp >= ? ORDER BY timestamp, id")
+        .bind(since)
+        .fetch(db.reader())
+}
+
+/// 删除 `before` 之前的请求耗时、更新延迟、请求失败与守护检查记录，返回删除的行数
+pub async fn prune_history(db: &Database, before: DateTime<Utc>) -> Result<u64> {
+    let mut tx = db.writer().begin().await?;
+    let metrics = sqlx::query("DELETE FROM request_metrics WHERE created_at < ?")
+        .bind(before)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let lags = sqlx::query("DELETE FROM update_lags WHERE created_at < ?")
+        .bind(before)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let failures = sqlx::query("DELETE FROM request_failures WHERE created_at < ?")
+        .bind(before)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let checks = sqlx::query("DELETE FROM health_checks WHERE timestamp < ?")
+        .bind(before)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+    Ok(metrics + lags + failures + checks)
+}
+
+/// 最近一次守护检查记录
+pub async fn latest_health_check(db: &Database) -> Result<Option<HealthRecord>> {
+    let record = sqlx::query_as::<_, HealthRecord>("SELECT * FROM health_checks ORDER BY timestamp DESC, id DESC LIMIT 1")
+        .fetch_optional(db.reader())
+        .await?;
+    Ok(record)
+}
+
+/// 数据库占用的字节数（页数 × 页大小），内存数据库同样适用
+pub async fn database_size(db: &Database) -> Result<i64> {
+    let size = sqlx::query_scalar("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+        .fetch_one(db.writer())
+        .await?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finalshell::CodeCase;
+
+    async fn test_pool() -> Database {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        Database::new(pool, None)
+    }
+
+    fn results(machine_code: &str) -> Vec<ActivationResult> {
+        ActivationCodeGenerator::new(CodeCase::Upper).generate_for(machine_code, &FinalShellVersionType::ALL)
+    }
+
+    const LIMITED: Quota = Quota { limit: 3, unlimited: false, period_start: None, trial: None, mode: QuotaMode::Requests };
+
+    /// 预扣后立即确认，相当于一次成功的生成；配额已用尽时返回 None
+    async fn record_generation(
+        db: &Database,
+        user_id: i64,
+        correlation_id: &str,
+        machine_code: &str,
+        quota: Quota,
+        language_code: Option<&str>,
+        results: &[ActivationResult],
+    ) -> Result<Option<i32>> {
+        let Some(reservation) = reserve_quota(db, user_id, quota, machine_code).await? else {
+            return Ok(None);
+        };
+        let committed = commit_generation(db, reservation, user_id, correlation_id, machine_code, quota, language_code, results).await?;
+        Ok(committed.map(|(count, _)| count))
+    }
+
+    #[tokio::test]
+    async fn test_record_generation_respects_limit_under_concurrency() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("test.db").display());
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        migrate(&pool).await.unwrap();
+        let pool = Database::new(pool, None);
+        get_or_create_user(&pool, 42, None, None, None, Lang::Zh).await.unwrap();
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    record_generation(&pool, 42, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap()
+                })
+            })
+            .collect();
+
+        let mut succeeded = 0;
+        for task in tasks {
+            if task.await.unwrap().is_some() {
+                succeeded += 1;
+            }
+        }
+
+        assert_eq!(succeeded, 3);
+        assert_eq!(get_user_by_id(&pool, 42).await.unwrap().request_count, 3);
+        // 被拒绝的请求不留下任何日志
+        assert_eq!(get_user_activation_logs(&pool, 42, 100).await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_data_dir() {
+        assert_eq!(data_dir("sqlite:/var/lib/bot/finalshell_bot.db?mode=rwc"), PathBuf::from("/var/lib/bot"));
+        assert_eq!(data_dir("sqlite:finalshell_bot.db"), PathBuf::from("."));
+        assert_eq!(data_dir("sqlite::memory:"), PathBuf::from("."));
+    }
+
+    #[tokio::test]
+    async fn test_premigration_backup_survives_failed_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("bot.db");
+        let backup_dir = dir.path().join("backups");
+        let url = format!("sqlite://{}?mode=rwc", db_path.display());
+
+        // 旧库中 users 是视图，迁移为其加列时必然失败
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        sqlx::query("CREATE TABLE legacy (id INTEGER PRIMARY KEY, note TEXT)").execute(&pool).await.unwrap();
+        sqlx::query("INSERT INTO legacy (note) VALUES ('keep me')").execute(&pool).await.unwrap();
+        sqlx::query("CREATE VIEW users AS SELECT id AS user_id FROM legacy").execute(&pool).await.unwrap();
+        pool.close().await;
+        let original = std::fs::read(&db_path).unwrap();
+
+        let error = init(&url, None, Some(&backup_dir)).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("迁移前的备份"));
+
+        let backups: Vec<_> = std::fs::read_dir(&backup_dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].file_name().unwrap().to_string_lossy().starts_with("finalshell_bot_premigrate_v0_"));
+        let backup = SqlitePool::connect(&format!("sqlite://{}", backups[0].display())).await.unwrap();
+        let note: String = sqlx::query_scalar("SELECT note FROM legacy").fetch_one(&backup).await.unwrap();
+        assert_eq!(note, "keep me");
+
+        // 迁移在事务中回滚，原文件保持不变
+        assert_eq!(std::fs::read(&db_path).unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn test_premigration_backup_only_when_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backups");
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("bot.db").display());
+
+        // 新建的空库不需要备份，迁移后记录当前版本，再次启动也不备份
+        init(&url, None, Some(&backup_dir)).await.unwrap().writer().close().await;
+        init(&url, None, Some(&backup_dir)).await.unwrap();
+        assert!(!backup_dir.exists());
+
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        assert_eq!(schema_version(&pool).await.unwrap(), SCHEMA_VERSION);
+        sqlx::query("PRAGMA user_version = 0").execute(&pool).await.unwrap();
+        assert!(backup_before_migration(&pool, &backup_dir).await.unwrap().unwrap().exists());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_upgrades_initial_schema() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        // 最初版本的表结构和数据
+        for statement in [
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER UNIQUE NOT NULL,
+                username TEXT,
+                first_name TEXT,
+                last_name TEXT,
+                is_admin BOOLEAN DEFAULT FALSE,
+                is_banned BOOLEAN DEFAULT FALSE,
+                request_count INTEGER DEFAULT 0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            "CREATE TABLE activation_logs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                machine_code TEXT NOT NULL,
+                activation_code TEXT NOT NULL,
+                finalshell_version TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            "INSERT INTO users (user_id, username, request_count, created_at, updated_at)
+             VALUES (42, 'old', 2, '2024-01-01 00:00:00', '2024-01-01 00:00:00')",
+            "INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at)
+             VALUES (42, 'OLD-MACHINE', 'OLDCODE', '4.5', '2024-01-01 00:00:00')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        migrate(&pool).await.unwrap();
+        // 重复迁移不应报错
+        migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+
+        let user = get_user_by_id(&db, 42).await.unwrap();
+        assert_eq!(user.username.as_deref(), Some("old"));
+        assert_eq!(user.request_count, 2);
+        assert!(user.ban_reason.is_none() && user.banned_until.is_none() && user.trusted_at.is_none());
+        assert_eq!(user.lang, None);
+        assert!(!user.split_codes);
+
+        let logs = get_user_activation_logs(&db, 42, 10).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].machine_code, "OLD-MACHINE");
+
+        let instance: String = sqlx::query_scalar("SELECT instance_id FROM activation_logs")
+            .fetch_one(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(instance, DEFAULT_INSTANCE_ID);
+
+        // 升级前的用户名按最后更新时间视为最近一次看到
+        let (user_id, seen_at) = find_user_by_username(&db, "@OLD").await.unwrap().unwrap();
+        assert_eq!(user_id, 42);
+        assert_eq!(seen_at, "2024-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        // 升级后的数据库可以继续正常写入
+        record_generation(&db, 42, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        assert_eq!(get_user_activation_logs(&db, 42, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_find_user_by_username_ignores_case() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, Some("SomeUser".to_string()), None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&db, 2, None, None, None, Lang::Zh).await.unwrap();
+
+        for name in ["SomeUser", "@someuser", "SOMEUSER", " @sOmEuSeR "] {
+            let (user_id, _) = find_user_by_username(&db, name).await.unwrap().unwrap();
+            assert_eq!(user_id, 1, "{}", name);
+        }
+        assert!(find_user_by_username(&db, "@nobody").await.unwrap().is_none());
+        assert!(find_user_by_username(&db, "@").await.unwrap().is_none());
+
+        // 用户名转移到另一个用户后只匹配新的持有者
+        get_or_create_user(&db, 2, Some("someUSER".to_string()), None, None, Lang::Zh).await.unwrap();
+        assert_eq!(find_user_by_username(&db, "someuser").await.unwrap().unwrap().0, 2);
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().username, None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_username_writes_only_on_change() {
+        let db = test_pool().await;
+        let user = get_or_create_user(&db, 1, Some("alice".to_string()), None, None, Lang::Zh).await.unwrap();
+        let seen_at = user.username_seen_at.unwrap();
+
+        // 用户名未变且一天内记录过时不写库，只读降级时也不影响处理
+        db.inject_write_fault(true);
+        refresh_username(&db, &user, Some("alice")).await;
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().username_seen_at, Some(seen_at));
+        refresh_username(&db, &user, Some("alice_new")).await;
+        assert!(get_or_create_user(&db, 1, Some("alice_new".to_string()), None, None, Lang::Zh).await.is_ok());
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().username.as_deref(), Some("alice"));
+
+        db.inject_write_fault(false);
+        refresh_username(&db, &user, Some("alice_new")).await;
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().username.as_deref(), Some("alice_new"));
+        assert!(user.username_needs_refresh("alice", seen_at + chrono::Duration::days(2)));
+    }
+
+    #[tokio::test]
+    async fn test_user_lang_set_on_creation_only() {
+        let db = test_pool().await;
+        let user = get_or_create_user(&db, 1, None, None, None, Lang::En).await.unwrap();
+        assert_eq!(user.lang(Lang::Zh), Lang::En);
+
+        // 已有用户再次进入不会被客户端语言覆盖
+        let user = get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        assert_eq!(user.lang(Lang::Zh), Lang::En);
+
+        set_user_lang(&db, 1, Lang::Zh).await.unwrap();
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().lang.as_deref(), Some("zh"));
+
+        assert!(!user.split_codes);
+        set_split_codes(&db, 1, true).await.unwrap();
+        assert!(get_user_by_id(&db, 1).await.unwrap().split_codes);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_quota_released_on_failure() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 12, None, None, None, Lang::Zh).await.unwrap();
+        record_generation(&db, 12, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+
+        // 进行中的预扣占用配额
+        let first = reserve_quota(&db, 12, LIMITED, "test@machine").await.unwrap().unwrap();
+        let second = reserve_quota(&db, 12, LIMITED, "test@machine").await.unwrap().unwrap();
+        assert_eq!(reserve_quota(&db, 12, LIMITED, "test@machine").await.unwrap(), None);
+
+        // 生成失败：退回后不计入已用次数，也不写入激活日志
+        assert!(release_quota(&db, first).await.unwrap());
+        assert!(!release_quota(&db, first).await.unwrap());
+        let user = get_user_by_id(&db, 12).await.unwrap();
+        assert_eq!(user.request_count, 1);
+        assert_eq!(quota_used(&db, &user, &LIMITED).await.unwrap(), 1);
+        assert_eq!(count_generations_since(&db, 12, Utc::now() - chrono::Duration::hours(1)).await.unwrap(), 1);
+
+        // 退回的配额可以再次使用
+        let third = reserve_quota(&db, 12, LIMITED, "test@machine").await.unwrap().unwrap();
+        assert_eq!(commit_generation(&db, second, 12, "7KQ2M3ZD", "ABC123DEF457", LIMITED, None, &results("ABC123DEF457")).await.unwrap().unwrap().0, 2);
+        assert_eq!(commit_generation(&db, third, 12, "7KQ2M3ZD", "ABC123DEF458", LIMITED, None, &results("ABC123DEF458")).await.unwrap().unwrap().0, 3);
+        assert_eq!(reserve_quota(&db, 12, LIMITED, "test@machine").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_reservation_frees_quota() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 13, None, None, None, Lang::Zh).await.unwrap();
+        let quota = Quota { limit: 1, ..LIMITED };
+        let stale = reserve_quota(&db, 13, quota, "test@machine").await.unwrap().unwrap();
+        assert_eq!(reserve_quota(&db, 13, quota, "test@machine").await.unwrap(), None);
+
+        // 进程中途退出时预扣既未确认也未退回，过期后不再占用配额
+        sqlx::query("UPDATE quota_reservations SET created_at = ? WHERE id = ?")
+            .bind(Utc::now() - chrono::Duration::minutes(RESERVATION_TTL_MINUTES + 1))
+            .bind(stale)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert!(reserve_quota(&db, 13, quota, "test@machine").await.unwrap().is_some());
+        assert!(!release_quota(&db, stale).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_commit_rechecks_quota_after_reservation_expired() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 14, None, None, None, Lang::Zh).await.unwrap();
+        let quota = Quota { limit: 1, ..LIMITED };
+        let expire = |id: i64| {
+            sqlx::query("UPDATE quota_reservations SET created_at = ? WHERE id = ?")
+                .bind(Utc::now() - chrono::Duration::minutes(RESERVATION_TTL_MINUTES + 1))
+                .bind(id)
+        };
+
+        // 预扣过期后配额仍有余量：照常记录
+        let slow = reserve_quota(&db, 14, quota, "ABC123DEF456").await.unwrap().unwrap();
+        expire(slow).execute(db.writer()).await.unwrap();
+        let committed = commit_generation(&db, slow, 14, "7KQ2M3ZD", "ABC123DEF456", quota, None, &results("ABC123DEF456")).await.unwrap();
+        assert_eq!(committed.map(|(count, _)| count), Some(1));
+
+        // 预扣过期期间额度被其他请求用完：不写入任何记录
+        let quota = Quota { limit: 2, ..LIMITED };
+        let slow = reserve_quota(&db, 14, quota, "ABC123DEF457").await.unwrap().unwrap();
+        expire(slow).execute(db.writer()).await.unwrap();
+        assert_eq!(record_generation(&db, 14, "7KQ2M3ZD", "ABC123DEF458", quota, None, &results("ABC123DEF458")).await.unwrap(), Some(2));
+        assert_eq!(
+            commit_generation(&db, slow, 14, "7KQ2M3ZD", "ABC123DEF457", quota, None, &results("ABC123DEF457")).await.unwrap(),
+            None
+        );
+        let user = get_user_by_id(&db, 14).await.unwrap();
+        assert_eq!(user.request_count, 2);
+        assert_eq!(quota_used(&db, &user, &quota).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_unavailable_errors() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 14, None, None, None, Lang::Zh).await.unwrap();
+
+        db.inject_write_fault(true);
+        let e = reserve_quota(&db, 14, LIMITED, "test@machine").await.unwrap_err();
+        assert!(is_write_unavailable(&e));
+        assert!(probe_write(&db).await.is_err());
+        // 读取不受影响
+        assert_eq!(get_user_by_id(&db, 14).await.unwrap().request_count, 0);
+
+        db.inject_write_fault(false);
+        probe_write(&db).await.unwrap();
+        assert!(reserve_quota(&db, 14, LIMITED, "test@machine").await.unwrap().is_some());
+
+        // 真实的只读连接返回 SQLITE_READONLY，普通 SQL 错误不算不可写
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("readonly.db");
+        migrate(&SqlitePool::connect(&format!("sqlite://{}?mode=rwc", path.display())).await.unwrap()).await.unwrap();
+        let readonly = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&path).read_only(true)).await.unwrap();
+        let readonly = Database::new(readonly, None);
+        assert!(is_write_unavailable(&probe_write(&readonly).await.unwrap_err()));
+        let e = anyhow::Error::from(sqlx::query("SELECT * FROM missing_table").execute(readonly.writer()).await.unwrap_err());
+        assert!(!is_write_unavailable(&e));
+    }
+
+    #[tokio::test]
+    async fn test_record_generation_unlimited() {
+        let pool = test_pool().await;
+        get_or_create_user(&pool, 7, None, None, None, Lang::Zh).await.unwrap();
+        let quota = Quota { limit: 1, unlimited: true, period_start: None, trial: None, mode: QuotaMode::Requests };
+
+        for expected in 1..=5 {
+            assert_eq!(record_generation(&pool, 7, "7KQ2M3ZD", "ABC123DEF456", quota, None, &results("ABC123DEF456")).await.unwrap(), Some(expected));
+        }
+        assert_eq!(record_generation(&pool, 8, "7KQ2M3ZD", "ABC123DEF456", quota, None, &results("ABC123DEF456")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_record_generation_writes_summary_and_details() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 5, None, None, None, Lang::Zh).await.unwrap();
+        let generated = results("ABC123DEF456");
+
+        assert_eq!(record_generation(&db, 5, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &generated).await.unwrap(), Some(1));
+
+        let logs = get_user_activation_logs(&db, 5, 10).await.unwrap();
+        let primary = ActivationCodeGenerator::primary_result("ABC123DEF456", &generated).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].activation_code, primary.professional_code);
+        assert_eq!(logs[0].finalshell_version, primary.version_type.log_label());
+
+        let details: Vec<(String, String)> = sqlx::query_as(
+            "SELECT version, professional_code FROM activation_log_details WHERE log_id = ? ORDER BY id",
+        )
+        .bind(logs[0].id)
+        .fetch_all(db.writer())
+        .await
+        .unwrap();
+        let expected: Vec<_> = generated
+            .iter()
+            .map(|r| (r.version_type.version_name_ascii().to_string(), r.professional_code.clone()))
+            .collect();
+        assert_eq!(details, expected);
+    }
+
+    #[tokio::test]
+    async fn test_record_generation_counts_current_period_only() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 9, None, None, None, Lang::Zh).await.unwrap();
+        for machine_code in ["ABC123DEF456", "ABC123DEF457"] {
+            record_generation(&db, 9, "7KQ2M3ZD", machine_code, LIMITED, None, &results(machine_code)).await.unwrap().unwrap();
+        }
+
+        // 新周期从此刻开始，上一周期的两次不计入
+        let monthly = Quota { limit: 2, period_start: Some(Utc::now()), ..LIMITED };
+        let user = get_user_by_id(&db, 9).await.unwrap();
+        assert_eq!(quota_used(&db, &user, &monthly).await.unwrap(), 0);
+        assert_eq!(quota_used(&db, &user, &LIMITED).await.unwrap(), 2);
+
+        assert_eq!(record_generation(&db, 9, "7KQ2M3ZD", "ABC123DEF458", monthly, None, &results("ABC123DEF458")).await.unwrap(), Some(1));
+        assert_eq!(record_generation(&db, 9, "7KQ2M3ZD", "ABC123DEF459", monthly, None, &results("ABC123DEF459")).await.unwrap(), Some(2));
+        assert_eq!(record_generation(&db, 9, "7KQ2M3ZD", "ABC123DEF460", monthly, None, &results("ABC123DEF460")).await.unwrap(), None);
+        // 总次数照常累计
+        assert_eq!(get_user_by_id(&db, 9).await.unwrap().request_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_quota_modes_at_limit_with_canonicalized_codes() {
+        let db = test_pool().await;
+        let daily = Some(Utc::now() - chrono::Duration::hours(1));
+        // 仅空白不同的输入规范化后是同一个机器码
+        let first = crate::finalshell::canonicalize(" ABC123 DEF456\n");
+        let same = crate::finalshell::canonicalize("ABC123\tDEF456");
+        assert_eq!(first, same);
+
+        // 按次数：同一机器码也每次计数，第 3 次超出
+        get_or_create_user(&db, 30, None, None, None, Lang::Zh).await.unwrap();
+        let requests = Quota { limit: 2, period_start: daily, ..LIMITED };
+        for (code, expected) in [(&first, Some(1)), (&same, Some(2)), (&first, None)] {
+            assert_eq!(record_generation(&db, 30, "7KQ2M3ZD", code, requests, None, &results(code)).await.unwrap(), expected);
+        }
+
+        // 按机器码：重复提交不计次，额度用完后只能继续为已提交过的机器码生成
+        get_or_create_user(&db, 31, None, None, None, Lang::Zh).await.unwrap();
+        let machines = Quota { mode: QuotaMode::Machines, ..requests };
+        for (code, expected) in [
+            (first.as_str(), Some(1)),
+            (same.as_str(), Some(1)),
+            ("ABC123DEF457", Some(2)),
+            ("ABC123DEF458", None),
+            (same.as_str(), Some(2)),
+        ] {
+            assert_eq!(record_generation(&db, 31, "7KQ2M3ZD", code, machines, None, &results(code)).await.unwrap(), expected, "{}", code);
+        }
+        let user = get_user_by_id(&db, 31).await.unwrap();
+        assert_eq!(quota_used(&db, &user, &machines).await.unwrap(), 2);
+        assert_eq!(user.request_count, 4);
+        assert!(machine_code_counted(&db, 31, &machines, &same).await.unwrap());
+        assert!(!machine_code_counted(&db, 31, &machines, "ABC123DEF458").await.unwrap());
+        assert!(!machine_code_counted(&db, 31, &requests, &same).await.unwrap());
+
+        // 同一机器码进行中的预扣只占一次额度
+        get_or_create_user(&db, 32, None, None, None, Lang::Zh).await.unwrap();
+        reserve_quota(&db, 32, machines, &first).await.unwrap().unwrap();
+        reserve_quota(&db, 32, machines, &same).await.unwrap().unwrap();
+        reserve_quota(&db, 32, machines, "ABC123DEF457").await.unwrap().unwrap();
+        assert_eq!(reserve_quota(&db, 32, machines, "ABC123DEF458").await.unwrap(), None);
+
+        // 累计配额连同归档日志统计，新周期前的机器码照常计入
+        let lifetime = Quota { period_start: None, ..machines };
+        assert_eq!(quota_used(&db, &user, &lifetime).await.unwrap(), 2);
+        assert_eq!(reserve_quota(&db, 31, lifetime, "ABC123DEF459").await.unwrap(), None);
+        assert!(reserve_quota(&db, 31, lifetime, &first).await.unwrap().is_some());
+
+        // 日志汇总删除后累计配额不会被重新放开
+        let tz = FixedOffset::east_opt(0).unwrap();
+        roll_up_activation_logs(&db, Utc::now() + chrono::Duration::days(2), tz).await.unwrap();
+        assert_eq!(quota_used(&db, &user, &lifetime).await.unwrap(), 2);
+        assert_eq!(reserve_quota(&db, 31, lifetime, "ABC123DEF459").await.unwrap(), None);
+        assert!(machine_code_counted(&db, 31, &lifetime, "ABC123DEF457").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_request_trace() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 5, None, None, None, Lang::Zh).await.unwrap();
+        record_generation(&db, 5, "AAAA1111", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        record_request_metric(&db, 5, "AAAA1111", Duration::from_millis(12)).await.unwrap();
+        record_update_lag(&db, "AAAA1111", Duration::from_millis(300)).await.unwrap();
+        record_request_failure(&db, "BBBB2222", 5, "generate", "后端超时").await.unwrap();
+
+        let trace = get_request_trace(&db, "AAAA1111").await.unwrap();
+        assert_eq!(trace.activations.len(), 1);
+        assert_eq!(trace.activations[0].machine_code, "ABC123DEF456");
+        assert_eq!((trace.metrics[0].duration_us, trace.metrics[0].update_lag_ms), (12_000, Some(300)));
+        assert!(trace.failures.is_empty());
+
+        let trace = get_request_trace(&db, "BBBB2222").await.unwrap();
+        assert!(trace.activations.is_empty());
+        assert_eq!(trace.failures[0].stage, "generate");
+        assert_eq!(trace.failures[0].detail.as_deref(), Some("后端超时"));
+
+        assert!(get_request_trace(&db, "CCCC3333").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_user_activation_logs_filters_by_user() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&db, 2, None, None, None, Lang::Zh).await.unwrap();
+        for (user_id, machine_code) in [(1, "MACHINE-A"), (2, "MACHINE-B"), (1, "MACHINE-C")] {
+            record_generation(&db, user_id, "7KQ2M3ZD", machine_code, LIMITED, None, &results(machine_code)).await.unwrap().unwrap();
+        }
+
+        let logs = get_user_activation_logs(&db, 1, 10).await.unwrap();
+        let codes: Vec<_> = logs.iter().map(|log| log.machine_code.as_str()).collect();
+        assert_eq!(codes, vec!["MACHINE-C", "MACHINE-A"]);
+
+        assert_eq!(get_user_activation_logs(&db, 1, 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_language_distribution() {
+        let db = test_pool().await;
+        for user_id in 1..=4 {
+            get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+        update_language_code(&db, 1, Some("zh-hans")).await.unwrap();
+        update_language_code(&db, 2, Some(" ZH-HANS ")).await.unwrap();
+        update_language_code(&db, 3, Some("en")).await.unwrap();
+        // 缺失时不覆盖已有值
+        record_generation(&db, 3, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        update_language_code(&db, 4, Some("")).await.unwrap();
+
+        assert_eq!(
+            get_language_distribution(&db).await.unwrap(),
+            vec![
+                (Some("zh-hans".to_string()), 2),
+                (None, 1),
+                (Some("en".to_string()), 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flagging_and_distinct_machine_codes() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 11, None, None, None, Lang::Zh).await.unwrap();
+        let quota = Quota { limit: 0, unlimited: true, period_start: None, trial: None, mode: QuotaMode::Requests };
+        for machine_code in ["MACHINE-A", "MACHINE-B", "MACHINE-A"] {
+            record_generation(&db, 11, "7KQ2M3ZD", machine_code, quota, None, &results(machine_code)).await.unwrap();
+        }
+
+        let since = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(count_distinct_machine_codes_since(&db, 11, since).await.unwrap(), 2);
+        assert_eq!(count_distinct_machine_codes_since(&db, 11, Utc::now() + chrono::Duration::hours(1)).await.unwrap(), 0);
+
+        assert!(flag_user(&db, 11).await.unwrap());
+        assert!(!flag_user(&db, 11).await.unwrap());
+        assert_eq!(get_flagged_users(&db, 10).await.unwrap().len(), 1);
+
+        assert!(clear_flag(&db, 11).await.unwrap());
+        assert!(!clear_flag(&db, 11).await.unwrap());
+        assert!(get_user_by_id(&db, 11).await.unwrap().flagged_at.is_none());
+
+        // 解除标记前的记录不再计入，下一次生成不会立即重新标记
+        assert_eq!(count_distinct_machine_codes_since(&db, 11, since).await.unwrap(), 0);
+        record_generation(&db, 11, "7KQ2M3ZD", "MACHINE-C", quota, None, &results("MACHINE-C")).await.unwrap();
+        assert_eq!(count_distinct_machine_codes_since(&db, 11, since).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ban_users_reports_unknown_ids() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&db, 3, None, None, None, Lang::Zh).await.unwrap();
+
+        assert_eq!(ban_users(&db, &[1, 2, 3], Some("spam"), None).await.unwrap(), vec![true, false, true]);
+        assert!(get_user_by_id(&db, 3).await.unwrap().is_banned);
+        assert!(get_user_by_id(&db, 2).await.is_err());
+
+        assert_eq!(unban_users(&db, &[3, 4]).await.unwrap(), vec![true, false]);
+        assert!(!get_user_by_id(&db, 3).await.unwrap().is_banned);
+        assert!(get_user_by_id(&db, 1).await.unwrap().is_banned);
+    }
+
+    #[tokio::test]
+    async fn test_archive_activation_logs_by_month() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 42, None, None, None, Lang::Zh).await.unwrap();
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        // 北京时间分别为 1 月 10 日、2 月 1 日 07:00（UTC 仍是 1 月）、2 月 15 日
+        for (code, created_at) in [("A", "2025-01-10T00:00:00Z"), ("B", "2025-01-31T23:00:00Z"), ("C", "2025-02-15T00:00:00Z")] {
+            let id = sqlx::query(
+                "INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at) VALUES (42, ?, ?, '4.5', ?)",
+            )
+            .bind(code)
+            .bind(code)
+            .bind(at(created_at))
+            .execute(db.writer())
+            .await
+            .unwrap()
+            .last_insert_rowid();
+            sqlx::query("INSERT INTO activation_log_details (log_id, version, advanced_code, professional_code) VALUES (?, '4.5', 'x', 'y')")
+                .bind(id)
+                .execute(db.writer())
+                .await
+                .unwrap();
+        }
+
+        let archived = archive_activation_logs(&db, at("2025-02-28T16:00:00Z"), tz).await.unwrap();
+        assert_eq!(archived, vec![("activation_logs_202501".to_string(), 1), ("activation_logs_202502".to_string(), 2)]);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_logs").fetch_one(db.writer()).await.unwrap();
+        assert_eq!(remaining, 0);
+        let details: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_log_details_202502").fetch_one(db.writer()).await.unwrap();
+        assert_eq!(details, 2);
+
+        // 统计与历史查询包含归档数据；再次归档没有可移动的记录
+        assert_eq!(get_system_stats(&db, None).await.unwrap().total_activations, 3);
+        assert_eq!(get_user_activation_logs(&db, 42, 10).await.unwrap().len(), 3);
+        assert_eq!(search_activation_logs(&db, "B", 10).await.unwrap().len(), 1);
+        assert!(archive_activation_logs(&db, at("2025-02-28T16:00:00Z"), tz).await.unwrap().is_empty());
+
+        // 重新迁移后视图仍包含归档表
+        migrate(db.writer()).await.unwrap();
+        assert_eq!(get_system_stats(&db, None).await.unwrap().total_activations, 3);
+
+        clear_stats(&db).await.unwrap();
+        assert_eq!(get_system_stats(&db, None).await.unwrap().total_activations, 0);
+        let mut conn = db.writer().acquire().await.unwrap();
+        assert!(archive_tables(&mut conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_rebuilds_legacy_archive_tables() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 42, None, None, None, Lang::Zh).await.unwrap();
+        // 模拟早期以 CREATE TABLE AS SELECT 建出的归档表
+        sqlx::query(&format!("CREATE TABLE activation_logs_202401 AS SELECT {} FROM activation_logs WHERE 0", LOG_COLUMNS))
+            .execute(db.writer())
+            .await
+            .unwrap();
+        sqlx::query(&format!("CREATE TABLE activation_log_details_202401 AS SELECT {} FROM activation_log_details WHERE 0", DETAIL_COLUMNS))
+            .execute(db.writer())
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO activation_logs_202401 (id, user_id, machine_code, activation_code, finalshell_version, created_at, instance_id) \
+             VALUES (7, 42, 'M', 'C', '4.5', '2024-01-05 00:00:00', 'default')",
+        )
+        .execute(db.writer())
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO activation_log_details_202401 (id, log_id, version, advanced_code, professional_code) VALUES (3, 7, '4.5', 'x', 'y')")
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        migrate(db.writer()).await.unwrap();
+
+        for table in ["activation_logs_202401", "activation_log_details_202401"] {
+            let pk: i64 = sqlx::query_scalar(&format!("SELECT pk FROM pragma_table_info('{}') WHERE name = 'id'", table))
+                .fetch_one(db.writer())
+                .await
+                .unwrap();
+            assert_eq!(pk, 1, "{} 应以 id 为主键", table);
+        }
+        let index: Option<String> = sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND name = 'idx_activation_logs_202401_user_created'",
+        )
+        .fetch_optional(db.writer())
+        .await
+        .unwrap();
+        assert!(index.is_some());
+        assert_eq!(get_user_activation_logs(&db, 42, 10).await.unwrap().len(), 1);
+        let details: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_log_details_202401").fetch_one(db.writer()).await.unwrap();
+        assert_eq!(details, 1);
+    }
+
+    #[tokio::test]
+    async fn test_roll_up_activation_logs_keeps_stats() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&db, 2, None, None, None, Lang::Zh).await.unwrap();
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        // 北京时间 3 月 1 日两人各一次、3 月 2 日用户 1 两次、3 月 3 日用户 2 一次
+        let logs = [
+            (1, "4.5", "2025-02-28T17:00:00Z"),
+            (2, "4.6+", "2025-03-01T03:00:00Z"),
+            (1, "4.5", "2025-03-02T01:00:00Z"),
+            (1, "4.5", "2025-03-02T02:00:00Z"),
+            (2, "4.5", "2025-03-03T01:00:00Z"),
+        ];
+        for (user_id, version, created_at) in logs {
+            let id = sqlx::query(
+                "INSERT INTO activation_logs (user_id, machine_code, activation_code, finalshell_version, created_at) VALUES (?, 'M', 'C', ?, ?)",
+            )
+            .bind(user_id)
+            .bind(version)
+            .bind(at(created_at))
+            .execute(db.writer())
+            .await
+            .unwrap()
+            .last_insert_rowid();
+            sqlx::query("INSERT INTO activation_log_details (log_id, version, advanced_code, professional_code) VALUES (?, ?, 'x', 'y')")
+                .bind(id)
+                .bind(version)
+                .execute(db.writer())
+                .await
+                .unwrap();
+        }
+        // 第一天先归档，汇总同样覆盖归档表中的记录
+        archive_activation_logs(&db, at("2025-03-01T16:00:00Z"), tz).await.unwrap();
+
+        let since = at("2025-02-28T16:00:00Z");
+        let total = get_system_stats(&db, None).await.unwrap().total_activations;
+        let instances = get_instance_distribution(&db).await.unwrap();
+        let daily = get_daily_activity(&db, since, tz).await.unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(daily.iter().map(|d| (d.day.as_str(), d.activations, d.distinct_users)).collect::<Vec<_>>(), vec![
+            ("2025-03-01", 2, 2),
+            ("2025-03-02", 2, 1),
+            ("2025-03-03", 1, 1),
+        ]);
+
+        // 截止时间向下取整到北京时间 3 月 3 日零点，汇总并删除前两天
+        assert_eq!(roll_up_activation_logs(&db, at("2025-03-02T20:00:00Z"), tz).await.unwrap(), (2, 4));
+        let raw: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_logs_all").fetch_one(db.writer()).await.unwrap();
+        assert_eq!(raw, 1);
+        let details: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM activation_log_details").fetch_one(db.writer()).await.unwrap();
+        assert_eq!(details, 1);
+
+        // 重复执行不会重复累加
+        assert_eq!(roll_up_activation_logs(&db, at("2025-03-02T20:00:00Z"), tz).await.unwrap(), (0, 0));
+        assert_eq!(get_system_stats(&db, None).await.unwrap().total_activations, total);
+        assert_eq!(get_instance_distribution(&db).await.unwrap(), instances);
+        assert_eq!(get_daily_activity(&db, since, tz).await.unwrap(), daily);
+        assert_eq!(daily[0].versions, BTreeMap::from([("4.5".to_string(), 1), ("4.6+".to_string(), 1)]));
+
+        clear_stats(&db).await.unwrap();
+        assert!(get_daily_activity(&db, since, tz).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_support_queries() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 7, None, None, None, Lang::Zh).await.unwrap();
+        assert_eq!(get_user_deactivated_at(&db, 7).await.unwrap(), None);
+        assert_eq!(get_user_deactivated_at(&db, 8).await.unwrap(), None);
+        mark_user_deactivated(&db, 7).await.unwrap();
+        assert!(get_user_deactivated_at(&db, 7).await.unwrap().is_some());
+
+        log_admin_action(&db, 1, "ban", Some(7), "刷号").await.unwrap();
+        log_admin_action(&db, 1, "view_support", Some(7), "").await.unwrap();
+        log_admin_action(&db, 1, "unban", Some(8), "").await.unwrap();
+        let actions = get_user_admin_actions(&db, 7, 10).await.unwrap();
+        assert_eq!(actions.iter().map(|a| a.action.as_str()).collect::<Vec<_>>(), vec!["ban"]);
+
+        record_request_failure(&db, "7KQ2M3ZD", 7, "invalid_machine_code", "abc").await.unwrap();
+        record_request_failure(&db, "8KQ2M3ZD", 8, "generate", "boom").await.unwrap();
+        let failures = get_user_failures(&db, 7, 10).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].stage_label(), "机器码格式错误");
+
+        create_appeal(&db, 7, None, "误封").await.unwrap();
+        create_code_report(&db, 7, "hash", "4.6+").await.unwrap();
+        assert_eq!(get_user_appeals(&db, 7, 10).await.unwrap().len(), 1);
+        assert_eq!(get_user_code_reports(&db, 7, 10).await.unwrap().len(), 1);
+        assert!(get_user_code_reports(&db, 8, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_welcome_debounces() {
+        use crate::clock::{Clock, MockClock};
+
+        let db = test_pool().await;
+        get_or_create_user(&db, 7, None, None, None, Lang::Zh).await.unwrap();
+        let clock = MockClock::new(Utc::now());
+        let debounce = chrono::Duration::seconds(30);
+
+        assert!(claim_welcome(&db, 7, clock.now_utc(), debounce).await.unwrap());
+        clock.advance(std::time::Duration::from_secs(10));
+        assert!(!claim_welcome(&db, 7, clock.now_utc(), debounce).await.unwrap());
+        // 间隔从上一次实际发送欢迎语时算起
+        clock.advance(std::time::Duration::from_secs(20));
+        assert!(claim_welcome(&db, 7, clock.now_utc(), debounce).await.unwrap());
+        assert!(claim_welcome(&db, 7, clock.now_utc(), chrono::Duration::zero()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lift_expired_bans() {
+        let db = test_pool().await;
+        for user_id in 1..=3 {
+            get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+        let now = Utc::now();
+        ban_users(&db, &[1], Some("刷号"), Some(now - chrono::Duration::minutes(1))).await.unwrap();
+        ban_users(&db, &[2], None, Some(now + chrono::Duration::hours(1))).await.unwrap();
+        ban_users(&db, &[3], None, None).await.unwrap();
+
+        assert_eq!(lift_expired_bans(&db, now).await.unwrap(), vec![1]);
+        let lifted = get_user_by_id(&db, 1).await.unwrap();
+        assert!(!lifted.is_banned && lifted.ban_reason.is_none() && lifted.banned_until.is_none());
+        assert!(get_user_by_id(&db, 2).await.unwrap().is_banned);
+        assert!(get_user_by_id(&db, 3).await.unwrap().is_banned);
+
+        assert!(lift_expired_bans(&db, now).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auto_ban_fires_once() {
+        let pool = test_pool().await;
+        get_or_create_user(&pool, 9, None, None, None, Lang::Zh).await.unwrap();
+
+        assert!(auto_ban_user(&pool, 9, "使用次数达到上限").await.unwrap());
+        assert!(!auto_ban_user(&pool, 9, "使用次数达到上限").await.unwrap());
+        assert!(get_user_by_id(&pool, 9).await.unwrap().is_banned);
+    }
+
+    #[tokio::test]
+    async fn test_stats_queries_use_replica() {
+        let primary = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&primary).await.unwrap();
+        let replica = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate(&replica).await.unwrap();
+        let db = Database::new(primary, Some(replica));
+
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+
+        // 写入只落在主库，统计查询读副本看不到
+        assert!(get_user_by_id(&db, 1).await.is_ok());
+        assert!(get_all_users(&db).await.unwrap().is_empty());
+        assert_eq!(get_system_stats(&db, None).await.unwrap().total_users, 0);
+    }
+
+    #[tokio::test]
+    async fn test_code_report_deduplicated_per_user() {
+        let db = test_pool().await;
+        for user_id in [1, 2] {
+            get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+
+        assert!(create_code_report(&db, 1, "hash-a", "4.6+").await.unwrap());
+        assert!(!create_code_report(&db, 1, "hash-a", "4.6+").await.unwrap());
+        assert!(create_code_report(&db, 1, "hash-a", "4.5").await.unwrap());
+        assert!(create_code_report(&db, 2, "hash-b", "4.6+").await.unwrap());
+
+        let since = Utc::now() - chrono::Duration::days(1);
+        assert_eq!(count_code_reports_since(&db, "4.6+", since).await.unwrap(), 2);
+        assert_eq!(get_recent_code_reports(&db, 10).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_import_bans() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&db, 2, None, None, None, Lang::Zh).await.unwrap();
+        ban_user(&db, 2, Some("旧原因"), None).await.unwrap();
+        // 已到期的临时封禁不算已封禁
+        get_or_create_user(&db, 4, None, None, None, Lang::Zh).await.unwrap();
+        ban_user(&db, 4, Some("临时"), Some(Utc::now() - chrono::Duration::hours(1))).await.unwrap();
+
+        let entries = [
+            BanEntry { line: 1, user_id: 1, reason: Some("共享名单".to_string()) },
+            BanEntry { line: 2, user_id: 2, reason: None },
+            BanEntry { line: 3, user_id: 3, reason: None },
+            BanEntry { line: 4, user_id: 4, reason: Some("共享名单".to_string()) },
+        ];
+        let summary = import_bans(&db, &entries).await.unwrap();
+
+        assert_eq!(summary, ImportSummary { applied: 3, already_banned: 1 });
+        let user = get_user_by_id(&db, 4).await.unwrap();
+        assert!(user.is_ban_active(Utc::now()));
+        assert_eq!(user.ban_reason.as_deref(), Some("共享名单"));
+        assert!(get_all_users(&db).await.unwrap().iter().any(|stats| stats.user_id == 4 && stats.is_banned));
+        assert_eq!(get_user_by_id(&db, 1).await.unwrap().ban_reason.as_deref(), Some("共享名单"));
+        assert_eq!(get_user_by_id(&db, 2).await.unwrap().ban_reason.as_deref(), Some("旧原因"));
+        assert!(get_user_by_id(&db, 3).await.unwrap().is_banned);
+    }
+
+    #[tokio::test]
+    async fn test_latency_summary() {
+        let db = test_pool().await;
+        let since = Utc::now() - chrono::Duration::hours(1);
+        assert_eq!(get_latency_summary(&db, since).await.unwrap(), None);
+
+        record_request_metric(&db, 1, "7KQ2M3ZD", Duration::from_micros(800)).await.unwrap();
+        record_request_metric(&db, 2, "7KQ2M3ZD", Duration::from_millis(23)).await.unwrap();
+        // 未生成成功的消息同样记录延迟
+        for lag_ms in [400, 1_800, 900] {
+            record_update_lag(&db, "7KQ2M3ZD", Duration::from_millis(lag_ms)).await.unwrap();
+        }
+
+        assert_eq!(
+            get_latency_summary(&db, since).await.unwrap(),
+            Some(LatencySummary { count: 2, avg_us: 11_900, max_us: 23_000 })
+        );
+        assert_eq!(
+            get_update_lag(&db, since).await.unwrap(),
+            Some(UpdateLag { count: 3, p50_ms: 900, p95_ms: 1_800 })
+        );
+        assert_eq!(get_latency_summary(&db, Utc::now() + chrono::Duration::hours(1)).await.unwrap(), None);
+
+        // 清理早于当前时间的记录后统计为空
+        assert_eq!(prune_history(&db, since).await.unwrap(), 0);
+        assert_eq!(prune_history(&db, Utc::now() + chrono::Duration::seconds(1)).await.unwrap(), 5);
+        assert_eq!(get_latency_summary(&db, since).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_stats_exclude_test_runs() {
+        let db = test_pool().await;
+        assert_eq!(get_broadcast_stats(&db).await.unwrap(), BroadcastStats::default());
+
+        record_broadcast(&db, 1, "维护通知", true, 2, 0).await.unwrap();
+        record_broadcast(&db, 1, "维护通知", false, 120, 3).await.unwrap();
+        record_broadcast(&db, 1, "新版本", false, 118, 5).await.unwrap();
+
+        assert_eq!(
+            get_broadcast_stats(&db).await.unwrap(),
+            BroadcastStats { runs: 2, delivered: 238, failed: 8 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_tampering() {
+        let db = test_pool().await;
+        assert!(verify_audit_chain(&db).await.unwrap().is_intact());
+        for (action, target) in [("ban", 101), ("unban", 101), ("trust", 102), ("ban", 103)] {
+            log_admin_action(&db, 42, action, Some(target), "测试").await.unwrap();
+        }
+
+        let entries = get_audit_entries(&db).await.unwrap();
+        assert_eq!(
+            verify_audit_chain(&db).await.unwrap(),
+            audit::ChainStatus::Intact { entries: 4, head: entries[3].hash.clone() }
+        );
+
+        // 修改一条记录
+        sqlx::query("UPDATE admin_actions SET detail = '已篡改' WHERE id = ?")
+            .bind(entries[2].id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(
+            verify_audit_chain(&db).await.unwrap(),
+            audit::ChainStatus::Broken { entry_id: entries[2].id, verified: 2 }
+        );
+        sqlx::query("UPDATE admin_actions SET detail = '测试' WHERE id = ?")
+            .bind(entries[2].id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert!(verify_audit_chain(&db).await.unwrap().is_intact());
+
+        // 删除一条记录，断点出现在它的下一条
+        sqlx::query("DELETE FROM admin_actions WHERE id = ?")
+            .bind(entries[1].id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(
+            verify_audit_chain(&db).await.unwrap(),
+            audit::ChainStatus::Broken { entry_id: entries[2].id, verified: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_tail_deletion() {
+        let db = test_pool().await;
+        for (action, target) in [("ban", 101), ("unban", 101), ("trust", 102)] {
+            log_admin_action(&db, 42, action, Some(target), "测试").await.unwrap();
+        }
+        let entries = get_audit_entries(&db).await.unwrap();
+
+        // 删掉最后一条后剩余记录仍然连续，只有锚点能发现
+        sqlx::query("DELETE FROM admin_actions WHERE id = ?")
+            .bind(entries[2].id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(
+            verify_audit_chain(&db).await.unwrap(),
+            audit::ChainStatus::Truncated { entries: 2, head: entries[1].hash.clone() }
+        );
+
+        // 新记录接在现有末条之后，锚点随之更新
+        log_admin_action(&db, 42, "ban", Some(103), "测试").await.unwrap();
+        assert!(verify_audit_chain(&db).await.unwrap().is_intact());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_anchors_existing_audit_chain() {
+        let db = test_pool().await;
+        log_admin_action(&db, 42, "ban", Some(101), "测试").await.unwrap();
+        log_admin_action(&db, 42, "unban", Some(101), "测试").await.unwrap();
+        let head = get_audit_entries(&db).await.unwrap()[1].hash.clone();
+
+        // 模拟锚点出现之前的数据库
+        sqlx::query("DELETE FROM settings WHERE key = ?")
+            .bind(AUDIT_HEAD_SETTING)
+            .execute(db.writer())
+            .await
+            .unwrap();
+        migrate(db.writer()).await.unwrap();
+        assert_eq!(
+            get_setting(&db, AUDIT_HEAD_SETTING).await.unwrap().map(|(value, _)| value),
+            head
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_variant_assignment_and_conversion() {
+        let db = test_pool().await;
+        for user_id in 1..=3 {
+            get_or_create_user(&db, user_id, None, None, None, Lang::Zh).await.unwrap();
+        }
+
+        assert_eq!(assign_copy_variant(&db, 1, "copy-v1", CopyVariant::Control).await.unwrap(), CopyVariant::Control);
+        assert_eq!(assign_copy_variant(&db, 2, "copy-v1", CopyVariant::Variant).await.unwrap(), CopyVariant::Variant);
+        assert_eq!(assign_copy_variant(&db, 3, "copy-v1", CopyVariant::Variant).await.unwrap(), CopyVariant::Variant);
+        // 已分组的用户不会因比例调整而换组
+        assert_eq!(assign_copy_variant(&db, 1, "copy-v1", CopyVariant::Variant).await.unwrap(), CopyVariant::Control);
+
+        record_generation(&db, 2, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        record_generation(&db, 2, "7KQ2M3ZD", "ABC123DEF457", LIMITED, None, &results("ABC123DEF457")).await.unwrap().unwrap();
+
+        assert_eq!(
+            get_copy_variant_stats(&db, "copy-v1").await.unwrap(),
+            vec![
+                CopyVariantStats { variant: "control".to_string(), users: 1, converted: 0 },
+                CopyVariantStats { variant: "variant".to_string(), users: 2, converted: 1 },
+            ]
+        );
+        assert!(get_copy_variant_stats(&db, "copy-v2").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stats_by_instance() {
+        let db = test_pool().await;
+        let other = db.clone().with_instance_id("bot-b");
+
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        get_or_create_user(&other, 2, None, None, None, Lang::Zh).await.unwrap();
+        record_generation(&db, 1, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        record_generation(&other, 2, "7KQ2M3ZD", "ABC123DEF457", LIMITED, None, &results("ABC123DEF457")).await.unwrap().unwrap();
+        record_generation(&other, 2, "7KQ2M3ZD", "ABC123DEF458", LIMITED, None, &results("ABC123DEF458")).await.unwrap();
+
+        let all = get_system_stats(&db, None).await.unwrap();
+        assert_eq!((all.total_users, all.total_activations), (2, 3));
+        let bot_b = get_system_stats(&db, Some("bot-b")).await.unwrap();
+        assert_eq!((bot_b.total_users, bot_b.total_activations, bot_b.activations_today), (1, 2, 2));
+
+        assert_eq!(
+            get_instance_distribution(&db).await.unwrap(),
+            vec![("bot-b".to_string(), 2), (DEFAULT_INSTANCE_ID.to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_activation_outcome() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        let mut log_ids = Vec::new();
+        for machine_code in ["ABC123DEF456", "ABC123DEF457", "ABC123DEF458"] {
+            let reservation = reserve_quota(&db, 1, LIMITED, machine_code).await.unwrap().unwrap();
+            let (_, log_id) = commit_generation(&db, reservation, 1, "7KQ2M3ZD", machine_code, LIMITED, None, &results(machine_code)).await.unwrap().unwrap();
+            log_ids.push(log_id);
+        }
+
+        assert!(get_outcome_stats(&db, None).await.unwrap().success_rate().is_none());
+        assert!(set_activation_outcome(&db, log_ids[0], 1, ActivationOutcome::Failure).await.unwrap());
+        // 可以改选，但不能替别人的记录反馈
+        assert!(set_activation_outcome(&db, log_ids[0], 1, ActivationOutcome::Success).await.unwrap());
+        assert!(set_activation_outcome(&db, log_ids[1], 1, ActivationOutcome::Failure).await.unwrap());
+        assert!(!set_activation_outcome(&db, log_ids[2], 2, ActivationOutcome::Success).await.unwrap());
+
+        let stats = get_outcome_stats(&db, None).await.unwrap();
+        assert_eq!((stats.success, stats.failure, stats.unknown), (1, 1, 1));
+        assert_eq!(stats.success_rate(), Some(50.0));
+        assert_eq!(get_outcome_stats(&db, Some("bot-b")).await.unwrap(), OutcomeStats { success: 0, failure: 0, unknown: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_pending_result_is_single_use() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        let reservation = reserve_quota(&db, 1, LIMITED, "ABC123DEF456").await.unwrap().unwrap();
+        let (_, log_id) = commit_generation(&db, reservation, 1, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+
+        let (machine_code, stored) = get_activation_result(&db, log_id, 1).await.unwrap().unwrap();
+        assert_eq!(machine_code, "ABC123DEF456");
+        assert_eq!(stored.len(), results("ABC123DEF456").len());
+        assert_eq!(stored[0].professional_code, results("ABC123DEF456")[0].professional_code);
+        assert!(get_activation_result(&db, log_id, 2).await.unwrap().is_none());
+
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::minutes(10);
+        store_pending_result(&db, "live", 1, b"sealed", now, expires_at).await.unwrap();
+        store_pending_result(&db, "stale", 1, b"old", now - chrono::Duration::minutes(20), now - chrono::Duration::minutes(10)).await.unwrap();
+
+        let (sealed, expiry) = take_pending_result(&db, "live").await.unwrap().unwrap();
+        assert_eq!((sealed.as_slice(), expiry), (&b"sealed"[..], expires_at));
+        // 取出即失效
+        assert!(take_pending_result(&db, "live").await.unwrap().is_none());
+
+        assert_eq!(purge_expired_pending_results(&db, now).await.unwrap(), 1);
+        assert!(take_pending_result(&db, "stale").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refund_generation_restores_quota() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 1, None, None, None, Lang::Zh).await.unwrap();
+        record_generation(&db, 1, "7KQ2M3ZD", "ABC123DEF456", LIMITED, None, &results("ABC123DEF456")).await.unwrap().unwrap();
+        let before = get_user_by_id(&db, 1).await.unwrap();
+        let used = quota_used(&db, &before, &LIMITED).await.unwrap();
+
+        // 生成成功但回复发送失败：撤销后剩余次数不变，也不留下激活日志
+        let reservation = reserve_quota(&db, 1, LIMITED, "ABC123DEF457").await.unwrap().unwrap();
+        let (_, log_id) = commit_generation(&db, reservation, 1, "7KQ2M3ZD", "ABC123DEF457", LIMITED, None, &results("ABC123DEF457")).await.unwrap().unwrap();
+        assert!(!refund_generation(&db, log_id, 2).await.unwrap());
+        assert!(refund_generation(&db, log_id, 1).await.unwrap());
+        assert!(!refund_generation(&db, log_id, 1).await.unwrap());
+
+        let after = get_user_by_id(&db, 1).await.unwrap();
+        assert_eq!(after.request_count, before.request_count);
+        assert_eq!(quota_used(&db, &after, &LIMITED).await.unwrap(), used);
+        assert!(get_activation_result(&db, log_id, 1).await.unwrap().is_none());
+        let rows = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM activation_logs WHERE machine_code = 'ABC123DEF457'")
+            .fetch_one(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_hourly_slot_limit() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 4, None, None, None, Lang::Zh).await.unwrap();
+        let generous = Quota { limit: 100, ..LIMITED };
+        for machine_code in ["ABC123DEF456", "ABC123DEF457"] {
+            record_generation(&db, 4, "7KQ2M3ZD", machine_code, generous, None, &results(machine_code)).await.unwrap();
+        }
+        let first: DateTime<Utc> = sqlx::query_scalar("SELECT MIN(created_at) FROM activation_logs WHERE user_id = 4")
+            .fetch_one(db.writer())
+            .await
+            .unwrap();
+        let now = Utc::now();
+
+        assert_eq!(hourly_slot_opens_at(&db, 4, &generous, None, now).await.unwrap(), None);
+        assert_eq!(hourly_slot_opens_at(&db, 4, &generous, Some(3), now).await.unwrap(), None);
+        assert_eq!(
+            hourly_slot_opens_at(&db, 4, &generous, Some(2), now).await.unwrap(),
+            Some(first + chrono::Duration::hours(1))
+        );
+        // 管理员与白名单用户的配额不受限，同样不受突发限制
+        let unlimited = Quota { unlimited: true, ..generous };
+        assert_eq!(hourly_slot_opens_at(&db, 4, &unlimited, Some(1), now).await.unwrap(), None);
+        // 滚动窗口：最早的记录满一小时后名额恢复
+        let later = first + chrono::Duration::hours(1);
+        assert_eq!(hourly_slot_opens_at(&db, 4, &generous, Some(2), later).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_record_generation_trial_limit() {
+        let db = test_pool().await;
+        get_or_create_user(&db, 7, None, None, None, Lang::Zh).await.unwrap();
+        let now = Utc::now();
+        let trial = TrialLimit {
+            daily_limit: 1,
+            window_start: now - chrono::Duration::hours(24),
+            ends_at: now + chrono::Duration::hours(24),
+        };
+        let quota = Quota { trial: Some(trial), ..LIMITED };
+
+        assert_eq!(record_generation(&db, 7, "7KQ2M3ZD", "ABC123DEF456", quota, None, &results("ABC123DEF456")).await.unwrap(), Some(1));
+        // 试用期每日额度用完，即使总次数未达上限也拒绝
+        assert_eq!(record_generation(&db, 7, "7KQ2M3ZD", "ABC123DEF457", quota, None, &results("ABC123DEF457")).await.unwrap(), None);
+        assert_eq!(count_generations_since(&db, 7, trial.window_start).await.unwrap(), 1);
+
+        // 试用额度不会放宽总次数上限
+        let generous = Quota { limit: 1, unlimited: false, period_start: None, trial: Some(TrialLimit { daily_limit: 5, ..trial }), mode: QuotaMode::Requests };
+        assert_eq!(record_generation(&db, 7, "7KQ2M3ZD", "ABC123DEF457", generous, None, &results("ABC123DEF457")).await.unwrap(), None);
+
+        // 试用期结束后恢复正常额度
+        assert_eq!(record_generation(&db, 7, "7KQ2M3ZD", "ABC123DEF457", LIMITED, None, &results("ABC123DEF457")).await.unwrap(), Some(2));
+
+        assert!(trust_user(&db, 7).await.unwrap());
+        assert!(get_user_by_id(&db, 7).await.unwrap().trusted_at.is_some());
+        assert!(!trust_user(&db, 8).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_setting_keeps_time_when_unchanged() {
+        let db = test_pool().await;
+        assert_eq!(get_setting(&db, "k").await.unwrap(), None);
+
+        let first = set_setting(&db, "k", "a").await.unwrap();
+        assert_eq!(set_setting(&db, "k", "a").await.unwrap(), first);
+        assert!(set_setting(&db, "k", "b").await.unwrap() >= first);
+        assert_eq!(get_setting(&db, "k").await.unwrap().unwrap().0, "b");
+
+        // 改写存储格式时保留原修改时间
+        let (_, since) = get_setting(&db, "k").await.unwrap().unwrap();
+        assert!(!replace_setting_value(&db, "k", "a", "c").await.unwrap());
+        assert!(replace_setting_value(&db, "k", "b", "c").await.unwrap());
+        assert_eq!(get_setting(&db, "k").await.unwrap(), Some(("c".to_string(), since)));
+    }
+
+    #[tokio::test]
+    async fn test_update_setting_reads_latest_value() {
+        let db = test_pool().await;
+        let append = |current: Option<&str>| Ok(format!("{}x", current.unwrap_or_default()));
+
+        assert_eq!(update_setting(&db, "k", append).await.unwrap(), "x");
+        set_setting(&db, "k", "ab").await.unwrap();
+        assert_eq!(update_setting(&db, "k", append).await.unwrap(), "abx");
+
+        // 计算失败时保留原值
+        assert!(update_setting(&db, "k", |_| anyhow::bail!("拒绝")).await.is_err());
+        assert_eq!(get_setting(&db, "k").await.unwrap().unwrap().0, "abx");
+    }
+
+    #[tokio::test]
+    async fn test_last_admin_activity() {
+        let db = test_pool().await;
+        assert_eq!(get_last_admin_activity(&db, 5).await.unwrap(), None);
+
+        get_or_create_user(&db, 5, None, None, None, Lang::Zh).await.unwrap();
+        let created = get_user_by_id(&db, 5).await.unwrap().updated_at;
+        assert_eq!(get_last_admin_activity(&db, 5).await.unwrap(), Some(created));
+
+        log_admin_action(&db, 5, "ban", Some(6), "").await.unwrap();
+        assert!(get_last_admin_activity(&db, 5).await.unwrap().unwrap() >= created);
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// 数据库不可写（如文件系统变为只读）时的降级状态，克隆后共享同一份数据。
+/// 降级期间激活码照常生成，配额改用内存计数，激活日志等记录不再写入
+#[derive(Debug, Clone, Default)]
+pub struct DegradedMode(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    since: Option<DateTime<Utc>>,
+    reason: String,
+    /// 降级期间各用户已生成的次数，退出降级时清空
+    consumed: HashMap<i64, i64>,
+    /// 按机器码计数时，降级期间各用户生成过的机器码及各自的生成次数，退出降级时清空
+    machine_codes: HashMap<i64, HashMap<String, i64>>,
+}
+
+impl DegradedMode {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 进入降级模式；返回是否为新进入，已处于降级时不更新开始时间
+    pub fn enter(&self, now: DateTime<Utc>, reason: impl Into<String>) -> bool {
+        let mut inner = self.lock();
+        if inner.since.is_some() {
+            return false;
+        }
+        inner.since = Some(now);
+        inner.reason = reason.into();
+        true
+    }
+
+    /// 退出降级模式并清空内存计数；返回降级开始的时间，未处于降级时返回 None
+    pub fn exit(&self) -> Option<DateTime<Utc>> {
+        let mut inner = self.lock();
+        inner.consumed.clear();
+        inner.machine_codes.clear();
+        inner.since.take()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.lock().since.is_some()
+    }
+
+    /// 降级开始的时间与触发原因
+    pub fn status(&self) -> Option<(DateTime<Utc>, String)> {
+        let inner = self.lock();
+        inner.since.map(|since| (since, inner.reason.clone()))
+    }
+
+    /// 在内存中扣减一次。`allowance` 为进入降级前数据库中记录的剩余次数，None 表示不受限；
+    /// 降级期间已用次数达到该值时返回 None，否则返回扣减后降级期间的已用次数。
+    /// 按机器码计数时传入 `machine_code`，已用次数为降级期间生成过的不同机器码个数，重复的机器码不再占用额度
+    pub fn try_consume(&self, user_id: i64, machine_code: Option<&str>, allowance: Option<i64>) -> Option<i64> {
+        let mut inner = self.lock();
+        let Some(machine_code) = machine_code else {
+            let consumed = inner.consumed.entry(user_id).or_default();
+            if allowance.is_some_and(|allowance| *consumed >= allowance) {
+                return None;
+            }
+            *consumed += 1;
+            return Some(*consumed);
+        };
+
+        let codes = inner.machine_codes.entry(user_id).or_default();
+        if let Some(count) = codes.get_mut(machine_code) {
+            *count += 1;
+        } else {
+            if allowance.is_some_and(|allowance| codes.len() as i64 >= allowance) {
+                return None;
+            }
+            codes.insert(machine_code.to_string(), 1);
+        }
+        Some(codes.len() as i64)
+    }
+
+    /// 按机器码计数时，降级期间生成过的不同机器码个数
+    pub fn machine_code_count(&self, user_id: i64) -> i64 {
+        self.lock().machine_codes.get(&user_id).map_or(0, |codes| codes.len() as i64)
+    }
+
+    /// 生成失败时退回 `try_consume` 扣减的次数，`machine_code` 与扣减时一致；没有对应的扣减时不做处理
+    pub fn release(&self, user_id: i64, machine_code: Option<&str>) {
+        let mut inner = self.lock();
+        let Some(machine_code) = machine_code else {
+            if let Some(consumed) = inner.consumed.get_mut(&user_id) {
+                *consumed = (*consumed - 1).max(0);
+            }
+            return;
+        };
+
+        if let Some(codes) = inner.machine_codes.get_mut(&user_id) {
+            if let Some(count) = codes.get_mut(machine_code) {
+                *count -= 1;
+                if *count <= 0 {
+                    codes.remove(machine_code);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_enter_and_exit() {
+        let mode = DegradedMode::default();
+        let now = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap();
+        assert!(!mode.is_active());
+        assert!(mode.enter(now, "readonly"));
+        assert!(!mode.enter(now + chrono::Duration::minutes(1), "disk I/O error"));
+        assert_eq!(mode.status(), Some((now, "readonly".to_string())));
+
+        mode.try_consume(1, None, Some(1)).unwrap();
+        mode.try_consume(1, Some("A"), Some(1)).unwrap();
+        assert_eq!(mode.exit(), Some(now));
+        assert!(!mode.is_active());
+        assert_eq!(mode.exit(), None);
+        // 退出时清空计数
+        assert_eq!(mode.try_consume(1, None, Some(1)), Some(1));
+        assert_eq!(mode.try_consume(1, Some("B"), Some(1)), Some(1));
+    }
+
+    #[test]
+    fn test_try_consume_and_release() {
+        let mode = DegradedMode::default();
+        assert_eq!(mode.try_consume(1, None, Some(2)), Some(1));
+        assert_eq!(mode.try_consume(1, None, Some(2)), Some(2));
+        assert_eq!(mode.try_consume(1, None, Some(2)), None);
+        assert_eq!(mode.try_consume(2, None, Some(0)), None);
+
+        mode.release(1, None);
+        assert_eq!(mode.try_consume(1, None, Some(2)), Some(2));
+        for expected in 1..=5 {
+            assert_eq!(mode.try_consume(3, None, None), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_try_consume_by_machine_code() {
+        let mode = DegradedMode::default();
+        assert_eq!(mode.try_consume(1, Some("A"), Some(2)), Some(1));
+        // 重复的机器码不占用新的额度
+        assert_eq!(mode.try_consume(1, Some("A"), Some(2)), Some(1));
+        assert_eq!(mode.try_consume(1, Some("B"), Some(2)), Some(2));
+        assert_eq!(mode.try_consume(1, Some("C"), Some(2)), None);
+        assert_eq!(mode.try_consume(1, Some("A"), Some(2)), Some(2));
+
+        // A 生成过三次，全部退回后才释放额度
+        for _ in 0..2 {
+            mode.release(1, Some("A"));
+            assert_eq!(mode.try_consume(1, Some("C"), Some(2)), None);
+        }
+        mode.release(1, Some("A"));
+        mode.release(1, Some("missing"));
+        assert_eq!(mode.try_consume(1, Some("C"), Some(2)), Some(2));
+    }
+}
@@ -0,0 +1,395 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use teloxide::{
+    prelude::Requester,
+    types::{ChatId, ChatMemberKind, ChatPermissions},
+    Bot,
+};
+
+use crate::{
+    config::Config,
+    database::{self, Database},
+    format, guard, log_level, telemetry, utils,
+};
+
+/// 会被 reqwest 读取的代理环境变量
+const PROXY_VARS: &[&str] = &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+
+/// 单项检查的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// 通过，附带简短说明
+    Pass(String),
+    /// 未通过，附带一行排查提示
+    Fail(String),
+    /// 不适用于当前配置
+    Skip(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    /// 关键检查未通过时命令行以非零状态退出
+    pub critical: bool,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    fn push(&mut self, name: &'static str, critical: bool, outcome: Outcome) {
+        self.checks.push(Check { name, critical, outcome });
+    }
+
+    pub fn has_critical_failure(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.critical && matches!(check.outcome, Outcome::Fail(_)))
+    }
+
+    /// 每项一行的检查清单，未通过的项下一行给出提示
+    pub fn render(&self) -> String {
+        let mut lines = vec!["🩺 运行诊断".to_string()];
+        for check in &self.checks {
+            let line = match &check.outcome {
+                Outcome::Pass(detail) if detail.is_empty() => format!("✅ {}", check.name),
+                Outcome::Pass(detail) => format!("✅ {}: {}", check.name, detail),
+                Outcome::Fail(hint) => format!("❌ {}{}\n   💡 {}", check.name, if check.critical { "" } else { " (非关键)" }, hint),
+                Outcome::Skip(reason) => format!("⏭️ {}: {}", check.name, reason),
+            };
+            lines.push(line);
+        }
+        let failed = self.checks.iter().filter(|c| matches!(c.outcome, Outcome::Fail(_))).count();
+        lines.push(if failed == 0 {
+            "\n全部检查通过".to_string()
+        } else {
+            format!("\n{} 项未通过", failed)
+        });
+        lines.join("\n")
+    }
+}
+
+/// 依次执行全部诊断项；单项出错只记为未通过，不中断其余检查。所有检查只读，不迁移也不写入设置
+pub async fn run(config: &Config, db: &Database) -> DoctorReport {
+    let mut report = DoctorReport::default();
+
+    report.push("配置校验", true, check_config(config));
+    report.push("数据库", true, check_database(db).await);
+    report.push("数据库写入", true, check_degraded(config));
+    report.push("磁盘空间", true, check_disk(config));
+    report.push("日志目录可写", true, check_log_dir(Path::new(guard::BOT_LOG_FILE)));
+
+    match &config.telegram {
+        Some(telegram) => {
+            let bot = Bot::new(&telegram.bot_token);
+            report.push("Telegram getMe", true, check_get_me(&bot).await);
+            report.push("轮询模式", true, check_polling(&bot).await);
+            report.push("管理群发送权限", true, check_send_permission(&bot, telegram.chat_id).await);
+        }
+        None => {
+            let reason = "未配置 BOT_TOKEN/CHAT_ID".to_string();
+            report.push("Telegram getMe", true, Outcome::Skip(reason.clone()));
+            report.push("轮询模式", true, Outcome::Skip(reason.clone()));
+            report.push("管理群发送权限", true, Outcome::Skip(reason));
+        }
+    }
+
+    report.push("代理连通性", false, check_proxy().await);
+    report.push("时钟偏差", false, check_clock_skew(config).await);
+    report.push("匿名统计", false, check_telemetry(config, db).await);
+    report.push("日志级别", false, check_log_level(config));
+    report
+}
+
+fn check_config(config: &Config) -> Outcome {
+    match config.validate() {
+        Ok(()) => Outcome::Pass(String::new()),
+        Err(e) => Outcome::Fail(format!("{}，修改 .env 后重启", e)),
+    }
+}
+
+/// 查询结构版本的同时作为连通性探测
+async fn check_database(db: &Database) -> Outcome {
+    match database::schema_version(db.writer()).await {
+        Ok(version) if version == database::SCHEMA_VERSION => Outcome::Pass(format!("结构版本 v{}", version)),
+        Ok(version) if version < database::SCHEMA_VERSION => Outcome::Fail(format!(
+            "结构版本 v{} 低于程序需要的 v{}，重启机器人以执行迁移",
+            version,
+            database::SCHEMA_VERSION
+        )),
+        Ok(version) => Outcome::Fail(format!(
+            "结构版本 v{} 高于程序支持的 v{}，请升级程序或恢复迁移前的备份",
+            version,
+            database::SCHEMA_VERSION
+        )),
+        Err(e) => Outcome::Fail(format!("无法查询数据库 ({})，检查 DATABASE_URL 与文件权限", e)),
+    }
+}
+
+/// 只展示是否开启与上次上报时间，不发送
+async fn check_telemetry(config: &Config, db: &Database) -> Outcome {
+    let Some(endpoint) = &config.telemetry_endpoint else {
+        return Outcome::Skip("未开启 (TELEMETRY_OPTIN)".to_string());
+    };
+    match telemetry::last_sent(db).await {
+        Ok(Some(sent)) => Outcome::Pass(format!(
+            "每周发送到 {}，上次发送于 {}",
+            endpoint,
+            format::fmt_datetime(&sent, config.default_lang, config.timezone())
+        )),
+        Ok(None) => Outcome::Pass(format!("每周发送到 {}，尚未发送", endpoint)),
+        Err(e) => Outcome::Fail(format!("无法读取上报状态 ({})", e)),
+    }
+}
+
+/// 当前生效的日志过滤器；临时调整时附带自动恢复时间
+fn check_log_level(config: &Config) -> Outcome {
+    let Some(level) = log_level::global() else {
+        return Outcome::Skip("日志系统未启用运行时调整".to_string());
+    };
+    match level.current() {
+        (filter, Some(revert_at)) => Outcome::Pass(format!(
+            "{}，临时调整，将于 {} 恢复为 {}",
+            filter,
+            format::fmt_datetime(&revert_at, config.default_lang, config.timezone()),
+            level.default_filter()
+        )),
+        (filter, None) => Outcome::Pass(filter),
+    }
+}
+
+/// 降级模式由写入失败触发，恢复后由后台探测自动退出
+fn check_degraded(config: &Config) -> Outcome {
+    match config.degraded.status() {
+        None => Outcome::Pass(String::new()),
+        Some((since, reason)) => Outcome::Fail(format!(
+            "{} 起处于降级模式 ({})，检查文件系统是否只读与磁盘 I/O 错误",
+            format::fmt_datetime(&since, config.default_lang, config.timezone()),
+            reason
+        )),
+    }
+}
+
+fn check_disk(config: &Config) -> Outcome {
+    match utils::check_disk_space() {
+        Ok(true) => {}
+        Ok(false) => return Outcome::Fail("磁盘使用率超过 90%，清理日志与旧备份".to_string()),
+        Err(e) => return Outcome::Fail(format!("无法读取磁盘信息 ({})", e)),
+    }
+    match utils::get_inode_usage(&database::data_dir(&config.database_url)) {
+        Some(usage) if usage >= config.inode_usage_threshold => Outcome::Fail(format!(
+            "inode 使用率 {:.1}% 超过 {}%，清理大量小文件",
+            usage, config.inode_usage_threshold
+        )),
+        Some(usage) => Outcome::Pass(format!("inode 使用率 {:.1}%", usage)),
+        None => Outcome::Pass(String::new()),
+    }
+}
+
+/// 日志文件已存在时检查能否追加（不改动内容），否则检查其所在目录能否创建文件
+fn check_log_dir(log_file: &Path) -> Outcome {
+    let dir = match log_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let result = if log_file.exists() {
+        OpenOptions::new().append(true).open(log_file).map(|_| ()).map_err(anyhow::Error::from)
+    } else {
+        utils::check_dir_writable(dir)
+    };
+    let shown = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    match result {
+        Ok(()) => Outcome::Pass(shown.display().to_string()),
+        Err(e) => Outcome::Fail(format!(
+            "无法写入 {} ({})，检查日志目录 {} 的权限与磁盘是否只读",
+            log_file.display(),
+            e,
+            shown.display()
+        )),
+    }
+}
+
+async fn check_get_me(bot: &Bot) -> Outcome {
+    match bot.get_me().await {
+        Ok(me) => Outcome::Pass(format!("@{}", me.username())),
+        Err(e) => Outcome::Fail(format!("{}；检查 BOT_TOKEN 是否有效、网络或代理是否可达 api.telegram.org", e)),
+    }
+}
+
+/// 机器人以长轮询接收更新，设置了 webhook 时 getUpdates 会被 Telegram 拒绝
+async fn check_polling(bot: &Bot) -> Outcome {
+    match bot.get_webhook_info().await {
+        Ok(info) => match info.url {
+            None => Outcome::Pass("未设置 webhook".to_string()),
+            Some(url) => Outcome::Fail(format!(
+                "已设置 webhook ({})，轮询收不到更新；调用 deleteWebhook 后重启",
+                url.host_str().unwrap_or_default()
+            )),
+        },
+        Err(e) => Outcome::Fail(format!("无法查询 webhook 状态 ({})", e)),
+    }
+}
+
+/// 机器人能否在 CHAT_ID 中发消息：告警、健康报告与通知都发往这里，没有权限时会一直失败
+pub async fn check_send_permission(bot: &Bot, chat_id: i64) -> Outcome {
+    let result = async {
+        let me = bot.get_me().await?;
+        let chat = bot.get_chat(ChatId(chat_id)).await?;
+        if chat.is_private() {
+            return Ok(Ok("私聊".to_string()));
+        }
+        let member = bot.get_chat_member(chat.id, me.id).await?;
+        Ok::<_, teloxide::RequestError>(send_permission(chat.is_channel(), &member.kind, chat.permissions()))
+    }
+    .await;
+    match result {
+        Ok(Ok(detail)) => Outcome::Pass(detail),
+        Ok(Err(reason)) => Outcome::Fail(format!("无发送权限: {}", reason)),
+        Err(e) => Outcome::Fail(format!("无发送权限: 无法读取 CHAT_ID {} ({})，确认机器人已加入该群且 CHAT_ID 正确", chat_id, e)),
+    }
+}
+
+/// 按机器人在群/频道中的成员身份判断能否发消息，不能时返回原因
+fn send_permission(is_channel: bool, member: &ChatMemberKind, chat_permissions: Option<ChatPermissions>) -> Result<String, String> {
+    match member {
+        ChatMemberKind::Owner(_) => Ok("所有者".to_string()),
+        ChatMemberKind::Administrator(admin) if is_channel && !admin.can_post_messages => {
+            Err("机器人是频道管理员但没有\"发布消息\"权限".to_string())
+        }
+        ChatMemberKind::Administrator(_) => Ok("管理员".to_string()),
+        ChatMemberKind::Member if is_channel => Err("频道中只有管理员能发消息，请将机器人设为管理员".to_string()),
+        ChatMemberKind::Member if chat_permissions.is_some_and(|p| !p.contains(ChatPermissions::SEND_MESSAGES)) => {
+            Err("群组禁止普通成员发言，请将机器人设为管理员".to_string())
+        }
+        ChatMemberKind::Member => Ok("成员".to_string()),
+        ChatMemberKind::Restricted(restricted) if !restricted.can_send_messages => Err("机器人在群中被禁言".to_string()),
+        ChatMemberKind::Restricted(_) => Ok("受限成员，可发消息".to_string()),
+        ChatMemberKind::Left => Err("机器人不在该群中，请先将其加入".to_string()),
+        ChatMemberKind::Banned(_) => Err("机器人已被移出并封禁".to_string()),
+    }
+}
+
+async fn check_proxy() -> Outcome {
+    let Some(var) = PROXY_VARS.iter().find(|var| std::env::var(var).is_ok_and(|v| !v.trim().is_empty())) else {
+        return Outcome::Skip("未配置代理".to_string());
+    };
+    // 请求经由 reqwest 读取的代理发出
+    match utils::fetch_date_header(guard::TELEGRAM_API_URL).await {
+        Some(_) => Outcome::Pass(format!("通过 {} 可达 Telegram", var)),
+        None => Outcome::Fail(format!("通过 {} 无法访问 {}，检查代理地址与认证", var, guard::TELEGRAM_API_URL)),
+    }
+}
+
+/// 只测量不记录，守护进程记下的上次偏差保持不变
+async fn check_clock_skew(config: &Config) -> Outcome {
+    match guard::probe_clock_skew().await {
+        Some(seconds) if seconds.abs() > config.clock_skew_threshold => Outcome::Fail(format!(
+            "与 Telegram 服务器相差 {} 秒，超过 {} 秒；启用 NTP 时间同步",
+            seconds, config.clock_skew_threshold
+        )),
+        Some(seconds) => Outcome::Pass(format!("{} 秒", seconds)),
+        None => Outcome::Fail("无法取得 Telegram 服务器时间，检查网络".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn check(name: &'static str, critical: bool, outcome: Outcome) -> Check {
+        Check { name, critical, outcome }
+    }
+
+    #[test]
+    fn test_critical_failure_and_render() {
+        let mut report = DoctorReport {
+            checks: vec![
+                check("数据库", true, Outcome::Pass("结构版本 v1".to_string())),
+                check("代理连通性", false, Outcome::Skip("未配置代理".to_string())),
+                check("时钟偏差", false, Outcome::Fail("启用 NTP 时间同步".to_string())),
+            ],
+        };
+        assert!(!report.has_critical_failure());
+        assert_eq!(
+            report.render(),
+            "🩺 运行诊断\n✅ 数据库: 结构版本 v1\n⏭️ 代理连通性: 未配置代理\n❌ 时钟偏差 (非关键)\n   💡 启用 NTP 时间同步\n\n1 项未通过"
+        );
+
+        report.push("日志目录可写", true, Outcome::Fail("检查目录权限".to_string()));
+        assert!(report.has_critical_failure());
+    }
+
+    #[tokio::test]
+    async fn test_check_database_reports_pending_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}", dir.path().join("bot.db").display());
+        let options = sqlx::sqlite::SqliteConnectOptions::from_str(&url).unwrap().create_if_missing(true);
+        let pool = sqlx::SqlitePool::connect_with(options).await.unwrap();
+        sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY)").execute(&pool).await.unwrap();
+        pool.close().await;
+
+        // 只读打开不会执行迁移，结构版本落后时如实报告
+        let db = database::open_read_only(&url).unwrap();
+        assert!(matches!(check_database(&db).await, Outcome::Fail(hint) if hint.contains("低于")));
+        assert!(matches!(check_database(&database::open_read_only("sqlite:/nonexistent/bot.db").unwrap()).await, Outcome::Fail(_)));
+    }
+
+    #[test]
+    fn test_check_log_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("bot.log");
+        assert!(matches!(check_log_dir(&log), Outcome::Pass(_)));
+        // 已有日志只做追加打开，内容保持不变
+        std::fs::write(&log, "line\n").unwrap();
+        assert!(matches!(check_log_dir(&log), Outcome::Pass(_)));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "line\n");
+        assert!(matches!(check_log_dir(&dir.path().join("missing/bot.log")), Outcome::Fail(_)));
+    }
+
+    #[test]
+    fn test_send_permission() {
+        let member: ChatMemberKind = serde_json::from_value(serde_json::json!({ "status": "member" })).unwrap();
+        assert_eq!(send_permission(false, &member, None), Ok("成员".to_string()));
+        assert!(send_permission(false, &member, Some(ChatPermissions::empty())).unwrap_err().contains("禁止普通成员发言"));
+        assert!(send_permission(true, &member, None).is_err());
+
+        let left: ChatMemberKind = serde_json::from_value(serde_json::json!({ "status": "left" })).unwrap();
+        assert!(send_permission(false, &left, None).unwrap_err().contains("不在该群中"));
+
+        let muted: ChatMemberKind = serde_json::from_value(serde_json::json!({
+            "status": "restricted",
+            "until_date": 0,
+            "is_member": true,
+            "can_send_messages": false,
+            "can_send_media_messages": false,
+            "can_send_other_messages": false,
+            "can_add_web_page_previews": false,
+            "can_change_info": false,
+            "can_invite_users": false,
+            "can_pin_messages": false,
+            "can_manage_topics": false,
+            "can_send_polls": false
+        }))
+        .unwrap();
+        assert!(send_permission(false, &muted, None).unwrap_err().contains("禁言"));
+    }
+
+    #[tokio::test]
+    async fn test_check_database_reports_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("doctor.db").display());
+        let db = database::init(&url, None, None).await.unwrap();
+        assert_eq!(check_database(&db).await, Outcome::Pass(format!("结构版本 v{}", database::SCHEMA_VERSION)));
+
+        sqlx::query(&format!("PRAGMA user_version = {}", database::SCHEMA_VERSION + 1))
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert!(matches!(check_database(&db).await, Outcome::Fail(hint) if hint.contains("高于程序支持")));
+    }
+}
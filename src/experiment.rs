@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// 欢迎语/教程文案实验中用户所在的分组
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CopyVariant {
+    /// 原有文案
+    Control,
+    /// 配置的新文案
+    Variant,
+}
+
+impl CopyVariant {
+    pub fn code(self) -> &'static str {
+        match self {
+            CopyVariant::Control => "control",
+            CopyVariant::Variant => "variant",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "control" => Some(CopyVariant::Control),
+            "variant" => Some(CopyVariant::Variant),
+            _ => None,
+        }
+    }
+}
+
+/// 用户在实验中的桶号 (0-99)；使用 FNV-1a 保证跨版本、跨进程稳定，实验名参与哈希，换实验即重新分桶
+pub fn bucket(user_id: i64, experiment: &str) -> u8 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in experiment.bytes().chain(user_id.to_le_bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % 100) as u8
+}
+
+/// 桶号小于 `variant_percent` 的用户看到新文案
+pub fn assign(user_id: i64, experiment: &str, variant_percent: u8) -> CopyVariant {
+    if bucket(user_id, experiment) < variant_percent {
+        CopyVariant::Variant
+    } else {
+        CopyVariant::Control
+    }
+}
+
+/// 替换自定义欢迎语中的占位符 {name} 与 {limit}
+pub fn render_welcome(template: &str, name: &str, daily_limit: i32) -> String {
+    template.replace("{name}", name).replace("{limit}", &daily_limit.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assignment_is_stable_and_proportional() {
+        assert_eq!(bucket(123456789, "copy-v1"), bucket(123456789, "copy-v1"));
+
+        let variants = (0..10_000).filter(|&user_id| assign(user_id, "copy-v1", 20) == CopyVariant::Variant).count();
+        assert!((1_700..=2_300).contains(&variants), "{}", variants);
+
+        assert!((0..1_000).all(|user_id| assign(user_id, "copy-v1", 0) == CopyVariant::Control));
+        assert!((0..1_000).all(|user_id| assign(user_id, "copy-v1", 100) == CopyVariant::Variant));
+    }
+
+    #[test]
+    fn test_render_welcome() {
+        assert_eq!(render_welcome("嗨 {name}，每天 {limit} 次", "Alice", 3), "嗨 Alice，每天 3 次");
+        assert_eq!(CopyVariant::from_code(CopyVariant::Variant.code()), Some(CopyVariant::Variant));
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::io::Write;
+use std::num::NonZeroU32;
+
+use crate::database::{self, Database};
+
+/// 健康历史 CSV 的列，与 `HealthCheck` 的数值字段一一对应
+const HEALTH_COLUMNS: [&str; 12] = [
+    "timestamp",
+    "overall",
+    "cpu_usage",
+    "memory_usage",
+    "disk_usage",
+    "inode_usage",
+    "latency_count",
+    "latency_avg_us",
+    "latency_max_us",
+    "clock_skew_secs",
+    "error_count",
+    "warning_count",
+];
+
+/// 审计日志 CSV 的列，含哈希链，便于与外部副本比对
+const AUDIT_COLUMNS: [&str; 7] = ["id", "admin_id", "action", "target_user_id", "detail", "created_at", "hash"];
+
+/// 加密导出文件的文件头，同时作为 AEAD 附加数据，格式变更时递增版本号
+const ENCRYPTED_MAGIC: &[u8; 8] = b"FUEXPv1\n";
+const SALT_LEN: usize = 16;
+/// 口令派生密钥 (PBKDF2-HMAC-SHA256) 的迭代次数
+const PBKDF2_ITERATIONS: u32 = 200_000;
+/// 加密后的导出文件追加的扩展名
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<LessSafeKey> {
+    let mut key = [0u8; 32];
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).expect("迭代次数不能为 0");
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| anyhow!("无法创建加密密钥"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// 用口令加密导出内容 (AES-256-GCM)：文件头 | 16 字节盐 | 12 字节随机数 | 密文与认证标签，
+/// 每次加密使用新的盐与随机数。用 `decrypt-export` 命令或 `decrypt` 解密
+pub fn encrypt(plain: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt).map_err(|_| anyhow!("无法生成随机盐"))?;
+    rng.fill(&mut nonce).map_err(|_| anyhow!("无法生成随机数"))?;
+
+    let mut sealed = plain.to_vec();
+    derive_key(passphrase, &salt)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(ENCRYPTED_MAGIC), &mut sealed)
+        .map_err(|_| anyhow!("加密导出文件失败"))?;
+
+    let mut out = Vec::with_capacity(ENCRYPTED_MAGIC.len() + SALT_LEN + NONCE_LEN + sealed.len());
+    out.extend_from_slice(ENCRYPTED_MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// 解密 `encrypt` 生成的文件；口令错误与文件被篡改无法区分，统一报错
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let Some(rest) = data.strip_prefix(ENCRYPTED_MAGIC.as_slice()) else {
+        bail!("不是加密的导出文件");
+    };
+    if rest.len() < SALT_LEN + NONCE_LEN + AES_256_GCM.tag_len() {
+        bail!("加密的导出文件不完整");
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("加密的导出文件不完整"))?;
+
+    let mut buffer = sealed.to_vec();
+    let plain = derive_key(passphrase, salt)?
+        .open_in_place(nonce, Aad::from(ENCRYPTED_MAGIC), &mut buffer)
+        .map_err(|_| anyhow!("口令错误或文件已损坏"))?;
+    Ok(plain.to_vec())
+}
+
+/// 写入一行 CSV；含逗号、引号或换行的字段按 RFC 4180 加引号转义
+pub fn write_csv_row<W: Write>(writer: &mut W, fields: &[String]) -> std::io::Result<()> {
+    let line = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(writer, "{}", line)
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// 将 `since` 之后的守护检查记录逐行写成 CSV，返回写入的记录数（不含表头）
+pub async fn export_health_csv<W: Write>(db: &Database, since: DateTime<Utc>, writer: &mut W) -> Result<usize> {
+    write_csv_row(writer, &HEALTH_COLUMNS.map(str::to_string))?;
+
+    let mut rows = database::stream_health_checks(db, since);
+    let mut count = 0;
+    while let Some(record) = rows.try_next().await? {
+        write_csv_row(
+            writer,
+            &[
+                record.timestamp.to_rfc3339(),
+                record.overall,
+                record.cpu_usage.to_string(),
+                record.memory_usage.to_string(),
+                record.disk_usage.to_string(),
+                optional(record.inode_usage),
+                optional(record.latency_count),
+                optional(record.latency_avg_us),
+                optional(record.latency_max_us),
+                optional(record.clock_skew_secs),
+                record.error_count.to_string(),
+                record.warning_count.to_string(),
+            ],
+        )?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// 将全部审计记录连同哈希写成 CSV，返回写入的记录数（不含表头）
+pub async fn export_audit_csv<W: Write>(db: &Database, writer: &mut W) -> Result<usize> {
+    write_csv_row(writer, &AUDIT_COLUMNS.map(str::to_string))?;
+
+    let entries = database::get_audit_entries(db).await?;
+    for entry in &entries {
+        write_csv_row(
+            writer,
+            &[
+                entry.id.to_string(),
+                entry.admin_id.to_string(),
+                entry.action.clone(),
+                optional(entry.target_user_id),
+                entry.detail.clone().unwrap_or_default(),
+                entry.created_at.clone(),
+                entry.hash.clone().unwrap_or_default(),
+            ],
+        )?;
+    }
+
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClockSkew, HealthCheck, LatencySummary};
+    use chrono::{Duration, TimeZone};
+
+    fn health(timestamp: DateTime<Utc>, cpu_usage: f64, latency: Option<LatencySummary>) -> HealthCheck {
+        HealthCheck {
+            timestamp,
+            bot_status: "running".to_string(),
+            guard_status: "running".to_string(),
+            cpu_usage,
+            memory_usage: 40.0,
+            disk_usage: 55.5,
+            inode_usage: None,
+            latency,
+            update_lag: None,
+            clock_skew: Some(ClockSkew { seconds: -2, previous: None }),
+            internet_connectivity: true,
+            telegram_api_status: Some(true),
+            error_count: 0,
+            warning_count: 3,
+        }
+    }
+
+    #[test]
+    fn test_write_csv_row_escapes() {
+        let mut out = Vec::new();
+        write_csv_row(&mut out, &["a,b".to_string(), "say \"hi\"".to_string(), "plain".to_string()]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\"a,b\",\"say \"\"hi\"\"\",plain\n");
+    }
+
+    #[tokio::test]
+    async fn test_export_health_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("test.db").display());
+        let db = database::init(&url, None, None).await.unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap();
+        let latency = Some(LatencySummary { count: 10, avg_us: 1_500, max_us: 9_000 });
+
+        // 乱序写入，导出按时间排序；窗口外的记录不导出
+        database::record_health_check(&db, &health(now, 20.5, None), "WARNING").await.unwrap();
+        database::record_health_check(&db, &health(now - Duration::hours(1), 12.25, latency), "OK").await.unwrap();
+        database::record_health_check(&db, &health(now - Duration::days(40), 99.0, None), "OK").await.unwrap();
+
+        let mut out = Vec::new();
+        let count = export_health_csv(&db, now - Duration::days(30), &mut out).await.unwrap();
+        assert_eq!(count, 2);
+
+        let text = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = text.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0], HEALTH_COLUMNS);
+        assert_eq!(
+            rows[1],
+            ["2025-08-15T11:00:00+00:00", "OK", "12.25", "40", "55.5", "", "10", "1500", "9000", "-2", "0", "3"]
+        );
+        assert_eq!(rows[2][0], "2025-08-15T12:00:00+00:00");
+        assert_eq!(rows[2][1], "WARNING");
+        assert_eq!(rows[2][2], "20.5");
+        assert_eq!(rows[2][6], "");
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let plain = "id,admin_id\n1,42\n".as_bytes();
+        let sealed = encrypt(plain, "correct horse").unwrap();
+        assert!(sealed.starts_with(ENCRYPTED_MAGIC));
+        assert!(!sealed.windows(plain.len()).any(|window| window == plain));
+        // 每次加密的盐与随机数不同
+        assert_ne!(sealed, encrypt(plain, "correct horse").unwrap());
+
+        assert_eq!(decrypt(&sealed, "correct horse").unwrap(), plain);
+        assert!(decrypt(&sealed, "wrong horse").is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&tampered, "correct horse").is_err());
+        assert!(decrypt(plain, "correct horse").is_err());
+        assert!(decrypt(&sealed[..ENCRYPTED_MAGIC.len() + 4], "correct horse").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_audit_includes_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("test.db").display());
+        let db = database::init(&url, None, None).await.unwrap();
+        database::log_admin_action(&db, 42, "ban", Some(7), "刷号, 批量").await.unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(export_audit_csv(&db, &mut out).await.unwrap(), 1);
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], AUDIT_COLUMNS.join(","));
+        let hash = database::get_audit_entries(&db).await.unwrap()[0].hash.clone().unwrap();
+        assert!(lines[1].starts_with("1,42,ban,7,\"刷号, 批量\","));
+        assert!(lines[1].ends_with(&format!(",{}", hash)));
+    }
+}
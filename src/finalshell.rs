@@ -1,11 +1,170 @@
 use anyhow::Result;
+use futures::future::BoxFuture;
 use md5::{Digest, Md5};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
 use sha3::Keccak384;
 
-use crate::models::FinalShellVersion;
+use crate::{i18n::Lang, models::FinalShellVersion};
+
+/// 机器码语法：至少 8 位，由字母、数字及 `@`、`-`、`_` 组成（FinalShell 机器码可能包含 @）
+const MACHINE_CODE_PATTERN: &str = r"[A-Za-z0-9@_-]{8,}";
+
+fn machine_code_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(MACHINE_CODE_PATTERN).expect("机器码正则无效"))
+}
+
+fn full_machine_code_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(&format!("^{}$", MACHINE_CODE_PATTERN)).expect("机器码正则无效"))
+}
+
+/// 是否为合法的规范机器码（不含空白）
+pub fn is_valid(machine_code: &str) -> bool {
+    full_machine_code_regex().is_match(machine_code)
+}
+
+/// 从任意文本中找出所有符合语法的机器码候选
+pub fn find_candidates(text: &str) -> Vec<&str> {
+    machine_code_regex().find_iter(text).map(|m| m.as_str()).collect()
+}
+
+/// 规范化用户输入：去掉所有空白字符（复制粘贴时常夹带空格与换行）
+pub fn canonicalize(machine_code: &str) -> String {
+    machine_code.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// 机器码校验位算法；按版本配置，未通过时只提示用户核对，不阻断生成
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckDigit {
+    /// 标准 Luhn (mod 10)，只取机器码中的数字
+    Luhn,
+    /// Luhn mod 36，取字母与数字（不区分大小写），忽略 `@`、`-`、`_`
+    LuhnMod36,
+}
+
+impl CheckDigit {
+    /// 解析配置值 luhn/luhn36，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "luhn" => Some(CheckDigit::Luhn),
+            "luhn36" => Some(CheckDigit::LuhnMod36),
+            _ => None,
+        }
+    }
+
+    pub fn verify(self, machine_code: &str) -> bool {
+        let (radix, values): (u32, Vec<u32>) = match self {
+            CheckDigit::Luhn => (10, machine_code.chars().filter_map(|c| c.to_digit(10)).collect()),
+            CheckDigit::LuhnMod36 => (36, machine_code.chars().filter_map(|c| c.to_digit(36)).collect()),
+        };
+        if values.len() < 2 {
+            return false;
+        }
+
+        // 从最右一位开始，隔位加倍，加倍结果按 radix 进制逐位相加
+        let sum: u32 = values
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(index, value)| {
+                let addend = if index % 2 == 1 { value * 2 } else { *value };
+                addend / radix + addend % radix
+            })
+            .sum();
+        sum.rem_euclid(radix) == 0
+    }
+}
+
+/// 返回配置了校验位且机器码未通过校验的版本（只检查 `versions` 中的版本）
+pub fn failed_checksums(
+    machine_code: &str,
+    profiles: &[(FinalShellVersionType, CheckDigit)],
+    versions: &[FinalShellVersionType],
+) -> Vec<FinalShellVersionType> {
+    profiles
+        .iter()
+        .filter(|(version, check)| versions.contains(version) && !check.verify(machine_code))
+        .map(|(version, _)| *version)
+        .collect()
+}
+
+/// 在机器码所有者私聊之外展示机器码时的脱敏方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RedactionPolicy {
+    /// 完整显示
+    Full,
+    /// 只显示前 4 位
+    #[default]
+    Prefix4,
+    /// 显示机器码指纹的前 8 位
+    Hash,
+    /// 完全隐藏
+    None,
+}
+
+impl RedactionPolicy {
+    /// 解析配置值 full/prefix4/hash/none，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "full" => Some(RedactionPolicy::Full),
+            "prefix4" => Some(RedactionPolicy::Prefix4),
+            "hash" => Some(RedactionPolicy::Hash),
+            "none" => Some(RedactionPolicy::None),
+            _ => None,
+        }
+    }
+}
+
+/// 按策略脱敏机器码；前缀模式下不足 5 位的机器码整体隐藏，避免完整泄露
+pub fn redact(machine_code: &str, policy: RedactionPolicy) -> String {
+    const PREFIX_LEN: usize = 4;
+    match policy {
+        RedactionPolicy::Full => machine_code.to_string(),
+        RedactionPolicy::Prefix4 if machine_code.chars().count() <= PREFIX_LEN => "****".to_string(),
+        RedactionPolicy::Prefix4 => format!("{}****", machine_code.chars().take(PREFIX_LEN).collect::<String>()),
+        RedactionPolicy::Hash => format!("#{}", &ActivationCodeGenerator::machine_code_fingerprint(machine_code)[..8]),
+        RedactionPolicy::None => "[已隐藏]".to_string(),
+    }
+}
+
+/// 激活码输出的大小写；默认大写以兼容历史输出
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CodeCase {
+    #[default]
+    Upper,
+    Lower,
+    /// 保持哈希原样输出（十六进制小写）
+    AsIs,
+}
+
+impl CodeCase {
+    /// 解析配置值 upper/lower/asis，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upper" => Some(CodeCase::Upper),
+            "lower" => Some(CodeCase::Lower),
+            "asis" => Some(CodeCase::AsIs),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, code: &str) -> String {
+        match self {
+            CodeCase::Upper => code.to_uppercase(),
+            CodeCase::Lower => code.to_lowercase(),
+            CodeCase::AsIs => code.to_string(),
+        }
+    }
+}
+
+/// 激活码结果中按展示顺序依次使用的图标
+const VERSION_ICONS: [&str; 4] = ["🔹", "🔸", "🔷", "🔶"];
 
 /// FinalShell版本枚举
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FinalShellVersionType {
     Legacy,      // < 3.9.6
     V396Plus,    // ≥ 3.9.6
@@ -13,6 +172,55 @@ pub enum FinalShellVersionType {
     V46,         // 4.6
 }
 
+impl FinalShellVersionType {
+    /// 按生成顺序排列的全部版本
+    pub const ALL: [FinalShellVersionType; 4] = [
+        FinalShellVersionType::Legacy,
+        FinalShellVersionType::V396Plus,
+        FinalShellVersionType::V45,
+        FinalShellVersionType::V46,
+    ];
+
+    /// 由 ASCII 版本名解析版本
+    pub fn from_name_ascii(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.version_name_ascii() == name)
+    }
+
+    /// 纯 ASCII 版本名，用于 JSON 等机器可读输出
+    pub fn version_name_ascii(&self) -> &'static str {
+        match self {
+            FinalShellVersionType::Legacy => "<3.9.6",
+            FinalShellVersionType::V396Plus => ">=3.9.6",
+            FinalShellVersionType::V45 => "4.5",
+            FinalShellVersionType::V46 => "4.6+",
+        }
+    }
+
+    /// 激活日志中记录的版本名，与历史记录中的 `finalshell_version` 保持一致
+    pub fn log_label(&self) -> &'static str {
+        match self {
+            FinalShellVersionType::Legacy => "< 3.9.6",
+            FinalShellVersionType::V396Plus => "≥ 3.9.6",
+            FinalShellVersionType::V45 => "4.5",
+            FinalShellVersionType::V46 => "4.6+",
+        }
+    }
+
+    /// 面向用户展示的本地化版本名
+    pub fn version_name_localized(&self, lang: Lang) -> &'static str {
+        match (self, lang) {
+            (FinalShellVersionType::Legacy, Lang::Zh) => "FinalShell < 3.9.6",
+            (FinalShellVersionType::V396Plus, Lang::Zh) => "FinalShell ≥ 3.9.6",
+            (FinalShellVersionType::V45, Lang::Zh) => "FinalShell 4.5",
+            (FinalShellVersionType::V46, Lang::Zh) => "FinalShell 4.6",
+            (FinalShellVersionType::Legacy, Lang::En) => "FinalShell before 3.9.6",
+            (FinalShellVersionType::V396Plus, Lang::En) => "FinalShell 3.9.6 and later",
+            (FinalShellVersionType::V45, Lang::En) => "FinalShell 4.5",
+            (FinalShellVersionType::V46, Lang::En) => "FinalShell 4.6 and later",
+        }
+    }
+}
+
 /// 激活码类型
 #[derive(Debug, Clone)]
 pub enum LicenseType {
@@ -26,54 +234,164 @@ pub struct ActivationResult {
     pub version_type: FinalShellVersionType,
     pub advanced_code: String,
     pub professional_code: String,
-    pub version_name: String,
 }
 
-/// FinalShell激活码生成器
-pub struct ActivationCodeGenerator;
+/// 激活码取摘要中的 8 个字节，即 16 位十六进制
+const CODE_BYTES: usize = 8;
+
+/// 摘要从第 `offset` 字节起的 `CODE_BYTES` 字节，直接按 `case` 编码为十六进制
+fn hex_code(digest: &[u8], offset: usize, case: CodeCase) -> String {
+    let digits: &[u8; 16] = match case {
+        CodeCase::Upper => b"0123456789ABCDEF",
+        CodeCase::Lower | CodeCase::AsIs => b"0123456789abcdef",
+    };
+    let mut code = String::with_capacity(CODE_BYTES * 2);
+    for byte in &digest[offset..offset + CODE_BYTES] {
+        code.push(digits[usize::from(byte >> 4)] as char);
+        code.push(digits[usize::from(byte & 0x0f)] as char);
+    }
+    code
+}
+
+/// 一种授权的盐值哈希：摘要输入为 `前缀 + 机器码 + 后缀`，前缀在创建时预先喂入哈希状态
+#[derive(Debug, Clone)]
+struct SaltedHasher<D> {
+    state: D,
+    suffix: &'static str,
+    offset: usize,
+}
+
+impl<D: Digest + Clone> SaltedHasher<D> {
+    fn new(prefix: &str, suffix: &'static str, offset: usize) -> Self {
+        let mut state = D::new();
+        state.update(prefix.as_bytes());
+        Self { state, suffix, offset }
+    }
+
+    fn code(&self, machine_code: &str, case: CodeCase) -> String {
+        let mut state = self.state.clone();
+        state.update(machine_code.as_bytes());
+        state.update(self.suffix.as_bytes());
+        hex_code(&state.finalize(), self.offset, case)
+    }
+}
+
+/// 同一版本的 (高级版, 专业版) 盐值
+type SaltPair<D> = (SaltedHasher<D>, SaltedHasher<D>);
+
+/// FinalShell激活码生成器。实例持有预先配置好盐值的哈希状态，批量或 HTTP 模式下可重复使用，
+/// 每次生成只克隆哈希状态并分段喂入机器码与后缀，不再拼接中间字符串
+#[derive(Debug, Clone)]
+pub struct ActivationCodeGenerator {
+    case: CodeCase,
+    legacy: SaltPair<Md5>,
+    /// 依次为 3.9.6 及以后、4.5、4.6+
+    keccak: [SaltPair<Keccak384>; 3],
+}
+
+impl Default for ActivationCodeGenerator {
+    fn default() -> Self {
+        Self::new(CodeCase::default())
+    }
+}
 
 impl ActivationCodeGenerator {
-    /// 根据机器码生成所有版本的激活码
-    pub fn generate_all(machine_code: &str) -> Result<Vec<ActivationResult>> {
-        let mut results = Vec::new();
-        
-        // 生成所有版本的激活码
-        results.push(Self::generate_legacy(machine_code)?);
-        results.push(Self::generate_v396_plus(machine_code)?);
-        results.push(Self::generate_v45(machine_code)?);
-        results.push(Self::generate_v46(machine_code)?);
-        
-        Ok(results)
+    /// 创建按 `case` 输出大小写的生成器，盐值与各版本的 `generate_*` 一致
+    pub fn new(case: CodeCase) -> Self {
+        Self {
+            case,
+            legacy: (SaltedHasher::new("61305", "8552", 4), SaltedHasher::new("2356", "13593", 4)),
+            keccak: [
+                (SaltedHasher::new("", "hSf(78cvVlS5E", 6), SaltedHasher::new("", "FF3Go(*Xvbb5s2", 6)),
+                (SaltedHasher::new("", "wcegS3gzA$", 6), SaltedHasher::new("", "b(xxkHn%z);x", 6)),
+                (SaltedHasher::new("", "csSf5*xlkgYSX,y", 6), SaltedHasher::new("", "Scfg*ZkvJZc,s,Y", 6)),
+            ],
+        }
+    }
+
+    /// 按给定顺序生成指定版本的激活码
+    pub fn generate_for(&self, machine_code: &str, versions: &[FinalShellVersionType]) -> Vec<ActivationResult> {
+        versions
+            .iter()
+            .map(|&version| {
+                let (advanced_code, professional_code) = match version {
+                    FinalShellVersionType::Legacy => self.codes(&self.legacy, machine_code),
+                    FinalShellVersionType::V396Plus => self.codes(&self.keccak[0], machine_code),
+                    FinalShellVersionType::V45 => self.codes(&self.keccak[1], machine_code),
+                    FinalShellVersionType::V46 => self.codes(&self.keccak[2], machine_code),
+                };
+                ActivationResult {
+                    version_type: version,
+                    advanced_code,
+                    professional_code,
+                }
+            })
+            .collect()
+    }
+
+    fn codes<D: Digest + Clone>(&self, (advanced, professional): &SaltPair<D>, machine_code: &str) -> (String, String) {
+        (advanced.code(machine_code, self.case), professional.code(machine_code, self.case))
+    }
+
+    /// 按给定顺序生成指定版本的激活码，并按 `case` 转换大小写；多次生成时应复用 `new` 创建的实例
+    pub fn generate_versions(
+        machine_code: &str,
+        versions: &[FinalShellVersionType],
+        case: CodeCase,
+    ) -> Result<Vec<ActivationResult>> {
+        Ok(Self::new(case).generate_for(machine_code, versions))
+    }
+
+    /// 每次拼接盐值字符串再整体哈希的原始实现，保留作 `bench` 的对照与测试中的参考结果
+    pub fn generate_versions_reference(
+        machine_code: &str,
+        versions: &[FinalShellVersionType],
+        case: CodeCase,
+    ) -> Result<Vec<ActivationResult>> {
+        versions
+            .iter()
+            .map(|version| Self::generate_version(machine_code, *version, case))
+            .collect()
+    }
+
+    fn generate_version(machine_code: &str, version: FinalShellVersionType, case: CodeCase) -> Result<ActivationResult> {
+        let result = match version {
+            FinalShellVersionType::Legacy => Self::generate_legacy(machine_code),
+            FinalShellVersionType::V396Plus => Self::generate_v396_plus(machine_code),
+            FinalShellVersionType::V45 => Self::generate_v45(machine_code),
+            FinalShellVersionType::V46 => Self::generate_v46(machine_code),
+        }?;
+
+        Ok(ActivationResult {
+            advanced_code: case.apply(&result.advanced_code),
+            professional_code: case.apply(&result.professional_code),
+            ..result
+        })
     }
     
-    /// 根据机器码生成默认版本激活码 (用于向后兼容)
-    pub fn generate(machine_code: &str) -> Result<(String, FinalShellVersion)> {
-        let version = FinalShellVersion::detect_version(machine_code);
-        
-        let activation_code = match version.version.as_str() {
-            "< 3.9.6" => {
-                let result = Self::generate_legacy(machine_code)?;
-                result.professional_code // 默认返回专业版
-            },
-            "≥ 3.9.6" => {
-                let result = Self::generate_v396_plus(machine_code)?;
-                result.professional_code
-            },
-            "4.5" => {
-                let result = Self::generate_v45(machine_code)?;
-                result.professional_code
-            },
-            "4.6+" => {
-                let result = Self::generate_v46(machine_code)?;
-                result.professional_code
-            },
-            _ => {
-                let result = Self::generate_v396_plus(machine_code)?;
-                result.professional_code
-            }
-        };
+    /// 生成延长 `days` 天试用期的延长码。
+    /// FinalShell 试用延长的算法目前未知，这里只预留接口：未配置盐值或已配置时都返回明确的错误
+    pub fn generate_trial_extension(_machine_code: &str, _days: u32, salt: Option<&str>) -> Result<String> {
+        if salt.is_none() {
+            anyhow::bail!("未配置试用延长码盐值 (TRIAL_EXTENSION_SALT)");
+        }
+        anyhow::bail!("试用延长码算法尚未实现")
+    }
+
+    /// 激活日志摘要行使用的结果：优先取按机器码推测出的版本，该版本未生成时取第一个
+    pub fn primary_result<'a>(machine_code: &str, results: &'a [ActivationResult]) -> Option<&'a ActivationResult> {
+        let detected = FinalShellVersion::detect_version(machine_code);
+        results
+            .iter()
+            .find(|result| result.version_type.log_label() == detected.version)
+            .or_else(|| results.first())
+    }
 
-        Ok((activation_code, version))
+    /// 机器码指纹，用于在不保存原文的场景下关联同一机器码
+    pub fn machine_code_fingerprint(machine_code: &str) -> String {
+        let mut hasher = Md5::new();
+        hasher.update(machine_code.as_bytes());
+        format!("{:x}", hasher.finalize())
     }
 
     /// 计算MD5哈希
@@ -96,17 +414,16 @@ impl ActivationCodeGenerator {
     fn generate_legacy(machine_code: &str) -> Result<ActivationResult> {
         // 🟡 高级版: MD5(61305{machine_id}8552)[8:24]
         let advanced_hash = Self::calc_md5(&format!("61305{}8552", machine_code))?;
-        let advanced_code = advanced_hash[8..24].to_uppercase();
+        let advanced_code = advanced_hash[8..24].to_string();
         
         // 🟢 专业版: MD5(2356{machine_id}13593)[8:24]
         let professional_hash = Self::calc_md5(&format!("2356{}13593", machine_code))?;
-        let professional_code = professional_hash[8..24].to_uppercase();
+        let professional_code = professional_hash[8..24].to_string();
 
         Ok(ActivationResult {
             version_type: FinalShellVersionType::Legacy,
             advanced_code,
             professional_code,
-            version_name: "FinalShell < 3.9.6".to_string(),
         })
     }
 
@@ -114,17 +431,16 @@ impl ActivationCodeGenerator {
     fn generate_v396_plus(machine_code: &str) -> Result<ActivationResult> {
         // 🟡 高级版: Keccak384({machine_id}hSf(78cvVlS5E)[12:28]
         let advanced_hash = Self::calc_keccak384(&format!("{}hSf(78cvVlS5E", machine_code))?;
-        let advanced_code = advanced_hash[12..28].to_uppercase();
+        let advanced_code = advanced_hash[12..28].to_string();
         
         // 🟢 专业版: Keccak384({machine_id}FF3Go(*Xvbb5s2)[12:28]
         let professional_hash = Self::calc_keccak384(&format!("{}FF3Go(*Xvbb5s2", machine_code))?;
-        let professional_code = professional_hash[12..28].to_uppercase();
+        let professional_code = professional_hash[12..28].to_string();
 
         Ok(ActivationResult {
             version_type: FinalShellVersionType::V396Plus,
             advanced_code,
             professional_code,
-            version_name: "FinalShell ≥ 3.9.6".to_string(),
         })
     }
 
@@ -132,17 +448,16 @@ impl ActivationCodeGenerator {
     fn generate_v45(machine_code: &str) -> Result<ActivationResult> {
         // 🟡 高级版: Keccak384({machine_id}wcegS3gzA$)[12:28]
         let advanced_hash = Self::calc_keccak384(&format!("{}wcegS3gzA$", machine_code))?;
-        let advanced_code = advanced_hash[12..28].to_uppercase();
+        let advanced_code = advanced_hash[12..28].to_string();
         
         // 🟢 专业版: Keccak384({machine_id}b(xxkHn%z);x)[12:28]
         let professional_hash = Self::calc_keccak384(&format!("{}b(xxkHn%z);x", machine_code))?;
-        let professional_code = professional_hash[12..28].to_uppercase();
+        let professional_code = professional_hash[12..28].to_string();
 
         Ok(ActivationResult {
             version_type: FinalShellVersionType::V45,
             advanced_code,
             professional_code,
-            version_name: "FinalShell 4.5".to_string(),
         })
     }
 
@@ -150,61 +465,48 @@ impl ActivationCodeGenerator {
     fn generate_v46(machine_code: &str) -> Result<ActivationResult> {
         // 🟡 高级版: Keccak384({machine_id}csSf5*xlkgYSX,y)[12:28]
         let advanced_hash = Self::calc_keccak384(&format!("{}csSf5*xlkgYSX,y", machine_code))?;
-        let advanced_code = advanced_hash[12..28].to_uppercase();
+        let advanced_code = advanced_hash[12..28].to_string();
         
         // 🟢 专业版: Keccak384({machine_id}Scfg*ZkvJZc,s,Y)[12:28]
         let professional_hash = Self::calc_keccak384(&format!("{}Scfg*ZkvJZc,s,Y", machine_code))?;
-        let professional_code = professional_hash[12..28].to_uppercase();
+        let professional_code = professional_hash[12..28].to_string();
 
         Ok(ActivationResult {
             version_type: FinalShellVersionType::V46,
             advanced_code,
             professional_code,
-            version_name: "FinalShell 4.6".to_string(),
         })
     }
 
-    /// 验证机器码格式
-    pub fn validate_machine_code(machine_code: &str) -> bool {
-        if machine_code.is_empty() || machine_code.len() < 8 {
-            return false;
+    /// 以纯文本输出已生成的激活码结果
+    pub fn format_results_plain(machine_code: &str, results: &[ActivationResult], lang: Lang) -> String {
+        let mut output = format!("机器码: {}\n", machine_code);
+        for result in results {
+            output.push_str(&format!(
+                "  {}\n    高级版: {}\n    专业版: {}\n",
+                result.version_type.version_name_localized(lang),
+                result.advanced_code,
+                result.professional_code
+            ));
         }
 
-        // 基本格式检查
-        let trimmed = machine_code.trim();
-        
-        // 检查是否包含有效字符（FinalShell机器码可能包含@符号）
-        trimmed.chars().all(|c| {
-            c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '@'
-        })
-    }
-
-    /// 清理机器码格式
-    pub fn clean_machine_code(machine_code: &str) -> String {
-        machine_code
-            .trim()
-            .replace(' ', "")
-            .replace('\n', "")
-            .replace('\r', "")
-            .replace('\t', "")
+        output
     }
 
-    /// 检测FinalShell版本信息
-    pub fn detect_version_info(machine_code: &str) -> String {
-        let version = FinalShellVersion::detect_version(machine_code);
-        match version.version.as_str() {
-            "< 3.9.6" => "FinalShell 3.9.6 以前版本".to_string(),
-            "≥ 3.9.6" => "FinalShell 3.9.6 及以后版本".to_string(),
-            "4.5" => "FinalShell 4.5 版本".to_string(),
-            "4.6+" => "FinalShell 4.6 及以后版本".to_string(),
-            _ => "FinalShell 通用版本".to_string(),
+    /// 每个激活码单独一段（版本标签 + 反引号包裹的激活码），用于逐条发送，避免客户端整段复制
+    pub fn format_code_snippets(results: &[ActivationResult], lang: Lang) -> Vec<String> {
+        let mut snippets = Vec::with_capacity(results.len() * 2);
+        for (index, result) in results.iter().enumerate() {
+            let version_icon = VERSION_ICONS[index % VERSION_ICONS.len()];
+            let version = result.version_type.version_name_localized(lang);
+            snippets.push(format!("{} {} · 🟡 高级版\n`{}`", version_icon, version, result.advanced_code));
+            snippets.push(format!("{} {} · 🟢 专业版\n`{}`", version_icon, version, result.professional_code));
         }
+        snippets
     }
 
-    /// 格式化所有版本的激活码结果
-    pub fn format_all_codes(machine_code: &str) -> Result<String> {
-        let results = Self::generate_all(machine_code)?;
-        
+    /// 格式化已生成的激活码结果，按结果顺序分配图标
+    pub fn format_results(machine_code: &str, results: &[ActivationResult], generated_at: &str, lang: Lang) -> String {
         let mut output = String::new();
         
         // 添加美化的头部
@@ -213,25 +515,20 @@ impl ActivationCodeGenerator {
         output.push_str("═══════════════════════════════════════\n\n");
         
         output.push_str(&format!("🔑 输入机器码: `{}`\n", machine_code));
-        output.push_str(&format!("📅 生成时间: {}\n\n", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")));
+        output.push_str(&format!("📅 生成时间: {}\n\n", generated_at));
         
         output.push_str("🎯 生成结果:\n\n");
         
         for (index, result) in results.iter().enumerate() {
-            let version_icon = match index {
-                0 => "🔹", // < 3.9.6
-                1 => "🔸", // ≥ 3.9.6
-                2 => "🔷", // 4.5
-                3 => "🔶", // 4.6
-                _ => "📌",
-            };
+            // 图标按展示位置分配，与版本无关
+            let version_icon = VERSION_ICONS[index % VERSION_ICONS.len()];
             
             output.push_str(&format!(
                 "{} {}\n\
                  ┣━ 🟡 高级版: `{}`\n\
                  ┗━ 🟢 专业版: `{}`\n\n",
                 version_icon,
-                result.version_name,
+                result.version_type.version_name_localized(lang),
                 result.advanced_code,
                 result.professional_code
             ));
@@ -242,48 +539,138 @@ impl ActivationCodeGenerator {
         output.push_str("🛡️ 请合理使用 滥用必究\n");
         output.push_str("═══════════════════════════════════════\n");
         
-        Ok(output)
+        output
+    }
+}
+
+/// 激活码计算后端；目前只有本地实现，为将来接入外部服务预留扩展点
+pub trait CodeBackend: Send + Sync {
+    /// 后端名称，用于日志
+    fn name(&self) -> &'static str;
+
+    /// 按给定顺序生成指定版本的激活码
+    fn generate<'a>(
+        &'a self,
+        machine_code: &'a str,
+        versions: &'a [FinalShellVersionType],
+    ) -> BoxFuture<'a, Result<Vec<ActivationResult>>>;
+
+    /// 生成延长 `days` 天试用期的延长码；默认不支持
+    fn generate_trial_extension<'a>(&'a self, _machine_code: &'a str, _days: u32) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move { anyhow::bail!("后端 {} 不支持生成试用延长码", self.name()) })
+    }
+}
+
+/// 本地计算后端，直接使用内置算法
+#[derive(Debug, Default, Clone)]
+pub struct LocalBackend {
+    /// 按配置的大小写创建一次，之后每次请求复用
+    pub generator: ActivationCodeGenerator,
+    /// 试用延长码的盐值，未配置时不能生成延长码
+    pub trial_extension_salt: Option<String>,
+}
+
+impl CodeBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn generate<'a>(
+        &'a self,
+        machine_code: &'a str,
+        versions: &'a [FinalShellVersionType],
+    ) -> BoxFuture<'a, Result<Vec<ActivationResult>>> {
+        Box::pin(async move {
+            Ok(self.generator.generate_for(machine_code, versions))
+        })
+    }
+
+    fn generate_trial_extension<'a>(&'a self, machine_code: &'a str, days: u32) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            ActivationCodeGenerator::generate_trial_extension(machine_code, days, self.trial_extension_salt.as_deref())
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_validate_machine_code() {
-        assert!(ActivationCodeGenerator::validate_machine_code("ABC123DEF456"));
-        assert!(ActivationCodeGenerator::validate_machine_code("abc-123-def"));
-        assert!(!ActivationCodeGenerator::validate_machine_code(""));
-        assert!(!ActivationCodeGenerator::validate_machine_code("123"));
-        assert!(!ActivationCodeGenerator::validate_machine_code("ABC@123"));
+        assert!(is_valid("ABC123DEF456"));
+        assert!(is_valid("abc-123-def"));
+        assert!(is_valid("user_001@machine"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("123"));
+        assert!(!is_valid("ABC@123"));
+        assert!(!is_valid("ABC123 DEF456"));
+        assert!(!is_valid("机器码ABC123DEF"));
     }
 
     #[test]
     fn test_clean_machine_code() {
         let input = " ABC 123\nDEF\t456 ";
         let expected = "ABC123DEF456";
-        assert_eq!(ActivationCodeGenerator::clean_machine_code(input), expected);
+        assert_eq!(canonicalize(input), expected);
     }
 
     #[test]
-    fn test_generate_activation_code() {
-        let machine_code = "ABC123DEF456";
-        let result = ActivationCodeGenerator::generate(machine_code);
-        assert!(result.is_ok());
-        
-        let (activation_code, version) = result.unwrap();
-        assert!(!activation_code.is_empty());
-        assert_eq!(activation_code.len(), 16); // 激活码长度应该是16位
+    fn test_find_candidates() {
+        let text = "我的机器码是 abc123@def456，另一台: XYZ-0000_1111 短码 abc";
+        assert_eq!(find_candidates(text), vec!["abc123@def456", "XYZ-0000_1111"]);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_canonicalize_is_idempotent(input in any::<String>()) {
+            let once = canonicalize(&input);
+            prop_assert_eq!(canonicalize(&once), once);
+        }
+
+        #[test]
+        fn prop_candidates_are_valid(text in any::<String>()) {
+            for candidate in find_candidates(&text) {
+                prop_assert!(is_valid(candidate));
+            }
+        }
+
+        #[test]
+        fn prop_validation_never_panics(input in any::<String>()) {
+            let _ = is_valid(&input);
+            let _ = is_valid(&canonicalize(&input));
+        }
+
+        #[test]
+        fn prop_reusable_generator_matches_reference(code in "[A-Za-z0-9@_-]{8,64}") {
+            for case in [CodeCase::Upper, CodeCase::Lower, CodeCase::AsIs] {
+                let generator = ActivationCodeGenerator::new(case);
+                // 同一实例连续生成两次，确认克隆的哈希状态互不影响
+                for _ in 0..2 {
+                    let got = generator.generate_for(&code, &FinalShellVersionType::ALL);
+                    let want = ActivationCodeGenerator::generate_versions_reference(&code, &FinalShellVersionType::ALL, case).unwrap();
+                    for (got, want) in got.iter().zip(&want) {
+                        prop_assert_eq!(got.version_type, want.version_type);
+                        prop_assert_eq!(&got.advanced_code, &want.advanced_code);
+                        prop_assert_eq!(&got.professional_code, &want.professional_code);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn prop_grammar_strings_are_valid(code in "[A-Za-z0-9@_-]{8,64}") {
+            prop_assert!(is_valid(&code));
+            let text = format!("前缀 {} 后缀", code);
+            prop_assert_eq!(find_candidates(&text), vec![code.as_str()]);
+        }
     }
 
     #[test]
     fn test_generate_all_codes() {
         let machine_code = "ABC123DEF456";
-        let result = ActivationCodeGenerator::generate_all(machine_code);
-        assert!(result.is_ok());
-        
-        let results = result.unwrap();
+        let results = ActivationCodeGenerator::new(CodeCase::Upper).generate_for(machine_code, &FinalShellVersionType::ALL);
         assert_eq!(results.len(), 4); // 应该生成4个版本的激活码
         
         for activation_result in results {
@@ -295,12 +682,15 @@ mod tests {
     }
 
     #[test]
-    fn test_format_all_codes() {
+    fn test_format_results() {
         let machine_code = "ABC123DEF456";
-        let result = ActivationCodeGenerator::format_all_codes(machine_code);
-        assert!(result.is_ok());
-        
-        let formatted = result.unwrap();
+        let upper = ActivationCodeGenerator::new(CodeCase::Upper).generate_for(machine_code, &FinalShellVersionType::ALL);
+        let formatted = ActivationCodeGenerator::format_results(machine_code, &upper, "2025-08-15 20:00:00 (UTC+08:00)", Lang::Zh);
+        assert!(formatted.contains(&format!("`{}`", upper[0].professional_code)));
+        let lower = ActivationCodeGenerator::new(CodeCase::Lower).generate_for(machine_code, &FinalShellVersionType::ALL);
+        let formatted_lower = ActivationCodeGenerator::format_results(machine_code, &lower, "now", Lang::Zh);
+        assert!(formatted_lower.contains(&format!("`{}`", upper[0].professional_code.to_lowercase())));
+        assert!(formatted.contains("2025-08-15 20:00:00 (UTC+08:00)"));
         assert!(formatted.contains("FinalShell < 3.9.6"));
         assert!(formatted.contains("FinalShell ≥ 3.9.6"));
         assert!(formatted.contains("FinalShell 4.5"));
@@ -309,6 +699,177 @@ mod tests {
         assert!(formatted.contains("专业版"));
     }
 
+    #[test]
+    fn test_format_code_snippets() {
+        let versions = [FinalShellVersionType::V46, FinalShellVersionType::Legacy];
+        let results = ActivationCodeGenerator::generate_versions("ABC123DEF456", &versions, CodeCase::default()).unwrap();
+        let snippets = ActivationCodeGenerator::format_code_snippets(&results, Lang::Zh);
+
+        assert_eq!(snippets.len(), 4);
+        assert_eq!(snippets[0], format!("🔹 FinalShell 4.6 · 🟡 高级版\n`{}`", results[0].advanced_code));
+        assert_eq!(snippets[3], format!("🔸 FinalShell < 3.9.6 · 🟢 专业版\n`{}`", results[1].professional_code));
+        assert!(snippets.iter().all(|s| s.matches('`').count() == 2));
+    }
+
+    #[test]
+    fn test_format_results_plain() {
+        let upper = ActivationCodeGenerator::new(CodeCase::Upper).generate_for("ABC123DEF456", &FinalShellVersionType::ALL);
+        let text = ActivationCodeGenerator::format_results_plain("ABC123DEF456", &upper, Lang::Zh);
+        assert!(text.starts_with("机器码: ABC123DEF456\n"));
+        assert_eq!(text.matches("专业版: ").count(), 4);
+        assert!(!text.contains('`'));
+
+        let lower = ActivationCodeGenerator::new(CodeCase::Lower).generate_for("ABC123DEF456", &FinalShellVersionType::ALL);
+        let lower = ActivationCodeGenerator::format_results_plain("ABC123DEF456", &lower, Lang::Zh);
+        let codes: Vec<&str> = lower.lines().filter_map(|line| line.trim().strip_prefix("专业版: ")).collect();
+        assert_eq!(codes.len(), 4);
+        assert!(codes.iter().all(|code| *code == code.to_lowercase()));
+    }
+
+    #[test]
+    fn test_custom_version_order_and_visibility() {
+        let versions = [FinalShellVersionType::V46, FinalShellVersionType::V396Plus];
+
+        let results = ActivationCodeGenerator::new(CodeCase::Upper).generate_for("ABC123DEF456", &versions);
+        let text = ActivationCodeGenerator::format_results("ABC123DEF456", &results, "now", Lang::Zh);
+        let v46 = text.find("🔹 FinalShell 4.6").expect("第一个版本使用第一个图标");
+        let v396 = text.find("🔸 FinalShell ≥ 3.9.6").expect("第二个版本使用第二个图标");
+        assert!(v46 < v396);
+        assert!(!text.contains("FinalShell 4.5"));
+        assert!(!text.contains("FinalShell < 3.9.6"));
+
+        let order: Vec<_> = results.iter().map(|r| r.version_type.version_name_ascii()).collect();
+        assert_eq!(order, vec!["4.6+", ">=3.9.6"]);
+    }
+
+    #[test]
+    fn test_check_digit() {
+        assert!(CheckDigit::Luhn.verify("79927398713"));
+        assert!(CheckDigit::Luhn.verify("7992-7398@713"));
+        assert!(!CheckDigit::Luhn.verify("79927398710"));
+        assert!(!CheckDigit::Luhn.verify("abcdefgh"));
+
+        assert!(CheckDigit::LuhnMod36.verify("abc123@def45x"));
+        assert!(CheckDigit::LuhnMod36.verify("ABC123@DEF45X"));
+        assert!(!CheckDigit::LuhnMod36.verify("abc123@def45y"));
+
+        assert_eq!(CheckDigit::from_name("Luhn36"), Some(CheckDigit::LuhnMod36));
+        assert_eq!(CheckDigit::from_name("crc"), None);
+    }
+
+    #[test]
+    fn test_failed_checksums_only_for_shown_versions() {
+        let profiles = [
+            (FinalShellVersionType::V46, CheckDigit::Luhn),
+            (FinalShellVersionType::V45, CheckDigit::LuhnMod36),
+        ];
+        let versions = [FinalShellVersionType::V46, FinalShellVersionType::Legacy];
+
+        assert_eq!(failed_checksums("79927398713", &profiles, &versions), Vec::new());
+        assert_eq!(failed_checksums("79927398710", &profiles, &versions), vec![FinalShellVersionType::V46]);
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("abc123@def456", RedactionPolicy::Full), "abc123@def456");
+        assert_eq!(redact("abc123@def456", RedactionPolicy::Prefix4), "abc1****");
+        assert_eq!(redact("ab@cdefgh", RedactionPolicy::Prefix4), "ab@c****");
+        assert_eq!(redact("abcd", RedactionPolicy::Prefix4), "****");
+        assert_eq!(redact("a@", RedactionPolicy::Prefix4), "****");
+        assert_eq!(redact("ABC123DEF456", RedactionPolicy::Hash), "#1d54be9d");
+        assert_eq!(redact("abc123@def456", RedactionPolicy::None), "[已隐藏]");
+
+        assert_eq!(RedactionPolicy::from_name(" Prefix4 "), Some(RedactionPolicy::Prefix4));
+        assert_eq!(RedactionPolicy::from_name("partial"), None);
+    }
+
+    #[tokio::test]
+    async fn test_trial_extension_reports_missing_algorithm() {
+        let unconfigured = LocalBackend::default();
+        let err = unconfigured.generate_trial_extension("ABC123DEF456", 7).await.unwrap_err();
+        assert!(err.to_string().contains("TRIAL_EXTENSION_SALT"));
+
+        let configured = LocalBackend { trial_extension_salt: Some("salt".to_string()), ..LocalBackend::default() };
+        let err = configured.generate_trial_extension("ABC123DEF456", 7).await.unwrap_err();
+        assert!(err.to_string().contains("尚未实现"));
+    }
+
+    #[tokio::test]
+    async fn test_local_backend_matches_generator() {
+        let backend: &dyn CodeBackend = &LocalBackend::default();
+        let versions = [FinalShellVersionType::V46, FinalShellVersionType::Legacy];
+
+        let results = backend.generate("ABC123DEF456", &versions).await.unwrap();
+        let expected = ActivationCodeGenerator::generate_versions("ABC123DEF456", &versions, CodeCase::Upper).unwrap();
+
+        assert_eq!(backend.name(), "local");
+        assert_eq!(results.len(), 2);
+        for (got, want) in results.iter().zip(&expected) {
+            assert_eq!(got.version_type, want.version_type);
+            assert_eq!(got.professional_code, want.professional_code);
+        }
+    }
+
+    #[test]
+    fn test_code_case() {
+        let machine_code = "ABC123DEF456";
+        let versions = FinalShellVersionType::ALL;
+        let upper = ActivationCodeGenerator::generate_versions(machine_code, &versions, CodeCase::Upper).unwrap();
+        let lower = ActivationCodeGenerator::generate_versions(machine_code, &versions, CodeCase::Lower).unwrap();
+        let as_is = ActivationCodeGenerator::generate_versions(machine_code, &versions, CodeCase::AsIs).unwrap();
+
+        for ((upper, lower), as_is) in upper.iter().zip(&lower).zip(&as_is) {
+            for code in [&upper.advanced_code, &upper.professional_code] {
+                assert!(!code.chars().any(|c| c.is_ascii_lowercase()));
+            }
+            for code in [&lower.advanced_code, &lower.professional_code] {
+                assert!(!code.chars().any(|c| c.is_ascii_uppercase()));
+            }
+            // 哈希输出本就是小写十六进制，原样输出与小写一致，且与大写仅大小写不同
+            assert_eq!(as_is.professional_code, lower.professional_code);
+            assert!(as_is.advanced_code.eq_ignore_ascii_case(&upper.advanced_code));
+        }
+
+        // 默认保持大写，与历史输出兼容
+        assert_eq!(CodeCase::default(), CodeCase::Upper);
+        assert_eq!(ActivationCodeGenerator::default().generate_for(machine_code, &versions)[0].professional_code, upper[0].professional_code);
+        assert_eq!(CodeCase::from_name(" AsIs "), Some(CodeCase::AsIs));
+        assert_eq!(CodeCase::from_name("title"), None);
+    }
+
+    #[test]
+    fn test_version_names() {
+        let results = ActivationCodeGenerator::new(CodeCase::Upper).generate_for("ABC123DEF456", &FinalShellVersionType::ALL);
+        let ascii: Vec<_> = results.iter().map(|r| r.version_type.version_name_ascii()).collect();
+        assert_eq!(ascii, vec!["<3.9.6", ">=3.9.6", "4.5", "4.6+"]);
+        for version in FinalShellVersionType::ALL {
+            assert_eq!(FinalShellVersionType::from_name_ascii(version.version_name_ascii()), Some(version));
+        }
+        assert_eq!(FinalShellVersionType::from_name_ascii("9.9"), None);
+        assert!(ascii.iter().all(|name| name.is_ascii()));
+
+        for result in &results {
+            assert!(result.version_type.version_name_localized(Lang::Zh).starts_with("FinalShell"));
+            assert!(result.version_type.version_name_localized(Lang::En).is_ascii());
+        }
+    }
+
+    #[test]
+    fn test_primary_result_prefers_detected_version() {
+        let machine_code = "ABC123DEF456";
+        let all = ActivationCodeGenerator::new(CodeCase::Upper).generate_for(machine_code, &FinalShellVersionType::ALL);
+        let version = FinalShellVersion::detect_version(machine_code);
+
+        let primary = ActivationCodeGenerator::primary_result(machine_code, &all).unwrap();
+        assert_eq!(primary.version_type.log_label(), version.version);
+
+        // 推测版本未启用时退回第一个结果
+        let only_v46 = ActivationCodeGenerator::generate_versions(machine_code, &[FinalShellVersionType::V46], CodeCase::Upper).unwrap();
+        let primary = ActivationCodeGenerator::primary_result(machine_code, &only_v46).unwrap();
+        assert_eq!(primary.version_type, FinalShellVersionType::V46);
+        assert!(ActivationCodeGenerator::primary_result(machine_code, &[]).is_none());
+    }
+
     #[test]
     fn test_version_detection() {
         let short_code = "ABC123";
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use teloxide::types::InlineKeyboardButton;
+
+use crate::format;
+
+/// 附加在 /start 与激活码结果下方的运营方链接（交流群、频道、赞助页等）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FooterLink {
+    pub label: String,
+    pub url: String,
+}
+
+/// 页脚链接的展示方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FooterStyle {
+    /// 消息末尾的 MarkdownV2 链接
+    Text,
+    /// 消息下方的一行链接按钮
+    #[default]
+    Keyboard,
+}
+
+impl FooterStyle {
+    /// 解析配置值 text/keyboard，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(FooterStyle::Text),
+            "keyboard" => Some(FooterStyle::Keyboard),
+            _ => None,
+        }
+    }
+}
+
+/// 解析 FOOTER_LINKS：JSON 数组 `[{"label": .., "url": ..}]`，或逗号分隔的 `名称|链接`；只允许 https 链接
+pub fn parse_links(value: &str) -> Result<Vec<FooterLink>> {
+    let value = value.trim();
+    let links = if value.starts_with('[') {
+        serde_json::from_str::<Vec<FooterLink>>(value).context("JSON 格式错误")?
+    } else {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (label, url) = item.split_once('|').with_context(|| format!("{} 缺少 |，格式为 名称|链接", item))?;
+                Ok(FooterLink { label: label.trim().to_string(), url: url.trim().to_string() })
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    for link in &links {
+        if link.label.trim().is_empty() {
+            anyhow::bail!("链接 {} 缺少名称", link.url);
+        }
+        let url = reqwest::Url::parse(&link.url).with_context(|| format!("链接格式错误: {}", link.url))?;
+        if url.scheme() != "https" || url.host_str().is_none() {
+            anyhow::bail!("只允许 https 链接: {}", link.url);
+        }
+    }
+    Ok(links)
+}
+
+/// 追加到 MarkdownV2 消息末尾的链接行；没有链接时为空
+pub fn render_markdown_v2(links: &[FooterLink]) -> String {
+    if links.is_empty() {
+        return String::new();
+    }
+    let rendered: Vec<String> = links
+        .iter()
+        .map(|link| {
+            // 链接地址中只有 ) 与 \ 需要转义
+            let url = link.url.replace('\\', "\\\\").replace(')', "\\)");
+            format!("[{}]({})", format::escape_markdown_v2(&link.label), url)
+        })
+        .collect();
+    format!("\n\n{}", rendered.join(" · "))
+}
+
+/// 链接按钮行；链接已在启动时校验
+pub fn keyboard_row(links: &[FooterLink]) -> Vec<InlineKeyboardButton> {
+    links
+        .iter()
+        .filter_map(|link| {
+            let url = reqwest::Url::parse(&link.url).ok()?;
+            Some(InlineKeyboardButton::url(link.label.clone(), url))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(label: &str, url: &str) -> FooterLink {
+        FooterLink { label: label.to_string(), url: url.to_string() }
+    }
+
+    #[test]
+    fn test_parse_links() {
+        assert_eq!(
+            parse_links("交流群|https://t.me/group, 赞助 | https://example.com/donate").unwrap(),
+            vec![link("交流群", "https://t.me/group"), link("赞助", "https://example.com/donate")]
+        );
+        assert_eq!(
+            parse_links(r#"[{"label": "频道", "url": "https://t.me/channel"}]"#).unwrap(),
+            vec![link("频道", "https://t.me/channel")]
+        );
+        assert!(parse_links("").unwrap().is_empty());
+
+        assert!(parse_links("交流群|http://t.me/group").is_err());
+        assert!(parse_links("交流群|tg://resolve?domain=group").is_err());
+        assert!(parse_links("https://t.me/group").is_err());
+        assert!(parse_links("|https://t.me/group").is_err());
+    }
+
+    #[test]
+    fn test_render_markdown_v2_escapes_labels() {
+        assert_eq!(render_markdown_v2(&[]), "");
+        assert_eq!(
+            render_markdown_v2(&[link("交流群 (v2.0)", "https://t.me/group"), link("赞助_me", "https://example.com/a_(b)")]),
+            "\n\n[交流群 \\(v2\\.0\\)](https://t.me/group) · [赞助\\_me](https://example.com/a_(b\\))"
+        );
+    }
+}
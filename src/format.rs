@@ -0,0 +1,349 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::ParseMode;
+
+use crate::i18n::Lang;
+
+/// /stats 的发送格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatsFormat {
+    /// 纯文本，与其他命令风格一致
+    #[default]
+    Text,
+    /// HTML 卡片，关键数字加粗，适合转发到其他群
+    Html,
+}
+
+impl StatsFormat {
+    /// 解析配置值 text/html，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "text" => Some(StatsFormat::Text),
+            "html" => Some(StatsFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+/// 转义 Telegram HTML 解析模式中的特殊字符
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 转义 Telegram MarkdownV2 解析模式中的全部特殊字符
+pub fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' | '!'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// 插入回复中的用户输入（用户名、封禁原因、申诉内容、机器码等）：超过 `max_len` 个字符时截断并以省略号结尾，
+/// 再按消息的解析模式转义；纯文本消息（`None`）只截断。项目不使用旧版 Markdown，其余模式按 MarkdownV2 转义
+pub fn safe_user_text(text: &str, max_len: usize, parse_mode: Option<ParseMode>) -> String {
+    let truncated = match text.char_indices().nth(max_len) {
+        Some(_) => {
+            let end = text.char_indices().nth(max_len.saturating_sub(1)).map_or(0, |(index, _)| index);
+            format!("{}…", &text[..end])
+        }
+        None => text.to_string(),
+    };
+    match parse_mode {
+        None => truncated,
+        Some(ParseMode::Html) => escape_html(&truncated),
+        Some(_) => escape_markdown_v2(&truncated),
+    }
+}
+
+/// 按语言与时区格式化日期时间
+pub fn fmt_datetime(dt: &DateTime<Utc>, lang: Lang, tz: FixedOffset) -> String {
+    let local = dt.with_timezone(&tz);
+    let pattern = match lang {
+        Lang::Zh => "%Y-%m-%d %H:%M:%S",
+        Lang::En => "%b %d, %Y %H:%M:%S",
+    };
+    format!("{} ({})", local.format(pattern), fmt_utc_offset(tz))
+}
+
+/// 按语言与时区格式化日期
+pub fn fmt_date(dt: &DateTime<Utc>, lang: Lang, tz: FixedOffset) -> String {
+    let local = dt.with_timezone(&tz);
+    match lang {
+        Lang::Zh => local.format("%Y-%m-%d").to_string(),
+        Lang::En => local.format("%b %d, %Y").to_string(),
+    }
+}
+
+/// 相对时间描述，如 "3 小时前" / "3 hours ago"
+pub fn fmt_relative(dt: &DateTime<Utc>, now: &DateTime<Utc>, lang: Lang) -> String {
+    let elapsed = now.signed_duration_since(*dt);
+    let seconds = elapsed.num_seconds();
+    let minutes = elapsed.num_minutes();
+    let hours = elapsed.num_hours();
+    let days = elapsed.num_days();
+
+    // 未来时间（时钟偏差）也按"刚刚"处理
+    if seconds < 60 {
+        return match lang {
+            Lang::Zh => "刚刚".to_string(),
+            Lang::En => "just now".to_string(),
+        };
+    }
+
+    match lang {
+        Lang::Zh => {
+            if minutes < 60 {
+                format!("{} 分钟前", minutes)
+            } else if hours < 24 {
+                format!("{} 小时前", hours)
+            } else if days < 2 {
+                "昨天".to_string()
+            } else if days < 30 {
+                format!("{} 天前", days)
+            } else if days < 60 {
+                "上个月".to_string()
+            } else if days < 365 {
+                format!("{} 个月前", days / 30)
+            } else if days < 730 {
+                "去年".to_string()
+            } else {
+                format!("{} 年前", days / 365)
+            }
+        }
+        Lang::En => {
+            if minutes < 60 {
+                plural_ago(minutes, "minute")
+            } else if hours < 24 {
+                plural_ago(hours, "hour")
+            } else if days < 2 {
+                "yesterday".to_string()
+            } else if days < 30 {
+                plural_ago(days, "day")
+            } else if days < 60 {
+                "last month".to_string()
+            } else if days < 365 {
+                plural_ago(days / 30, "month")
+            } else if days < 730 {
+                "last year".to_string()
+            } else {
+                plural_ago(days / 365, "year")
+            }
+        }
+    }
+}
+
+fn plural_ago(value: i64, unit: &str) -> String {
+    if value == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", value, unit)
+    }
+}
+
+/// 带千位分隔符的数字
+pub fn fmt_count(value: i64) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let grouped = digits
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if value < 0 {
+        format!("-{}", grouped)
+    } else {
+        grouped
+    }
+}
+
+/// 短时长的显示形式：不足 1ms 显示微秒，不足 1s 显示毫秒，如 "850µs"、"23ms"、"1.25s"
+pub fn fmt_latency(duration: std::time::Duration) -> String {
+    let micros = duration.as_micros();
+    if micros < 1_000 {
+        format!("{}µs", micros)
+    } else if micros < 1_000_000 {
+        format!("{}ms", micros / 1_000)
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+/// 时区偏移的显示形式，如 "UTC+08:00"
+pub fn fmt_utc_offset(tz: FixedOffset) -> String {
+    let total_minutes = tz.local_minus_utc() / 60;
+    let sign = if total_minutes < 0 { '-' } else { '+' };
+    let total_minutes = total_minutes.abs();
+    format!("UTC{}{:02}:{:02}", sign, total_minutes / 60, total_minutes % 60)
+}
+
+/// 解析时区偏移，支持 "+08:00"、"+8"、"UTC+8"、"-05:30" 等写法
+pub fn parse_utc_offset(text: &str) -> Option<FixedOffset> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("UTC")
+        .or_else(|| text.strip_prefix("utc"))
+        .unwrap_or(text);
+
+    if text.is_empty() {
+        return FixedOffset::east_opt(0);
+    }
+
+    let (sign, rest) = match text.as_bytes()[0] {
+        b'+' => (1, &text[1..]),
+        b'-' => (-1, &text[1..]),
+        _ => (1, text),
+    };
+
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h.parse::<i32>().ok()?, m.parse::<i32>().ok()?),
+        None => (rest.parse::<i32>().ok()?, 0),
+    };
+
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn tz8() -> FixedOffset {
+        FixedOffset::east_opt(8 * 3600).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<b>a & \"b\"</b>"), "&lt;b&gt;a &amp; &quot;b&quot;&lt;/b&gt;");
+        assert_eq!(escape_html("zh-hans"), "zh-hans");
+        assert_eq!(StatsFormat::from_name(" HTML "), Some(StatsFormat::Html));
+        assert_eq!(StatsFormat::from_name("markdown"), None);
+    }
+
+    #[test]
+    fn test_escape_markdown_v2() {
+        assert_eq!(escape_markdown_v2("a_b (v1.0) `x` \\"), "a\\_b \\(v1\\.0\\) \\`x\\` \\\\");
+        assert_eq!(escape_markdown_v2("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_safe_user_text() {
+        assert_eq!(safe_user_text("alice", 5, None), "alice");
+        assert_eq!(safe_user_text("alice_bob", 5, None), "alic…");
+        assert_eq!(safe_user_text("用户名很长很长", 4, None), "用户名…");
+        assert_eq!(safe_user_text("😀😀😀", 2, None), "😀…");
+        assert_eq!(safe_user_text("*a*_b", 10, Some(ParseMode::MarkdownV2)), "\\*a\\*\\_b");
+        assert_eq!(safe_user_text("<b>&</b>", 5, Some(ParseMode::Html)), "&lt;b&gt;&amp;…");
+        assert_eq!(safe_user_text("abc", 0, None), "…");
+    }
+
+    #[test]
+    fn test_fmt_datetime() {
+        let dt = now();
+        assert_eq!(fmt_datetime(&dt, Lang::Zh, tz8()), "2025-08-15 20:00:00 (UTC+08:00)");
+        assert_eq!(fmt_datetime(&dt, Lang::En, tz8()), "Aug 15, 2025 20:00:00 (UTC+08:00)");
+        assert_eq!(fmt_datetime(&dt, Lang::Zh, FixedOffset::east_opt(0).unwrap()), "2025-08-15 12:00:00 (UTC+00:00)");
+    }
+
+    #[test]
+    fn test_fmt_date_crosses_midnight_in_timezone() {
+        let dt = Utc.with_ymd_and_hms(2025, 8, 15, 18, 0, 0).unwrap();
+        assert_eq!(fmt_date(&dt, Lang::Zh, tz8()), "2025-08-16");
+        assert_eq!(fmt_date(&dt, Lang::En, tz8()), "Aug 16, 2025");
+    }
+
+    #[test]
+    fn test_fmt_relative_zh_boundaries() {
+        let now = now();
+        let ago = |d: Duration| fmt_relative(&(now - d), &now, Lang::Zh);
+
+        assert_eq!(ago(Duration::seconds(0)), "刚刚");
+        assert_eq!(ago(Duration::seconds(59)), "刚刚");
+        assert_eq!(ago(Duration::seconds(60)), "1 分钟前");
+        assert_eq!(ago(Duration::minutes(59)), "59 分钟前");
+        assert_eq!(ago(Duration::minutes(60)), "1 小时前");
+        assert_eq!(ago(Duration::hours(23)), "23 小时前");
+        assert_eq!(ago(Duration::hours(24)), "昨天");
+        assert_eq!(ago(Duration::hours(47)), "昨天");
+        assert_eq!(ago(Duration::hours(48)), "2 天前");
+        assert_eq!(ago(Duration::days(29)), "29 天前");
+        assert_eq!(ago(Duration::days(30)), "上个月");
+        assert_eq!(ago(Duration::days(59)), "上个月");
+        assert_eq!(ago(Duration::days(60)), "2 个月前");
+        assert_eq!(ago(Duration::days(365)), "去年");
+        assert_eq!(ago(Duration::days(800)), "2 年前");
+        assert_eq!(fmt_relative(&(now + Duration::minutes(5)), &now, Lang::Zh), "刚刚");
+    }
+
+    #[test]
+    fn test_fmt_relative_en_boundaries() {
+        let now = now();
+        let ago = |d: Duration| fmt_relative(&(now - d), &now, Lang::En);
+
+        assert_eq!(ago(Duration::seconds(10)), "just now");
+        assert_eq!(ago(Duration::seconds(60)), "1 minute ago");
+        assert_eq!(ago(Duration::minutes(2)), "2 minutes ago");
+        assert_eq!(ago(Duration::hours(1)), "1 hour ago");
+        assert_eq!(ago(Duration::hours(3)), "3 hours ago");
+        assert_eq!(ago(Duration::hours(30)), "yesterday");
+        assert_eq!(ago(Duration::days(2)), "2 days ago");
+        assert_eq!(ago(Duration::days(45)), "last month");
+        assert_eq!(ago(Duration::days(90)), "3 months ago");
+        assert_eq!(ago(Duration::days(400)), "last year");
+        assert_eq!(ago(Duration::days(1100)), "3 years ago");
+    }
+
+    #[test]
+    fn test_fmt_count() {
+        assert_eq!(fmt_count(0), "0");
+        assert_eq!(fmt_count(999), "999");
+        assert_eq!(fmt_count(1000), "1,000");
+        assert_eq!(fmt_count(1234567), "1,234,567");
+        assert_eq!(fmt_count(-12345), "-12,345");
+    }
+
+    #[test]
+    fn test_fmt_latency() {
+        use std::time::Duration as StdDuration;
+        assert_eq!(fmt_latency(StdDuration::from_micros(850)), "850µs");
+        assert_eq!(fmt_latency(StdDuration::from_micros(23_400)), "23ms");
+        assert_eq!(fmt_latency(StdDuration::from_millis(1_250)), "1.25s");
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("+08:00"), Some(tz8()));
+        assert_eq!(parse_utc_offset("8"), Some(tz8()));
+        assert_eq!(parse_utc_offset("UTC+8"), Some(tz8()));
+        assert_eq!(parse_utc_offset("UTC"), FixedOffset::east_opt(0));
+        assert_eq!(parse_utc_offset("-05:30"), FixedOffset::west_opt(5 * 3600 + 30 * 60));
+        assert_eq!(parse_utc_offset("+25"), None);
+        assert_eq!(parse_utc_offset("Asia/Shanghai"), None);
+    }
+}
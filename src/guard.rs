@@ -1,46 +1,226 @@
-use anyhow::Result;
-use chrono::Utc;
-use sqlx::SqlitePool;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::Path;
 use std::time::Duration;
 use tokio::time;
 use tracing::{error, info, warn};
 
 use crate::{
-    config::Config,
-    models::HealthCheck,
-    utils::{self, SystemInfo},
+    config::{Config, TelegramConfig},
+    database::{self, Database},
+    doctor, format,
+    health::{HealthReport, HygieneFinding, Level, ProcessStatus, Thresholds},
+    models::{ClockSkew, HealthCheck},
+    quota::QuotaPeriod,
+    scheduler::Scheduler,
+    telegram_health::TelegramHealth,
+    telemetry, utils,
 };
 
-/// 启动守护进程
-pub async fn run(config: Config, db: SqlitePool) -> Result<()> {
+/// 备份文件所在目录
+pub const BACKUP_DIR: &str = "backups";
+/// 配置文件路径
+const ENV_FILE: &str = ".env";
+/// 保存 Bot Token 指纹的设置项，用于推算 Token 的使用时长
+const TOKEN_FINGERPRINT_KEY: &str = "bot_token_fingerprint";
+/// 保存上次测得的时钟偏差（秒）的设置项
+const CLOCK_SKEW_KEY: &str = "clock_skew_secs";
+/// start.sh 将机器人的输出重定向到该日志文件
+pub const BOT_LOG_FILE: &str = "bot.log";
+/// 用于比对时钟的 Telegram 服务器地址
+pub const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+
+/// 启动守护进程：系统检查、备份与清理按各自的间隔运行，任一任务失败单独告警
+pub async fn run(config: Config, db: Database) -> Result<()> {
     info!("启动 Guard 守护进程...");
 
-    // 创建定时任务
-    let mut interval = time::interval(Duration::from_secs(config.guard_check_interval));
+    // 健康报告与告警都发往 CHAT_ID，启动时先确认有发送权限
+    if let Some(telegram) = &config.telegram {
+        let bot = teloxide::Bot::new(&telegram.bot_token);
+        if let doctor::Outcome::Fail(hint) = doctor::check_send_permission(&bot, telegram.chat_id).await {
+            error!("管理群 (CHAT_ID {}) {}，健康报告与告警将无法送达", telegram.chat_id, hint);
+        }
+    }
 
-    loop {
-        interval.tick().await;
-        
-        match perform_check(&config, &db).await {
-            Ok(_) => {
+    let check = {
+        let (config, db) = (config.clone(), db.clone());
+        move || {
+            let (config, db) = (config.clone(), db.clone());
+            async move {
+                perform_check(&config, &db, None, false).await?;
                 info!("系统检查完成");
+                Ok(())
             }
-            Err(e) => {
-                error!("系统检查失败: {}", e);
-            }
         }
+    };
+    let backup = {
+        let clock = config.clock.clone();
+        move || backup_data(clock.now_utc())
+    };
+    let cleanup = {
+        let (config, db) = (config.clone(), db.clone());
+        move || {
+            let (config, db) = (config.clone(), db.clone());
+            async move { scheduled_cleanup(&config, &db).await }
+        }
+    };
+
+    let ban_expiry = {
+        let (config, db) = (config.clone(), db.clone());
+        move || {
+            let (config, db) = (config.clone(), db.clone());
+            async move { lift_expired_bans(&config, &db).await }
+        }
+    };
+
+    let telemetry_ping = {
+        let (config, db) = (config.clone(), db.clone());
+        move || {
+            let (config, db) = (config.clone(), db.clone());
+            async move { telemetry::send_if_due(&config, &db).await }
+        }
+    };
+    let telemetry_interval = match config.telemetry_endpoint {
+        Some(_) => Duration::from_secs(telemetry::CHECK_INTERVAL_SECS),
+        None => Duration::ZERO,
+    };
+
+    Scheduler::new()
+        .every("系统检查", Duration::from_secs(config.guard_check_interval), check)
+        .every("数据备份", Duration::from_secs(config.guard_backup_interval), backup)
+        .every("清理", Duration::from_secs(config.guard_cleanup_interval), cleanup)
+        .every("解除到期封禁", Duration::from_secs(config.ban_expiry_interval), ban_expiry)
+        .every("匿名统计", telemetry_interval, telemetry_ping)
+        .run(
+            {
+                let config = config.clone();
+                move |name, message| {
+                    let config = config.clone();
+                    async move {
+                        // 同一任务的失败按任务去重，错误描述不同也视为同一告警
+                        let key = format!("定时任务「{}」失败", name);
+                        if let Err(e) = send_keyed_alert(&config, &key, &format!("{}: {}", key, message)).await {
+                            error!("发送定时任务告警失败: {}", e);
+                        }
+                    }
+                }
+            },
+            move |name| {
+                let config = config.clone();
+                async move {
+                    if let Err(e) = resolve_alert(&config, &format!("定时任务「{}」失败", name)).await {
+                        error!("发送定时任务恢复通知失败: {}", e);
+                    }
+                }
+            },
+        )
+        .await;
+
+    Ok(())
+}
+
+/// 定时清理过期日志文件与超出保留期的历史记录
+async fn scheduled_cleanup(config: &Config, db: &Database) -> Result<()> {
+    let cleaned = utils::cleanup_logs(config.clock.now_utc()).await?;
+    info!("清理了 {} 个日志文件", cleaned);
+
+    let purged = database::purge_expired_pending_results(db, config.clock.now_utc()).await?;
+    if purged > 0 {
+        info!("清理了 {} 个过期的一次性结果链接", purged);
+    }
+
+    if config.history_retention_days > 0 {
+        let before = config.clock.now_utc() - chrono::Duration::days(config.history_retention_days);
+        let pruned = database::prune_history(db, before).await?;
+        info!("清理了 {} 条 {} 天前的历史记录", pruned, config.history_retention_days);
     }
+
+    if config.archive_activation_logs {
+        for (table, moved) in database::archive_activation_logs(db, archive_cutoff(config, config.clock.now_utc()), config.timezone()).await? {
+            info!("归档了 {} 条激活日志到 {}", moved, table);
+        }
+    }
+
+    if config.activation_log_retention_days > 0 {
+        let (days, pruned) = database::roll_up_activation_logs(db, rollup_cutoff(config, config.clock.now_utc()), config.timezone()).await?;
+        info!("汇总了 {} 天的激活日志，删除 {} 条原始记录", days, pruned);
+    }
+
+    Ok(())
+}
+
+/// 激活日志的归档截止时间：本月（配置时区）之前，且不早于按用户统计次数时回看的窗口，
+/// 月初时试用期与滥用检测仍能看到上月末的记录
+fn archive_cutoff(config: &Config, now: DateTime<Utc>) -> DateTime<Utc> {
+    let month_start = QuotaPeriod::Monthly.period_start(now, config.timezone()).unwrap_or(now);
+    let lookback = now - chrono::Duration::hours(config.abuse_window_hours.max(24));
+    month_start.min(lookback)
 }
 
-/// 执行系统检查
-pub async fn perform_check(config: &Config, db: &SqlitePool) -> Result<()> {
+/// 激活日志汇总删除的截止时间：保留期之前，且不晚于归档截止时间，配额与滥用检测需要的记录始终保留
+fn rollup_cutoff(config: &Config, now: DateTime<Utc>) -> DateTime<Utc> {
+    let retention = now - chrono::Duration::days(config.activation_log_retention_days);
+    retention.min(archive_cutoff(config, now))
+}
+
+/// 解除已到期的临时封禁，即使用户之后不再发消息触发机器人里的惰性解封；开启通知时私信告知用户
+async fn lift_expired_bans(config: &Config, db: &Database) -> Result<()> {
+    use teloxide::{Bot, prelude::*};
+
+    let user_ids = database::lift_expired_bans(db, config.clock.now_utc()).await?;
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+    info!("临时封禁到期，已自动解封 {} 个用户: {:?}", user_ids.len(), user_ids);
+
+    let Some(telegram) = config.telegram.as_ref().filter(|_| config.ban_expiry_notify) else {
+        return Ok(());
+    };
+    let bot = Bot::new(&telegram.bot_token);
+    for user_id in user_ids {
+        // 用户可能已屏蔽机器人，通知失败不影响解封
+        if let Err(e) = bot
+            .send_message(teloxide::types::ChatId(user_id), config.render("✅ 您的临时封禁已到期，现在可以继续使用机器人。"))
+            .await
+        {
+            warn!("通知用户 {} 解封失败: {}", user_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// 执行系统检查；指定 `output` 时同时将报告写入该文件，`json` 为真时本地输出 JSON 格式
+pub async fn perform_check(config: &Config, db: &Database, output: Option<&Path>, json: bool) -> Result<()> {
     info!("开始执行系统检查...");
 
     // 生成健康检查报告
-    let report = generate_health_report(config, db).await?;
-    
-    // 发送报告到Telegram
-    send_health_report(config, &report).await?;
+    // 守护进程与机器人不在同一进程，看不到机器人的限流状态
+    let report = generate_health_report(config, db, None).await?;
+    info!("系统检查结果: {}", report.render_log_line());
+
+    let text = report.render_telegram(config.default_lang, config.timezone());
+    let local = if json { report.render_json()? } else { text.clone() };
+
+    if let Some(path) = output {
+        std::fs::write(path, &local).with_context(|| format!("写入报告文件失败: {:?}", path))?;
+        info!("健康检查报告已写入 {:?}", path);
+    }
+    if json {
+        println!("{}", local);
+    }
+
+    // 发送报告到Telegram；未配置时只在本地输出
+    match &config.telegram {
+        Some(telegram) => send_health_report(config, telegram, &text).await?,
+        None => {
+            if !json {
+                println!("{}", local);
+            }
+            warn!("未配置 BOT_TOKEN/CHAT_ID，跳过发送到 Telegram");
+        }
+    }
     
     // 执行自动修复
     perform_auto_repair(config).await?;
@@ -48,132 +228,263 @@ pub async fn perform_check(config: &Config, db: &SqlitePool) -> Result<()> {
     Ok(())
 }
 
-/// 生成健康检查报告
-pub async fn generate_health_report(config: &Config, _db: &SqlitePool) -> Result<String> {
-    let timestamp = Utc::now();
+/// 生成健康检查报告；`telegram` 为机器人进程内的限流状态，不可见时传 None
+pub async fn generate_health_report(
+    config: &Config,
+    db: &Database,
+    telegram: Option<&TelegramHealth>,
+) -> Result<HealthReport> {
+    let timestamp = config.clock.now_utc();
     
     // 获取系统信息
     let system_info = utils::get_system_info()?;
     
     // 检查网络连通性
     let internet_connectivity = utils::check_internet_connectivity().await;
-    let telegram_api_status = utils::check_telegram_api(&config.bot_token).await;
+    let telegram_api_status = match &config.telegram {
+        Some(telegram) => Some(utils::check_telegram_api(&telegram.bot_token).await),
+        None => None,
+    };
     
     // 检查bot进程状态
     let bot_status = check_bot_process().await;
     
     // 分析日志错误
-    let (error_count, warning_count) = analyze_logs().await?;
+    let (error_count, warning_count) = analyze_logs(timestamp).await?;
+
+    // 最近 24 小时的生成耗时
+    let latency = match database::get_latency_summary(db, timestamp - chrono::Duration::hours(24)).await {
+        Ok(latency) => latency,
+        Err(e) => {
+            warn!("读取生成耗时统计失败: {}", e);
+            None
+        }
+    };
+    let update_lag = match database::get_update_lag(db, timestamp - chrono::Duration::hours(24)).await {
+        Ok(lag) => lag,
+        Err(e) => {
+            warn!("读取更新处理延迟统计失败: {}", e);
+            None
+        }
+    };
     
+    let current_pid = utils::get_current_pid();
+    let process_info = utils::get_process_info(current_pid);
+    let process = ProcessStatus {
+        pid: current_pid,
+        cpu_usage: process_info.as_ref().map(|p| p.cpu_usage).unwrap_or(0.0),
+        uptime: process_info.as_ref().map(|p| utils::calculate_uptime(p.start_time, timestamp)),
+    };
+
     // 生成报告
-    let report = format_health_report(HealthCheck {
+    let health = HealthCheck {
         timestamp,
         bot_status,
         guard_status: "running".to_string(),
         cpu_usage: system_info.cpu_usage,
         memory_usage: system_info.memory_usage,
         disk_usage: system_info.disk_usage,
+        inode_usage: utils::get_inode_usage(&database::data_dir(&config.database_url)),
+        latency,
+        update_lag,
+        clock_skew: measure_clock_skew(db).await,
         internet_connectivity,
         telegram_api_status,
         error_count,
         warning_count,
-    }, &system_info)?;
+    };
+    let throttle = telegram.map(|t| t.snapshot());
+
+    let mut report = HealthReport::build(
+        &health,
+        &system_info,
+        &process,
+        throttle.as_ref(),
+        &Thresholds {
+            throttle_warn_after: Duration::from_secs(config.throttle_warn_after),
+            inode_usage: config.inode_usage_threshold,
+            clock_skew_secs: config.clock_skew_threshold,
+            update_lag: Duration::from_secs(config.update_lag_threshold),
+        },
+    );
+    match hygiene_checks(config, db).await {
+        Ok(findings) => report.add_hygiene(findings),
+        Err(e) => warn!("运维卫生检查失败: {}", e),
+    }
+    match database::verify_audit_chain(db).await {
+        Ok(status) => report.add_audit_chain(&status),
+        Err(e) => warn!("校验审计日志失败: {}", e),
+    }
+    if let Err(e) = database::record_health_check(db, &health, report.overall.name()).await {
+        warn!("保存健康检查记录失败: {}", e);
+    }
 
     Ok(report)
 }
 
-/// 格式化健康检查报告
-fn format_health_report(health: HealthCheck, system_info: &SystemInfo) -> Result<String> {
-    let status_emoji = if health.cpu_usage < 80.0 
-        && health.memory_usage < 80.0 
-        && health.disk_usage < 90.0 
-        && health.internet_connectivity 
-        && health.telegram_api_status {
-        "✅ NORMAL"
-    } else {
-        "⚠️ WARNING"
-    };
+/// 测量本机与 Telegram 服务器的时钟偏差，并记下本次结果供下次比较；取不到服务器时间时返回 None
+pub async fn measure_clock_skew(db: &Database) -> Option<ClockSkew> {
+    let seconds = probe_clock_skew().await?;
 
-    let bot_status_emoji = match health.bot_status.as_str() {
-        "running" => "✅ running",
-        "stopped" => "❌ stopped",
-        _ => "❓ unknown",
+    let previous = match database::get_setting(db, CLOCK_SKEW_KEY).await {
+        Ok(previous) => previous.and_then(|(value, _)| value.parse().ok()),
+        Err(e) => {
+            warn!("读取上次时钟偏差失败: {}", e);
+            None
+        }
     };
+    if let Err(e) = database::set_setting(db, CLOCK_SKEW_KEY, &seconds.to_string()).await {
+        warn!("记录时钟偏差失败: {}", e);
+    }
 
-    let internet_status = if health.internet_connectivity { "✅ 正常" } else { "❌ 异常" };
-    let telegram_status = if health.telegram_api_status { "✅ 正常" } else { "❌ 异常" };
+    Some(ClockSkew { seconds, previous })
+}
 
-    let cpu_status = if health.cpu_usage < 80.0 { "✅" } else { "⚠️" };
-    let memory_status = if health.memory_usage < 80.0 { "✅" } else { "⚠️" };
-    let disk_status = if health.disk_usage < 90.0 { "✅" } else { "⚠️" };
+/// 本机与 Telegram 服务器的时钟偏差秒数，只测量不记录；取不到服务器时间时返回 None
+pub async fn probe_clock_skew() -> Option<i64> {
+    let date = utils::fetch_date_header(TELEGRAM_API_URL).await?;
+    // 测量的是系统时钟本身的偏差，因此直接读取系统时间而非注入的时钟
+    clock_skew(&date, Utc::now())
+}
 
-    let current_pid = utils::get_current_pid();
-    let process_info = utils::get_process_info(current_pid);
-    let uptime = process_info
-        .as_ref()
-        .map(|p| utils::calculate_uptime(p.start_time))
-        .unwrap_or_else(|| "未知".to_string());
-
-    let report = format!(
-        "🛡️ FinalShell机器人 系统自检报告\n\n\
-         📊 报告概览\n\
-         📅 检查日期: {}\n\
-         ⏰ 检查时间: {}\n\
-         🎯 整体状态: {}\n\
-         🔄 报告版本: Guard v2.0\n\n\
-         🔍 详细检查结果\n\n\
-         🤖 机器人进程状态\n\
-         • 运行状态: {} (PID: {})\n\
-         • CPU使用率: {:.1}%\n\
-         • 内存使用: {}\n\
-         • 运行时长: {}\n\n\
-         💻 系统资源监控\n\
-         • CPU: {:.1}% {}\n\
-         • 内存: {:.1}% {}\n\
-         • 磁盘: {:.1}% {}\n\n\
-         📋 日志文件分析\n\
-         • 错误数量: {} {}\n\
-         • 警告数量: {} {}\n\n\
-         🌐 网络连接检查\n\
-         • 互联网连接: {}\n\
-         • Telegram API: {}\n\n\
-         报告生成时间: {}",
-        health.timestamp.format("%Y-%m-%d"),
-        utils::format_datetime_china(&health.timestamp),
-        status_emoji,
-        bot_status_emoji,
-        current_pid,
-        process_info.map(|p| p.cpu_usage).unwrap_or(0.0),
-        utils::format_file_size(system_info.used_memory),
-        uptime,
-        health.cpu_usage,
-        cpu_status,
-        health.memory_usage,
-        memory_status,
-        health.disk_usage,
-        disk_status,
-        health.error_count,
-        if health.error_count == 0 { "✅ 正常" } else { "⚠️ 需要关注" },
-        health.warning_count,
-        if health.warning_count < 5 { "✅ 正常" } else { "⚠️ 需要关注" },
-        internet_status,
-        telegram_status,
-        utils::format_datetime_china(&health.timestamp)
-    );
+/// 本机时间相对 HTTP `Date` 头（RFC 2822 格式）的偏差秒数，正数表示本机偏快；无法解析时返回 None
+pub fn clock_skew(date_header: &str, local: DateTime<Utc>) -> Option<i64> {
+    let remote = DateTime::parse_from_rfc2822(date_header.trim()).ok()?;
+    Some((local - remote.with_timezone(&Utc)).num_seconds())
+}
 
-    Ok(report)
+/// 运维卫生检查：Token 轮换、备份时效、管理员活跃度与 .env 权限；只返回发现的问题
+pub async fn hygiene_checks(config: &Config, db: &Database) -> Result<Vec<HygieneFinding>> {
+    let now = config.clock.now_utc();
+    let mut findings = Vec::new();
+
+    if let Some(telegram) = &config.telegram {
+        let fingerprint = token_fingerprint(&telegram.bot_token);
+        // 旧版本保存的是 MD5 指纹，Token 未更换时换成新指纹并沿用原来的生效时间
+        database::replace_setting_value(db, TOKEN_FINGERPRINT_KEY, &legacy_token_fingerprint(&telegram.bot_token), &fingerprint)
+            .await?;
+        let since = database::set_setting(db, TOKEN_FINGERPRINT_KEY, &fingerprint).await?;
+        findings.extend(check_token_age(since, now, config.token_rotation_days));
+    }
+
+    findings.extend(check_backup_age(latest_backup_time(Path::new(BACKUP_DIR)), now, config.backup_max_age_days));
+
+    let mut activity = Vec::with_capacity(config.admin_ids.len());
+    for &admin_id in &config.admin_ids {
+        activity.push((admin_id, database::get_last_admin_activity(db, admin_id).await?));
+    }
+    findings.extend(check_inactive_admins(&activity, now, config.admin_inactive_days));
+
+    findings.extend(check_env_permissions(Path::new(ENV_FILE)));
+
+    Ok(findings)
+}
+
+/// Token 指纹 (SHA-256)，只用于判断 Token 是否更换，不保存明文
+fn token_fingerprint(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// 旧版本使用的 MD5 指纹，仅用于升级时识别
+fn legacy_token_fingerprint(token: &str) -> String {
+    format!("{:x}", Md5::digest(token.as_bytes()))
+}
+
+fn check_token_age(since: DateTime<Utc>, now: DateTime<Utc>, max_days: i64) -> Option<HygieneFinding> {
+    let days = (now - since).num_days();
+    (max_days > 0 && days >= max_days).then(|| HygieneFinding {
+        key: "token_age",
+        label: "Bot Token",
+        message: format!("已 {} 天未更换，建议每 {} 天轮换", days, max_days),
+        level: Level::Warning,
+    })
+}
+
+/// 备份目录中最新文件的修改时间
+fn latest_backup_time(dir: &Path) -> Option<DateTime<Utc>> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .max()
+        .map(DateTime::<Utc>::from)
+}
+
+fn check_backup_age(latest: Option<DateTime<Utc>>, now: DateTime<Utc>, max_days: i64) -> Option<HygieneFinding> {
+    if max_days <= 0 {
+        return None;
+    }
+    let message = match latest {
+        None => "未找到备份文件，请使用 /backup 备份".to_string(),
+        Some(latest) if (now - latest).num_days() >= max_days => {
+            format!("最近一次备份在 {} 天前，超过 {} 天", (now - latest).num_days(), max_days)
+        }
+        Some(_) => return None,
+    };
+    Some(HygieneFinding {
+        key: "backup_age",
+        label: "备份",
+        message,
+        level: Level::Warning,
+    })
+}
+
+/// `activity` 为各管理员的最近活动时间，None 表示没有记录
+fn check_inactive_admins(
+    activity: &[(i64, Option<DateTime<Utc>>)],
+    now: DateTime<Utc>,
+    max_days: i64,
+) -> Option<HygieneFinding> {
+    if max_days <= 0 {
+        return None;
+    }
+    let inactive: Vec<String> = activity
+        .iter()
+        .filter_map(|(admin_id, last)| match last {
+            None => Some(format!("{} (无活动记录)", admin_id)),
+            Some(last) if (now - *last).num_days() >= max_days => {
+                Some(format!("{} ({} 天)", admin_id, (now - *last).num_days()))
+            }
+            Some(_) => None,
+        })
+        .collect();
+
+    (!inactive.is_empty()).then(|| HygieneFinding {
+        key: "inactive_admins",
+        label: "长期未活动的管理员",
+        message: inactive.join(", "),
+        level: Level::Warning,
+    })
+}
+
+#[cfg(unix)]
+fn check_env_permissions(path: &Path) -> Option<HygieneFinding> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path).ok()?.permissions().mode() & 0o777;
+    (mode & 0o004 != 0).then(|| HygieneFinding {
+        key: "env_permissions",
+        label: ".env 权限",
+        message: format!("所有用户可读 ({:o})，建议 chmod 600", mode),
+        level: Level::Error,
+    })
+}
+
+#[cfg(not(unix))]
+fn check_env_permissions(_path: &Path) -> Option<HygieneFinding> {
+    None
 }
 
 /// 发送健康检查报告到Telegram
-async fn send_health_report(config: &Config, report: &str) -> Result<()> {
+async fn send_health_report(config: &Config, telegram: &TelegramConfig, report: &str) -> Result<()> {
     use teloxide::{Bot, prelude::*};
 
-    let bot = Bot::new(&config.bot_token);
-    
-    match bot
-        .send_message(teloxide::types::ChatId(config.chat_id), report)
-        .await
-    {
+    let bot = Bot::new(&telegram.bot_token);
+    let mut request = bot.send_message(teloxide::types::ChatId(telegram.chat_id), config.render(report));
+    if let Some(topic_id) = config.report_topic_id {
+        request = request.message_thread_id(topic_id);
+    }
+
+    match request.await {
         Ok(_) => {
             info!("健康检查报告已发送到 Telegram");
             Ok(())
@@ -193,14 +504,14 @@ async fn check_bot_process() -> String {
 }
 
 /// 分析日志文件中的错误和警告
-async fn analyze_logs() -> Result<(i64, i64)> {
+async fn analyze_logs(now: DateTime<Utc>) -> Result<(i64, i64)> {
     let mut error_count = 0;
     let mut warning_count = 0;
 
     // 分析今天的日志文件
-    let guard_log_name = format!("guard_{}.log", Utc::now().format("%Y%m%d"));
+    let guard_log_name = format!("guard_{}.log", now.format("%Y%m%d"));
     let log_patterns = vec![
-        "bot.log",
+        BOT_LOG_FILE,
         &guard_log_name,
     ];
 
@@ -221,7 +532,7 @@ async fn perform_auto_repair(config: &Config) -> Result<()> {
     // 检查磁盘空间
     if !utils::check_disk_space()? {
         warn!("磁盘空间不足，执行日志清理...");
-        match utils::cleanup_logs().await {
+        match utils::cleanup_logs(config.clock.now_utc()).await {
             Ok(cleaned) => info!("清理了 {} 个日志文件", cleaned),
             Err(e) => error!("日志清理失败: {}", e),
         }
@@ -234,9 +545,11 @@ async fn perform_auto_repair(config: &Config) -> Result<()> {
     }
 
     // 检查Telegram API
-    if !utils::check_telegram_api(&config.bot_token).await {
-        warn!("Telegram API连接异常");
-        // 可以在这里实现重试逻辑
+    if let Some(telegram) = &config.telegram {
+        if !utils::check_telegram_api(&telegram.bot_token).await {
+            warn!("Telegram API连接异常");
+            // 可以在这里实现重试逻辑
+        }
     }
 
     Ok(())
@@ -259,15 +572,78 @@ pub async fn monitor_bot_process(config: &Config) -> Result<()> {
             
             // 这里可以实现重启逻辑
             // restart_bot().await?;
+        } else {
+            resolve_alert(config, "Bot进程异常，正在尝试自动重启").await?;
         }
     }
 }
 
-/// 发送告警消息
-async fn send_alert(config: &Config, message: &str) -> Result<()> {
+/// 发送告警消息；相同告警在 ALERT_DEDUP_WINDOW 内只发送一次，重复的累计次数，窗口结束时发一条汇总
+pub async fn send_alert(config: &Config, message: &str) -> Result<()> {
+    send_keyed_alert(config, message, message).await
+}
+
+/// 按 `key` 去重的告警，`key` 相同即视为同一告警
+pub async fn send_keyed_alert(config: &Config, key: &str, message: &str) -> Result<()> {
+    let window = Duration::from_secs(config.alert_dedup_window);
+    if window.is_zero() {
+        return deliver_alert(config, message).await;
+    }
+    let Some(started) = config.alerts.admit(key, config.clock.instant(), window) else {
+        info!("相同告警仍在冷却窗口内，已累计: {}", message);
+        return Ok(());
+    };
+
+    let (summary_config, summary_key, summary_message) = (config.clone(), key.to_string(), message.to_string());
+    tokio::spawn(async move {
+        time::sleep(window).await;
+        let Some(repeats) = summary_config.alerts.expire(&summary_key, started) else {
+            return;
+        };
+        if repeats == 0 {
+            return;
+        }
+        let summary = format!(
+            "🔁 告警汇总: 以下告警在 {} 分钟内又出现了 {} 次\n\n{}",
+            window.as_secs().div_ceil(60),
+            repeats,
+            summary_message
+        );
+        if let Err(e) = deliver_alert(&summary_config, &summary).await {
+            error!("发送告警汇总失败: {}", e);
+        }
+    });
+
+    let result = deliver_alert(config, message).await;
+    if result.is_err() {
+        // 未送达时不占用窗口，下次重复的告警仍会尝试发送
+        config.alerts.resolve(key);
+    }
+    result
+}
+
+/// 告警对应的问题已恢复：结束冷却窗口并发送恢复通知，附带窗口内的重复次数；没有进行中的告警时不发送
+pub async fn resolve_alert(config: &Config, key: &str) -> Result<()> {
+    let Some(repeats) = config.alerts.resolve(key) else {
+        return Ok(());
+    };
+    let repeated = match repeats {
+        0 => String::new(),
+        n => format!("\n🔁 恢复前又出现了 {} 次", n),
+    };
+    deliver_alert(config, &format!("✅ 已恢复: {}{}", key, repeated)).await
+}
+
+/// 直接发送告警，不经过去重
+async fn deliver_alert(config: &Config, message: &str) -> Result<()> {
     use teloxide::{Bot, prelude::*};
 
-    let bot = Bot::new(&config.bot_token);
+    let Some(telegram) = &config.telegram else {
+        warn!("未配置 BOT_TOKEN/CHAT_ID，告警仅记录在日志中: {}", message);
+        return Ok(());
+    };
+
+    let bot = Bot::new(&telegram.bot_token);
     let alert_message = format!(
         "╔══════════════════════════════════════╗\n\
          ║         🚨 系统告警 🚨         ║\n\
@@ -275,24 +651,26 @@ async fn send_alert(config: &Config, message: &str) -> Result<()> {
          {}\n\n\
          🕒 告警时间: {}", 
         message, 
-        utils::format_datetime_china(&Utc::now())
+        format::fmt_datetime(&config.clock.now_utc(), config.default_lang, config.timezone())
     );
 
-    bot.send_message(teloxide::types::ChatId(config.chat_id), alert_message)
-
-        .await?;
+    let mut request = bot.send_message(teloxide::types::ChatId(telegram.chat_id), config.render(alert_message));
+    if let Some(topic_id) = config.alert_topic_id {
+        request = request.message_thread_id(topic_id);
+    }
+    request.await?;
 
     Ok(())
 }
 
 /// 备份重要数据
-pub async fn backup_data() -> Result<()> {
+pub async fn backup_data(now: DateTime<Utc>) -> Result<()> {
     info!("开始备份重要数据...");
     
-    let backup_dir = "backups";
+    let backup_dir = BACKUP_DIR;
     std::fs::create_dir_all(backup_dir)?;
     
-    let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
+    let timestamp = now.format("%Y%m%d_%H%M%S");
     
     // 备份数据库
     if std::path::Path::new("finalshell_bot.db").exists() {
@@ -309,14 +687,14 @@ pub async fn backup_data() -> Result<()> {
     }
     
     // 清理旧备份 (保留最近7天)
-    cleanup_old_backups(backup_dir, 7).await?;
+    cleanup_old_backups(backup_dir, 7, now).await?;
     
     Ok(())
 }
 
 /// 清理旧备份文件
-async fn cleanup_old_backups(backup_dir: &str, keep_days: u64) -> Result<()> {
-    let cutoff_time = std::time::SystemTime::now() - Duration::from_secs(keep_days * 24 * 3600);
+async fn cleanup_old_backups(backup_dir: &str, keep_days: u64, now: DateTime<Utc>) -> Result<()> {
+    let cutoff_time = std::time::SystemTime::from(now) - Duration::from_secs(keep_days * 24 * 3600);
     
     if let Ok(entries) = std::fs::read_dir(backup_dir) {
         for entry in entries {
@@ -345,13 +723,82 @@ mod tests {
 
     #[tokio::test]
     async fn test_analyze_logs() {
-        let result = analyze_logs().await;
+        let result = analyze_logs(Utc::now()).await;
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_clock_skew() {
+        use chrono::TimeZone;
+        let local = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 42).unwrap();
+
+        assert_eq!(clock_skew("Fri, 15 Aug 2025 12:00:00 GMT", local), Some(42));
+        assert_eq!(clock_skew(" Fri, 15 Aug 2025 12:01:00 GMT ", local), Some(-18));
+        assert_eq!(clock_skew("Fri, 15 Aug 2025 12:00:42 +0000", local), Some(0));
+        assert_eq!(clock_skew("2025-08-15T12:00:00Z", local), None);
+        assert_eq!(clock_skew("", local), None);
+    }
+
+    fn days_ago(now: DateTime<Utc>, days: i64) -> DateTime<Utc> {
+        now - chrono::Duration::days(days)
+    }
+
+    #[test]
+    fn test_check_token_age() {
+        let now = Utc::now();
+        assert_eq!(check_token_age(days_ago(now, 89), now, 90), None);
+        let finding = check_token_age(days_ago(now, 90), now, 90).unwrap();
+        assert_eq!(finding.message, "已 90 天未更换，建议每 90 天轮换");
+        assert_eq!(check_token_age(days_ago(now, 400), now, 0), None);
+        assert_ne!(token_fingerprint("123:abc"), token_fingerprint("123:abd"));
+        assert_eq!(token_fingerprint("123:abc").len(), 64);
+    }
+
+    #[test]
+    fn test_check_backup_age() {
+        let now = Utc::now();
+        assert_eq!(check_backup_age(Some(days_ago(now, 1)), now, 7), None);
+        assert_eq!(check_backup_age(Some(days_ago(now, 8)), now, 7).unwrap().message, "最近一次备份在 8 天前，超过 7 天");
+        assert_eq!(check_backup_age(None, now, 7).unwrap().key, "backup_age");
+        assert_eq!(check_backup_age(None, now, 0), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(latest_backup_time(dir.path()), None);
+        std::fs::write(dir.path().join("env_1.backup"), "x").unwrap();
+        assert!(latest_backup_time(dir.path()).is_some());
+    }
+
+    #[test]
+    fn test_check_inactive_admins() {
+        let now = Utc::now();
+        let activity = [(1, Some(days_ago(now, 3))), (2, Some(days_ago(now, 120))), (3, None)];
+        let finding = check_inactive_admins(&activity, now, 90).unwrap();
+        assert_eq!(finding.message, "2 (120 天), 3 (无活动记录)");
+        assert_eq!(check_inactive_admins(&activity[..1], now, 90), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_env_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        assert_eq!(check_env_permissions(&path), None);
+
+        std::fs::write(&path, "BOT_TOKEN=x").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let finding = check_env_permissions(&path).unwrap();
+        assert_eq!(finding.message, "所有用户可读 (644)，建议 chmod 600");
+        assert_eq!(finding.level, Level::Error);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(check_env_permissions(&path), None);
+    }
+
     #[tokio::test]
     async fn test_backup_data() {
-        let result = backup_data().await;
+        let result = backup_data(Utc::now()).await;
         assert!(result.is_ok());
     }
 }
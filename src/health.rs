@@ -0,0 +1,547 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::{
+    audit::ChainStatus,
+    format,
+    i18n::Lang,
+    models::{ClockSkew, HealthCheck, LatencySummary, UpdateLag},
+    telegram_health::ThrottleSnapshot,
+    utils::{self, SystemInfo},
+};
+
+/// 报告版本，显示在 Telegram 报告概览中
+const REPORT_VERSION: &str = "Guard v2.0";
+
+/// 检查项状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Ok,
+    Warning,
+    Error,
+    Unknown,
+    /// 未配置，不参与整体状态判断
+    Disabled,
+}
+
+impl Level {
+    fn icon(self) -> &'static str {
+        match self {
+            Level::Ok => "✅",
+            Level::Warning => "⚠️",
+            Level::Error => "❌",
+            Level::Unknown => "❓",
+            Level::Disabled => "⚪",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Ok => "OK",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR",
+            Level::Unknown => "UNKNOWN",
+            Level::Disabled => "DISABLED",
+        }
+    }
+}
+
+/// 报告中的一项检查结果；`level` 为 None 的项仅作展示
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportItem {
+    pub key: &'static str,
+    pub label: &'static str,
+    pub value: String,
+    pub level: Option<Level>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportSection {
+    pub key: &'static str,
+    #[serde(skip)]
+    pub icon: &'static str,
+    pub title: &'static str,
+    pub items: Vec<ReportItem>,
+}
+
+/// 当前进程的运行情况
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStatus {
+    pub pid: u32,
+    pub cpu_usage: f64,
+    pub uptime: Option<String>,
+}
+
+/// 结构化的健康检查报告，由各渲染函数输出为 Telegram 文本、JSON 或单行日志
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub generated_at: DateTime<Utc>,
+    /// 整体状态，只取 Ok 或 Warning
+    pub overall: Level,
+    pub sections: Vec<ReportSection>,
+}
+
+/// 各检查项由 OK 变为 WARNING 的阈值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// 待发送队列积压持续的时长
+    pub throttle_warn_after: Duration,
+    /// inode 使用率 (%)
+    pub inode_usage: f64,
+    /// 与 Telegram 服务器的时钟偏差（秒）
+    pub clock_skew_secs: i64,
+    /// 更新处理延迟的 p95
+    pub update_lag: Duration,
+}
+
+/// 运维卫生检查发现的问题，如 Token 长期未轮换、备份过旧
+#[derive(Debug, Clone, PartialEq)]
+pub struct HygieneFinding {
+    pub key: &'static str,
+    pub label: &'static str,
+    pub message: String,
+    pub level: Level,
+}
+
+fn item(key: &'static str, label: &'static str, value: impl Into<String>, level: Option<Level>) -> ReportItem {
+    ReportItem { key, label, value: value.into(), level }
+}
+
+fn describe_latency(latency: Option<&LatencySummary>) -> String {
+    match latency {
+        Some(latency) => format!(
+            "平均 {}, 最大 {}, 共 {} 次",
+            format::fmt_latency(Duration::from_micros(latency.avg_us.max(0) as u64)),
+            format::fmt_latency(Duration::from_micros(latency.max_us.max(0) as u64)),
+            latency.count
+        ),
+        None => "无记录".to_string(),
+    }
+}
+
+fn describe_update_lag(lag: Option<&UpdateLag>) -> String {
+    match lag {
+        Some(lag) => format!(
+            "p50 {}, p95 {}, 共 {} 次",
+            format::fmt_latency(Duration::from_millis(lag.p50_ms.max(0) as u64)),
+            format::fmt_latency(Duration::from_millis(lag.p95_ms.max(0) as u64)),
+            lag.count
+        ),
+        None => "无记录".to_string(),
+    }
+}
+
+fn describe_clock_skew(skew: &ClockSkew) -> String {
+    match skew.previous {
+        Some(previous) => format!("{:+}s (上次 {:+}s)", skew.seconds, previous),
+        None => format!("{:+}s", skew.seconds),
+    }
+}
+
+fn threshold_level(usage: f64, limit: f64) -> Level {
+    if usage < limit {
+        Level::Ok
+    } else {
+        Level::Warning
+    }
+}
+
+impl HealthReport {
+    /// 根据采集到的数据评估各项状态并组装报告
+    pub fn build(
+        health: &HealthCheck,
+        system_info: &SystemInfo,
+        process: &ProcessStatus,
+        throttle: Option<&ThrottleSnapshot>,
+        thresholds: &Thresholds,
+    ) -> Self {
+        let bot_level = match health.bot_status.as_str() {
+            "running" => Level::Ok,
+            "stopped" => Level::Error,
+            _ => Level::Unknown,
+        };
+        let cpu_level = threshold_level(health.cpu_usage, 80.0);
+        let memory_level = threshold_level(health.memory_usage, 80.0);
+        let disk_level = threshold_level(health.disk_usage, 90.0);
+        let inode_level = health.inode_usage.map(|usage| threshold_level(usage, thresholds.inode_usage));
+        let skew_level = health.clock_skew.map(|skew| {
+            if skew.seconds.abs() > thresholds.clock_skew_secs { Level::Warning } else { Level::Ok }
+        });
+        let lag_level = health.update_lag.map(|lag| {
+            if Duration::from_millis(lag.p95_ms.max(0) as u64) > thresholds.update_lag { Level::Warning } else { Level::Ok }
+        });
+        let internet_level = if health.internet_connectivity { Level::Ok } else { Level::Error };
+        let (telegram_value, telegram_level) = match health.telegram_api_status {
+            Some(true) => ("正常", Level::Ok),
+            Some(false) => ("异常", Level::Error),
+            None => ("未配置", Level::Disabled),
+        };
+
+        let mut network = vec![
+            item("internet", "互联网连接", if health.internet_connectivity { "正常" } else { "异常" }, Some(internet_level)),
+            item("telegram_api", "Telegram API", telegram_value, Some(telegram_level)),
+        ];
+        // 未能取得 Telegram 服务器时间时不显示该项
+        if let Some(skew) = &health.clock_skew {
+            network.push(item("clock_skew", "时钟偏差", describe_clock_skew(skew), skew_level));
+        }
+        let mut throttle_degraded = false;
+        if let Some(throttle) = throttle {
+            throttle_degraded = throttle.is_degraded(thresholds.throttle_warn_after);
+            network.push(item(
+                "throttle",
+                "当前限流",
+                throttle.describe(),
+                Some(if throttle_degraded { Level::Warning } else { Level::Ok }),
+            ));
+        }
+
+        let healthy = [cpu_level, memory_level, disk_level, internet_level].iter().all(|l| *l == Level::Ok)
+            && inode_level != Some(Level::Warning)
+            && skew_level != Some(Level::Warning)
+            && lag_level != Some(Level::Warning)
+            && telegram_level != Level::Error
+            && !throttle_degraded;
+
+        let mut resources = vec![
+            item("cpu", "CPU", format!("{:.1}%", health.cpu_usage), Some(cpu_level)),
+            item("memory", "内存", format!("{:.1}%", health.memory_usage), Some(memory_level)),
+            item("disk", "磁盘", format!("{:.1}%", health.disk_usage), Some(disk_level)),
+        ];
+        // Windows 或不报告 inode 的文件系统不显示该项
+        if let Some(usage) = health.inode_usage {
+            resources.push(item("inodes", "Inode", format!("{:.1}%", usage), inode_level));
+        }
+
+        let sections = vec![
+            ReportSection {
+                key: "process",
+                icon: "🤖",
+                title: "机器人进程状态",
+                items: vec![
+                    item("bot_status", "运行状态", format!("{} (PID: {})", health.bot_status, process.pid), Some(bot_level)),
+                    item("process_cpu", "CPU使用率", format!("{:.1}%", process.cpu_usage), None),
+                    item("process_memory", "内存使用", utils::format_file_size(system_info.used_memory), None),
+                    item("uptime", "运行时长", process.uptime.clone().unwrap_or_else(|| "未知".to_string()), None),
+                    item("latency", "生成耗时 (24h)", describe_latency(health.latency.as_ref()), None),
+                    item("update_lag", "更新处理延迟 (24h)", describe_update_lag(health.update_lag.as_ref()), lag_level),
+                ],
+            },
+            ReportSection {
+                key: "resources",
+                icon: "💻",
+                title: "系统资源监控",
+                items: resources,
+            },
+            ReportSection {
+                key: "logs",
+                icon: "📋",
+                title: "日志文件分析",
+                items: vec![
+                    item(
+                        "errors",
+                        "错误数量",
+                        health.error_count.to_string(),
+                        Some(if health.error_count == 0 { Level::Ok } else { Level::Warning }),
+                    ),
+                    item(
+                        "warnings",
+                        "警告数量",
+                        health.warning_count.to_string(),
+                        Some(if health.warning_count < 5 { Level::Ok } else { Level::Warning }),
+                    ),
+                ],
+            },
+            ReportSection {
+                key: "network",
+                icon: "🌐",
+                title: "网络连接检查",
+                items: network,
+            },
+        ];
+
+        HealthReport {
+            generated_at: health.timestamp,
+            overall: if healthy { Level::Ok } else { Level::Warning },
+            sections,
+        }
+    }
+
+    /// 追加运维卫生检查结果；没有发现问题时不添加该部分。
+    /// 这些只是提醒，不影响整体状态
+    pub fn add_hygiene(&mut self, findings: Vec<HygieneFinding>) {
+        if findings.is_empty() {
+            return;
+        }
+        self.sections.push(ReportSection {
+            key: "hygiene",
+            icon: "🧹",
+            title: "运维卫生检查",
+            items: findings
+                .into_iter()
+                .map(|finding| item(finding.key, finding.label, finding.message, Some(finding.level)))
+                .collect(),
+        });
+    }
+
+    /// 追加审计日志哈希链的校验结果；链断开说明记录被篡改，整体状态为 WARNING
+    pub fn add_audit_chain(&mut self, status: &ChainStatus) {
+        let level = if status.is_intact() { Level::Ok } else { Level::Error };
+        if level == Level::Error {
+            self.overall = Level::Warning;
+        }
+        self.sections.push(ReportSection {
+            key: "audit",
+            icon: "🔏",
+            title: "审计日志",
+            items: vec![item("audit_chain", "哈希链", status.describe(), Some(level))],
+        });
+    }
+
+    fn overall_name(&self) -> &'static str {
+        if self.overall == Level::Ok {
+            "NORMAL"
+        } else {
+            "WARNING"
+        }
+    }
+
+    /// Telegram 消息格式
+    pub fn render_telegram(&self, lang: Lang, tz: FixedOffset) -> String {
+        let mut output = format!(
+            "🛡️ FinalShell机器人 系统自检报告\n\n\
+             📊 报告概览\n\
+             📅 检查日期: {}\n\
+             ⏰ 检查时间: {}\n\
+             🎯 整体状态: {} {}\n\
+             🔄 报告版本: {}\n\n\
+             🔍 详细检查结果\n\n",
+            format::fmt_date(&self.generated_at, lang, tz),
+            format::fmt_datetime(&self.generated_at, lang, tz),
+            self.overall.icon(),
+            self.overall_name(),
+            REPORT_VERSION
+        );
+
+        for section in &self.sections {
+            output.push_str(&format!("{} {}\n", section.icon, section.title));
+            for item in &section.items {
+                match item.level {
+                    Some(level) => output.push_str(&format!("• {}: {} {}\n", item.label, item.value, level.icon())),
+                    None => output.push_str(&format!("• {}: {}\n", item.label, item.value)),
+                }
+            }
+            output.push('\n');
+        }
+
+        output.push_str(&format!("报告生成时间: {}", format::fmt_datetime(&self.generated_at, lang, tz)));
+        output
+    }
+
+    /// JSON 格式，供命令行与外部系统使用
+    pub fn render_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// 单行日志格式；只标注状态不是 OK 的项
+    pub fn render_log_line(&self) -> String {
+        let items: Vec<String> = self
+            .sections
+            .iter()
+            .flat_map(|section| &section.items)
+            .map(|item| match item.level {
+                Some(level) if level != Level::Ok => format!("{}={}({})", item.key, item.value, level.name()),
+                _ => format!("{}={}", item.key, item.value),
+            })
+            .collect();
+        format!("[{}] {}", self.overall_name(), items.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample_report(disk_usage: f64, inode_usage: Option<f64>, telegram_api_status: Option<bool>) -> HealthReport {
+        build_sample(&sample_health(disk_usage, inode_usage, telegram_api_status))
+    }
+
+    fn sample_health(disk_usage: f64, inode_usage: Option<f64>, telegram_api_status: Option<bool>) -> HealthCheck {
+        HealthCheck {
+            timestamp: Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap(),
+            bot_status: "running".to_string(),
+            guard_status: "running".to_string(),
+            cpu_usage: 12.5,
+            memory_usage: 40.0,
+            disk_usage,
+            inode_usage,
+            latency: Some(LatencySummary { count: 128, avg_us: 1_800, max_us: 23_000 }),
+            update_lag: Some(UpdateLag { count: 130, p50_ms: 350, p95_ms: 1_800 }),
+            clock_skew: None,
+            internet_connectivity: true,
+            telegram_api_status,
+            error_count: 0,
+            warning_count: 7,
+        }
+    }
+
+    fn build_sample(health: &HealthCheck) -> HealthReport {
+        let system_info = SystemInfo {
+            cpu_usage: 12.5,
+            memory_usage: 40.0,
+            disk_usage: health.disk_usage,
+            total_memory: 4 * 1024 * 1024 * 1024,
+            used_memory: 2 * 1024 * 1024 * 1024,
+        };
+        let process = ProcessStatus {
+            pid: 4242,
+            cpu_usage: 1.0,
+            uptime: Some("2 小时 5 分钟".to_string()),
+        };
+        let thresholds = Thresholds {
+            throttle_warn_after: Duration::from_secs(180),
+            inode_usage: 90.0,
+            clock_skew_secs: 30,
+            update_lag: Duration::from_secs(5),
+        };
+        HealthReport::build(health, &system_info, &process, None, &thresholds)
+    }
+
+    #[test]
+    fn test_render_telegram() {
+        let report = sample_report(50.0, Some(12.0), Some(true));
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+
+        assert_eq!(
+            report.render_telegram(Lang::Zh, tz),
+            "🛡️ FinalShell机器人 系统自检报告\n\n\
+             📊 报告概览\n\
+             📅 检查日期: 2025-08-15\n\
+             ⏰ 检查时间: 2025-08-15 20:00:00 (UTC+08:00)\n\
+             🎯 整体状态: ✅ NORMAL\n\
+             🔄 报告版本: Guard v2.0\n\n\
+             🔍 详细检查结果\n\n\
+             🤖 机器人进程状态\n\
+             • 运行状态: running (PID: 4242) ✅\n\
+             • CPU使用率: 1.0%\n\
+             • 内存使用: 2.0 GB\n\
+             • 运行时长: 2 小时 5 分钟\n\
+             • 生成耗时 (24h): 平均 1ms, 最大 23ms, 共 128 次\n\
+             • 更新处理延迟 (24h): p50 350ms, p95 1.80s, 共 130 次 ✅\n\n\
+             💻 系统资源监控\n\
+             • CPU: 12.5% ✅\n\
+             • 内存: 40.0% ✅\n\
+             • 磁盘: 50.0% ✅\n\
+             • Inode: 12.0% ✅\n\n\
+             📋 日志文件分析\n\
+             • 错误数量: 0 ✅\n\
+             • 警告数量: 7 ⚠️\n\n\
+             🌐 网络连接检查\n\
+             • 互联网连接: 正常 ✅\n\
+             • Telegram API: 正常 ✅\n\n\
+             报告生成时间: 2025-08-15 20:00:00 (UTC+08:00)"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let report = sample_report(95.0, None, None);
+        let json: serde_json::Value = serde_json::from_str(&report.render_json().unwrap()).unwrap();
+
+        assert_eq!(json["generated_at"], "2025-08-15T12:00:00Z");
+        assert_eq!(json["overall"], "warning");
+        assert_eq!(
+            json["sections"][1],
+            serde_json::json!({
+                "key": "resources",
+                "title": "系统资源监控",
+                "items": [
+                    {"key": "cpu", "label": "CPU", "value": "12.5%", "level": "ok"},
+                    {"key": "memory", "label": "内存", "value": "40.0%", "level": "ok"},
+                    {"key": "disk", "label": "磁盘", "value": "95.0%", "level": "warning"},
+                ]
+            })
+        );
+        assert_eq!(json["sections"][3]["items"][1]["level"], "disabled");
+    }
+
+    #[test]
+    fn test_broken_audit_chain_warns() {
+        let mut report = sample_report(50.0, Some(12.0), Some(true));
+        report.add_audit_chain(&ChainStatus::Intact { entries: 0, head: None });
+        assert_eq!(report.overall, Level::Ok);
+        assert!(report.render_log_line().ends_with("audit_chain=完整，暂无记录"));
+
+        report.add_audit_chain(&ChainStatus::Broken { entry_id: 7, verified: 5 });
+        assert_eq!(report.overall, Level::Warning);
+        assert!(report.render_log_line().contains("audit_chain=在记录 #7 处断开"));
+    }
+
+    #[test]
+    fn test_hygiene_section_only_when_findings() {
+        let mut report = sample_report(50.0, None, Some(true));
+        report.add_hygiene(Vec::new());
+        assert_eq!(report.sections.len(), 4);
+
+        report.add_hygiene(vec![HygieneFinding {
+            key: "env_permissions",
+            label: ".env 权限",
+            message: "所有用户可读 (644)".to_string(),
+            level: Level::Error,
+        }]);
+        assert_eq!(report.sections[4].key, "hygiene");
+        assert_eq!(report.overall, Level::Ok);
+        assert!(report.render_log_line().ends_with("env_permissions=所有用户可读 (644)(ERROR)"));
+    }
+
+    #[test]
+    fn test_inode_usage_affects_overall() {
+        assert_eq!(sample_report(50.0, Some(89.9), Some(true)).overall, Level::Ok);
+        assert_eq!(sample_report(50.0, Some(90.0), Some(true)).overall, Level::Warning);
+        assert_eq!(sample_report(50.0, None, Some(true)).overall, Level::Ok);
+    }
+
+    #[test]
+    fn test_clock_skew_affects_overall() {
+        let mut health = sample_health(50.0, None, Some(true));
+
+        health.clock_skew = Some(ClockSkew { seconds: -30, previous: None });
+        let report = build_sample(&health);
+        assert_eq!(report.overall, Level::Ok);
+        assert!(report.render_log_line().ends_with("telegram_api=正常 clock_skew=-30s"));
+
+        health.clock_skew = Some(ClockSkew { seconds: 45, previous: Some(12) });
+        let report = build_sample(&health);
+        assert_eq!(report.overall, Level::Warning);
+        assert!(report.render_log_line().ends_with("clock_skew=+45s (上次 +12s)(WARNING)"));
+    }
+
+    #[test]
+    fn test_sustained_update_lag_warns() {
+        let mut health = sample_health(50.0, Some(12.0), Some(true));
+        assert_eq!(build_sample(&health).overall, Level::Ok);
+
+        health.update_lag = Some(UpdateLag { count: 130, p50_ms: 2_000, p95_ms: 6_500 });
+        let report = build_sample(&health);
+        assert_eq!(report.overall, Level::Warning);
+        assert!(report.render_log_line().contains("update_lag=p50 2.00s, p95 6.50s, 共 130 次(WARNING)"));
+
+        health.update_lag = None;
+        assert!(build_sample(&health).render_log_line().contains("update_lag=无记录 "));
+    }
+
+    #[test]
+    fn test_render_log_line() {
+        let report = sample_report(95.0, Some(93.0), Some(false));
+
+        assert_eq!(
+            report.render_log_line(),
+            "[WARNING] bot_status=running (PID: 4242) process_cpu=1.0% process_memory=2.0 GB \
+             uptime=2 小时 5 分钟 latency=平均 1ms, 最大 23ms, 共 128 次 update_lag=p50 350ms, p95 1.80s, 共 130 次 cpu=12.5% memory=40.0% disk=95.0%(WARNING) inodes=93.0%(WARNING) errors=0 \
+             warnings=7(WARNING) internet=正常 telegram_api=异常(ERROR)"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::Result;
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::config::Config;
+use crate::finalshell::{self, ActivationResult, RedactionPolicy};
+
+/// 一次成功生成后传给扩展钩子的信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationContext {
+    pub user_id: i64,
+    /// 请求的关联 ID，与日志中的 trace_id 一致
+    pub correlation_id: String,
+    /// 请求来源：message（聊天消息）、batch（批量文件）或 http（HTTP 接口）
+    pub source: &'static str,
+    /// 生成的版本，ASCII 版本名，顺序与回复一致
+    pub versions: Vec<&'static str>,
+    /// 按 MACHINE_CODE_DISPLAY 脱敏后的机器码
+    pub machine_code: String,
+    /// 生成耗时，不含与 Telegram 的往返
+    pub latency: Duration,
+}
+
+impl GenerationContext {
+    pub fn new(
+        user_id: i64,
+        correlation_id: &str,
+        source: &'static str,
+        machine_code: &str,
+        display: RedactionPolicy,
+        results: &[ActivationResult],
+        latency: Duration,
+    ) -> Self {
+        GenerationContext {
+            user_id,
+            correlation_id: correlation_id.to_string(),
+            source,
+            versions: results.iter().map(|r| r.version_type.version_name_ascii()).collect(),
+            machine_code: finalshell::redact(machine_code, display),
+            latency,
+        }
+    }
+}
+
+/// 生成成功后的扩展点，供下游在不修改处理流程的情况下追加动作（推送 webhook、发放积分等）。
+/// 钩子在回复发出后执行，返回错误或 panic 只记录日志，不影响生成结果与其他钩子
+pub trait GenerationHook: Send + Sync {
+    /// 钩子名称，用于日志
+    fn name(&self) -> &'static str;
+
+    fn on_generated<'a>(&'a self, ctx: &'a GenerationContext) -> BoxFuture<'a, Result<()>>;
+}
+
+/// 已注册的钩子，克隆后共享同一份列表
+#[derive(Clone, Default)]
+pub struct HookRegistry {
+    hooks: Vec<Arc<dyn GenerationHook>>,
+}
+
+impl HookRegistry {
+    /// 按配置注册编译进来的钩子；下游新增钩子时在此按 cargo feature 注册
+    #[cfg_attr(not(feature = "webhook-hook"), allow(unused_variables, unused_mut))]
+    pub fn from_config(config: &Config) -> Self {
+        let mut registry = HookRegistry::default();
+        #[cfg(feature = "webhook-hook")]
+        if let Some(url) = &config.generation_webhook_url {
+            registry.register(Arc::new(WebhookHook::new(url.clone())));
+        }
+        registry
+    }
+
+    pub fn register(&mut self, hook: Arc<dyn GenerationHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// 并发执行全部钩子，逐个隔离错误与 panic
+    pub async fn run(&self, ctx: &GenerationContext) {
+        join_all(self.hooks.iter().map(|hook| async move {
+            match AssertUnwindSafe(hook.on_generated(ctx)).catch_unwind().await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("生成钩子 {} 执行失败: {:#}", hook.name(), e),
+                Err(_) => error!("生成钩子 {} 发生 panic，已忽略", hook.name()),
+            }
+        }))
+        .await;
+    }
+
+    /// 在后台执行钩子，不阻塞当前请求
+    pub fn spawn(&self, ctx: GenerationContext) {
+        if self.is_empty() {
+            return;
+        }
+        let registry = self.clone();
+        tokio::spawn(async move { registry.run(&ctx).await });
+    }
+}
+
+/// 参考实现：将每次生成以 JSON POST 到 GENERATION_WEBHOOK_URL
+#[cfg(feature = "webhook-hook")]
+pub struct WebhookHook {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "webhook-hook")]
+impl WebhookHook {
+    /// webhook 请求超时
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(url: String) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Self::TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        WebhookHook { url, client }
+    }
+
+    /// 请求体，字段固定，供接收方解析
+    pub fn payload(ctx: &GenerationContext) -> serde_json::Value {
+        serde_json::json!({
+            "event": "generated",
+            "user_id": ctx.user_id,
+            "correlation_id": ctx.correlation_id,
+            "source": ctx.source,
+            "versions": ctx.versions,
+            "machine_code": ctx.machine_code,
+            "latency_ms": ctx.latency.as_millis() as u64,
+        })
+    }
+}
+
+#[cfg(feature = "webhook-hook")]
+impl GenerationHook for WebhookHook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn on_generated<'a>(&'a self, ctx: &'a GenerationContext) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .post(&self.url)
+                .json(&Self::payload(ctx))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn context() -> GenerationContext {
+        GenerationContext {
+            user_id: 42,
+            correlation_id: "7KQ2M3ZD".to_string(),
+            source: "message",
+            versions: vec!["4.6+", "4.5"],
+            machine_code: "ABC1…".to_string(),
+            latency: Duration::from_micros(12_345),
+        }
+    }
+
+    struct Counting(AtomicUsize);
+
+    impl GenerationHook for Counting {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn on_generated<'a>(&'a self, _ctx: &'a GenerationContext) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    struct Failing;
+
+    impl GenerationHook for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn on_generated<'a>(&'a self, ctx: &'a GenerationContext) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                if ctx.user_id == 42 {
+                    panic!("bad hook");
+                }
+                anyhow::bail!("unreachable")
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_does_not_affect_others() {
+        let counting = Arc::new(Counting(AtomicUsize::new(0)));
+        let mut registry = HookRegistry::default();
+        registry.register(Arc::new(Failing));
+        registry.register(counting.clone());
+
+        registry.run(&context()).await;
+        registry.run(&GenerationContext { user_id: 7, ..context() }).await;
+        assert_eq!(counting.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "webhook-hook")]
+    #[test]
+    fn test_webhook_payload() {
+        assert_eq!(
+            WebhookHook::payload(&context()),
+            serde_json::json!({
+                "event": "generated",
+                "user_id": 42,
+                "correlation_id": "7KQ2M3ZD",
+                "source": "message",
+                "versions": ["4.6+", "4.5"],
+                "machine_code": "ABC1…",
+                "latency_ms": 12,
+            })
+        );
+    }
+}
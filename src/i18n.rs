@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+use crate::quota::{QuotaMode, QuotaPeriod};
+
+/// 支持的界面语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Lang {
+    #[default]
+    Zh,
+    En,
+}
+
+impl Lang {
+    /// 从语言代码解析（如 "zh"、"en"），不区分大小写
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim().to_ascii_lowercase().as_str() {
+            "zh" => Some(Lang::Zh),
+            "en" => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    /// 存入数据库的语言代码
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Zh => "zh",
+            Lang::En => "en",
+        }
+    }
+
+    /// 将 Telegram 客户端的 IETF 语言标签（如 zh-Hans、en-GB）映射为支持的语言
+    pub fn from_locale(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next().unwrap_or_default();
+        Self::from_code(primary)
+    }
+
+    /// 新用户的初始语言：客户端语言受支持时使用它，否则使用配置的默认语言
+    pub fn detect(tag: Option<&str>, default: Lang) -> Lang {
+        tag.and_then(Self::from_locale).unwrap_or(default)
+    }
+}
+
+/// /start 附带的快捷菜单按钮，按下后以按钮文字作为消息发来
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    Generate,
+    Usage,
+    Help,
+}
+
+impl MenuAction {
+    pub const ALL: [MenuAction; 3] = [MenuAction::Generate, MenuAction::Usage, MenuAction::Help];
+
+    /// 按钮文字
+    pub fn label(self, lang: Lang) -> &'static str {
+        match (self, lang) {
+            (MenuAction::Generate, Lang::Zh) => "📝 生成激活码",
+            (MenuAction::Generate, Lang::En) => "📝 Generate code",
+            (MenuAction::Usage, Lang::Zh) => "📊 我的用量",
+            (MenuAction::Usage, Lang::En) => "📊 My usage",
+            (MenuAction::Help, Lang::Zh) => "❓ 帮助",
+            (MenuAction::Help, Lang::En) => "❓ Help",
+        }
+    }
+
+    /// 识别按钮文字；用户切换语言或 USE_EMOJI 设置变化后旧键盘上的按钮仍然有效
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        Self::ALL.into_iter().find(|action| {
+            [Lang::Zh, Lang::En].into_iter().any(|lang| {
+                let label = action.label(lang);
+                label == text || crate::utils::strip_decorations(label) == text
+            })
+        })
+    }
+}
+
+/// 按下「生成激活码」后的提示
+pub fn machine_code_prompt(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Zh => "💬 请发送您的机器码",
+        Lang::En => "💬 Please send your machine code",
+    }
+}
+
+/// /hidemenu 隐藏快捷菜单后的回复
+pub fn menu_hidden(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Zh => "✅ 快捷菜单已隐藏，发送 /start 可重新显示。",
+        Lang::En => "✅ Quick menu hidden. Send /start to show it again.",
+    }
+}
+
+/// 「我的用量」中展示的数据，时间已按用户的语言与时区格式化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub used: i32,
+    pub limit: i32,
+    pub period: QuotaPeriod,
+    pub mode: QuotaMode,
+    /// 下一次重置时间，累计配额为 None
+    pub reset: Option<String>,
+    /// 试用期剩余次数与结束时间
+    pub trial: Option<(i64, String)>,
+}
+
+/// 管理员与白名单用户的「我的用量」
+pub fn unlimited_usage(lang: Lang, admin: bool) -> String {
+    match (lang, admin) {
+        (Lang::Zh, true) => "📊 我的用量\n┗━ ♾️ 无限制 (管理员)".to_string(),
+        (Lang::Zh, false) => "📊 我的用量\n┗━ ♾️ 无限制 (白名单)".to_string(),
+        (Lang::En, true) => "📊 My usage\n┗━ ♾️ Unlimited (admin)".to_string(),
+        (Lang::En, false) => "📊 My usage\n┗━ ♾️ Unlimited (whitelisted)".to_string(),
+    }
+}
+
+/// 「我的用量」：本周期已用次数、额度、重置时间与试用期剩余次数
+pub fn usage_summary(lang: Lang, usage: &Usage) -> String {
+    let remaining = (usage.limit - usage.used).max(0);
+    let mut text = match lang {
+        Lang::Zh => format!(
+            "📊 我的用量\n┣━ 已用: {} 次\n┣━ 额度: {}\n",
+            usage.used,
+            usage.period.describe(usage.limit, usage.mode)
+        ),
+        Lang::En => {
+            let unit = match usage.mode {
+                QuotaMode::Requests => "requests",
+                QuotaMode::Machines => "machine codes",
+            };
+            let period = match usage.period {
+                QuotaPeriod::Daily => "per day",
+                QuotaPeriod::Monthly => "per month",
+                QuotaPeriod::Lifetime => "in total",
+            };
+            format!("📊 My usage\n┣━ Used: {}\n┣━ Quota: {} {} {}\n", usage.used, usage.limit, unit, period)
+        }
+    };
+    text.push_str(&match (lang, &usage.reset) {
+        (Lang::Zh, Some(reset)) => format!("┗━ 剩余: {} 次 ({} 重置)", remaining, reset),
+        (Lang::Zh, None) => format!("┗━ 剩余: {} 次", remaining),
+        (Lang::En, Some(reset)) => format!("┗━ Remaining: {} (resets {})", remaining, reset),
+        (Lang::En, None) => format!("┗━ Remaining: {}", remaining),
+    });
+    if let Some((left, ends_at)) = &usage.trial {
+        text.push_str(&match lang {
+            Lang::Zh => format!("\n⏳ 新用户试用期: 24 小时内剩余 {} 次，{} 后恢复正常额度", left.max(&0), ends_at),
+            Lang::En => format!("\n⏳ New user trial: {} left in the last 24 hours, normal quota from {}", left.max(&0), ends_at),
+        });
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_from_locale() {
+        assert_eq!(Lang::detect(Some("zh-Hans"), Lang::En), Lang::Zh);
+        assert_eq!(Lang::detect(Some("zh-TW"), Lang::En), Lang::Zh);
+        assert_eq!(Lang::detect(Some("en-GB"), Lang::Zh), Lang::En);
+        assert_eq!(Lang::detect(Some("ru"), Lang::En), Lang::En);
+        assert_eq!(Lang::detect(Some(""), Lang::Zh), Lang::Zh);
+        assert_eq!(Lang::detect(None, Lang::En), Lang::En);
+    }
+
+    #[test]
+    fn test_menu_action_parse() {
+        for action in MenuAction::ALL {
+            assert_eq!(MenuAction::parse(action.label(Lang::Zh)), Some(action));
+            assert_eq!(MenuAction::parse(&format!(" {} ", action.label(Lang::En))), Some(action));
+        }
+        // USE_EMOJI=false 时按钮不带表情
+        assert_eq!(MenuAction::parse("帮助"), Some(MenuAction::Help));
+        assert_eq!(MenuAction::parse("My usage"), Some(MenuAction::Usage));
+        assert_eq!(MenuAction::parse("帮助一下"), None);
+        assert_eq!(MenuAction::parse("ABC123@DEF456"), None);
+    }
+
+    #[test]
+    fn test_usage_summary() {
+        let mut usage = Usage {
+            used: 5,
+            limit: 3,
+            period: QuotaPeriod::Daily,
+            mode: QuotaMode::Requests,
+            reset: Some("2026-01-02 00:00:00 (UTC+8)".to_string()),
+            trial: None,
+        };
+        assert_eq!(
+            usage_summary(Lang::Zh, &usage),
+            "📊 我的用量\n┣━ 已用: 5 次\n┣━ 额度: 每日 3 次\n┗━ 剩余: 0 次 (2026-01-02 00:00:00 (UTC+8) 重置)"
+        );
+
+        usage.period = QuotaPeriod::Lifetime;
+        usage.mode = QuotaMode::Machines;
+        usage.used = 1;
+        usage.reset = None;
+        usage.trial = Some((-1, "Jan 02, 2026 00:00:00 (UTC+8)".to_string()));
+        let text = usage_summary(Lang::En, &usage);
+        assert_eq!(
+            text,
+            "📊 My usage\n┣━ Used: 1\n┣━ Quota: 3 machine codes in total\n┗━ Remaining: 2\n\
+             ⏳ New user trial: 0 left in the last 24 hours, normal quota from Jan 02, 2026 00:00:00 (UTC+8)"
+        );
+        assert!(!text.chars().any(|c| ('\u{4e00}'..='\u{9fff}').contains(&c)));
+        assert!(unlimited_usage(Lang::En, true).contains("Unlimited (admin)"));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 幂等键查询结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<T> {
+    /// 新键，已为本次请求占位；请求结束后需调用 `complete` 或 `abort`（或用 `guard` 自动释放）
+    Reserved,
+    /// TTL 内的重复请求，直接返回首次的结果
+    Cached(T),
+    /// 相同的键已用于另一个机器码
+    Conflict,
+    /// 相同的键对应的请求仍在处理中
+    InProgress,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    machine_code: String,
+    expires_at: Instant,
+    /// None 表示首次请求尚未完成
+    value: Option<T>,
+}
+
+/// 生成请求的幂等键缓存，键按用户隔离并与机器码绑定，仅保存在内存中
+#[derive(Debug)]
+pub struct IdempotencyCache<T> {
+    ttl: Duration,
+    entries: Mutex<HashMap<(i64, String), Entry<T>>>,
+}
+
+impl<T: Clone> IdempotencyCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(i64, String), Entry<T>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 查询幂等键；未命中时为本次请求占位
+    pub fn begin(&self, user_id: i64, key: &str, machine_code: &str) -> Lookup<T> {
+        self.begin_at(user_id, key, machine_code, Instant::now())
+    }
+
+    fn begin_at(&self, user_id: i64, key: &str, machine_code: &str, now: Instant) -> Lookup<T> {
+        let mut entries = self.lock();
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        let id = (user_id, key.to_string());
+        if let Some(entry) = entries.get(&id) {
+            return if entry.machine_code != machine_code {
+                Lookup::Conflict
+            } else {
+                match &entry.value {
+                    Some(value) => Lookup::Cached(value.clone()),
+                    None => Lookup::InProgress,
+                }
+            };
+        }
+
+        entries.insert(
+            id,
+            Entry {
+                machine_code: machine_code.to_string(),
+                expires_at: now + self.ttl,
+                value: None,
+            },
+        );
+        Lookup::Reserved
+    }
+
+    /// 保存首次请求的结果，TTL 从此时起算
+    pub fn complete(&self, user_id: i64, key: &str, value: T) {
+        self.complete_at(user_id, key, value, Instant::now());
+    }
+
+    fn complete_at(&self, user_id: i64, key: &str, value: T, now: Instant) {
+        if let Some(entry) = self.lock().get_mut(&(user_id, key.to_string())) {
+            entry.expires_at = now + self.ttl;
+            entry.value = Some(value);
+        }
+    }
+
+    /// 首次请求失败时释放占位，允许用同一个键重试
+    pub fn abort(&self, user_id: i64, key: &str) {
+        self.lock().remove(&(user_id, key.to_string()));
+    }
+
+    /// 为 `begin` 返回 `Reserved` 的占位创建守卫：未调用 `Pending::complete` 就被丢弃时
+    /// （请求失败，或客户端断开导致处理被取消）自动释放占位，不必等到 TTL 过期
+    pub fn guard(&self, user_id: i64, key: &str) -> Pending<'_, T> {
+        Pending {
+            cache: self,
+            user_id,
+            key: key.to_string(),
+            completed: false,
+        }
+    }
+}
+
+/// 进行中的幂等请求，丢弃前未完成时释放占位
+#[derive(Debug)]
+pub struct Pending<'a, T: Clone> {
+    cache: &'a IdempotencyCache<T>,
+    user_id: i64,
+    key: String,
+    completed: bool,
+}
+
+impl<T: Clone> Pending<'_, T> {
+    /// 保存首次请求的结果
+    pub fn complete(mut self, value: T) {
+        self.cache.complete(self.user_id, &self.key, value);
+        self.completed = true;
+    }
+}
+
+impl<T: Clone> Drop for Pending<'_, T> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.abort(self.user_id, &self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeat_returns_cached_result_until_ttl() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(cache.begin_at(1, "k1", "ABC123DEF456", start), Lookup::Reserved);
+        assert_eq!(cache.begin_at(1, "k1", "ABC123DEF456", start), Lookup::InProgress);
+
+        cache.complete_at(1, "k1", 7, start);
+        assert_eq!(cache.begin_at(1, "k1", "ABC123DEF456", start + Duration::from_secs(59)), Lookup::Cached(7));
+        assert_eq!(cache.begin_at(1, "k1", "ABC123DEF456", start + Duration::from_secs(60)), Lookup::Reserved);
+    }
+
+    #[test]
+    fn test_key_is_bound_to_user_and_machine_code() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Reserved);
+        cache.complete(1, "k1", 7);
+
+        assert_eq!(cache.begin(1, "k1", "XYZ987654321"), Lookup::Conflict);
+        // 其他用户使用相同的键互不影响
+        assert_eq!(cache.begin(2, "k1", "XYZ987654321"), Lookup::Reserved);
+    }
+
+    #[test]
+    fn test_abort_allows_retry() {
+        let cache: IdempotencyCache<i32> = IdempotencyCache::new(Duration::from_secs(60));
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Reserved);
+        cache.abort(1, "k1");
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Reserved);
+    }
+
+    #[tokio::test]
+    async fn test_guard_releases_cancelled_request() {
+        let cache: IdempotencyCache<i32> = IdempotencyCache::new(Duration::from_secs(60));
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Reserved);
+
+        // 客户端断开时处理请求的 future 被丢弃，守卫随之释放占位
+        let request = async {
+            let _pending = cache.guard(1, "k1");
+            std::future::pending::<()>().await;
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(10), request).await.is_err());
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Reserved);
+
+        cache.guard(1, "k1").complete(7);
+        assert_eq!(cache.begin(1, "k1", "ABC123DEF456"), Lookup::Cached(7));
+    }
+}
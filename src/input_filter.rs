@@ -0,0 +1,126 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// 链接的识别规则：带协议或 www. 的地址、Telegram 链接，以及常见顶级域名的裸域名。
+/// 机器码只含字母、数字、`@`、`-`、`_`，不会出现 `.`、`/`、`:`，因此不会被误拦
+const URL_PATTERN: &str = r"(?i)(?:[a-z][a-z0-9+.-]*://|www\.|t\.me/|telegram\.me/|\b[a-z0-9-]+(?:\.[a-z0-9-]+)*\.(?:com|net|org|info|io|me|cc|co|xyz|top|vip|shop|site|online|club|link|cn|ru|tk)\b)";
+
+fn url_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(URL_PATTERN).expect("链接正则无效"))
+}
+
+/// 机器码输入的敏感词/广告过滤规则，命中的消息不进入生成流程
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputFilter {
+    /// 是否拦截包含链接的输入
+    pub block_urls: bool,
+    /// 敏感词，已转为小写，不区分大小写匹配；纯 ASCII 的词按整词匹配，其余按子串匹配
+    pub words: Vec<String>,
+}
+
+/// 输入命中的规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterMatch {
+    Url(String),
+    Word(String),
+}
+
+impl FilterMatch {
+    /// 告警与日志中使用的简短描述
+    pub fn describe(&self) -> String {
+        match self {
+            FilterMatch::Url(url) => format!("链接 {}", url),
+            FilterMatch::Word(word) => format!("敏感词 {}", word),
+        }
+    }
+}
+
+impl InputFilter {
+    /// 解析逗号分隔的敏感词列表，忽略空项
+    pub fn new(block_urls: bool, words: &str) -> Self {
+        let words = words
+            .split([',', '，'])
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        InputFilter { block_urls, words }
+    }
+
+    /// 返回输入命中的第一条规则；未命中返回 None
+    pub fn check(&self, text: &str) -> Option<FilterMatch> {
+        if self.block_urls {
+            if let Some(found) = url_regex().find(text) {
+                return Some(FilterMatch::Url(found.as_str().to_string()));
+            }
+        }
+        let lower = text.to_lowercase();
+        self.words
+            .iter()
+            .find(|word| contains_word(&lower, word))
+            .map(|word| FilterMatch::Word(word.clone()))
+    }
+}
+
+/// 纯 ASCII 的词只在前后不是字母或数字时算命中，避免机器码中恰好出现 "vx" 之类的片段被误拦；
+/// 中文等其他词没有词边界，按子串匹配
+fn contains_word(text: &str, word: &str) -> bool {
+    if !word.is_ascii() {
+        return text.contains(word);
+    }
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric()) && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urls_are_blocked() {
+        let filter = InputFilter::new(true, "");
+        for text in [
+            "https://example.com/buy",
+            "便宜激活码 http://spam.test 联系我",
+            "加群 t.me/spam_group",
+            "www.spam.cn",
+            "访问 cheap-codes.xyz 购买",
+            "HTTPS://EXAMPLE.COM",
+        ] {
+            assert!(matches!(filter.check(text), Some(FilterMatch::Url(_))), "{}", text);
+        }
+        assert_eq!(filter.check("t.me/spam_group"), Some(FilterMatch::Url("t.me/".to_string())));
+    }
+
+    #[test]
+    fn test_machine_codes_pass() {
+        let filter = InputFilter::new(true, "代理,VX");
+        for text in ["abc123def456", "ABCD-1234_efgh@5678", "abcd 1234\nefgh 5678", "comnet123456"] {
+            assert_eq!(filter.check(text), None, "{}", text);
+        }
+        assert!(InputFilter::new(false, "").check("https://example.com").is_none());
+    }
+
+    #[test]
+    fn test_words_match_case_insensitively() {
+        let filter = InputFilter::new(false, " 代理 ,，vx,");
+        assert_eq!(filter.words, vec!["代理", "vx"]);
+        assert_eq!(filter.check("加 VX 领取"), Some(FilterMatch::Word("vx".to_string())));
+        assert_eq!(filter.check("诚招代理"), Some(FilterMatch::Word("代理".to_string())));
+    }
+
+    #[test]
+    fn test_ascii_words_match_whole_words() {
+        let filter = InputFilter::new(false, "vx,qq");
+        assert_eq!(filter.check("加vx:abc123"), Some(FilterMatch::Word("vx".to_string())));
+        assert_eq!(filter.check("QQ 12345"), Some(FilterMatch::Word("qq".to_string())));
+        // 机器码中恰好含有敏感词片段时不拦截
+        for text in ["abcvx1234567", "VXQQ12345678@abc", "abc123qq"] {
+            assert_eq!(filter.check(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::{database, utils};
+
+/// 单实例锁：在数据库文件旁创建 `<db>.lock`，防止同一数据库被多个机器人实例同时使用
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+/// 锁文件中记录的持有者信息
+#[derive(Debug, Clone, PartialEq)]
+struct LockOwner {
+    pid: u32,
+    start_time: u64,
+}
+
+impl InstanceLock {
+    /// 为数据库获取实例锁；内存数据库无需加锁，返回 None。
+    /// 不同 INSTANCE_ID 的机器人可共用一个库，各自持有 `<db>.<instance_id>.lock`
+    pub fn acquire_for_database(database_url: &str, instance_id: &str) -> Result<Option<Self>> {
+        match database::sqlite_file_path(database_url) {
+            Some(db_path) => {
+                // 在数据库初始化之前加锁，此时数据库目录可能尚未创建
+                if let Some(dir) = db_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    fs::create_dir_all(dir).with_context(|| format!("无法创建数据库目录: {:?}", dir))?;
+                }
+                let mut lock_path = db_path.into_os_string();
+                if instance_id != database::DEFAULT_INSTANCE_ID {
+                    lock_path.push(format!(".{}", instance_id));
+                }
+                lock_path.push(".lock");
+                Self::acquire(lock_path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 获取锁文件；若已被存活进程持有则返回错误，残留的过期锁会被自动清理。
+    /// 锁文件内容无法识别时视为仍被持有（可能是其他实例的旧版写入），重试后仍不可读则拒绝启动
+    pub fn acquire(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let me = current_owner();
+
+        for _ in 0..LOCK_ATTEMPTS {
+            match link_owner_file(&path, &me) {
+                Ok(()) => {
+                    info!("已获取实例锁: {:?} (PID: {})", path, me.pid);
+                    return Ok(InstanceLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => match read_owner(&path) {
+                    Some(owner) if is_owner_alive(&owner) => {
+                        anyhow::bail!(
+                            "另一个实例 (PID: {}) 正在使用该数据库，锁文件: {:?}。请先停止该实例再启动",
+                            owner.pid,
+                            path
+                        );
+                    }
+                    Some(owner) => {
+                        warn!("检测到残留的实例锁 {:?}，持有进程 (PID: {}) 已不存在，自动清理", path, owner.pid);
+                        break_stale_lock(&path, &owner, &me)?;
+                    }
+                    // 锁文件刚被释放，直接重试
+                    None if !path.exists() => {}
+                    None => std::thread::sleep(LOCK_RETRY_DELAY),
+                },
+                Err(e) => {
+                    return Err(e).with_context(|| format!("创建实例锁失败: {:?}", path));
+                }
+            }
+        }
+
+        anyhow::bail!(
+            "无法获取实例锁: {:?}，锁文件内容无法识别。若确认没有其他实例在运行，请手动删除该文件",
+            path
+        )
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // 仅删除自己持有的锁，避免误删被其他实例接管的锁文件
+        if read_owner(&self.path) == Some(current_owner()) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("释放实例锁失败 {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+fn current_owner() -> LockOwner {
+    let pid = utils::get_current_pid();
+    let start_time = utils::get_process_info(pid)
+        .map(|p| p.start_time)
+        .unwrap_or(0);
+    LockOwner { pid, start_time }
+}
+
+/// 获取锁的最大尝试次数及两次尝试之间的等待时间
+const LOCK_ATTEMPTS: u32 = 25;
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 先把持有者信息写入临时文件，再硬链接到 `path`，使锁文件一出现就带有完整内容。
+/// `path` 已存在时返回 `AlreadyExists`
+fn link_owner_file(path: &Path, owner: &LockOwner) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}.{}.tmp", owner.pid, TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = fs::write(&temp_path, format!("{}\n{}\n", owner.pid, owner.start_time))
+        .and_then(|_| fs::hard_link(&temp_path, path));
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+/// 清理持有者已退出的锁。通过 `<lock>.break` 保证同一时刻只有一个进程在清理，
+/// 并在持有该标记后重新确认锁仍属于 `stale`，避免误删其他实例刚创建的新锁
+fn break_stale_lock(path: &Path, stale: &LockOwner, me: &LockOwner) -> Result<()> {
+    let mut guard_path = path.as_os_str().to_owned();
+    guard_path.push(".break");
+    let guard_path = PathBuf::from(guard_path);
+
+    match link_owner_file(&guard_path, me) {
+        Ok(()) => {
+            let result = if read_owner(path).as_ref() == Some(stale) {
+                match fs::remove_file(path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        Err(e).with_context(|| format!("清理残留实例锁失败: {:?}", path))
+                    }
+                    _ => Ok(()),
+                }
+            } else {
+                Ok(())
+            };
+            let _ = fs::remove_file(&guard_path);
+            result
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            // 另一个进程正在清理；若它已在清理途中退出，则移除其遗留的标记
+            if let Some(owner) = read_owner(&guard_path) {
+                if !is_owner_alive(&owner) {
+                    let _ = fs::remove_file(&guard_path);
+                }
+            }
+            std::thread::sleep(LOCK_RETRY_DELAY);
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("创建实例锁清理标记失败: {:?}", guard_path)),
+    }
+}
+
+fn read_owner(path: &Path) -> Option<LockOwner> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+    let pid = lines.next()?.trim().parse().ok()?;
+    let start_time = lines.next().and_then(|l| l.trim().parse().ok()).unwrap_or(0);
+    Some(LockOwner { pid, start_time })
+}
+
+/// 判断锁持有者是否仍存活；通过启动时间识别 PID 被复用的情况
+fn is_owner_alive(owner: &LockOwner) -> bool {
+    match utils::get_process_info(owner.pid) {
+        Some(process) => owner.start_time == 0 || process.start_time == owner.start_time,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("bot.db.lock");
+
+        let first = InstanceLock::acquire(&lock_path).expect("第一个实例应获取成功");
+        let second = InstanceLock::acquire(&lock_path);
+        let err = second.expect_err("第二个实例应被拒绝").to_string();
+        assert!(err.contains(&utils::get_current_pid().to_string()));
+
+        drop(first);
+        assert!(!lock_path.exists());
+        assert!(InstanceLock::acquire(&lock_path).is_ok());
+    }
+
+    #[test]
+    fn test_lock_with_unreadable_owner_is_not_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("bot.db.lock");
+        // 空文件可能是另一个实例尚未写完的锁，不能当作残留锁清理
+        fs::write(&lock_path, "").unwrap();
+
+        assert!(InstanceLock::acquire(&lock_path).is_err());
+        assert!(lock_path.exists());
+    }
+
+    #[test]
+    fn test_stale_lock_from_dead_process_is_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("bot.db.lock");
+        fs::write(&lock_path, format!("{}\n{}\n", u32::MAX - 1, 12345)).unwrap();
+
+        let lock = InstanceLock::acquire(&lock_path).expect("残留锁应被清理");
+        assert_eq!(read_owner(&lock_path), Some(current_owner()));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_stale_lock_with_reused_pid_is_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("bot.db.lock");
+        // 相同 PID 但启动时间不同，说明 PID 已被复用
+        fs::write(&lock_path, format!("{}\n{}\n", utils::get_current_pid(), 1)).unwrap();
+
+        assert!(InstanceLock::acquire(&lock_path).is_ok());
+    }
+
+    #[test]
+    fn test_memory_database_needs_no_lock() {
+        assert!(InstanceLock::acquire_for_database("sqlite::memory:", database::DEFAULT_INSTANCE_ID).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_instances_sharing_database_use_separate_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}", dir.path().join("bot.db").display());
+
+        let default = InstanceLock::acquire_for_database(&url, database::DEFAULT_INSTANCE_ID).unwrap();
+        let other = InstanceLock::acquire_for_database(&url, "bot-b").unwrap();
+        assert!(default.is_some() && other.is_some());
+        assert!(dir.path().join("bot.db.bot-b.lock").exists());
+        assert!(InstanceLock::acquire_for_database(&url, "bot-b").is_err());
+    }
+
+    #[test]
+    fn test_lock_before_database_directory_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}", dir.path().join("data/bot.db").display());
+
+        let lock = InstanceLock::acquire_for_database(&url, database::DEFAULT_INSTANCE_ID).unwrap();
+        assert!(lock.is_some());
+        assert!(dir.path().join("data/bot.db.lock").exists());
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    Row,
+};
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+use crate::database::{self, Database};
+
+/// 旧版表中的一个字段：`name` 为本版的字段名，`candidates` 为旧版中可能使用的列名，按顺序取第一个存在的
+struct Field {
+    name: &'static str,
+    candidates: &'static [&'static str],
+    required: bool,
+}
+
+/// 旧版的一张表：`tables` 为可能的表名，按顺序取第一个存在的
+struct Mapping {
+    tables: &'static [&'static str],
+    fields: &'static [Field],
+}
+
+/// 旧 Python 版用户表 → users
+const USER_MAPPING: Mapping = Mapping {
+    tables: &["users", "user"],
+    fields: &[
+        Field { name: "user_id", candidates: &["user_id", "telegram_id", "tg_id"], required: true },
+        Field { name: "username", candidates: &["username"], required: false },
+        Field { name: "first_name", candidates: &["first_name"], required: false },
+        Field { name: "last_name", candidates: &["last_name"], required: false },
+        Field { name: "is_banned", candidates: &["is_banned", "banned"], required: false },
+        Field { name: "request_count", candidates: &["request_count", "usage_count", "count"], required: false },
+        Field { name: "created_at", candidates: &["created_at", "join_date", "first_seen"], required: false },
+    ],
+};
+
+/// 旧 Python 版激活日志表 → activation_logs
+const LOG_MAPPING: Mapping = Mapping {
+    tables: &["activation_logs", "logs", "usage_logs"],
+    fields: &[
+        Field { name: "user_id", candidates: &["user_id", "telegram_id", "tg_id"], required: true },
+        Field { name: "machine_code", candidates: &["machine_code", "machine_id"], required: true },
+        Field { name: "activation_code", candidates: &["activation_code", "code"], required: false },
+        Field { name: "finalshell_version", candidates: &["finalshell_version", "version"], required: false },
+        Field { name: "created_at", candidates: &["created_at", "timestamp", "time"], required: false },
+    ],
+};
+
+/// 旧版日志缺少版本时记录的版本名
+const UNKNOWN_VERSION: &str = "legacy";
+
+/// 旧版中的一个用户
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyUser {
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub is_banned: bool,
+    pub request_count: i32,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// 旧版中的一条激活日志
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyLog {
+    pub user_id: i64,
+    pub machine_code: String,
+    pub activation_code: String,
+    pub version: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// 写入本版数据库的结果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LegacyImportSummary {
+    pub users_imported: usize,
+    /// user_id 已存在而跳过的用户
+    pub users_skipped: usize,
+    pub logs_imported: usize,
+    /// 已导入过而跳过的日志
+    pub logs_skipped: usize,
+}
+
+/// 一次迁移的完整结果
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub summary: LegacyImportSummary,
+    /// 缺少 user_id 等必填字段而无法导入的行
+    pub malformed: usize,
+    /// 导入前的数据库备份，内存数据库时为 None
+    pub backup: Option<PathBuf>,
+}
+
+/// 从旧 Python 版的 SQLite 数据库迁移用户与激活日志：先读取旧库，再备份当前库，最后在一个事务中导入
+pub async fn migrate(db: &Database, database_url: &str, from: &Path, backup_dir: &Path) -> Result<MigrationReport> {
+    let legacy = SqlitePool::connect_with(SqliteConnectOptions::new().filename(from).read_only(true))
+        .await
+        .with_context(|| format!("无法打开旧版数据库: {}", from.display()))?;
+    let (users, bad_users) = read_users(&legacy).await?;
+    let (logs, bad_logs) = read_logs(&legacy).await?;
+    legacy.close().await;
+    info!("旧版数据库读取完成: 用户 {}，日志 {}，无效行 {}", users.len(), logs.len(), bad_users + bad_logs);
+
+    let backup = match database::sqlite_file_path(database_url) {
+        Some(_) => {
+            let file_name = format!("finalshell_bot_premigrate_legacy_{}.db", Utc::now().format("%Y%m%d_%H%M%S"));
+            let path = database::snapshot(db.writer(), backup_dir, &file_name)
+                .await
+                .context("导入前备份数据库失败，未执行导入")?;
+            info!("导入前已备份数据库: {}", path.display());
+            Some(path)
+        }
+        None => None,
+    };
+
+    let summary = database::import_legacy(db, &users, &logs).await?;
+    let detail = format!(
+        "来源: {}; 用户: 导入 {} 跳过 {}; 日志: 导入 {} 跳过 {}",
+        from.display(),
+        summary.users_imported,
+        summary.users_skipped,
+        summary.logs_imported,
+        summary.logs_skipped
+    );
+    if let Err(e) = database::log_admin_action(db, database::SYSTEM_ACTOR_ID, "migrate_legacy", None, &detail).await {
+        error!("记录审计日志失败: {}", e);
+    }
+
+    Ok(MigrationReport {
+        summary,
+        malformed: bad_users + bad_logs,
+        backup,
+    })
+}
+
+/// 读取旧版用户，返回有效用户与无效行数；同一 user_id 只保留第一行
+async fn read_users(pool: &SqlitePool) -> Result<(Vec<LegacyUser>, usize)> {
+    let mut seen = std::collections::HashSet::new();
+    let mut users = Vec::new();
+    let mut malformed = 0;
+    for row in select(pool, &USER_MAPPING).await? {
+        let Some(user_id) = row[0].as_deref().and_then(parse_id) else {
+            malformed += 1;
+            continue;
+        };
+        if !seen.insert(user_id) {
+            continue;
+        }
+        users.push(LegacyUser {
+            user_id,
+            username: row[1].clone(),
+            first_name: row[2].clone(),
+            last_name: row[3].clone(),
+            is_banned: row[4].as_deref().is_some_and(parse_bool),
+            request_count: row[5].as_deref().and_then(parse_id).unwrap_or(0).clamp(0, i32::MAX as i64) as i32,
+            created_at: row[6].as_deref().and_then(parse_time),
+        });
+    }
+    Ok((users, malformed))
+}
+
+/// 读取旧版激活日志，返回有效日志与无效行数
+async fn read_logs(pool: &SqlitePool) -> Result<(Vec<LegacyLog>, usize)> {
+    let mut logs = Vec::new();
+    let mut malformed = 0;
+    for row in select(pool, &LOG_MAPPING).await? {
+        let user_id = row[0].as_deref().and_then(parse_id);
+        let machine_code = row[1].as_deref().map(str::trim).filter(|code| !code.is_empty());
+        let (Some(user_id), Some(machine_code)) = (user_id, machine_code) else {
+            malformed += 1;
+            continue;
+        };
+        logs.push(LegacyLog {
+            user_id,
+            machine_code: machine_code.to_string(),
+            activation_code: row[2].clone().unwrap_or_default(),
+            version: row[3].clone().filter(|v| !v.is_empty()).unwrap_or_else(|| UNKNOWN_VERSION.to_string()),
+            created_at: row[4].as_deref().and_then(parse_time),
+        });
+    }
+    Ok((logs, malformed))
+}
+
+/// 按映射查询旧表，每行按 `fields` 的顺序返回文本值；旧库中没有的可选列为 None
+async fn select(pool: &SqlitePool, mapping: &Mapping) -> Result<Vec<Vec<Option<String>>>> {
+    let mut table = None;
+    for name in mapping.tables {
+        let exists: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(name)
+            .fetch_one(pool)
+            .await?;
+        if exists > 0 {
+            table = Some(*name);
+            break;
+        }
+    }
+    let Some(table) = table else {
+        anyhow::bail!("旧版数据库中找不到表: {}", mapping.tables.join(" / "));
+    };
+
+    let columns: Vec<String> = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    let mut projection = Vec::new();
+    for field in mapping.fields {
+        match field.candidates.iter().find(|candidate| columns.iter().any(|c| c.eq_ignore_ascii_case(candidate))) {
+            Some(column) => projection.push(format!("CAST({} AS TEXT) AS {}", column, field.name)),
+            None if field.required => anyhow::bail!("旧版表 {} 缺少字段 {}", table, field.candidates.join(" / ")),
+            None => projection.push(format!("NULL AS {}", field.name)),
+        }
+    }
+
+    let rows = sqlx::query(&format!("SELECT {} FROM {} ORDER BY rowid", projection.join(", "), table))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| (0..mapping.fields.len()).map(|i| row.get::<Option<String>, _>(i)).collect())
+        .collect())
+}
+
+fn parse_id(value: &str) -> Option<i64> {
+    let value = value.trim();
+    value.parse().ok().or_else(|| value.strip_suffix(".0")?.parse().ok())
+}
+
+fn parse_bool(value: &str) -> bool {
+    matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+/// 旧版时间可能是 ISO 8601、Python 的 `str(datetime)` 或 Unix 时间戳；不带时区的按 UTC 处理
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(Utc.from_utc_datetime(&time));
+        }
+    }
+    let seconds: f64 = value.parse().ok()?;
+    Utc.timestamp_opt(seconds as i64, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Lang;
+
+    #[test]
+    fn test_parse_time() {
+        let expected = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(parse_time("2024-01-02 03:04:05.123456").map(|t| t.timestamp()), Some(expected.timestamp()));
+        assert_eq!(parse_time("2024-01-02T11:04:05+08:00"), Some(expected));
+        assert_eq!(parse_time("1704164645"), Some(expected));
+        assert_eq!(parse_time("1704164645.5"), Some(expected));
+        assert_eq!(parse_time("昨天"), None);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_from_python_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("python.db");
+        let legacy = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&path).create_if_missing(true))
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, user_id INTEGER, username TEXT, first_name TEXT, banned TEXT, usage_count INTEGER, join_date TEXT)",
+            "INSERT INTO users (user_id, username, first_name, banned, usage_count, join_date) VALUES
+                (1, 'old_name', 'A', 'False', 9, '2024-01-02 03:04:05.123456'),
+                (2, 'b', 'B', 'True', 3, '2024-01-03 00:00:00'),
+                (2, 'b_dup', 'B', 'False', 0, NULL),
+                (NULL, 'orphan', NULL, NULL, NULL, NULL)",
+            "CREATE TABLE logs (id INTEGER PRIMARY KEY, user_id INTEGER, machine_id TEXT, code TEXT, version TEXT, timestamp REAL)",
+            "INSERT INTO logs (user_id, machine_id, code, version, timestamp) VALUES
+                (2, 'ABC123DEF456', 'CODE', '4.5', 1704164645.0),
+                (2, '', 'CODE', '4.5', 1704164646.0)",
+        ] {
+            sqlx::query(statement).execute(&legacy).await.unwrap();
+        }
+        legacy.close().await;
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        database::get_or_create_user(&db, 1, Some("current".to_string()), None, None, Lang::Zh).await.unwrap();
+
+        let report = migrate(&db, "sqlite::memory:", &path, &dir.path().join("backups")).await.unwrap();
+        assert_eq!(
+            report.summary,
+            LegacyImportSummary { users_imported: 1, users_skipped: 1, logs_imported: 1, logs_skipped: 0 }
+        );
+        assert_eq!(report.malformed, 2);
+        assert!(report.backup.is_none());
+
+        // 冲突的用户保留本版数据
+        assert_eq!(database::get_user_by_id(&db, 1).await.unwrap().username.as_deref(), Some("current"));
+        let imported = database::get_user_by_id(&db, 2).await.unwrap();
+        assert!(imported.is_banned);
+        assert_eq!(imported.request_count, 3);
+        assert_eq!(database::get_user_activation_logs(&db, 2, 10).await.unwrap().len(), 1);
+
+        // 重复导入不产生重复记录
+        let again = migrate(&db, "sqlite::memory:", &path, &dir.path().join("backups")).await.unwrap();
+        assert_eq!(
+            again.summary,
+            LegacyImportSummary { users_imported: 0, users_skipped: 2, logs_imported: 0, logs_skipped: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_backs_up_file_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("python.db");
+        let legacy = SqlitePool::connect_with(SqliteConnectOptions::new().filename(&legacy_path).create_if_missing(true))
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE users (user_id INTEGER)").execute(&legacy).await.unwrap();
+        sqlx::query("CREATE TABLE logs (user_id INTEGER, machine_code TEXT)").execute(&legacy).await.unwrap();
+        legacy.close().await;
+
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("bot.db").display());
+        let db = database::init(&url, None, None).await.unwrap();
+        let report = migrate(&db, &url, &legacy_path, &dir.path().join("backups")).await.unwrap();
+        assert!(report.backup.unwrap().exists());
+        assert_eq!(report.summary, LegacyImportSummary::default());
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tracing::{info, warn};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+use crate::clock::SharedClock;
+
+/// 未设置 RUST_LOG 时的默认过滤器
+pub const DEFAULT_FILTER: &str = "finalunlock_all_rust=info,teloxide=info";
+/// SIGUSR1 切换到的调试过滤器
+pub const DEBUG_FILTER: &str = "finalunlock_all_rust=debug,teloxide=info";
+/// 检查临时过滤器是否到期的间隔
+const REVERT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+static GLOBAL: OnceLock<LogLevel> = OnceLock::new();
+
+/// 运行时可替换的日志过滤器。临时修改在到期后由 `revert_if_due` 恢复为启动时的过滤器，
+/// 避免调试日志被遗忘而一直开着
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default: String,
+    /// 临时修改的自动恢复时间；未修改时为 None
+    revert_at: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl LogLevel {
+    /// 创建过滤器层与对应的句柄；层装入订阅者后句柄才对输出生效
+    pub fn new(default: &str) -> Result<(Self, reload::Layer<EnvFilter, Registry>)> {
+        let filter = parse(default)?;
+        let (layer, handle) = reload::Layer::new(filter);
+        let level = LogLevel {
+            handle,
+            default: default.to_string(),
+            revert_at: Arc::default(),
+        };
+        Ok((level, layer))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<DateTime<Utc>>> {
+        self.revert_at.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 启动时的过滤器，临时修改到期后恢复为它
+    pub fn default_filter(&self) -> &str {
+        &self.default
+    }
+
+    /// 当前生效的过滤器与自动恢复时间
+    pub fn current(&self) -> (String, Option<DateTime<Utc>>) {
+        let filter = self
+            .handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_else(|_| self.default.clone());
+        (filter, *self.lock())
+    }
+
+    /// 临时替换过滤器，`revert_at` 之后恢复；过滤器语法错误时不做任何改动
+    pub fn set(&self, filter: &str, revert_at: DateTime<Utc>) -> Result<()> {
+        self.handle.reload(parse(filter)?).context("替换日志过滤器失败")?;
+        *self.lock() = Some(revert_at);
+        Ok(())
+    }
+
+    /// 立即恢复启动时的过滤器，返回此前是否有临时修改
+    pub fn reset(&self) -> Result<bool> {
+        self.handle.reload(parse(&self.default)?).context("恢复日志过滤器失败")?;
+        Ok(self.lock().take().is_some())
+    }
+
+    /// 临时修改已到期时恢复，返回是否恢复
+    pub fn revert_if_due(&self, now: DateTime<Utc>) -> Result<bool> {
+        if self.lock().is_some_and(|revert_at| now >= revert_at) {
+            return self.reset();
+        }
+        Ok(false)
+    }
+
+    /// SIGUSR1：已有临时修改时恢复，否则切换到调试过滤器；返回切换后是否为调试日志
+    pub fn toggle_debug(&self, revert_at: DateTime<Utc>) -> Result<bool> {
+        if self.lock().is_some() {
+            self.reset()?;
+            return Ok(false);
+        }
+        self.set(DEBUG_FILTER, revert_at)?;
+        Ok(true)
+    }
+}
+
+fn parse(filter: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(filter).with_context(|| format!("日志过滤器格式错误: {}", filter))
+}
+
+/// 初始化全局日志：优先使用 RUST_LOG，无效时回退默认过滤器
+pub fn init(rust_log: Option<String>) {
+    let (level, layer) = match rust_log.as_deref().map(LogLevel::new) {
+        Some(Ok(pair)) => pair,
+        _ => LogLevel::new(DEFAULT_FILTER).expect("默认日志过滤器无效"),
+    };
+    tracing_subscriber::registry().with(layer).with(fmt::layer()).init();
+    let _ = GLOBAL.set(level);
+}
+
+/// 全局日志的过滤器句柄；`init` 之前为 None
+pub fn global() -> Option<&'static LogLevel> {
+    GLOBAL.get()
+}
+
+/// 长期运行的进程中定期检查临时过滤器是否到期，并监听 SIGUSR1 切换调试日志
+pub fn spawn_control(clock: SharedClock, revert_after: Duration) {
+    let Some(level) = global() else {
+        return;
+    };
+
+    {
+        let clock = clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REVERT_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                match level.revert_if_due(clock.now_utc()) {
+                    Ok(true) => info!("临时日志级别已到期，恢复为 {}", level.default_filter()),
+                    Ok(false) => {}
+                    Err(e) => warn!("恢复日志级别失败: {:#}", e),
+                }
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                warn!("无法监听 SIGUSR1，不能通过信号切换调试日志: {}", e);
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            let revert_at = clock.now_utc() + chrono::Duration::from_std(revert_after).unwrap_or_default();
+            match level.toggle_debug(revert_at) {
+                Ok(true) => info!("收到 SIGUSR1，已开启调试日志，将于 {} 自动恢复", revert_at.to_rfc3339()),
+                Ok(false) => info!("收到 SIGUSR1，已恢复日志级别 {}", level.default_filter()),
+                Err(e) => warn!("切换调试日志失败: {:#}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn test_set_updates_filter() {
+        let (level, _layer) = LogLevel::new(DEFAULT_FILTER).unwrap();
+        let (filter, revert_at) = level.current();
+        assert!(filter.contains("finalunlock_all_rust=info"));
+        assert_eq!(revert_at, None);
+
+        let later = Utc::now() + chrono::Duration::minutes(30);
+        level.set("finalunlock_all_rust=trace", later).unwrap();
+        assert_eq!(level.current(), ("finalunlock_all_rust=trace".to_string(), Some(later)));
+
+        // 语法错误时保留原有过滤器
+        assert!(level.set("finalunlock_all_rust=loud", later).is_err());
+        assert_eq!(level.current().0, "finalunlock_all_rust=trace");
+
+        assert!(level.reset().unwrap());
+        assert!(!level.reset().unwrap());
+        assert!(level.current().0.contains("finalunlock_all_rust=info"));
+    }
+
+    #[test]
+    fn test_revert_fires_with_mock_clock() {
+        let (level, _layer) = LogLevel::new(DEFAULT_FILTER).unwrap();
+        let clock = MockClock::new(Utc::now());
+        let window = Duration::from_secs(600);
+
+        assert!(level.toggle_debug(clock.now_utc() + chrono::Duration::from_std(window).unwrap()).unwrap());
+        assert!(level.current().0.contains("finalunlock_all_rust=debug"));
+
+        clock.advance(window - Duration::from_secs(1));
+        assert!(!level.revert_if_due(clock.now_utc()).unwrap());
+        assert!(level.current().0.contains("finalunlock_all_rust=debug"));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(level.revert_if_due(clock.now_utc()).unwrap());
+        assert_eq!(level.current().1, None);
+        assert!(level.current().0.contains("finalunlock_all_rust=info"));
+        assert!(!level.revert_if_due(clock.now_utc()).unwrap());
+
+        // 再次切换时开启，已开启时关闭
+        assert!(level.toggle_debug(clock.now_utc()).unwrap());
+        assert!(!level.toggle_debug(clock.now_utc()).unwrap());
+        assert_eq!(level.current().1, None);
+    }
+}
@@ -1,23 +1,62 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use std::env;
+use std::path::{Path, PathBuf};
 use tracing::info;
 
+mod abuse;
+mod alerts;
+mod audit;
+mod banlist;
+mod bench;
 mod bot;
+mod clock;
 mod config;
+mod cooldown;
+mod correlation;
 mod database;
+mod degraded;
+mod doctor;
+mod experiment;
+mod export;
 mod finalshell;
+mod footer;
+mod format;
 mod guard;
+mod health;
+mod hooks;
+mod i18n;
+mod idempotency;
+mod input_filter;
+mod instance;
+mod legacy;
+mod log_level;
+mod metrics;
 mod models;
+mod quota;
+mod result_image;
+mod result_link;
+mod scheduler;
+mod selftest;
+mod server;
+mod support;
+mod telegram_health;
+mod telemetry;
+mod trial;
+mod upload;
 mod utils;
 
 use config::Config;
+use instance::InstanceLock;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// 启动时有待执行的数据库迁移也不预先备份
+    #[arg(long, global = true)]
+    no_premigration_backup: bool,
 }
 
 #[derive(Subcommand)]
@@ -26,21 +65,90 @@ enum Commands {
     Bot,
     /// 启动守护进程
     Guard,
-    /// 手动执行系统检查
-    Check,
+    /// 手动执行系统检查；未配置 BOT_TOKEN 时只在本地输出报告
+    Check {
+        /// 同时将报告写入该文件
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// 以 JSON 格式输出报告
+        #[arg(long)]
+        json: bool,
+    },
+    /// 逐项运行故障诊断，关键检查未通过时以非零状态退出
+    Doctor,
     /// 初始化数据库
     InitDb,
+    /// 在当前目录生成带注释的配置模板 (.env.example)
+    InitConfig {
+        /// 文件已存在时覆盖
+        #[arg(long)]
+        force: bool,
+    },
+    /// 导出守护检查历史为 CSV
+    ExportHealth {
+        /// 导出最近多少天的记录
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+        /// 输出文件路径
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// 用 EXPORT_PASSPHRASE 解密 /export 发送的 .enc 文件，不需要其余配置与数据库
+    DecryptExport {
+        /// 加密的导出文件
+        file: PathBuf,
+        /// 输出文件路径，默认去掉 .enc 后缀
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// 用内置的已知向量校验激活码算法，不需要配置与数据库
+    SelfTest,
+    /// 对比原始实现与复用生成器的激活码生成耗时，不需要配置与数据库
+    Bench {
+        /// 重复生成的轮数，每轮覆盖全部内置向量与版本
+        #[arg(long, default_value_t = 10_000)]
+        iterations: u32,
+        /// 另外在临时数据库中并发记录这么多次生成，对比逐条写入与单事务的耗时
+        #[arg(long)]
+        db: Option<u32>,
+    },
+    /// 从旧 Python 版的 SQLite 数据库导入用户与激活日志，导入前自动备份当前数据库
+    Migrate {
+        /// 旧版数据库文件路径
+        #[arg(long)]
+        from: PathBuf,
+    },
+    /// 用户管理
+    Users {
+        #[command(subcommand)]
+        command: UserCommands,
+    },
+    /// 匿名使用统计 (需 TELEMETRY_OPTIN=true 才会上报)
+    Telemetry {
+        #[command(subcommand)]
+        command: TelemetryCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommands {
+    /// 从 CSV 文件导入封禁名单 (每行 user_id,reason)
+    ImportBans {
+        /// CSV 文件路径
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum TelemetryCommands {
+    /// 打印将要上报的 JSON 原文，便于审核；不会发送
+    Preview,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 初始化日志系统
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            env::var("RUST_LOG")
-                .unwrap_or_else(|_| "finalunlock_all_rust=info,teloxide=info".into()),
-        )
-        .init();
+    // 初始化日志系统，过滤器可在运行时通过 /loglevel 或 SIGUSR1 临时调整
+    log_level::init(env::var("RUST_LOG").ok());
 
     // 加载环境变量
     dotenv::dotenv().ok();
@@ -48,14 +156,81 @@ async fn main() -> Result<()> {
     // 解析命令行参数
     let cli = Cli::parse();
 
+    // 生成配置模板不需要已有配置与数据库
+    if let Some(Commands::InitConfig { force }) = &cli.command {
+        let path = Path::new(config::TEMPLATE_FILE);
+        config::write_template(path, *force)?;
+        println!("已生成配置模板: {}，复制为 .env 并按需修改后即可启动", path.display());
+        return Ok(());
+    }
+
+    if let Some(Commands::SelfTest) = &cli.command {
+        let report = selftest::run(selftest::VECTORS);
+        println!("{}", report.render());
+        if !report.mismatches.is_empty() {
+            anyhow::bail!("激活码算法与已知向量不一致");
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Bench { iterations, db }) = &cli.command {
+        println!("{}", bench::run(*iterations)?.render());
+        if let Some(db_iterations) = db {
+            println!("{}", bench::run_db(*db_iterations).await?.render());
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::DecryptExport { file, out }) = &cli.command {
+        let out = decrypt_export(file, out.as_deref())?;
+        println!("已解密到 {}", out.display());
+        return Ok(());
+    }
+
     // 加载配置
     let config = Config::load()?;
+    // 启动机器人必须配置 Telegram，缺失时在获取实例锁、初始化数据库之前就退出
+    let runs_bot = matches!(cli.command, None | Some(Commands::Bot));
+    if runs_bot {
+        config.require_telegram()?;
+    }
     info!("配置加载成功");
+    if let Some(endpoint) = &config.telemetry_endpoint {
+        info!(
+            "📡 匿名使用统计已开启 (TELEMETRY_OPTIN=true)：每周向 {} 发送版本、系统与分桶后的用户数/激活数，可用 telemetry preview 查看内容",
+            endpoint
+        );
+    }
+
+    // 诊断只读：不执行迁移，结构版本落后时才能如实报告
+    if let Some(Commands::Doctor) = &cli.command {
+        let db = database::open_read_only(&config.database_url)?;
+        let report = doctor::run(&config, &db).await;
+        println!("{}", report.render());
+        if report.has_critical_failure() {
+            anyhow::bail!("关键诊断项未通过");
+        }
+        return Ok(());
+    }
+
+    // 先获取实例锁再初始化数据库，第二个实例不会在锁检查之前执行迁移或备份
+    let _lock = if runs_bot {
+        InstanceLock::acquire_for_database(&config.database_url, &config.instance_id)?
+    } else {
+        None
+    };
 
     // 初始化数据库
-    let db = database::init(&config.database_url).await?;
+    let backup_dir = (!cli.no_premigration_backup).then(|| Path::new(guard::BACKUP_DIR));
+    let db = database::init(&config.database_url, config.read_replica_url.as_deref(), backup_dir)
+        .await?
+        .with_instance_id(config.instance_id.clone());
     info!("数据库初始化成功");
 
+    if matches!(cli.command, None | Some(Commands::Bot) | Some(Commands::Guard)) {
+        log_level::spawn_control(config.clock.clone(), std::time::Duration::from_secs(config.log_level_revert));
+    }
+
     match &cli.command {
         Some(Commands::Bot) => {
             info!("启动 Telegram 机器人...");
@@ -65,10 +240,40 @@ async fn main() -> Result<()> {
             info!("启动守护进程...");
             guard::run(config, db).await?;
         }
-        Some(Commands::Check) => {
+        Some(Commands::Check { output, json }) => {
             info!("执行系统检查...");
-            guard::perform_check(&config, &db).await?;
+            guard::perform_check(&config, &db, output.as_deref(), *json).await?;
+        }
+        Some(Commands::ExportHealth { days, out }) => {
+            let file = std::fs::File::create(out).with_context(|| format!("无法创建文件: {:?}", out))?;
+            let mut writer = std::io::BufWriter::new(file);
+            let since = chrono::Utc::now() - chrono::Duration::days(*days);
+            let count = export::export_health_csv(&db, since, &mut writer).await?;
+            std::io::Write::flush(&mut writer)?;
+            println!("已导出 {} 条健康检查记录到 {}", count, out.display());
         }
+        Some(Commands::Migrate { from }) => {
+            let report = legacy::migrate(&db, &config.database_url, from, Path::new(guard::BACKUP_DIR)).await?;
+            if let Some(path) = &report.backup {
+                println!("已备份当前数据库: {}", path.display());
+            }
+            println!("用户: 导入 {}，已存在跳过 {}", report.summary.users_imported, report.summary.users_skipped);
+            println!("激活日志: 导入 {}，重复跳过 {}", report.summary.logs_imported, report.summary.logs_skipped);
+            println!("无效行: {}", report.malformed);
+        }
+        Some(Commands::Telemetry { command: TelemetryCommands::Preview }) => {
+            let ping = telemetry::preview_ping(&db, chrono::Utc::now()).await?;
+            println!("{}", serde_json::to_string_pretty(&ping)?);
+            match &config.telemetry_endpoint {
+                Some(endpoint) => eprintln!("已开启，每周发送到 {}", endpoint),
+                None => eprintln!("未开启，设置 TELEMETRY_OPTIN=true 与 TELEMETRY_ENDPOINT 后才会发送"),
+            }
+        }
+        Some(Commands::Users { command: UserCommands::ImportBans { file } }) => {
+            import_bans_from_file(&db, file).await?;
+        }
+        Some(Commands::InitConfig { .. }) | Some(Commands::SelfTest) | Some(Commands::Bench { .. }) | Some(Commands::DecryptExport { .. }) => unreachable!("已在加载配置前处理"),
+        Some(Commands::Doctor) => unreachable!("已在初始化数据库前处理"),
         Some(Commands::InitDb) => {
             info!("初始化数据库...");
             // 数据库已经在上面的init调用中初始化和迁移
@@ -83,3 +288,48 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// 解密 /export 发送的加密文件，返回输出路径；不覆盖已存在的文件
+fn decrypt_export(file: &Path, out: Option<&Path>) -> Result<PathBuf> {
+    let passphrase = env::var(config::EXPORT_PASSPHRASE_VAR)
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .with_context(|| format!("请通过 {} 环境变量提供导出时使用的口令", config::EXPORT_PASSPHRASE_VAR))?;
+    let out = match out {
+        Some(out) => out.to_path_buf(),
+        None if file.extension().is_some_and(|ext| ext == export::ENCRYPTED_EXTENSION) => file.with_extension(""),
+        None => anyhow::bail!("文件名不以 .{} 结尾，请用 --out 指定输出路径", export::ENCRYPTED_EXTENSION),
+    };
+    if out.exists() {
+        anyhow::bail!("输出文件已存在: {}", out.display());
+    }
+
+    let data = std::fs::read(file).with_context(|| format!("无法读取文件: {:?}", file))?;
+    let plain = export::decrypt(&data, &passphrase)?;
+    std::fs::write(&out, plain).with_context(|| format!("无法写入文件: {:?}", out))?;
+    Ok(out)
+}
+
+/// 命令行导入封禁名单，与机器人 /importbans 共用解析与导入逻辑
+async fn import_bans_from_file(db: &database::Database, file: &Path) -> Result<()> {
+    let size = std::fs::metadata(file)
+        .with_context(|| format!("无法读取文件: {:?}", file))?
+        .len();
+    if size as usize > banlist::MAX_IMPORT_FILE_SIZE {
+        anyhow::bail!("文件过大，最多 {} KB", banlist::MAX_IMPORT_FILE_SIZE / 1024);
+    }
+
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("文件不是有效的 UTF-8 文本: {:?}", file))?;
+    let source = format!("命令行 {}", file.display());
+    let report = banlist::import(db, &content, database::SYSTEM_ACTOR_ID, &source).await?;
+
+    println!("新增封禁: {}", report.summary.applied);
+    println!("已是封禁状态: {}", report.summary.already_banned);
+    println!("格式错误: {}", report.malformed.len());
+    for (line, problem) in &report.malformed {
+        println!("  第 {} 行: {}", line, problem);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 数据库累计值的缓存时长，监控系统频繁抓取时不必每次都查询数据库
+pub const TOTALS_TTL: Duration = Duration::from_secs(60);
+
+/// 自启动以来的实时计数，只保存在内存中，进程重启后归零。机器人与 HTTP 服务共享同一个 `Arc<Metrics>`，
+/// `/metrics` 直接读取这些值，数据库中的累计值按 `TOTALS_TTL` 缓存
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    generated: AtomicU64,
+    failed: AtomicU64,
+    bans: AtomicU64,
+    totals: Mutex<Option<(Instant, Totals)>>,
+}
+
+/// 数据库中的累计用户数与激活次数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub users: i64,
+    pub activations: i64,
+}
+
+/// 某一时刻的计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// 收到的生成请求（聊天消息与 HTTP 接口）
+    pub requests: u64,
+    /// 成功生成并送达的次数
+    pub generated: u64,
+    /// 生成或发送失败的次数
+    pub failed: u64,
+    /// 触发的封禁（管理员封禁与超额自动封禁）
+    pub bans: u64,
+}
+
+impl Metrics {
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_generated(&self) {
+        self.generated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bans(&self, count: u64) {
+        self.bans.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// `now` 时仍在有效期内的数据库累计值
+    pub fn cached_totals(&self, now: Instant) -> Option<Totals> {
+        let cached = self.totals.lock().unwrap_or_else(|e| e.into_inner());
+        cached.filter(|(at, _)| now.saturating_duration_since(*at) < TOTALS_TTL).map(|(_, totals)| totals)
+    }
+
+    /// 缓存 `now` 时读取的数据库累计值
+    pub fn store_totals(&self, now: Instant, totals: Totals) {
+        *self.totals.lock().unwrap_or_else(|e| e.into_inner()) = Some((now, totals));
+    }
+
+    /// 各计数分别读取，彼此之间不保证是同一瞬间的值
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            generated: self.generated.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            bans: self.bans.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Prometheus 文本格式；`totals` 为数据库中的累计值，读取失败时省略
+pub fn render(snapshot: Snapshot, totals: Option<Totals>) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: i64| {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+    };
+
+    let counters = [
+        ("finalunlock_requests_total", "自启动以来收到的生成请求数", snapshot.requests),
+        ("finalunlock_generated_total", "自启动以来成功生成并送达的次数", snapshot.generated),
+        ("finalunlock_failures_total", "自启动以来生成或发送失败的次数", snapshot.failed),
+        ("finalunlock_bans_total", "自启动以来触发的封禁数", snapshot.bans),
+    ];
+    for (name, help, value) in counters {
+        metric(name, "counter", help, value.try_into().unwrap_or(i64::MAX));
+    }
+
+    if let Some(totals) = totals {
+        metric("finalunlock_users", "gauge", "数据库中的累计用户数", totals.users);
+        metric("finalunlock_activations", "gauge", "数据库中的累计激活次数", totals.activations);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_concurrent_counts() {
+        let metrics = Arc::new(Metrics::default());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        metrics.record_request();
+                        metrics.record_generated();
+                    }
+                    metrics.record_failure();
+                    metrics.record_bans(2);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            metrics.snapshot(),
+            Snapshot { requests: 8000, generated: 8000, failed: 8, bans: 16 }
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let snapshot = Snapshot { requests: 3, generated: 2, failed: 1, bans: 0 };
+        let text = render(snapshot, None);
+        assert!(text.contains("# TYPE finalunlock_requests_total counter\nfinalunlock_requests_total 3\n"));
+        assert!(text.contains("finalunlock_failures_total 1\n"));
+        assert!(!text.contains("finalunlock_activations"));
+        let text = render(snapshot, Some(Totals { users: 5, activations: 9 }));
+        assert!(text.contains("finalunlock_activations 9\n"));
+    }
+
+    #[test]
+    fn test_totals_cache_expires() {
+        let metrics = Metrics::default();
+        let start = Instant::now();
+        assert_eq!(metrics.cached_totals(start), None);
+
+        let totals = Totals { users: 5, activations: 9 };
+        metrics.store_totals(start, totals);
+        assert_eq!(metrics.cached_totals(start + TOTALS_TTL - Duration::from_secs(1)), Some(totals));
+        assert_eq!(metrics.cached_totals(start + TOTALS_TTL), None);
+    }
+}
@@ -1,6 +1,10 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::BTreeMap;
+
+use crate::finalshell::{self, RedactionPolicy};
+use crate::i18n::Lang;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct User {
@@ -14,6 +18,66 @@ pub struct User {
     pub request_count: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub ban_reason: Option<String>,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub banned_until: Option<DateTime<Utc>>,
+    pub language_code: Option<String>,
+    pub flagged_at: Option<DateTime<Utc>>,
+    /// 管理员通过 /trust 提前结束试用期的时间
+    pub trusted_at: Option<DateTime<Utc>>,
+    /// 私聊中当前置顶的生成结果消息
+    pub pinned_message_id: Option<i32>,
+    /// 界面语言，新用户按客户端语言初始化，可通过 /lang 修改
+    pub lang: Option<String>,
+    /// 是否将每个激活码作为独立消息发送，通过 /split 开启
+    pub split_codes: bool,
+    /// 最近一次看到该用户名的时间
+    pub username_seen_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// 封禁是否仍然有效（临时封禁到期后视为已解除）
+    pub fn is_ban_active(&self, now: DateTime<Utc>) -> bool {
+        self.is_banned && !matches!(self.banned_until, Some(until) if until <= now)
+    }
+
+    /// 用户名有变化或超过一天未记录时需要刷新；其余情况跳过，避免每条消息都写库
+    pub fn username_needs_refresh(&self, username: &str, now: DateTime<Utc>) -> bool {
+        self.username.as_deref() != Some(username)
+            || !matches!(self.username_seen_at, Some(seen) if now - seen < chrono::Duration::days(1))
+    }
+
+    /// 用户的界面语言，未设置时使用配置的默认语言
+    pub fn lang(&self, default: Lang) -> Lang {
+        self.lang.as_deref().and_then(Lang::from_code).unwrap_or(default)
+    }
+}
+
+/// 按最近一次请求时间划分的用户活跃度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Activity {
+    Active,
+    Dormant,
+    Churned,
+}
+
+impl Activity {
+    /// 最近 `active_days` 天内有请求为活跃，`dormant_days` 天内为沉睡，更早或从未请求为流失
+    pub fn classify(last_request: Option<DateTime<Utc>>, now: DateTime<Utc>, active_days: i64, dormant_days: i64) -> Self {
+        match last_request {
+            Some(at) if now - at <= chrono::Duration::days(active_days) => Activity::Active,
+            Some(at) if now - at <= chrono::Duration::days(dormant_days) => Activity::Dormant,
+            _ => Activity::Churned,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Activity::Active => "🟢 活跃",
+            Activity::Dormant => "🟡 沉睡",
+            Activity::Churned => "🔴 流失",
+        }
+    }
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
@@ -26,6 +90,40 @@ pub struct ActivationLog {
     pub created_at: DateTime<Utc>,
 }
 
+/// 管理员操作审计记录；`hash` 为哈希链上本条的 SHA-256
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub admin_id: i64,
+    pub action: String,
+    pub target_user_id: Option<i64>,
+    pub detail: Option<String>,
+    /// 按数据库中保存的原始文本参与哈希，避免时间格式转换造成误报
+    pub created_at: String,
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Appeal {
+    pub id: i64,
+    pub user_id: i64,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub content: String,
+    pub status: String,
+    pub decided_by: Option<i64>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct CodeReport {
+    pub id: i64,
+    pub user_id: i64,
+    pub machine_code_hash: String,
+    pub version: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct SystemStats {
     pub id: i64,
@@ -33,10 +131,193 @@ pub struct SystemStats {
     pub total_activations: i64,
     pub active_users_today: i64,
     pub activations_today: i64,
+    /// Telegram 返回账号已注销的用户数
+    pub deactivated_users: i64,
+    /// 当前屏蔽了机器人的用户数
+    pub blocked_users: i64,
+    /// 近 7 天内屏蔽过机器人的人数
+    pub blocked_this_week: i64,
     pub system_status: String,
     pub created_at: DateTime<Utc>,
 }
 
+/// 一段时间内的激活码生成耗时统计（微秒）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: i64,
+    pub avg_us: i64,
+    pub max_us: i64,
+}
+
+/// 一次成功生成的耗时记录
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct RequestMetric {
+    pub user_id: i64,
+    pub duration_us: i64,
+    pub update_lag_ms: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 某一天（配置时区）的激活统计
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailySummary {
+    /// 日期，YYYY-MM-DD
+    pub day: String,
+    pub activations: i64,
+    pub distinct_users: i64,
+    /// 各版本的激活次数
+    pub versions: BTreeMap<String, i64>,
+}
+
+/// 未能生成激活码的请求：机器码格式错误或生成失败
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct RequestFailure {
+    pub id: i64,
+    pub correlation_id: String,
+    pub user_id: i64,
+    /// 失败阶段，如 invalid_machine_code、generate
+    pub stage: String,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RequestFailure {
+    /// 失败阶段的中文说明，未知阶段原样返回
+    pub fn stage_label(&self) -> &str {
+        match self.stage.as_str() {
+            "invalid_machine_code" => "机器码格式错误",
+            "generate" => "生成失败",
+            other => other,
+        }
+    }
+
+    /// 失败详情；格式错误时记录的是用户发送的机器码，按配置脱敏后展示
+    pub fn display_detail(&self, policy: RedactionPolicy) -> String {
+        match (self.stage.as_str(), self.detail.as_deref()) {
+            ("invalid_machine_code", Some(machine_code)) => finalshell::redact(machine_code, policy),
+            (_, detail) => detail.unwrap_or("-").to_string(),
+        }
+    }
+}
+
+/// 按关联 ID 查到的一次请求的全部记录；批量上传时同一 ID 对应多条激活日志
+#[derive(Debug, Clone, Default)]
+pub struct RequestTrace {
+    pub activations: Vec<ActivationLog>,
+    pub metrics: Vec<RequestMetric>,
+    pub failures: Vec<RequestFailure>,
+}
+
+impl RequestTrace {
+    pub fn is_empty(&self) -> bool {
+        self.activations.is_empty() && self.metrics.is_empty() && self.failures.is_empty()
+    }
+}
+
+/// 文案实验中一个分组的转化情况
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyVariantStats {
+    pub variant: String,
+    pub users: i64,
+    /// 分组后至少生成过一次激活码的用户数
+    pub converted: i64,
+}
+
+impl CopyVariantStats {
+    /// 转化率 (%)
+    pub fn conversion_rate(&self) -> f64 {
+        if self.users == 0 {
+            0.0
+        } else {
+            self.converted as f64 * 100.0 / self.users as f64
+        }
+    }
+}
+
+/// 用户对生成结果的使用反馈，保存在 activation_logs.outcome，未反馈为空
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationOutcome {
+    Success,
+    Failure,
+}
+
+impl ActivationOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActivationOutcome::Success => "success",
+            ActivationOutcome::Failure => "failure",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "success" => Some(ActivationOutcome::Success),
+            "failure" => Some(ActivationOutcome::Failure),
+            _ => None,
+        }
+    }
+}
+
+/// 生成结果的整体反馈情况。反馈按整次生成记录，用户实际使用的版本未知，因此不按版本拆分
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutcomeStats {
+    pub success: i64,
+    pub failure: i64,
+    /// 未反馈的生成次数
+    pub unknown: i64,
+}
+
+impl OutcomeStats {
+    /// 有反馈的记录中激活成功的比例 (%)，没有反馈时为 None
+    pub fn success_rate(&self) -> Option<f64> {
+        let answered = self.success + self.failure;
+        (answered > 0).then(|| self.success as f64 * 100.0 / answered as f64)
+    }
+}
+
+/// 正式广播的累计投递情况，不含发给管理员的测试广播
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastStats {
+    pub runs: i64,
+    pub delivered: i64,
+    pub failed: i64,
+}
+
+/// 从 Telegram 创建消息到开始处理的延迟分布（毫秒），用于判断机器人是否处理不及时
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateLag {
+    pub count: i64,
+    pub p50_ms: i64,
+    pub p95_ms: i64,
+}
+
+impl UpdateLag {
+    /// 按最近秩法计算分位数；没有样本时返回 None
+    pub fn from_samples(mut samples: Vec<i64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let percentile = |p: usize| {
+            let rank = (p * samples.len()).div_ceil(100);
+            samples[rank.max(1) - 1]
+        };
+        Some(UpdateLag {
+            count: samples.len() as i64,
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+        })
+    }
+}
+
+/// 本机与 Telegram 服务器的时钟偏差（秒），正数表示本机时间偏快
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSkew {
+    pub seconds: i64,
+    /// 上一次检查测得的偏差，用于判断是否在持续漂移
+    pub previous: Option<i64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthCheck {
     pub timestamp: DateTime<Utc>,
@@ -45,8 +326,35 @@ pub struct HealthCheck {
     pub cpu_usage: f64,
     pub memory_usage: f64,
     pub disk_usage: f64,
+    /// 数据目录所在文件系统的 inode 使用率，None 表示平台或文件系统不支持
+    pub inode_usage: Option<f64>,
+    /// 最近 24 小时的激活码生成耗时，None 表示没有记录
+    pub latency: Option<LatencySummary>,
+    /// 最近 24 小时的更新处理延迟，None 表示没有记录
+    pub update_lag: Option<UpdateLag>,
+    /// None 表示未能获取 Telegram 服务器时间
+    pub clock_skew: Option<ClockSkew>,
     pub internet_connectivity: bool,
-    pub telegram_api_status: bool,
+    /// None 表示未配置 Telegram
+    pub telegram_api_status: Option<bool>,
+    pub error_count: i64,
+    pub warning_count: i64,
+}
+
+/// 持久化的一次守护检查，用于导出健康历史
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct HealthRecord {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub overall: String,
+    pub cpu_usage: f64,
+    pub memory_usage: f64,
+    pub disk_usage: f64,
+    pub inode_usage: Option<f64>,
+    pub latency_count: Option<i64>,
+    pub latency_avg_us: Option<i64>,
+    pub latency_max_us: Option<i64>,
+    pub clock_skew_secs: Option<i64>,
     pub error_count: i64,
     pub warning_count: i64,
 }
@@ -90,5 +398,50 @@ pub struct UserStats {
     pub username: Option<String>,
     pub total_requests: i32,
     pub last_request: Option<DateTime<Utc>>,
+    /// 封禁是否仍然有效，已到期的临时封禁不算
     pub is_banned: bool,
+    /// 账号已注销时不再接收广播
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// 屏蔽了机器人时同样不再接收广播
+    pub blocked_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_update_lag_percentiles() {
+        assert_eq!(UpdateLag::from_samples(Vec::new()), None);
+        assert_eq!(
+            UpdateLag::from_samples(vec![700]),
+            Some(UpdateLag { count: 1, p50_ms: 700, p95_ms: 700 })
+        );
+
+        // 1..=20 秒乱序：p50 取第 10 个，p95 取第 19 个
+        let samples: Vec<i64> = (1..=20).rev().map(|s| s * 1000).collect();
+        assert_eq!(
+            UpdateLag::from_samples(samples),
+            Some(UpdateLag { count: 20, p50_ms: 10_000, p95_ms: 19_000 })
+        );
+
+        assert_eq!(
+            UpdateLag::from_samples(vec![100, 300, 200, 400]),
+            Some(UpdateLag { count: 4, p50_ms: 200, p95_ms: 400 })
+        );
+    }
+
+    #[test]
+    fn test_activity_classify() {
+        let now = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap();
+        let classify = |days: i64| Activity::classify(Some(now - Duration::days(days)), now, 7, 30);
+
+        assert_eq!(classify(0), Activity::Active);
+        assert_eq!(classify(7), Activity::Active);
+        assert_eq!(classify(8), Activity::Dormant);
+        assert_eq!(classify(30), Activity::Dormant);
+        assert_eq!(classify(31), Activity::Churned);
+        assert_eq!(Activity::classify(None, now, 7, 30), Activity::Churned);
+    }
 }
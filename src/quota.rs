@@ -0,0 +1,167 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::i18n::Lang;
+
+/// 普通用户配额 (MAX_USER_REQUESTS) 的计算周期
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuotaPeriod {
+    /// 按配置时区的自然日重置
+    Daily,
+    /// 按配置时区的自然月重置
+    Monthly,
+    /// 累计次数，永不重置；用完后自动拉黑
+    #[default]
+    Lifetime,
+}
+
+impl QuotaPeriod {
+    /// 解析配置值 daily/monthly/lifetime，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "daily" => Some(QuotaPeriod::Daily),
+            "monthly" => Some(QuotaPeriod::Monthly),
+            "lifetime" => Some(QuotaPeriod::Lifetime),
+            _ => None,
+        }
+    }
+
+    /// 配额说明，如 "每日 3 次"、"每日 3 个机器码"
+    pub fn describe(self, limit: i32, mode: QuotaMode) -> String {
+        let unit = mode.unit();
+        match self {
+            QuotaPeriod::Daily => format!("每日 {} {}", limit, unit),
+            QuotaPeriod::Monthly => format!("每月 {} {}", limit, unit),
+            QuotaPeriod::Lifetime => format!("共 {} {}", limit, unit),
+        }
+    }
+
+    /// `now` 所在周期的起点；累计配额没有周期，返回 None
+    pub fn period_start(self, now: DateTime<Utc>, tz: FixedOffset) -> Option<DateTime<Utc>> {
+        let today = now.with_timezone(&tz).date_naive();
+        match self {
+            QuotaPeriod::Daily => Some(local_midnight(today, tz)),
+            QuotaPeriod::Monthly => NaiveDate::from_ymd_opt(today.year(), today.month(), 1).map(|day| local_midnight(day, tz)),
+            QuotaPeriod::Lifetime => None,
+        }
+    }
+
+    /// 下一次重置的时间；累计配额返回 None
+    pub fn next_reset(self, now: DateTime<Utc>, tz: FixedOffset) -> Option<DateTime<Utc>> {
+        let today = now.with_timezone(&tz).date_naive();
+        let next = match self {
+            QuotaPeriod::Daily => today.succ_opt(),
+            QuotaPeriod::Monthly if today.month() == 12 => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+            QuotaPeriod::Monthly => NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1),
+            QuotaPeriod::Lifetime => None,
+        }?;
+        Some(local_midnight(next, tz))
+    }
+}
+
+/// 普通用户配额的计数方式 (QUOTA_MODE)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuotaMode {
+    /// 每次成功生成计一次
+    #[default]
+    Requests,
+    /// 按周期内提交过的不同机器码计数，同一机器码（规范化后相同）重复生成不再计次
+    Machines,
+}
+
+impl QuotaMode {
+    /// 解析配置值 requests/machines，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "requests" => Some(QuotaMode::Requests),
+            "machines" => Some(QuotaMode::Machines),
+            _ => None,
+        }
+    }
+
+    /// 配额的计数单位
+    pub fn unit(self) -> &'static str {
+        match self {
+            QuotaMode::Requests => "次",
+            QuotaMode::Machines => "个机器码",
+        }
+    }
+
+    /// 向用户说明计数方式，附在剩余次数之后
+    pub fn explain(self) -> &'static str {
+        match self {
+            QuotaMode::Requests => "每次生成计 1 次",
+            QuotaMode::Machines => "按机器码计数，同一机器码重复生成不额外计次",
+        }
+    }
+}
+
+/// 触发每小时突发限制时的提示，`opens_at` 为已按用户语言格式化的下一次可用时间
+pub fn burst_limit_message(lang: Lang, per_hour: u32, opens_at: &str) -> String {
+    match lang {
+        Lang::Zh => format!("⏳ 生成过于频繁：每小时最多 {} 次，下一次可在 {} 后生成。", per_hour, opens_at),
+        Lang::En => format!("⏳ Too many requests: at most {} per hour. The next one is available after {}.", per_hour, opens_at),
+    }
+}
+
+/// 配置时区中某天 00:00 对应的 UTC 时间；固定偏移不存在夏令时歧义
+fn local_midnight(day: NaiveDate, tz: FixedOffset) -> DateTime<Utc> {
+    tz.from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap()).unwrap().with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_monthly_resets_at_local_month_boundary() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+
+        // 北京时间 1 月 31 日 23:59:59，仍属一月
+        let before = utc("2025-01-31T15:59:59Z");
+        assert_eq!(QuotaPeriod::Monthly.period_start(before, tz), Some(utc("2024-12-31T16:00:00Z")));
+        assert_eq!(QuotaPeriod::Monthly.next_reset(before, tz), Some(utc("2025-01-31T16:00:00Z")));
+
+        // 北京时间 2 月 1 日 00:00:00 起算新周期，UTC 仍是 1 月 31 日
+        let after = utc("2025-01-31T16:00:00Z");
+        assert_eq!(QuotaPeriod::Monthly.period_start(after, tz), Some(after));
+        assert_eq!(QuotaPeriod::Monthly.next_reset(after, tz), Some(utc("2025-02-28T16:00:00Z")));
+
+        // 跨年
+        let december = utc("2025-12-15T00:00:00Z");
+        assert_eq!(QuotaPeriod::Monthly.next_reset(december, tz), Some(utc("2025-12-31T16:00:00Z")));
+
+        // 西半球时区：UTC 已进入 3 月，本地仍是 2 月
+        let west = FixedOffset::west_opt(5 * 3600).unwrap();
+        let late_feb = utc("2024-03-01T04:59:59Z");
+        assert_eq!(QuotaPeriod::Monthly.period_start(late_feb, west), Some(utc("2024-02-01T05:00:00Z")));
+        assert_eq!(QuotaPeriod::Monthly.next_reset(late_feb, west), Some(utc("2024-03-01T05:00:00Z")));
+    }
+
+    #[test]
+    fn test_daily_and_lifetime() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let now = utc("2025-08-15T16:30:00Z");
+        assert_eq!(QuotaPeriod::Daily.period_start(now, tz), Some(utc("2025-08-15T16:00:00Z")));
+        assert_eq!(QuotaPeriod::Daily.next_reset(now, tz), Some(utc("2025-08-16T16:00:00Z")));
+
+        assert_eq!(QuotaPeriod::Lifetime.period_start(now, tz), None);
+        assert_eq!(QuotaPeriod::Lifetime.next_reset(now, tz), None);
+
+        assert_eq!(QuotaPeriod::from_name(" Monthly "), Some(QuotaPeriod::Monthly));
+        assert_eq!(QuotaPeriod::from_name("weekly"), None);
+    }
+
+    #[test]
+    fn test_quota_mode() {
+        assert_eq!(QuotaMode::from_name(" Machines "), Some(QuotaMode::Machines));
+        assert_eq!(QuotaMode::from_name("requests"), Some(QuotaMode::Requests));
+        assert_eq!(QuotaMode::from_name("users"), None);
+        assert_eq!(QuotaPeriod::Daily.describe(3, QuotaMode::Requests), "每日 3 次");
+        assert_eq!(QuotaPeriod::Daily.describe(3, QuotaMode::Machines), "每日 3 个机器码");
+    }
+}
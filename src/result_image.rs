@@ -0,0 +1,146 @@
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::path::Path;
+
+/// 字号（像素）
+const FONT_SIZE: f32 = 28.0;
+/// 文字四周的留白（像素）
+const PADDING: u32 = 24;
+/// 图片单边的最大尺寸，超出部分截断；Telegram 照片宽高之和不得超过 10000
+const MAX_DIMENSION: u32 = 4096;
+/// 用于确认字体包含中文字形的字符
+const CJK_PROBE: char = '激';
+
+/// 激活结果图片使用的字体，启动时从 RESULT_IMAGE_FONT 加载
+#[derive(Clone)]
+pub struct ResultFont(FontArc);
+
+impl fmt::Debug for ResultFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResultFont")
+    }
+}
+
+impl ResultFont {
+    /// 加载 TTF/OTF 字体（TTC 取第一个字体），不含中文字形时返回错误
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("无法读取字体文件: {}", path.display()))?;
+        let font = FontArc::try_from_vec(data).with_context(|| format!("无法解析字体文件: {}", path.display()))?;
+        if font.glyph_id(CJK_PROBE) == GlyphId(0) {
+            anyhow::bail!("字体不包含中文字形: {}", path.display());
+        }
+        Ok(ResultFont(font))
+    }
+}
+
+/// 将多行文字渲染为白底黑字的灰度 PNG
+pub fn render_png(font: &ResultFont, text: &str) -> Result<Vec<u8>> {
+    let font = &font.0;
+    let scaled = font.as_scaled(PxScale::from(FONT_SIZE));
+    let line_height = (scaled.height() + scaled.line_gap()).ceil();
+    let lines: Vec<&str> = text.lines().collect();
+
+    let text_width = lines.iter().map(|line| line_width(&scaled, line)).fold(0.0_f32, f32::max);
+    let width = (text_width.ceil() as u32 + PADDING * 2).min(MAX_DIMENSION);
+    let height = ((lines.len() as f32 * line_height).ceil() as u32 + PADDING * 2).min(MAX_DIMENSION);
+
+    let mut pixels = vec![u8::MAX; (width * height) as usize];
+    for (index, line) in lines.iter().enumerate() {
+        let baseline = PADDING as f32 + index as f32 * line_height + scaled.ascent();
+        let mut caret = point(PADDING as f32, baseline);
+        let mut previous: Option<GlyphId> = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret.x += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+
+            let glyph = id.with_scale_and_position(scaled.scale(), caret);
+            caret.x += scaled.h_advance(id);
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let px = bounds.min.x as i64 + i64::from(x);
+                let py = bounds.min.y as i64 + i64::from(y);
+                if px < 0 || py < 0 || px >= i64::from(width) || py >= i64::from(height) {
+                    return;
+                }
+                let pixel = &mut pixels[(py as u32 * width + px as u32) as usize];
+                let shade = u8::MAX - (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+                *pixel = (*pixel).min(shade);
+            });
+        }
+    }
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context("写入 PNG 头失败")?;
+    writer.write_image_data(&pixels).context("写入 PNG 数据失败")?;
+    writer.finish().context("写入 PNG 数据失败")?;
+    Ok(output)
+}
+
+fn line_width<F: Font, SF: ScaleFont<F>>(scaled: &SF, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous: Option<GlyphId> = None;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_rejects_invalid_font() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("font.ttf");
+        assert!(ResultFont::load(&path).is_err());
+
+        std::fs::write(&path, b"not a font").unwrap();
+        let error = ResultFont::load(&path).unwrap_err();
+        assert!(error.to_string().contains("无法解析字体文件"));
+    }
+
+    /// 只含字形 A 的极小测试字体（来自 ttf-parser 的测试用例，MIT/Apache-2.0）
+    const DEMO_FONT: &[u8] = include_bytes!("testdata/demo.ttf");
+
+    #[test]
+    fn test_load_rejects_font_without_cjk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo.ttf");
+        std::fs::write(&path, DEMO_FONT).unwrap();
+        let error = ResultFont::load(&path).unwrap_err();
+        assert!(error.to_string().contains("字体不包含中文字形"));
+    }
+
+    #[test]
+    fn test_render_png_decodes() {
+        let font = ResultFont(FontArc::try_from_slice(DEMO_FONT).unwrap());
+        let png_data = render_png(&font, "AAAA\nA").unwrap();
+
+        let mut reader = png::Decoder::new(png_data.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(frame.color_type, png::ColorType::Grayscale);
+        assert!(frame.width > PADDING * 2 && frame.height > PADDING * 2);
+
+        let pixels = &pixels[..frame.buffer_size()];
+        // 留白为白色，文字区域有深色像素
+        assert_eq!(pixels[0], u8::MAX);
+        assert!(pixels.iter().any(|&pixel| pixel < 128));
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+use sha2::{Digest, Sha256};
+
+/// 结果链接的有效期；取出一次后立即失效
+pub const TTL_MINUTES: i64 = 10;
+/// `/start` 参数中结果令牌的前缀，与其他深链接参数区分
+const PAYLOAD_PREFIX: &str = "r_";
+/// 令牌的随机字节数 (128 位)，十六进制编码后为 32 位
+const TOKEN_BYTES: usize = 16;
+const TOKEN_LENGTH: usize = TOKEN_BYTES * 2;
+/// 由令牌派生加密密钥 (HKDF-SHA256) 时使用的盐
+const KEY_SALT: &[u8] = b"finalunlock result link v1";
+
+/// 由系统安全随机数生成一次性令牌；令牌只出现在发给用户的链接中，数据库只保存它的哈希
+pub fn new_token() -> Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| anyhow!("无法生成随机令牌"))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// `t.me` 深链接，用户在任意设备上打开后向机器人发送 `/start r_<令牌>`
+pub fn deep_link(bot_username: &str, token: &str) -> String {
+    format!("https://t.me/{}?start={}{}", bot_username, PAYLOAD_PREFIX, token)
+}
+
+/// 从 `/start` 消息中取出结果令牌；没有参数或不是结果链接时返回 None
+pub fn start_token(text: &str) -> Option<&str> {
+    let token = text.split_whitespace().nth(1)?.strip_prefix(PAYLOAD_PREFIX)?;
+    (token.len() == TOKEN_LENGTH && token.bytes().all(|b| b.is_ascii_hexdigit())).then_some(token)
+}
+
+/// 数据库中按令牌哈希查找，泄露数据库也无法还原链接
+pub fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::new().chain_update(b"lookup:").chain_update(token).finalize())
+}
+
+/// 由令牌经 HKDF-SHA256 派生 AES-256-GCM 密钥，与查找用的哈希互不相关
+fn derive_key(token: &str) -> Result<LessSafeKey> {
+    let mut key = [0u8; 32];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, KEY_SALT)
+        .extract(token.as_bytes())
+        .expand(&[b"seal"], &AES_256_GCM)
+        .and_then(|okm| okm.fill(&mut key))
+        .map_err(|_| anyhow!("无法派生结果密钥"))?;
+    let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| anyhow!("无法创建结果密钥"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// 用令牌派生的密钥加密结果 (AES-256-GCM)：12 字节随机数 | 密文与认证标签；没有令牌无法解密
+pub fn seal(token: &str, plaintext: &str) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| anyhow!("无法生成随机数"))?;
+    let mut sealed = plaintext.as_bytes().to_vec();
+    derive_key(token)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+        .map_err(|_| anyhow!("加密结果失败"))?;
+
+    let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// 校验并解密 `seal` 的结果；令牌不匹配或数据被改动时返回 None
+pub fn open(token: &str, sealed: &[u8]) -> Option<String> {
+    if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut buffer = ciphertext.to_vec();
+    let plaintext = derive_key(token).ok()?.open_in_place(nonce, Aad::empty(), &mut buffer).ok()?;
+    String::from_utf8(plaintext.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_round_trip() {
+        let token = new_token().unwrap();
+        let text = "🟢 专业版: 70CBF092805F479D\n".repeat(5);
+        let sealed = seal(&token, &text).unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("70CBF092805F479D"));
+        assert_eq!(open(&token, &sealed).as_deref(), Some(text.as_str()));
+        // 每次加密使用新的随机数
+        assert_ne!(seal(&token, &text).unwrap(), sealed);
+
+        // 换一个令牌或数据不完整都无法解密
+        assert_eq!(open(&new_token().unwrap(), &sealed), None);
+        assert_eq!(open(&token, &sealed[..NONCE_LEN + 15]), None);
+        assert_eq!(open(&token, &sealed[..sealed.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let token = new_token().unwrap();
+        let sealed = seal(&token, "🟢 专业版: 70CBF092805F479D").unwrap();
+        // 随机数、密文与认证标签中任意一位被改动都无法解密
+        for index in [0, NONCE_LEN, NONCE_LEN + 5, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 0x01;
+            assert_eq!(open(&token, &tampered), None, "字节 {}", index);
+        }
+    }
+
+    #[test]
+    fn test_start_token() {
+        let token = new_token().unwrap();
+        assert_ne!(new_token().unwrap(), token);
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        assert_ne!(token_hash(&token), token);
+
+        let link = deep_link("finalunlock_bot", &token);
+        let payload = link.split("?start=").nth(1).unwrap();
+        assert_eq!(start_token(&format!("/start {}", payload)), Some(token.as_str()));
+        assert_eq!(start_token("/start"), None);
+        assert_eq!(start_token("/start ref_abc"), None);
+        assert_eq!(start_token("/start r_not-a-token"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{error, info};
+
+type Job = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+type FailureHandler = Arc<dyn Fn(&'static str, String) -> BoxFuture<'static, ()> + Send + Sync>;
+type RecoveryHandler = Arc<dyn Fn(&'static str) -> BoxFuture<'static, ()> + Send + Sync>;
+
+struct Task {
+    name: &'static str,
+    interval: Duration,
+    job: Job,
+}
+
+/// 按各自周期运行多个定时任务；任务之间互不阻塞，一个任务失败或 panic 不影响其他任务
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册每隔 `interval` 运行一次的任务，启动时立即运行第一次；间隔为 0 表示不启用
+    pub fn every<F, Fut>(mut self, name: &'static str, interval: Duration, job: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        if interval.is_zero() {
+            info!("定时任务 {} 未启用", name);
+        } else {
+            self.tasks.push(Task {
+                name,
+                interval,
+                job: Arc::new(move || Box::pin(job())),
+            });
+        }
+        self
+    }
+
+    /// 启动全部任务；每次失败都会调用 `on_failure(任务名, 错误描述)`，失败后首次成功时调用 `on_recovery(任务名)`
+    pub async fn run<F, Fut, R, RFut>(self, on_failure: F, on_recovery: R)
+    where
+        F: Fn(&'static str, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+        R: Fn(&'static str) -> RFut + Send + Sync + 'static,
+        RFut: Future<Output = ()> + Send + 'static,
+    {
+        let on_failure: FailureHandler = Arc::new(move |name, message| Box::pin(on_failure(name, message)));
+        let on_recovery: RecoveryHandler = Arc::new(move |name| Box::pin(on_recovery(name)));
+        let handles: Vec<_> = self
+            .tasks
+            .into_iter()
+            .map(|task| tokio::spawn(run_task(task, on_failure.clone(), on_recovery.clone())))
+            .collect();
+        futures::future::join_all(handles).await;
+    }
+}
+
+async fn run_task(task: Task, on_failure: FailureHandler, on_recovery: RecoveryHandler) {
+    info!("定时任务 {} 已启动，间隔 {} 秒", task.name, task.interval.as_secs());
+    let mut interval = time::interval(task.interval);
+    // 任务耗时超过间隔时顺延，不连续补跑
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut failing = false;
+    loop {
+        interval.tick().await;
+
+        // 每次运行放入独立任务，panic 只算作一次失败
+        let failure = match tokio::spawn((task.job)()).await {
+            Ok(Ok(())) => {
+                if std::mem::take(&mut failing) {
+                    info!("定时任务 {} 已恢复", task.name);
+                    on_recovery(task.name).await;
+                }
+                continue;
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => format!("任务异常退出: {}", e),
+        };
+        error!("定时任务 {} 失败: {}", task.name, failure);
+        failing = true;
+        on_failure(task.name, failure).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_failing_tasks_do_not_stop_others() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let recoveries = Arc::new(Mutex::new(Vec::new()));
+        let flaky_runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = runs.clone();
+        let scheduler = Scheduler::new()
+            .every("count", Duration::from_millis(10), move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+            .every("fail", Duration::from_millis(10), || async { anyhow::bail!("磁盘已满") })
+            .every("panic", Duration::from_secs(60), || async { panic!("boom") })
+            .every("disabled", Duration::ZERO, || async { anyhow::bail!("不应运行") })
+            .every("flaky", Duration::from_millis(10), {
+                let flaky_runs = flaky_runs.clone();
+                move || {
+                    let run = flaky_runs.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if run == 0 {
+                            anyhow::bail!("网络抖动");
+                        }
+                        Ok(())
+                    }
+                }
+            });
+
+        let reported = failures.clone();
+        let recovered = recoveries.clone();
+        let run = scheduler.run(
+            move |name, message| {
+                let reported = reported.clone();
+                async move { reported.lock().unwrap().push((name, message)) }
+            },
+            move |name| {
+                let recovered = recovered.clone();
+                async move { recovered.lock().unwrap().push(name) }
+            },
+        );
+        let _ = time::timeout(Duration::from_millis(200), run).await;
+
+        assert!(runs.load(Ordering::SeqCst) >= 3);
+        let failures = failures.lock().unwrap();
+        assert!(failures.iter().any(|(name, message)| *name == "fail" && message == "磁盘已满"));
+        assert!(failures.iter().any(|(name, _)| *name == "panic"));
+        assert!(failures.iter().all(|(name, _)| *name != "count" && *name != "disabled"));
+        // 失败后首次成功时只通知一次恢复
+        assert_eq!(*recoveries.lock().unwrap(), vec!["flaky"]);
+    }
+}
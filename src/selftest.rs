@@ -0,0 +1,143 @@
+use crate::finalshell::{ActivationCodeGenerator, CodeCase, FinalShellVersionType};
+
+/// 一组已知向量：机器码及各版本期望的 (高级版, 专业版) 激活码（大写）
+pub struct Vector {
+    pub machine_code: &'static str,
+    pub expected: [(FinalShellVersionType, &'static str, &'static str); 4],
+}
+
+/// 内置的已知向量，由独立实现的 MD5/Keccak384 计算得出；修改算法后应全部保持不变
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        machine_code: "ABC123DEF456",
+        expected: [
+            (FinalShellVersionType::Legacy, "1E2A9542FD15BA67", "F0A82121DED0ADA2"),
+            (FinalShellVersionType::V396Plus, "35182A1C2BA126F6", "70CBF092805F479D"),
+            (FinalShellVersionType::V45, "A691E37A0576367F", "6D55F88ACC24DECE"),
+            (FinalShellVersionType::V46, "EF7B9E523B1E38BA", "67C27E5DFFC8506F"),
+        ],
+    },
+    Vector {
+        machine_code: "finalshell@2024-test",
+        expected: [
+            (FinalShellVersionType::Legacy, "323E424FE0B59905", "3011DDB6FF7472BF"),
+            (FinalShellVersionType::V396Plus, "DEA65CF3A6260D10", "1C8BCCBAA8A43517"),
+            (FinalShellVersionType::V45, "8E93A309B0AAE039", "4ABFA3E87049219C"),
+            (FinalShellVersionType::V46, "8AC90D4A27A14BEC", "078A4F0B46744F6E"),
+        ],
+    },
+    Vector {
+        machine_code: "e4d9c0b7a1f2-3c5d_88aa",
+        expected: [
+            (FinalShellVersionType::Legacy, "A97616DAAD1D8AAB", "FA8082D4007A729B"),
+            (FinalShellVersionType::V396Plus, "60DD839A8356BE5C", "C739DFF18A325056"),
+            (FinalShellVersionType::V45, "9953C907C44BE5D6", "57F20EDC6A61A3CD"),
+            (FinalShellVersionType::V46, "43FA46511662806C", "EE2192417F3051BD"),
+        ],
+    },
+];
+
+/// 与期望不一致的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub machine_code: &'static str,
+    pub version: FinalShellVersionType,
+    pub edition: &'static str,
+    pub expected: &'static str,
+    pub actual: String,
+}
+
+/// 校验结果：检查的激活码数与全部差异
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// 用当前算法逐个重新生成并与期望比较
+pub fn run(vectors: &[Vector]) -> Report {
+    let mut report = Report::default();
+    for vector in vectors {
+        for (version, advanced, professional) in vector.expected {
+            let actual = ActivationCodeGenerator::generate_versions(vector.machine_code, &[version], CodeCase::Upper)
+                .map(|mut results| results.remove(0));
+            for (edition, expected) in [("高级版", advanced), ("专业版", professional)] {
+                report.checked += 1;
+                let actual = match &actual {
+                    Ok(result) if edition == "高级版" => result.advanced_code.clone(),
+                    Ok(result) => result.professional_code.clone(),
+                    Err(e) => format!("生成失败: {}", e),
+                };
+                if actual != expected {
+                    report.mismatches.push(Mismatch {
+                        machine_code: vector.machine_code,
+                        version,
+                        edition,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+    report
+}
+
+impl Report {
+    pub fn render(&self) -> String {
+        if self.mismatches.is_empty() {
+            return format!("✅ 自测通过：{} 个激活码全部与已知向量一致", self.checked);
+        }
+
+        let mut output = format!("❌ 自测失败：{} 个激活码中有 {} 个不一致\n", self.checked, self.mismatches.len());
+        for mismatch in &self.mismatches {
+            output.push_str(&format!(
+                "  {} [{} {}]\n    期望: {}\n    实际: {}\n",
+                mismatch.machine_code,
+                mismatch.version.version_name_ascii(),
+                mismatch.edition,
+                mismatch.expected,
+                mismatch.actual
+            ));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_vectors_pass() {
+        let report = run(VECTORS);
+        assert_eq!(report.checked, VECTORS.len() * 8);
+        assert!(report.mismatches.is_empty(), "{}", report.render());
+    }
+
+    #[test]
+    fn test_mismatch_is_reported() {
+        let broken = [Vector {
+            machine_code: "ABC123DEF456",
+            expected: [
+                (FinalShellVersionType::Legacy, "1E2A9542FD15BA67", "F0A82121DED0ADA2"),
+                (FinalShellVersionType::V396Plus, "35182A1C2BA126F6", "70CBF092805F479D"),
+                (FinalShellVersionType::V45, "0000000000000000", "6D55F88ACC24DECE"),
+                (FinalShellVersionType::V46, "EF7B9E523B1E38BA", "67C27E5DFFC8506F"),
+            ],
+        }];
+
+        let report = run(&broken);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                machine_code: "ABC123DEF456",
+                version: FinalShellVersionType::V45,
+                edition: "高级版",
+                expected: "0000000000000000",
+                actual: "A691E37A0576367F".to_string(),
+            }]
+        );
+        assert!(report.render().contains("ABC123DEF456 [4.5 高级版]\n    期望: 0000000000000000\n    实际: A691E37A0576367F\n"));
+    }
+}
@@ -0,0 +1,548 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    abuse,
+    config::Config,
+    correlation,
+    database::{self, Database},
+    finalshell::{self, CodeBackend},
+    guard,
+    hooks::{GenerationContext, HookRegistry},
+    idempotency::{IdempotencyCache, Lookup},
+    metrics::{self, Metrics},
+    telegram_health::TelegramHealth,
+};
+
+/// 构建版本，随 /status 返回
+const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+/// 生成接口的幂等键请求头
+const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+#[derive(Clone)]
+struct AppState {
+    config: Config,
+    db: Database,
+    telegram: Arc<TelegramHealth>,
+    backend: Arc<dyn CodeBackend>,
+    hooks: HookRegistry,
+    idempotency: Arc<IdempotencyCache<GenerateResponse>>,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    user_id: i64,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnbanRequest {
+    user_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateRequest {
+    user_id: i64,
+    machine_code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct GenerateResponse {
+    ok: bool,
+    /// 扣减后用户在当前配额周期内的使用次数 (QUOTA_PERIOD=lifetime 时为累计次数)
+    request_count: i32,
+    codes: Vec<GeneratedCode>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct GeneratedCode {
+    version: &'static str,
+    advanced_code: String,
+    professional_code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiResponse {
+    ok: bool,
+    message: String,
+}
+
+type ApiResult = (StatusCode, Json<ApiResponse>);
+
+/// `GET /status` 的响应。字段是告警规则依赖的稳定契约，改动时需同步更新快照测试；
+/// 无法取得的值输出为 null
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    version: &'static str,
+    telegram: TelegramStatus,
+    database: DatabaseStatus,
+    guard: GuardStatus,
+    quota: QuotaStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct TelegramStatus {
+    ok: bool,
+    latency_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DatabaseStatus {
+    ok: bool,
+    latency_ms: Option<u64>,
+    size_bytes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct GuardStatus {
+    last_check: Option<DateTime<Utc>>,
+    /// 最近一次检查的整体状态 (ok/warning/error)
+    level: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct QuotaStatus {
+    active_users_today: Option<i64>,
+}
+
+fn reply(status: StatusCode, message: impl Into<String>) -> ApiResult {
+    (
+        status,
+        Json(ApiResponse {
+            ok: status.is_success(),
+            message: message.into(),
+        }),
+    )
+}
+
+/// 启动 HTTP 服务
+pub async fn serve(
+    bind: &str,
+    config: Config,
+    db: Database,
+    telegram: Arc<TelegramHealth>,
+    backend: Arc<dyn CodeBackend>,
+    hooks: HookRegistry,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    let addr: SocketAddr = bind
+        .parse()
+        .with_context(|| format!("HTTP_BIND 格式错误: {}", bind))?;
+
+    if config.http_api_key.is_none() {
+        warn!("未配置 HTTP_API_KEY，生成与封禁接口将拒绝所有请求");
+    }
+
+    let idempotency = Arc::new(IdempotencyCache::new(Duration::from_secs(config.idempotency_ttl)));
+    let app = Router::new()
+        .route("/status", get(status))
+        .route("/status/report", get(status_report))
+        .route("/metrics", get(metrics_text))
+        .route("/generate", post(generate))
+        .route("/ban", post(ban))
+        .route("/unban", post(unban))
+        .with_state(AppState { config, db, telegram, backend, hooks, idempotency, metrics });
+
+    info!("HTTP 服务监听于 {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+/// 校验请求头中的 API Key；未配置 Key 时拒绝所有请求
+fn is_authorized(headers: &HeaderMap, api_key: Option<&str>) -> bool {
+    let Some(expected) = api_key else {
+        return false;
+    };
+
+    let provided = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    // 等长比较，避免通过响应时间猜测 Key
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// 各组件状态，无需 API Key，供监控系统抓取
+async fn status(State(state): State<AppState>) -> Json<StatusResponse> {
+    Json(collect_status(&state.db, &state.telegram).await)
+}
+
+/// 当场执行一次完整检查，返回与 /guard、`check --json` 相同的 JSON 报告；需要 API Key
+async fn status_report(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !is_authorized(&headers, state.config.http_api_key.as_deref()) {
+        return reply(StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    }
+
+    match guard::generate_health_report(&state.config, &state.db, Some(&state.telegram)).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            error!("HTTP 接口生成健康报告失败: {}", e);
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "health check failed").into_response()
+        }
+    }
+}
+
+/// 自启动以来的实时计数 (Prometheus 文本格式)，附带数据库中的累计用户数与激活次数（缓存一分钟）；无需 API Key
+async fn metrics_text(State(state): State<AppState>) -> Response {
+    let now = Instant::now();
+    let totals = match state.metrics.cached_totals(now) {
+        Some(totals) => Some(totals),
+        None => match database::get_system_stats(&state.db, None).await {
+            Ok(stats) => {
+                let totals = metrics::Totals { users: stats.total_users, activations: stats.total_activations };
+                state.metrics.store_totals(now, totals);
+                Some(totals)
+            }
+            Err(e) => {
+                warn!("指标接口读取统计失败: {}", e);
+                None
+            }
+        },
+    };
+    let body = metrics::render(state.metrics.snapshot(), totals);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")], body).into_response()
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// 汇总内存中的 Telegram 状态、守护检查记录与统计数据，不重新执行检查
+async fn collect_status(db: &Database, telegram: &TelegramHealth) -> StatusResponse {
+    let throttle = telegram.snapshot();
+
+    // 读取数据库大小的同时作为连通性探测
+    let started = Instant::now();
+    let size = database::database_size(db).await;
+    let database = match size {
+        Ok(size) => DatabaseStatus {
+            ok: true,
+            latency_ms: Some(millis(started.elapsed())),
+            size_bytes: Some(size),
+        },
+        Err(e) => {
+            warn!("状态接口查询数据库失败: {}", e);
+            DatabaseStatus { ok: false, latency_ms: None, size_bytes: None }
+        }
+    };
+
+    let last_check = database::latest_health_check(db)
+        .await
+        .map_err(|e| warn!("状态接口读取守护检查记录失败: {}", e))
+        .ok()
+        .flatten();
+    let active_users_today = database::get_system_stats(db, None)
+        .await
+        .map_err(|e| warn!("状态接口读取统计失败: {}", e))
+        .ok()
+        .map(|stats| stats.active_users_today);
+
+    StatusResponse {
+        version: VERSION,
+        telegram: TelegramStatus {
+            ok: throttle.is_ok(),
+            latency_ms: throttle.last_latency.map(millis),
+            last_error: throttle.last_error,
+        },
+        database,
+        guard: GuardStatus {
+            last_check: last_check.as_ref().map(|record| record.timestamp),
+            level: last_check.map(|record| record.overall.to_lowercase()),
+        },
+        quota: QuotaStatus { active_users_today },
+    }
+}
+
+/// 为已注册用户生成激活码并扣减配额；携带 Idempotency-Key 时，TTL 内的重复请求返回首次结果且不再计费
+async fn generate(State(state): State<AppState>, headers: HeaderMap, Json(req): Json<GenerateRequest>) -> Response {
+    if !is_authorized(&headers, state.config.http_api_key.as_deref()) {
+        return reply(StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    }
+
+    let machine_code = finalshell::canonicalize(&req.machine_code);
+    if !finalshell::is_valid(&machine_code) {
+        return reply(StatusCode::BAD_REQUEST, "invalid machine code").into_response();
+    }
+
+    let key = headers
+        .get(IDEMPOTENCY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty());
+    let mut pending = None;
+    if let Some(key) = key {
+        match state.idempotency.begin(req.user_id, key, &machine_code) {
+            Lookup::Reserved => pending = Some(state.idempotency.guard(req.user_id, key)),
+            Lookup::Cached(response) => {
+                info!("幂等键命中，返回用户 {} 的缓存结果", req.user_id);
+                return Json(response).into_response();
+            }
+            Lookup::Conflict => {
+                return reply(StatusCode::UNPROCESSABLE_ENTITY, "idempotency key already used for another machine code").into_response()
+            }
+            Lookup::InProgress => {
+                return reply(StatusCode::CONFLICT, "request with this idempotency key is still in progress").into_response()
+            }
+        }
+    }
+
+    state.metrics.record_request();
+    let correlation_id = correlation::new_id(req.user_id);
+    let span = info_span!("http_generate", trace_id = %correlation_id);
+    let result = generate_codes(&state, req.user_id, &correlation_id, &machine_code).instrument(span).await;
+    // 只缓存成功的结果；失败或客户端中途断开时守卫释放占位，允许用同一个键重试
+    if let (Some(pending), Ok(response)) = (pending, &result) {
+        pending.complete(response.clone());
+    }
+
+    match result {
+        Ok(response) => Json(response).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+async fn generate_codes(state: &AppState, user_id: i64, correlation_id: &str, machine_code: &str) -> Result<GenerateResponse, ApiResult> {
+    let user = match database::get_user_by_id(&state.db, user_id).await {
+        Ok(user) => user,
+        Err(e) if matches!(e.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => {
+            return Err(reply(StatusCode::NOT_FOUND, "user not found"));
+        }
+        Err(e) => {
+            error!("HTTP 接口查询用户失败: {}", e);
+            return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, "database error"));
+        }
+    };
+    let now = state.config.clock.now_utc();
+    if user.is_ban_active(now) || abuse::is_restricted(&state.config, &user) {
+        return Err(reply(StatusCode::FORBIDDEN, "user is not allowed to generate"));
+    }
+    if let Some(cpu_usage) = state.config.busy_cpu_usage(user_id) {
+        info!("CPU 使用率 {:.1}% 超过阈值，暂不处理用户 {} 的 HTTP 生成请求", cpu_usage, user_id);
+        return Err(reply(StatusCode::SERVICE_UNAVAILABLE, "server busy, retry later"));
+    }
+
+    let quota = state.config.quota(&user);
+    let burst = database::hourly_slot_opens_at(&state.db, user_id, &quota, state.config.max_requests_per_hour, now)
+        .await
+        .map_err(|e| {
+            error!("HTTP 接口查询突发限制失败: {}", e);
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+        })?;
+    if let Some(opens_at) = burst {
+        return Err(reply(StatusCode::TOO_MANY_REQUESTS, format!("hourly limit exceeded, retry after {}", opens_at.to_rfc3339())));
+    }
+
+    // 先预扣配额，生成失败时退回
+    let reservation = database::reserve_quota(&state.db, user_id, quota, machine_code)
+        .await
+        .map_err(|e| {
+            error!("HTTP 接口预扣配额失败: {}", e);
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+        })?
+        .ok_or_else(|| reply(StatusCode::TOO_MANY_REQUESTS, "quota exceeded"))?;
+
+    let started = Instant::now();
+    let results = match state.backend.generate(machine_code, &state.config.display_versions()).await {
+        Ok(results) => results,
+        Err(e) => {
+            error!("HTTP 接口生成激活码失败: {}", e);
+            state.metrics.record_failure();
+            release_quota(state, reservation).await;
+            if let Err(e) = database::record_request_failure(&state.db, correlation_id, user_id, "generate", &format!("{:#}", e)).await {
+                error!("记录请求失败信息失败: {}", e);
+            }
+            return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, format!("generation failed (ref {})", correlation_id)));
+        }
+    };
+
+    let request_count = match database::commit_generation(&state.db, reservation, user_id, correlation_id, machine_code, quota, None, &results).await {
+        Ok(Some((count, _))) => count,
+        // 预扣已过期，期间其他请求用完了配额
+        Ok(None) => return Err(reply(StatusCode::TOO_MANY_REQUESTS, "quota exceeded")),
+        Err(e) => {
+            error!("HTTP 接口写入激活日志失败: {}", e);
+            state.metrics.record_failure();
+            release_quota(state, reservation).await;
+            return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, "database error"));
+        }
+    };
+    info!("HTTP 接口为用户 {} 生成了激活码，本周期已用 {} 次", user_id, request_count);
+    state.metrics.record_generated();
+    state.hooks.spawn(GenerationContext::new(
+        user_id,
+        correlation_id,
+        "http",
+        machine_code,
+        state.config.machine_code_display,
+        &results,
+        started.elapsed(),
+    ));
+
+    Ok(GenerateResponse {
+        ok: true,
+        request_count,
+        codes: results
+            .into_iter()
+            .map(|r| GeneratedCode {
+                version: r.version_type.version_name_ascii(),
+                advanced_code: r.advanced_code,
+                professional_code: r.professional_code,
+            })
+            .collect(),
+    })
+}
+
+/// 退回预扣的配额；失败时预扣会在过期后自动失效，这里只记录日志
+async fn release_quota(state: &AppState, reservation: i64) {
+    if let Err(e) = database::release_quota(&state.db, reservation).await {
+        error!("退回预扣配额失败: {}", e);
+    }
+}
+
+async fn ban(State(state): State<AppState>, headers: HeaderMap, Json(req): Json<BanRequest>) -> ApiResult {
+    if !is_authorized(&headers, state.config.http_api_key.as_deref()) {
+        return reply(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    let reason = req.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    match database::ban_user(&state.db, req.user_id, reason, None).await {
+        Ok(true) => {
+            info!("HTTP 接口封禁了用户 {}", req.user_id);
+            state.metrics.record_bans(1);
+            let detail = format!("来源: HTTP API; 原因: {}", reason.unwrap_or("未说明"));
+            if let Err(e) = database::log_admin_action(&state.db, database::SYSTEM_ACTOR_ID, "ban", Some(req.user_id), &detail).await {
+                error!("记录审计日志失败: {}", e);
+            }
+            reply(StatusCode::OK, "banned")
+        }
+        Ok(false) => reply(StatusCode::NOT_FOUND, "user not found"),
+        Err(e) => {
+            error!("HTTP 接口封禁用户失败: {}", e);
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+        }
+    }
+}
+
+async fn unban(State(state): State<AppState>, headers: HeaderMap, Json(req): Json<UnbanRequest>) -> ApiResult {
+    if !is_authorized(&headers, state.config.http_api_key.as_deref()) {
+        return reply(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    match database::unban_user(&state.db, req.user_id).await {
+        Ok(true) => {
+            info!("HTTP 接口解封了用户 {}", req.user_id);
+            if let Err(e) = database::log_admin_action(&state.db, database::SYSTEM_ACTOR_ID, "unban", Some(req.user_id), "来源: HTTP API").await {
+                error!("记录审计日志失败: {}", e);
+            }
+            reply(StatusCode::OK, "unbanned")
+        }
+        Ok(false) => reply(StatusCode::NOT_FOUND, "user not found"),
+        Err(e) => {
+            error!("HTTP 接口解封用户失败: {}", e);
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with_key(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", key.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        assert!(is_authorized(&headers_with_key("secret"), Some("secret")));
+        assert!(!is_authorized(&headers_with_key("secrex"), Some("secret")));
+        assert!(!is_authorized(&headers_with_key("secret-longer"), Some("secret")));
+        assert!(!is_authorized(&HeaderMap::new(), Some("secret")));
+    }
+
+    #[test]
+    fn test_missing_api_key_rejects_everything() {
+        assert!(!is_authorized(&headers_with_key(""), None));
+        assert!(!is_authorized(&HeaderMap::new(), None));
+    }
+
+    #[test]
+    fn test_status_schema_snapshot() {
+        let status = StatusResponse {
+            version: "finalunlock-all-rust 2.0.0",
+            telegram: TelegramStatus { ok: false, latency_ms: Some(120), last_error: Some("Network error".to_string()) },
+            database: DatabaseStatus { ok: true, latency_ms: Some(3), size_bytes: Some(4096) },
+            guard: GuardStatus {
+                last_check: Some("2025-08-15T12:00:00Z".parse().unwrap()),
+                level: Some("warning".to_string()),
+            },
+            quota: QuotaStatus { active_users_today: Some(7) },
+        };
+
+        let expected = r#"{
+  "version": "finalunlock-all-rust 2.0.0",
+  "telegram": {
+    "ok": false,
+    "latency_ms": 120,
+    "last_error": "Network error"
+  },
+  "database": {
+    "ok": true,
+    "latency_ms": 3,
+    "size_bytes": 4096
+  },
+  "guard": {
+    "last_check": "2025-08-15T12:00:00Z",
+    "level": "warning"
+  },
+  "quota": {
+    "active_users_today": 7
+  }
+}"#;
+        assert_eq!(serde_json::to_string_pretty(&status).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_collect_status_before_first_check() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        let telegram = TelegramHealth::new(100);
+        telegram.record_success(Duration::from_millis(80));
+
+        let status = collect_status(&db, &telegram).await;
+        assert_eq!(status.version, VERSION);
+        assert!(status.telegram.ok);
+        assert_eq!(status.telegram.latency_ms, Some(80));
+        assert!(status.database.ok);
+        assert!(status.database.size_bytes.unwrap() > 0);
+        assert_eq!(status.guard.last_check, None);
+        assert_eq!(status.guard.level, None);
+        assert_eq!(status.quota.active_users_today, Some(0));
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::Config,
+    database::{self, Database, Quota},
+    finalshell, format,
+    models::{ActivationLog, Appeal, AuditEntry, CodeReport, RequestFailure, User},
+};
+
+/// 每类记录最多展示的条数
+const SECTION_LIMIT: i64 = 10;
+/// Telegram 单条消息的最大长度（UTF-16 码元）
+pub const MESSAGE_LIMIT: usize = 4096;
+
+/// 客服排查用的用户档案，汇总多张表中与该用户相关的记录
+#[derive(Debug, Clone)]
+pub struct Dossier {
+    pub user: User,
+    /// 发送消息时 Telegram 返回账号已注销的时间
+    pub deactivated_at: Option<DateTime<Utc>>,
+    pub quota: Quota,
+    /// 当前配额周期内已用次数
+    pub quota_used: i32,
+    /// 试用期内当日剩余次数
+    pub trial_remaining: Option<i64>,
+    pub generations: Vec<ActivationLog>,
+    pub failures: Vec<RequestFailure>,
+    pub appeals: Vec<Appeal>,
+    /// 封禁、解封等针对该用户的管理操作
+    pub moderation: Vec<AuditEntry>,
+    pub reports: Vec<CodeReport>,
+}
+
+/// 组装用户档案；用户不存在时返回 None
+pub async fn build_dossier(db: &Database, config: &Config, user_id: i64) -> Result<Option<Dossier>> {
+    let user = match database::get_user_by_id(db, user_id).await {
+        Ok(user) => user,
+        Err(e) if matches!(e.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let quota = config.quota(&user);
+    let quota_used = database::quota_used(db, &user, &quota).await?;
+    let trial_remaining = match &quota.trial {
+        Some(trial) => Some(i64::from(trial.daily_limit) - database::count_generations_since(db, user_id, trial.window_start).await?),
+        None => None,
+    };
+
+    Ok(Some(Dossier {
+        deactivated_at: database::get_user_deactivated_at(db, user_id).await?,
+        quota,
+        quota_used,
+        trial_remaining,
+        generations: database::get_user_activation_logs(db, user_id, SECTION_LIMIT).await?,
+        failures: database::get_user_failures(db, user_id, SECTION_LIMIT).await?,
+        appeals: database::get_user_appeals(db, user_id, SECTION_LIMIT).await?,
+        moderation: database::get_user_admin_actions(db, user_id, SECTION_LIMIT).await?,
+        reports: database::get_user_code_reports(db, user_id, SECTION_LIMIT).await?,
+        user,
+    }))
+}
+
+impl Dossier {
+    /// 紧凑的纯文本档案，机器码按 MACHINE_CODE_DISPLAY 脱敏
+    pub fn render(&self, config: &Config) -> String {
+        let lang = config.default_lang;
+        let tz = config.timezone();
+        let time = |dt: &DateTime<Utc>| format::fmt_datetime(dt, lang, tz);
+        let user = &self.user;
+
+        let name = [user.first_name.as_deref(), user.last_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut lines = vec![
+            format!("🗂️ 用户档案 {}", user.user_id),
+            format!(
+                "👤 {} · @{} · {}",
+                if name.is_empty() { "-" } else { &name },
+                user.username.as_deref().unwrap_or("-"),
+                user.lang(lang).code()
+            ),
+            format!("📅 注册于 {} · 累计生成 {} 次", time(&user.created_at), user.request_count),
+        ];
+        if let Some(trusted_at) = &user.trusted_at {
+            lines.push(format!("🤝 已信任 · {}", time(trusted_at)));
+        }
+        if let Some(flagged_at) = &user.flagged_at {
+            lines.push(format!("🚩 已标记可疑 · {}", time(flagged_at)));
+        }
+        lines.push(match &self.deactivated_at {
+            Some(at) => format!("📭 消息投递: 账号已注销 ({})", time(at)),
+            None => "📬 消息投递: 正常".to_string(),
+        });
+
+        lines.push(String::new());
+        lines.push(if self.quota.unlimited {
+            "🎫 配额: 不受限".to_string()
+        } else {
+            format!(
+                "🎫 配额: 已用 {} · {}",
+                self.quota_used,
+                config.quota_period.describe(self.quota.limit, self.quota.mode)
+            )
+        });
+        if let (Some(trial), Some(remaining)) = (&self.quota.trial, self.trial_remaining) {
+            lines.push(format!(
+                "🧪 试用期至 {} · 今日剩余 {}/{} 次",
+                time(&trial.ends_at),
+                remaining.max(0),
+                trial.daily_limit
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(if user.is_ban_active(config.clock.now_utc()) {
+            format!(
+                "🚫 封禁中: {}{}",
+                user.ban_reason.as_deref().unwrap_or("未注明原因"),
+                user.banned_until.map(|until| format!("，至 {}", time(&until))).unwrap_or_default()
+            )
+        } else {
+            "✅ 未封禁".to_string()
+        });
+        for entry in &self.moderation {
+            lines.push(format!(
+                "• {} · {} · 管理员 {}{}",
+                entry.created_at,
+                entry.action,
+                entry.admin_id,
+                entry.detail.as_deref().filter(|d| !d.is_empty()).map(|d| format!(" · {}", d)).unwrap_or_default()
+            ));
+        }
+
+        push_section(&mut lines, "📨 申诉", &self.appeals, |appeal| {
+            format!("• {} · {} · {}", time(&appeal.created_at), appeal.status, appeal.content)
+        });
+        push_section(&mut lines, "🔑 最近生成", &self.generations, |log| {
+            format!(
+                "• {} · {} · {}",
+                time(&log.created_at),
+                finalshell::redact(&log.machine_code, config.machine_code_display),
+                log.finalshell_version
+            )
+        });
+        push_section(&mut lines, "❌ 最近失败", &self.failures, |failure| {
+            format!(
+                "• {} · {}: {} · {}",
+                time(&failure.created_at),
+                failure.stage_label(),
+                failure.display_detail(config.machine_code_display),
+                failure.correlation_id
+            )
+        });
+        push_section(&mut lines, "🐞 激活失败反馈", &self.reports, |report| {
+            format!("• {} · {} · {}", time(&report.created_at), report.version, report.machine_code_hash)
+        });
+
+        lines.join("\n")
+    }
+}
+
+fn push_section<T>(lines: &mut Vec<String>, title: &str, items: &[T], line: impl Fn(&T) -> String) {
+    lines.push(String::new());
+    if items.is_empty() {
+        lines.push(format!("{}: 无", title));
+        return;
+    }
+    lines.push(format!("{} ({} 条):", title, items.len()));
+    lines.extend(items.iter().map(line));
+}
+
+/// 按行把长文本切成不超过 `limit` 个 UTF-16 码元的片段；单行超长时在字符边界处截断
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for line in text.lines() {
+        let mut line = line;
+        loop {
+            let separator = usize::from(!current.is_empty());
+            let line_len = line.encode_utf16().count();
+            if current_len + separator + line_len <= limit {
+                if separator == 1 {
+                    current.push('\n');
+                }
+                current.push_str(line);
+                current_len += separator + line_len;
+                break;
+            }
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+                continue;
+            }
+            // 空片段仍放不下，说明单行超长
+            let mut units = 0;
+            let split = line
+                .char_indices()
+                .find(|(_, c)| {
+                    units += c.len_utf16();
+                    units > limit
+                })
+                .map(|(index, _)| index)
+                .unwrap_or(line.len());
+            chunks.push(line[..split].to_string());
+            line = &line[split..];
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("a\nbb\nccc", 5), vec!["a\nbb", "ccc"]);
+        assert_eq!(split_message("abcdefg", 3), vec!["abc", "def", "g"]);
+        // 表情占两个 UTF-16 码元
+        assert_eq!(split_message("😀😀\n激", 4), vec!["😀😀", "激"]);
+        assert!(split_message("", 10).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Telegram 请求状态：最近一次 RetryAfter、请求耗时、错误与待发送队列长度，由发送封装更新，仅保存在内存中
+#[derive(Debug)]
+pub struct TelegramHealth {
+    /// 队列长度超过该值视为积压
+    queue_threshold: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    last_retry_after: Option<Duration>,
+    retry_until: Option<Instant>,
+    queue_depth: usize,
+    backlog_since: Option<Instant>,
+    last_latency: Option<Duration>,
+    last_error: Option<String>,
+}
+
+/// 某一时刻的限流状态快照
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleSnapshot {
+    /// 仍需等待的时长，None 表示当前未被限流
+    pub wait: Option<Duration>,
+    /// 最近一次 Telegram 要求等待的时长
+    pub last_retry_after: Option<Duration>,
+    pub queue_depth: usize,
+    /// 队列持续超过阈值的时长
+    pub backlog_for: Option<Duration>,
+    /// 最近一次成功请求的耗时
+    pub last_latency: Option<Duration>,
+    /// 最近一次请求失败的原因，请求成功后清除
+    pub last_error: Option<String>,
+}
+
+impl TelegramHealth {
+    pub fn new(queue_threshold: usize) -> Self {
+        Self {
+            queue_threshold,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 记录 Telegram 返回的 RetryAfter
+    pub fn record_retry_after(&self, wait: Duration) {
+        self.record_retry_after_at(wait, Instant::now());
+    }
+
+    fn record_retry_after_at(&self, wait: Duration, now: Instant) {
+        let mut inner = self.lock();
+        inner.last_retry_after = Some(wait);
+        inner.retry_until = Some(now + wait);
+    }
+
+    /// 发送成功后记录耗时，并清除限流状态和错误
+    pub fn record_success(&self, latency: Duration) {
+        let mut inner = self.lock();
+        inner.last_retry_after = None;
+        inner.retry_until = None;
+        inner.last_latency = Some(latency);
+        inner.last_error = None;
+    }
+
+    /// 记录限流以外的请求失败
+    pub fn record_error(&self, error: impl Into<String>) {
+        self.lock().last_error = Some(error.into());
+    }
+
+    /// 更新待发送队列长度
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.set_queue_depth_at(depth, Instant::now());
+    }
+
+    fn set_queue_depth_at(&self, depth: usize, now: Instant) {
+        let mut inner = self.lock();
+        inner.queue_depth = depth;
+        if depth > self.queue_threshold {
+            inner.backlog_since.get_or_insert(now);
+        } else {
+            inner.backlog_since = None;
+        }
+    }
+
+    pub fn snapshot(&self) -> ThrottleSnapshot {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> ThrottleSnapshot {
+        let inner = self.lock();
+        ThrottleSnapshot {
+            wait: inner
+                .retry_until
+                .map(|until| until.saturating_duration_since(now))
+                .filter(|wait| !wait.is_zero()),
+            last_retry_after: inner.last_retry_after,
+            queue_depth: inner.queue_depth,
+            backlog_for: inner.backlog_since.map(|since| now.saturating_duration_since(since)),
+            last_latency: inner.last_latency,
+            last_error: inner.last_error.clone(),
+        }
+    }
+}
+
+impl ThrottleSnapshot {
+    /// 未被限流且最近一次请求没有失败
+    pub fn is_ok(&self) -> bool {
+        self.wait.is_none() && self.last_error.is_none()
+    }
+
+    /// 队列积压持续超过 `warn_after` 时视为异常
+    pub fn is_degraded(&self, warn_after: Duration) -> bool {
+        self.backlog_for.is_some_and(|backlog| backlog >= warn_after)
+    }
+
+    /// 形如 "等待 34s, 队列 120 条" 的简短描述
+    pub fn describe(&self) -> String {
+        match self.wait {
+            Some(wait) => format!("等待 {}s, 队列 {} 条", wait.as_secs().max(1), self.queue_depth),
+            None if self.queue_depth > 0 => format!("未限流, 队列 {} 条", self.queue_depth),
+            None => "无".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after_is_cleared_on_success() {
+        let health = TelegramHealth::new(100);
+        let start = Instant::now();
+
+        health.record_retry_after_at(Duration::from_secs(34), start);
+        health.set_queue_depth_at(120, start);
+        let snapshot = health.snapshot_at(start);
+        assert_eq!(snapshot.wait, Some(Duration::from_secs(34)));
+        assert_eq!(snapshot.describe(), "等待 34s, 队列 120 条");
+
+        // 等待时间过去后不再显示等待，但保留最近一次的值
+        let later = health.snapshot_at(start + Duration::from_secs(40));
+        assert_eq!(later.wait, None);
+        assert_eq!(later.last_retry_after, Some(Duration::from_secs(34)));
+
+        health.record_error("Network error");
+        assert!(!health.snapshot_at(start + Duration::from_secs(40)).is_ok());
+
+        health.record_success(Duration::from_millis(120));
+        health.set_queue_depth_at(0, start);
+        let recovered = health.snapshot_at(start);
+        assert_eq!(recovered.last_retry_after, None);
+        assert_eq!(recovered.last_latency, Some(Duration::from_millis(120)));
+        assert!(recovered.is_ok());
+        assert_eq!(recovered.describe(), "无");
+    }
+
+    #[test]
+    fn test_backlog_degrades_only_after_sustained_period() {
+        let health = TelegramHealth::new(100);
+        let warn_after = Duration::from_secs(180);
+        let start = Instant::now();
+
+        health.set_queue_depth_at(150, start);
+        health.set_queue_depth_at(140, start + Duration::from_secs(60));
+        assert!(!health.snapshot_at(start + Duration::from_secs(60)).is_degraded(warn_after));
+        assert!(health.snapshot_at(start + Duration::from_secs(200)).is_degraded(warn_after));
+
+        // 队列回落到阈值以下后重新计时
+        health.set_queue_depth_at(50, start + Duration::from_secs(200));
+        health.set_queue_depth_at(150, start + Duration::from_secs(210));
+        assert!(!health.snapshot_at(start + Duration::from_secs(300)).is_degraded(warn_after));
+    }
+}
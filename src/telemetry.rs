@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    database::{self, Database},
+};
+
+/// 保存随机安装 ID 的设置项，首次生成后不再变化
+const INSTALL_ID_KEY: &str = "telemetry_install_id";
+/// 保存上次成功上报时间 (RFC 3339) 的设置项
+const LAST_SENT_KEY: &str = "telemetry_last_sent";
+/// 两次上报的间隔
+const SEND_INTERVAL_DAYS: i64 = 7;
+/// 守护进程检查是否到期的间隔；上报失败时不更新上次发送时间，下次检查自动重试
+pub const CHECK_INTERVAL_SECS: u64 = 3600;
+/// 单次检查内的最大尝试次数
+const MAX_ATTEMPTS: u32 = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 数量分桶的下界与标签，上报时只给出所在区间
+const BUCKETS: &[(i64, &str)] = &[
+    (5000, "5000+"),
+    (1000, "1000-5000"),
+    (500, "500-1000"),
+    (100, "100-500"),
+    (10, "10-100"),
+    (1, "1-10"),
+    (0, "0"),
+];
+
+/// 上报的全部内容；不含任何用户 ID、机器码或配置项，`telemetry preview` 原样打印
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ping {
+    pub version: &'static str,
+    pub install_id: String,
+    pub os: &'static str,
+    pub users: &'static str,
+    pub weekly_activations: &'static str,
+}
+
+/// 数量所在的区间标签，下界包含、上界不含
+pub fn bucket(count: i64) -> &'static str {
+    BUCKETS
+        .iter()
+        .find(|(min, _)| count >= *min)
+        .map(|(_, label)| *label)
+        .unwrap_or("0")
+}
+
+/// 组装本次上报内容；安装 ID 不存在时随机生成并保存
+pub async fn build_ping(db: &Database, now: DateTime<Utc>) -> Result<Ping> {
+    assemble_ping(db, now, true).await
+}
+
+/// `telemetry preview` 使用：安装 ID 尚未生成时临时生成一个展示，不写入数据库
+pub async fn preview_ping(db: &Database, now: DateTime<Utc>) -> Result<Ping> {
+    assemble_ping(db, now, false).await
+}
+
+async fn assemble_ping(db: &Database, now: DateTime<Utc>, persist: bool) -> Result<Ping> {
+    let stats = database::get_system_stats(db, None).await?;
+    let weekly = database::count_activations_since(db, now - chrono::Duration::days(SEND_INTERVAL_DAYS)).await?;
+    Ok(Ping {
+        version: env!("CARGO_PKG_VERSION"),
+        install_id: install_id(db, persist).await?,
+        os: std::env::consts::OS,
+        users: bucket(stats.total_users),
+        weekly_activations: bucket(weekly),
+    })
+}
+
+async fn install_id(db: &Database, persist: bool) -> Result<String> {
+    if let Some((id, _)) = database::get_setting(db, INSTALL_ID_KEY).await? {
+        return Ok(id);
+    }
+    // 128 位系统随机数，与部署的任何信息无关
+    let mut bytes = [0u8; 16];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| anyhow!("无法生成安装 ID"))?;
+    let id = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    if persist {
+        database::set_setting(db, INSTALL_ID_KEY, &id).await?;
+    }
+    Ok(id)
+}
+
+/// 上次成功上报的时间
+pub async fn last_sent(db: &Database) -> Result<Option<DateTime<Utc>>> {
+    Ok(database::get_setting(db, LAST_SENT_KEY)
+        .await?
+        .and_then(|(value, _)| DateTime::parse_from_rfc3339(&value).ok())
+        .map(|time| time.with_timezone(&Utc)))
+}
+
+/// 守护进程的定时任务：未开启时什么也不做；距上次成功上报满一周时发送，失败只记录日志，下次检查时重试
+pub async fn send_if_due(config: &Config, db: &Database) -> Result<()> {
+    let Some(endpoint) = &config.telemetry_endpoint else {
+        return Ok(());
+    };
+    let now = config.clock.now_utc();
+    if last_sent(db).await?.is_some_and(|sent| now - sent < chrono::Duration::days(SEND_INTERVAL_DAYS)) {
+        return Ok(());
+    }
+
+    let ping = build_ping(db, now).await?;
+    let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = async { client.post(endpoint).json(&ping).send().await?.error_for_status() }.await;
+        match result {
+            Ok(_) => {
+                database::set_setting(db, LAST_SENT_KEY, &now.to_rfc3339()).await?;
+                info!("已发送匿名使用统计");
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("发送匿名使用统计失败 (第 {}/{} 次): {}", attempt, MAX_ATTEMPTS, e);
+                if attempt < MAX_ATTEMPTS {
+                    tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Lang;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(0), "0");
+        assert_eq!(bucket(1), "1-10");
+        assert_eq!(bucket(99), "10-100");
+        assert_eq!(bucket(100), "100-500");
+        assert_eq!(bucket(4999), "1000-5000");
+        assert_eq!(bucket(1_000_000), "5000+");
+    }
+
+    #[tokio::test]
+    async fn test_ping_contains_only_coarse_data() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        database::migrate(&pool).await.unwrap();
+        let db = Database::new(pool, None);
+        for user_id in 1..=12 {
+            database::get_or_create_user(&db, user_id, Some(format!("user{}", user_id)), None, None, Lang::Zh).await.unwrap();
+        }
+
+        // 预览不保存安装 ID
+        let preview = preview_ping(&db, Utc::now()).await.unwrap();
+        assert_eq!(preview.install_id.len(), 32);
+        assert!(database::get_setting(&db, INSTALL_ID_KEY).await.unwrap().is_none());
+
+        let ping = build_ping(&db, Utc::now()).await.unwrap();
+        assert_eq!((ping.users, ping.weekly_activations), ("10-100", "0"));
+        assert_eq!(ping.install_id.len(), 32);
+        // 安装 ID 保存后保持不变
+        assert_eq!(build_ping(&db, Utc::now()).await.unwrap().install_id, ping.install_id);
+        assert_eq!(preview_ping(&db, Utc::now()).await.unwrap().install_id, ping.install_id);
+
+        let json = serde_json::to_value(&ping).unwrap();
+        let fields: Vec<_> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(fields, vec!["install_id", "os", "users", "version", "weekly_activations"]);
+        assert!(!json.to_string().contains("user1"));
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{database::TrialLimit, models::User};
+
+/// 试用期内的每日额度按滚动 24 小时计算
+const TRIAL_WINDOW_HOURS: i64 = 24;
+
+/// 新用户注册后 `trial_hours` 小时内处于试用期，每 24 小时最多生成 `daily_limit` 次；
+/// 未开启试用期、已被管理员信任或试用期已过时返回 None
+pub fn active_trial(user: &User, trial_hours: i64, daily_limit: i32, now: DateTime<Utc>) -> Option<TrialLimit> {
+    if trial_hours <= 0 || user.trusted_at.is_some() {
+        return None;
+    }
+
+    let ends_at = user.created_at + Duration::hours(trial_hours);
+    (now < ends_at).then(|| TrialLimit {
+        daily_limit,
+        window_start: now - Duration::hours(TRIAL_WINDOW_HOURS),
+        ends_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn user(created_at: DateTime<Utc>) -> User {
+        User {
+            id: 1,
+            user_id: 42,
+            username: None,
+            first_name: None,
+            last_name: None,
+            is_admin: false,
+            is_banned: false,
+            request_count: 0,
+            created_at,
+            updated_at: created_at,
+            ban_reason: None,
+            banned_at: None,
+            banned_until: None,
+            language_code: None,
+            flagged_at: None,
+            trusted_at: None,
+            pinned_message_id: None,
+            lang: None,
+            split_codes: false,
+            username_seen_at: None,
+        }
+    }
+
+    #[test]
+    fn test_trial_ends_at_boundary() {
+        let created_at = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap();
+        let user = user(created_at);
+
+        let just_before = created_at + Duration::hours(48) - Duration::seconds(1);
+        let trial = active_trial(&user, 48, 1, just_before).expect("试用期内");
+        assert_eq!(trial.daily_limit, 1);
+        assert_eq!(trial.ends_at, created_at + Duration::hours(48));
+        assert_eq!(trial.window_start, just_before - Duration::hours(24));
+
+        assert_eq!(active_trial(&user, 48, 1, created_at + Duration::hours(48)), None);
+    }
+
+    #[test]
+    fn test_trial_disabled_or_trusted() {
+        let now = Utc.with_ymd_and_hms(2025, 8, 15, 12, 0, 0).unwrap();
+        let mut user = user(now);
+
+        assert_eq!(active_trial(&user, 0, 1, now), None);
+
+        user.trusted_at = Some(now);
+        assert_eq!(active_trial(&user, 48, 1, now), None);
+    }
+}
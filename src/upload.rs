@@ -0,0 +1,182 @@
+use futures::{Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use teloxide::{net::Download, prelude::*, types::Document};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+/// 上传文件临时目录的名称，位于数据库所在目录下（见 `Config::upload_dir`）；
+/// 文件名由程序生成，从不使用客户端提供的文件名
+pub const UPLOAD_DIR_NAME: &str = "uploads";
+
+/// 同一微秒内的多次上传依靠计数器区分
+static UPLOAD_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 期望的文件内容，下载后按文件头校验
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadKind {
+    /// UTF-8 文本（机器码列表、CSV），不允许出现 NUL 字节
+    Text,
+}
+
+impl UploadKind {
+    fn description(self) -> &'static str {
+        match self {
+            UploadKind::Text => "UTF-8 文本",
+        }
+    }
+
+    /// 根据文件内容判断是否为期望的类型
+    pub fn matches(self, content: &[u8]) -> bool {
+        match self {
+            UploadKind::Text => !content.contains(&0) && std::str::from_utf8(content).is_ok(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("文件过大，最多 {0} KB")]
+    TooLarge(u64),
+    #[error("文件类型不符，需要{}", .0.description())]
+    WrongType(UploadKind),
+    #[error("下载文件失败: {0}")]
+    Download(String),
+    #[error("保存文件失败: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// 已下载并通过校验的临时文件，离开作用域时自动删除
+#[derive(Debug)]
+pub struct Upload {
+    path: PathBuf,
+}
+
+impl Upload {
+    pub async fn read_to_string(&self) -> Result<String, UploadError> {
+        let bytes = tokio::fs::read(&self.path).await?;
+        String::from_utf8(bytes).map_err(|_| UploadError::WrongType(UploadKind::Text))
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        remove_temp(&self.path);
+    }
+}
+
+fn remove_temp(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("删除临时文件 {:?} 失败: {}", path, e);
+        }
+    }
+}
+
+fn temp_path(dir: &Path) -> PathBuf {
+    let seq = UPLOAD_SEQ.fetch_add(1, Ordering::Relaxed);
+    let micros = chrono::Utc::now().timestamp_micros();
+    dir.join(format!("{}-{}-{}.part", std::process::id(), micros, seq))
+}
+
+/// 下载 Telegram 文档到 `dir`：先按声明的大小拒绝，下载时再按实际字节数截断，最后校验文件头
+pub async fn download_document(
+    bot: &Bot,
+    document: &Document,
+    dir: &Path,
+    max_size: u64,
+    kind: UploadKind,
+) -> Result<Upload, UploadError> {
+    if u64::from(document.file.size) > max_size {
+        return Err(UploadError::TooLarge(max_size / 1024));
+    }
+
+    let file = bot.get_file(&document.file.id).await.map_err(|e| UploadError::Download(e.to_string()))?;
+    save_stream(bot.download_file_stream(&file.path), dir, max_size, kind).await
+}
+
+/// 将数据流写入 `dir` 下的唯一临时文件；超出大小、类型不符或数据流出错时删除临时文件并返回错误
+pub async fn save_stream<S, B, E>(stream: S, dir: &Path, max_size: u64, kind: UploadKind) -> Result<Upload, UploadError>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    tokio::fs::create_dir_all(dir).await?;
+    // 先创建守卫，之后任何提前返回都会删除临时文件
+    let upload = Upload { path: temp_path(dir) };
+    let mut file = tokio::fs::File::create(&upload.path).await?;
+
+    let mut written = 0u64;
+    let mut head = Vec::new();
+    futures::pin_mut!(stream);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| UploadError::Download(e.to_string()))?;
+        let chunk = chunk.as_ref();
+        written += chunk.len() as u64;
+        if written > max_size {
+            return Err(UploadError::TooLarge(max_size / 1024));
+        }
+        // 文本需要整体校验，其余类型只需文件头
+        if kind == UploadKind::Text || head.len() < 16 {
+            head.extend_from_slice(chunk);
+        }
+        file.write_all(chunk).await?;
+    }
+    file.flush().await?;
+
+    if !kind.matches(&head) {
+        return Err(UploadError::WrongType(kind));
+    }
+    Ok(upload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: Vec<Result<&'static [u8], &'static str>>) -> impl Stream<Item = Result<&'static [u8], &'static str>> {
+        futures::stream::iter(parts)
+    }
+
+    fn is_empty(dir: &Path) -> bool {
+        std::fs::read_dir(dir).unwrap().next().is_none()
+    }
+
+    #[tokio::test]
+    async fn test_save_text_and_cleanup_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload = save_stream(chunks(vec![Ok(b"111,spam\n"), Ok(b"222\n")]), dir.path(), 1024, UploadKind::Text)
+            .await
+            .unwrap();
+        assert_eq!(upload.read_to_string().await.unwrap(), "111,spam\n222\n");
+        assert!(upload.path.starts_with(dir.path()));
+
+        drop(upload);
+        assert!(is_empty(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn test_oversize_is_rejected_mid_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = save_stream(chunks(vec![Ok(b"12345"), Ok(b"67890")]), dir.path(), 8, UploadKind::Text).await;
+        assert!(matches!(result, Err(UploadError::TooLarge(_))));
+        assert!(is_empty(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = save_stream(chunks(vec![Ok(b"\x00\x01binary")]), dir.path(), 1024, UploadKind::Text).await;
+        assert!(matches!(result, Err(UploadError::WrongType(UploadKind::Text))));
+        assert!(is_empty(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = save_stream(chunks(vec![Ok(b"111\n"), Err("connection reset")]), dir.path(), 1024, UploadKind::Text).await;
+        assert!(matches!(result, Err(UploadError::Download(message)) if message == "connection reset"));
+        assert!(is_empty(dir.path()));
+    }
+}
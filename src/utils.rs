@@ -4,19 +4,83 @@ use std::fs;
 use std::path::Path;
 use tracing::{info, warn};
 
-/// 格式化日期时间为可读字符串
-pub fn format_datetime(dt: &DateTime<Utc>) -> String {
-    dt.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+/// 判断字符是否为 emoji 或其组合修饰符
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF
+            | 0x2600..=0x27BF
+            | 0x2B00..=0x2BFF
+            | 0x2300..=0x23FF
+            | 0x2194..=0x2199
+            | 0xFE00..=0xFE0F
+            | 0x200D
+            | 0x20E3
+            | 0x2139
+            | 0x3030
+            | 0x303D
+    )
 }
 
-/// 格式化日期时间为中国时区字符串
-pub fn format_datetime_china(dt: &DateTime<Utc>) -> String {
-    let china_dt = *dt + chrono::Duration::hours(8);
-    china_dt.format("%Y-%m-%d %H:%M:%S (Asia/Shanghai)").to_string()
+/// 判断字符是否为制表符（边框装饰）
+fn is_box_drawing(c: char) -> bool {
+    matches!(c as u32, 0x2500..=0x257F)
+}
+
+/// 去除文案中的 emoji 与装饰边框，输出朴素文本
+pub fn strip_decorations(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        let is_tree_item = line.trim_start().starts_with(['┣', '┗']);
+        let cleaned: String = line
+            .chars()
+            .filter(|c| !is_emoji(*c) && !is_box_drawing(*c))
+            .collect();
+        let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        if cleaned.is_empty() {
+            // 纯边框行直接去掉，原有空行保留为段落分隔
+            if line.trim().is_empty() && lines.last().is_some_and(|l| !l.is_empty()) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+
+        if is_tree_item {
+            lines.push(format!("• {}", cleaned));
+        } else {
+            lines.push(cleaned);
+        }
+    }
+
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+/// 解析时长字符串，如 "30m"、"12h"、"7d"
+pub fn parse_duration(text: &str) -> Option<chrono::Duration> {
+    let text = text.trim();
+    if text.len() < 2 || !text.is_ascii() {
+        return None;
+    }
+
+    let (value, unit) = text.split_at(text.len() - 1);
+    let value = value.parse::<i64>().ok().filter(|v| *v > 0)?;
+
+    match unit {
+        "m" => Some(chrono::Duration::minutes(value)),
+        "h" => Some(chrono::Duration::hours(value)),
+        "d" => Some(chrono::Duration::days(value)),
+        _ => None,
+    }
 }
 
 /// 清理日志文件
-pub async fn cleanup_logs() -> Result<usize> {
+pub async fn cleanup_logs(now: DateTime<Utc>) -> Result<usize> {
     let mut cleaned_files = 0;
     let log_patterns = vec!["*.log", "guard_*.log", "bot_*.log"];
     
@@ -26,7 +90,7 @@ pub async fn cleanup_logs() -> Result<usize> {
                 for entry in paths {
                     match entry {
                         Ok(path) => {
-                            if should_cleanup_log(&path)? {
+                            if should_cleanup_log(&path, now)? {
                                 match fs::remove_file(&path) {
                                     Ok(_) => {
                                         info!("删除日志文件: {:?}", path);
@@ -54,13 +118,12 @@ pub async fn cleanup_logs() -> Result<usize> {
 }
 
 /// 判断是否应该清理某个日志文件
-fn should_cleanup_log(path: &Path) -> Result<bool> {
+fn should_cleanup_log(path: &Path, now: DateTime<Utc>) -> Result<bool> {
     let metadata = fs::metadata(path)?;
-    let modified = metadata.modified()?;
-    let age = std::time::SystemTime::now().duration_since(modified)?;
+    let modified = DateTime::<Utc>::from(metadata.modified()?);
     
     // 清理7天前的日志文件
-    Ok(age.as_secs() > 7 * 24 * 3600)
+    Ok(now - modified > chrono::Duration::days(7))
 }
 
 /// 格式化文件大小
@@ -98,6 +161,17 @@ pub async fn check_telegram_api(bot_token: &str) -> bool {
     }
 }
 
+/// 以 HEAD 请求读取服务器返回的 `Date` 头，请求失败或没有该头时返回 None
+pub async fn fetch_date_header(url: &str) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .ok()?;
+    let response = client.head(url).send().await.ok()?;
+    let date = response.headers().get(reqwest::header::DATE)?;
+    date.to_str().ok().map(str::to_string)
+}
+
 /// 获取系统信息
 pub fn get_system_info() -> Result<SystemInfo> {
     use sysinfo::System;
@@ -105,7 +179,7 @@ pub fn get_system_info() -> Result<SystemInfo> {
     let mut sys = System::new_all();
     sys.refresh_all();
     
-    let cpu_usage = sys.global_cpu_info().cpu_usage();
+    let cpu_usage = sample_cpu_usage();
     let total_memory = sys.total_memory();
     let used_memory = sys.used_memory();
     let memory_usage = (used_memory as f64 / total_memory as f64) * 100.0;
@@ -114,7 +188,7 @@ pub fn get_system_info() -> Result<SystemInfo> {
     let disk_usage = 0.0; // 暂时设为0，避免API变化问题
     
     Ok(SystemInfo {
-        cpu_usage: cpu_usage as f64,
+        cpu_usage,
         memory_usage,
         disk_usage,
         total_memory,
@@ -122,6 +196,67 @@ pub fn get_system_info() -> Result<SystemInfo> {
     })
 }
 
+/// 全局 CPU 使用率 (%)，按与上一次采样之间的差值计算；首次调用返回 0。
+/// 两次采样间隔不足 sysinfo 要求的最小间隔时返回上一次的结果，可在每条消息上调用
+pub fn sample_cpu_usage() -> f64 {
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Instant;
+    use sysinfo::{System, MINIMUM_CPU_UPDATE_INTERVAL};
+
+    struct Sampler {
+        sys: System,
+        sampled_at: Instant,
+        usage: f64,
+    }
+
+    static SAMPLER: OnceLock<Mutex<Sampler>> = OnceLock::new();
+    let sampler = SAMPLER.get_or_init(|| {
+        let mut sys = System::new();
+        sys.refresh_cpu_usage();
+        Mutex::new(Sampler { sys, sampled_at: Instant::now(), usage: 0.0 })
+    });
+
+    let mut sampler = sampler.lock().unwrap_or_else(|e| e.into_inner());
+    if sampler.sampled_at.elapsed() >= MINIMUM_CPU_UPDATE_INTERVAL {
+        sampler.sys.refresh_cpu_usage();
+        sampler.sampled_at = Instant::now();
+        sampler.usage = f64::from(sampler.sys.global_cpu_info().cpu_usage());
+    }
+    sampler.usage
+}
+
+/// 获取路径所在文件系统的 inode 使用率 (%)；非 Unix 平台或文件系统不报告 inode 时返回 None
+#[cfg(unix)]
+pub fn get_inode_usage(path: &Path) -> Option<f64> {
+    let output = std::process::Command::new("df")
+        .args(["-P", "-i"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_df_inodes(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(unix))]
+pub fn get_inode_usage(_path: &Path) -> Option<f64> {
+    None
+}
+
+/// 解析 `df -P -i` 输出中的 inode 总数与已用数
+#[cfg_attr(not(unix), allow(dead_code))]
+fn parse_df_inodes(output: &str) -> Option<f64> {
+    let fields: Vec<&str> = output.lines().nth(1)?.split_whitespace().collect();
+    let total = fields.get(1)?.parse::<u64>().ok()?;
+    let used = fields.get(2)?.parse::<u64>().ok()?;
+    // btrfs 等文件系统不限制 inode，总数报告为 0
+    if total == 0 {
+        return None;
+    }
+    Some(used as f64 / total as f64 * 100.0)
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemInfo {
     pub cpu_usage: f64,
@@ -159,13 +294,8 @@ pub struct ProcessInfo {
 }
 
 /// 计算运行时长
-pub fn calculate_uptime(start_time: u64) -> String {
-    let current_time = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    
-    let uptime_seconds = current_time - start_time;
+pub fn calculate_uptime(start_time: u64, now: DateTime<Utc>) -> String {
+    let uptime_seconds = (now.timestamp().max(0) as u64).saturating_sub(start_time);
     let days = uptime_seconds / 86400;
     let hours = (uptime_seconds % 86400) / 3600;
     let minutes = (uptime_seconds % 3600) / 60;
@@ -184,6 +314,14 @@ pub fn check_disk_space() -> Result<bool> {
     Ok(system_info.disk_usage < 90.0) // 磁盘使用率小于90%认为是正常
 }
 
+/// 在目录中创建并删除一个探测文件，确认当前进程可以写入该目录
+pub fn check_dir_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(format!(".write_test_{}", std::process::id()));
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
 /// 压缩日志文件
 pub async fn compress_logs() -> Result<usize> {
     // 这里可以实现日志压缩逻辑
@@ -226,14 +364,7 @@ pub fn is_process_running(pid: u32) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::Utc;
-
-    #[test]
-    fn test_format_datetime() {
-        let dt = Utc::now();
-        let formatted = format_datetime(&dt);
-        assert!(formatted.contains("UTC"));
-    }
+    use crate::clock::{Clock, MockClock};
 
     #[test]
     fn test_format_file_size() {
@@ -242,15 +373,65 @@ mod tests {
         assert_eq!(format_file_size(500), "500 B");
     }
 
+    #[test]
+    fn test_parse_df_inodes() {
+        let output = "Filesystem Inodes IUsed IFree IUse% Mounted on\n/dev/sda1 1000 925 75 93% /\n";
+        assert_eq!(parse_df_inodes(output), Some(92.5));
+        let btrfs = "Filesystem Inodes IUsed IFree IUse% Mounted on\n/dev/sdb1 0 0 0 - /data\n";
+        assert_eq!(parse_df_inodes(btrfs), None);
+        assert_eq!(parse_df_inodes(""), None);
+    }
+
+    #[test]
+    fn test_strip_decorations() {
+        let text = "╔══════╗\n║ 🎉 标题 🎉 ║\n╚══════╝\n\n👋 欢迎\n┣━ 1️⃣ 第一步\n┗━ ✅ 完成\n\n═══════\n";
+        assert_eq!(strip_decorations(text), "标题\n\n欢迎\n• 1 第一步\n• 完成");
+    }
+
+    #[test]
+    fn test_strip_decorations_keeps_plain_text() {
+        let text = "机器码: `abc-123@def`\n帮助 → 注册\n版本 ≥ 3.9.6";
+        assert_eq!(strip_decorations(text), text);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m"), Some(chrono::Duration::minutes(30)));
+        assert_eq!(parse_duration("12h"), Some(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("7d"), Some(chrono::Duration::days(7)));
+        assert_eq!(parse_duration("0d"), None);
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("7w"), None);
+        assert_eq!(parse_duration("spam"), None);
+    }
+
     #[test]
     fn test_calculate_uptime() {
-        let start_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() - 3661; // 1小时1分钟1秒前
-        
-        let uptime = calculate_uptime(start_time);
+        let clock = MockClock::new(Utc::now());
+        let start_time = clock.now_utc().timestamp() as u64;
+        clock.advance(std::time::Duration::from_secs(3661)); // 1小时1分钟1秒后
+
+        let uptime = calculate_uptime(start_time, clock.now_utc());
         assert!(uptime.contains("01:01:01"));
+        clock.advance(std::time::Duration::from_secs(86400));
+        assert_eq!(calculate_uptime(start_time, clock.now_utc()), "1 days, 01:01:01");
+    }
+
+    #[test]
+    fn test_check_dir_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_dir_writable(dir.path()).is_ok());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(check_dir_writable(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_sample_cpu_usage() {
+        let first = sample_cpu_usage();
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        let second = sample_cpu_usage();
+        assert!((0.0..=100.0).contains(&first));
+        assert!((0.0..=100.0).contains(&second));
     }
 
     #[test]